@@ -1,21 +1,97 @@
+use crate::ip_detection::Strategy;
+
+use std::fmt;
+use std::error;
 use std::fs;
 use serde::{Serialize, Deserialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Credential {
     pub provider: String,
+    /// User name of `dyndns2` and No-IP, name of the TSIG key of RFC 2136, access key ID of Route 53
+    /// and API key of Mythic Beasts
+    #[serde(default)]
     pub user: String,
+    /// Password of `dyndns2` and No-IP, base64 secret of the TSIG key of RFC 2136, secret access key
+    /// of Route 53 and API secret of Mythic Beasts
+    #[serde(default)]
     pub pass: String,
+    /// API token of Cloudflare, deSEC, DigitalOcean, Gandi, Hetzner and Linode, API key of PowerDNS
+    /// and session token of Route 53
+    pub token: Option<String>,
     pub zone: Option<String>,
     pub host: Option<String>,
     pub r#type: Option<String>,
+    /// Base URL of the API of the HTTP providers, instead of their default one. Required by
+    /// `dyndns2`, which has no default server, and by RFC 2136 as the address of the DNS server.
+    pub api_url: Option<String>,
+    /// Endpoint Mythic Beasts hands out API tokens from, instead of the default one
+    pub auth_url: Option<String>,
+    /// File Mythic Beasts API tokens are kept in between runs
+    pub token_cache: Option<String>,
+    /// TSIG algorithm of RFC 2136 (e.g. `hmac-sha256`)
+    pub algorithm: Option<String>,
+    /// Program the exec provider runs
+    pub command: Option<String>,
+    /// Arguments given to the program of the exec provider
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Seconds the program of the exec provider may run for
+    pub timeout: Option<u64>,
+    /// Zone file edited by the zonefile provider
+    pub path: Option<String>,
+    /// Command the zonefile provider runs after changing the zone file (e.g. `rndc reload`)
+    pub reload_command: Option<String>,
+    /// How the address of the host is detected, instead of DDNS telling it from the request
+    pub ip_detection: Option<Strategy>,
 }
 
 pub type Credentials = Vec<Credential>;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Settings of the HTTP client shared by all providers
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HttpSettings {
+    /// Seconds to wait for a whole request, including reading the response
+    pub timeout: Option<u64>,
+    /// Seconds to wait for a connection to be established
+    pub connect_timeout: Option<u64>,
+    /// URL of the HTTP(S) or SOCKS5 proxy to send all requests through (e.g. `http://proxy:3128`, `socks5h://proxy:1080`)
+    pub proxy: Option<String>,
+    /// PEM files with certificate authorities to trust on top of the system ones
+    #[serde(default)]
+    pub ca_certificates: Vec<String>,
+    /// PKCS#12 file with the certificate (and its key) to authenticate the client with
+    pub client_certificate: Option<String>,
+    pub client_certificate_password: Option<String>,
+    pub user_agent: Option<String>,
+    /// Number of times a request failing for a temporary reason is sent at most
+    pub max_attempts: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Configuration {
     pub credentials: Credentials,
+    #[serde(default)]
+    pub http: HttpSettings,
+}
+
+/// An error which can be returned when loading the configuration file
+#[derive(Debug)]
+pub struct ConfigError {
+    kind: ConfigErrorKind,
+    /// Error of a dependency which caused this one, available through `Error::source()`
+    source: Box<dyn error::Error + Send + Sync + 'static>,
+}
+
+/// Why the configuration file could not be loaded.
+///
+/// The errors of the file system and the YAML parser are not part of the kinds, so upgrading
+/// those does not change this API; they can be reached through `Error::source()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum ConfigErrorKind {
+    IoError,
+    SerdeYamlError,
 }
 
 
@@ -23,13 +99,75 @@ impl Configuration {
     pub fn new() -> Self {
         Configuration {
             credentials: vec![],
+            http: HttpSettings::default(),
         }
     }
 
-    pub fn from_path(path: &str) -> Configuration {
-        let config_contents = fs::read_to_string(path).expect("Unable to read config file!");
-        let config: Configuration = serde_yaml::from_str(&config_contents).unwrap();
+    pub fn from_path(path: &str) -> Result<Configuration, ConfigError> {
+        let config_contents = fs::read_to_string(path)?;
+        let config: Configuration = serde_yaml::from_str(&config_contents)?;
+
+        Ok(config)
+    }
+}
+
+
+impl ConfigError {
+    fn caused_by<E: error::Error + Send + Sync + 'static>(kind: ConfigErrorKind, err: E) -> Self {
+        ConfigError {
+            kind,
+            source: Box::new(err),
+        }
+    }
+
+    /// Why the configuration file could not be loaded
+    pub fn kind(&self) -> ConfigErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ConfigErrorKind::IoError => write!(f, "Unable to read config file: {}", self.source),
+            ConfigErrorKind::SerdeYamlError => write!(f, "Invalid config file: {}", self.source),
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> ConfigError {
+        ConfigError::caused_by(ConfigErrorKind::IoError, err)
+    }
+}
+
+impl From<serde_yaml::Error> for ConfigError {
+    fn from(err: serde_yaml::Error) -> ConfigError {
+        ConfigError::caused_by(ConfigErrorKind::SerdeYamlError, err)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn keeps_the_cause_behind_source() {
+        let error = Configuration::from_path("/nonexistent/ddnsclient.yaml").unwrap_err();
+        assert_eq!(error.kind(), ConfigErrorKind::IoError);
+        assert!(error.source().unwrap().downcast_ref::<std::io::Error>().is_some());
 
-        config
+        let error = ConfigError::from(serde_yaml::from_str::<Configuration>("credentials: 3").unwrap_err());
+        assert_eq!(error.kind(), ConfigErrorKind::SerdeYamlError);
+        assert!(error.to_string().starts_with("Invalid config file: "));
+        assert!(error.source().unwrap().downcast_ref::<serde_yaml::Error>().is_some());
     }
 }
@@ -0,0 +1,562 @@
+//! Keeping hosts pointed at the public address of the client from a long-running process.
+//!
+//! The address is checked on an interval, with the strategy configured for the host or else with
+//! the service at `--ip-url`, and only published when it changed since it was last
+//! published, by this process or by an earlier run as told by the state file. Failures which are
+//! likely to go away (the network or the API being down) are tried again after a growing delay,
+//! while other failures (e.g. wrong credentials) stop the daemon. SIGTERM and SIGINT stop it once
+//! the current check is done, and SIGHUP reloads the configuration.
+//!
+//! On Linux the daemon can also watch the network, and check the address as soon as an address
+//! or a default route changes. The check waits for the network to settle first, so a flapping link
+//! does not end up in a burst of API calls.
+use ddnsclient::{Credential, Provider, Record, RecordSelector, DynamicDnsRequest, DynamicDnsResponse, UpdateRequest, ProviderErrorKind, Result, ip_detection};
+use ddnsclient::ip_detection::Strategy;
+use ddnsclient::state::State;
+
+use std::net::IpAddr;
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+/// Wait after the first failed check, doubled after every failure in a row
+const ERROR_DELAY: Duration = Duration::from_secs(30);
+
+/// Longest wait after failed checks
+const MAX_ERROR_DELAY: Duration = Duration::from_secs(3600);
+
+/// While the network keeps changing, the address is still checked after this many settle times
+const MAX_SETTLE: u32 = 6;
+
+/// What wakes the daemon up before the next check is due
+#[derive(Debug, PartialEq)]
+enum Signal {
+    Stop,
+    Reload,
+    /// An address or a default route changed
+    Changed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Time between two checks of the address
+    pub interval: Duration,
+    /// Service telling the public address of the client
+    pub ip_url: String,
+    /// TTL of the records, for providers which are updated with a record instead of DDNS
+    pub ttl: u32,
+    /// Publish the address on the first check even if the state file tells it was published
+    pub force: bool,
+    /// Check the address when the network changes as well
+    pub watch: bool,
+    /// Time without network changes to wait for before checking the address
+    pub debounce: Duration,
+}
+
+/// Everything the daemon works with which comes from the configuration, and is rebuilt when it is
+/// reloaded
+pub struct Setup {
+    pub provider: Box<dyn Provider>,
+    pub credentials: Vec<Credential>,
+    pub selectors: Vec<RecordSelector>,
+    pub settings: Settings,
+}
+
+
+/// Keep the hosts pointed at the public address until asked to stop, and return the exit code.
+/// `reload` gives the setup built from the reloaded configuration, or `None` to keep using the
+/// current one.
+pub fn run<F>(setup: Setup, state: Option<State>, reload: F) -> i32
+where
+    F: Fn() -> Option<Setup>,
+{
+    let (sender, signals) = mpsc::channel();
+    if let Err(e) = listen(sender.clone()) {
+        log::error!("Unable to listen for signals: {}", e);
+        return exitcode::OSERR;
+    }
+    let mut watching = false;
+    if setup.settings.watch {
+        if let Err(e) = watch(sender.clone()) {
+            log::error!("Unable to watch the network: {}", e);
+            return exitcode::OSERR;
+        }
+        watching = true;
+    }
+
+    let Setup { mut provider, mut credentials, mut selectors, mut settings } = setup;
+    let mut published: Vec<Option<IpAddr>> = vec![None; selectors.len()];
+    let mut failures = 0;
+    let mut force = settings.force;
+    log::info!("Checking the public address every {} seconds", settings.interval.as_secs());
+
+    loop {
+        let outcome = check(provider.as_ref(), &credentials, &selectors, &settings, state.as_ref(), force, &mut published);
+        if outcome.is_ok() {
+            force = false;
+        }
+        let delay = match next_check(outcome, &mut failures, settings.interval) {
+            Some(d) => d,
+            None => return exitcode::UNAVAILABLE,
+        };
+
+        match wait(&signals, delay, settings.debounce) {
+            Some(Signal::Reload) => {
+                log::info!("Reloading the configuration");
+                if let Some(setup) = reload() {
+                    if setup.settings.watch && !watching {
+                        match watch(sender.clone()) {
+                            Ok(()) => watching = true,
+                            Err(e) => log::error!("Unable to watch the network: {}", e),
+                        }
+                    } else if !setup.settings.watch && watching {
+                        log::warn!("Watching the network only stops with a restart");
+                    }
+
+                    provider = setup.provider;
+                    credentials = setup.credentials;
+                    selectors = setup.selectors;
+                    settings = setup.settings;
+                    // The records may have changed along with the configuration
+                    published = vec![None; selectors.len()];
+                    failures = 0;
+                    force = true;
+                    log::info!("Checking the public address every {} seconds", settings.interval.as_secs());
+                }
+            },
+            Some(Signal::Stop) => {
+                log::info!("Shutting down");
+                return exitcode::OK;
+            },
+            Some(Signal::Changed) | None => (),
+        }
+    }
+}
+
+/// How long to wait for after a check, counting the failures in a row, or `None` when the failure
+/// is not likely to go away and the daemon has to stop
+fn next_check(outcome: Result<()>, failures: &mut u32, interval: Duration) -> Option<Duration> {
+    match outcome {
+        Ok(()) => {
+            *failures = 0;
+            Some(interval)
+        },
+        Err(e) if e.is_transient() => {
+            *failures += 1;
+            let delay = backoff(*failures);
+            log::error!("{}", e);
+            log::warn!("Checking again in {} seconds", delay.as_secs());
+            Some(delay)
+        },
+        Err(e) => {
+            log::error!("{}", e);
+            None
+        },
+    }
+}
+
+/// Wait for the delay to pass, and return the signal arriving before it does. When the network
+/// changes, wait for it to stay the same for the settle time instead (up to `MAX_SETTLE` times as
+/// long while it keeps changing), and return `None` as it is time to check the address.
+fn wait(signals: &Receiver<Signal>, delay: Duration, settle: Duration) -> Option<Signal> {
+    let mut deadline = Instant::now() + delay;
+    let mut changed: Option<Instant> = None;
+
+    loop {
+        match signals.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(Signal::Changed) => {
+                let first = *changed.get_or_insert_with(|| {
+                    log::info!("The network changed, checking the address once it settles");
+                    Instant::now()
+                });
+                deadline = (Instant::now() + settle).min(first + settle * MAX_SETTLE);
+            },
+            Ok(signal) => return Some(signal),
+            Err(RecvTimeoutError::Timeout) => return None,
+            Err(RecvTimeoutError::Disconnected) => return Some(Signal::Stop),
+        }
+    }
+}
+
+/// Detect the address of every host and publish it for the hosts it changed for, unless forced to
+/// publish it for all of them. All hosts are tried before failing, with the failure which is not
+/// transient reported first.
+fn check(provider: &dyn Provider, credentials: &[Credential], selectors: &[RecordSelector], settings: &Settings, state: Option<&State>, force: bool, published: &mut [Option<IpAddr>]) -> Result<()> {
+    let mut outcome: Result<()> = Ok(());
+    // Hosts detected with the same strategy share the address
+    let mut detected: Vec<(Strategy, IpAddr)> = Vec::new();
+
+    for (selector, last) in selectors.iter().zip(published.iter_mut()) {
+        let host = selector.host().unwrap_or("@");
+        let configured = ip_detection::configured(credentials, selector.zone(), selector.host());
+        let strategy = configured.clone().unwrap_or_else(|| Strategy::Http { url: settings.ip_url.clone() });
+
+        let address = match detected.iter().find(|(s, _)| *s == strategy) {
+            Some((_, a)) => *a,
+            None => match ip_detection::detect(&strategy) {
+                Ok(a) => {
+                    detected.push((strategy, a));
+                    a
+                },
+                Err(e) => {
+                    log::error!("Unable to detect the address of {} in {}: {}", host, selector.zone(), e);
+                    keep_first(&mut outcome, e);
+                    continue;
+                },
+            },
+        };
+
+        let known = *last == Some(address) || !force && state.is_some_and(|s| s.is_published(&provider.get_name(), selector.zone(), host, address));
+        if known {
+            log::debug!("{} in {} already points at {}", host, selector.zone(), address);
+            *last = Some(address);
+            continue;
+        }
+
+        let result = publish(provider, selector, address, settings.ttl, configured.is_none());
+        if let Some(state) = state {
+            state.remember(&provider.get_name(), selector.zone(), host, Some(address), &result);
+        }
+
+        match result {
+            Ok(response) => {
+                match response.address {
+                    Some(a) => log::info!("Pointed {} in {} at {}", host, selector.zone(), a),
+                    None => log::info!("Pointed {} in {} at the address of the client: {}", host, selector.zone(), response.message),
+                }
+                // Published again when the detected address changes, whichever the provider took
+                *last = Some(address);
+            },
+            Err(e) => {
+                log::error!("Unable to point {} in {} at {}: {}", host, selector.zone(), address, e);
+                keep_first(&mut outcome, e);
+            },
+        }
+    }
+
+    outcome
+}
+
+/// Keep the first failure, unless it is transient and the new one is not
+fn keep_first(outcome: &mut Result<()>, error: ddnsclient::ProviderError) {
+    let first = match outcome {
+        Ok(()) => true,
+        Err(f) => f.is_transient() && !error.is_transient(),
+    };
+    if first {
+        *outcome = Err(error);
+    }
+}
+
+/// Point the host at the address with DDNS when asked to, or by updating its A/AAAA record when
+/// the address was detected otherwise or the provider does not support DDNS
+fn publish(provider: &dyn Provider, selector: &RecordSelector, address: IpAddr, ttl: u32, ddns: bool) -> Result<DynamicDnsResponse> {
+    if ddns {
+        match provider.dynamic_dns(&DynamicDnsRequest { selector: selector.clone(), address: Some(address) }) {
+            Err(e) if matches!(e.kind(), ProviderErrorKind::Unsupported) => log::debug!("{} Updating the record instead", e),
+            outcome => return outcome,
+        }
+    }
+
+    provider.update(&address_update(selector, address, ttl))?;
+
+    Ok(address_updated(selector, address))
+}
+
+/// Update of the A/AAAA record of the host with the address
+pub fn address_update(selector: &RecordSelector, address: IpAddr, ttl: u32) -> UpdateRequest {
+    let rtype = ip_detection::record_type(&address);
+
+    UpdateRequest {
+        selector: RecordSelector::new(selector.zone(), selector.host(), Some(rtype)),
+        records: vec![Record {
+            host: selector.host().unwrap_or("@").to_string(),
+            ttl,
+            r#type: rtype.to_string(),
+            data: address.to_string(),
+            ..Default::default()
+        }],
+        wait: false,
+    }
+}
+
+/// What was published by updating the A/AAAA record of the host with the address
+pub fn address_updated(selector: &RecordSelector, address: IpAddr) -> DynamicDnsResponse {
+    DynamicDnsResponse {
+        address: Some(address),
+        message: format!("Updated the {} record of {} to {}", ip_detection::record_type(&address), selector.host().unwrap_or("@"), address),
+    }
+}
+
+fn backoff(failures: u32) -> Duration {
+    ERROR_DELAY
+        .checked_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .map_or(MAX_ERROR_DELAY, |d| d.min(MAX_ERROR_DELAY))
+}
+
+/// Send the signals sent to the process, as they arrive
+#[cfg(unix)]
+fn listen(sender: Sender<Signal>) -> io::Result<()> {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+
+    let mut signals = signal_hook::iterator::Signals::new([SIGTERM, SIGINT, SIGHUP])?;
+
+    std::thread::spawn(move || {
+        for signal in signals.forever() {
+            let signal = if signal == SIGHUP { Signal::Reload } else { Signal::Stop };
+            if sender.send(signal).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Without signals the daemon runs until it is killed
+#[cfg(not(unix))]
+fn listen(sender: Sender<Signal>) -> io::Result<()> {
+    std::mem::forget(sender);
+
+    Ok(())
+}
+
+/// Send the changes of the network, as they happen. Once they can not be told any more, the
+/// address is only checked on the interval.
+#[cfg(target_os = "linux")]
+fn watch(sender: Sender<Signal>) -> io::Result<()> {
+    let mut changes = ip_detection::NetworkChanges::subscribe()?;
+
+    std::thread::spawn(move || loop {
+        if let Err(e) = changes.wait() {
+            log::error!("Stopped watching the network: {}", e);
+            break;
+        }
+        if sender.send(Signal::Changed).is_err() {
+            break;
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch(_sender: Sender<Signal>) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "watching the network is only supported on Linux"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ddnsclient::{ProviderError, SearchRequest, DeleteRequest};
+
+    use std::sync::Mutex;
+    use std::thread;
+
+    const SETTLE: Duration = Duration::from_millis(100);
+    const INTERVAL: Duration = Duration::from_secs(300);
+
+    /// The error the update of a host fails with
+    type Failure = (&'static str, fn() -> ProviderError);
+
+    /// A provider failing the updates of the hosts as told, and keeping the updates it was asked for
+    #[derive(Debug, Default)]
+    struct Fake {
+        failures: Vec<Failure>,
+        updates: Mutex<Vec<UpdateRequest>>,
+    }
+
+    impl Provider for Fake {
+        fn get_name(&self) -> String {
+            String::from("fake")
+        }
+
+        fn set_credentials(&mut self, _c: Vec<Credential>) {}
+
+        fn dynamic_dns(&self, _request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+            Err(ProviderError::new(ProviderErrorKind::Unsupported))
+        }
+
+        fn search(&self, _request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+            Ok(None)
+        }
+
+        fn update(&self, request: &UpdateRequest) -> Result<bool> {
+            self.updates.lock().unwrap().push(request.clone());
+
+            match self.failures.iter().find(|(h, _)| request.selector.host() == Some(h)) {
+                Some((_, error)) => Err(error()),
+                None => Ok(true),
+            }
+        }
+
+        fn delete(&self, _request: &DeleteRequest) -> Result<bool> {
+            Ok(true)
+        }
+    }
+
+    fn server_error() -> ProviderError {
+        ProviderError::new(ProviderErrorKind::ServerError)
+    }
+
+    fn rate_limited() -> ProviderError {
+        ProviderError::new(ProviderErrorKind::RateLimited)
+    }
+
+    fn authentication_failed() -> ProviderError {
+        ProviderError::new(ProviderErrorKind::AuthenticationFailed)
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            interval: INTERVAL,
+            ip_url: String::from("http://127.0.0.1:9/"),
+            ttl: 300,
+            force: false,
+            watch: false,
+            debounce: SETTLE,
+        }
+    }
+
+    /// Hosts of example.com with a static address, so checking them needs no network
+    fn hosts(hosts: &[&str]) -> (Vec<Credential>, Vec<RecordSelector>) {
+        let credentials = vec![Credential {
+            provider: String::from("fake"),
+            zone: Some(String::from("example.com")),
+            ip_detection: Some(Strategy::Static { address: "192.0.2.1".parse().unwrap() }),
+            ..Default::default()
+        }];
+        let selectors = hosts.iter().map(|h| RecordSelector::new("example.com", Some(h), None)).collect();
+
+        (credentials, selectors)
+    }
+
+    fn check_hosts(provider: &Fake, names: &[&str], published: &mut [Option<IpAddr>]) -> Result<()> {
+        let (credentials, selectors) = hosts(names);
+        check(provider, &credentials, &selectors, &settings(), None, false, published)
+    }
+
+    #[test]
+    fn backs_off_while_the_failures_are_transient() {
+        let provider = Fake { failures: vec![("home", server_error)], ..Default::default() };
+        let mut published = vec![None];
+        let mut failures = 0;
+
+        let delays: Vec<Option<Duration>> = (0..9)
+            .map(|_| next_check(check_hosts(&provider, &["home"], &mut published), &mut failures, INTERVAL))
+            .collect();
+        let seconds: Vec<u64> = delays.iter().map(|d| d.unwrap().as_secs()).collect();
+
+        assert_eq!(seconds, vec![30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert_eq!(failures, 9);
+        // Nothing was published, so every check tries again
+        assert_eq!(provider.updates.lock().unwrap().len(), 9);
+        assert_eq!(published, vec![None]);
+    }
+
+    #[test]
+    fn goes_back_to_the_interval_once_a_check_succeeds() {
+        let provider = Fake::default();
+        let mut published = vec![None];
+        let mut failures = 4;
+
+        let delay = next_check(check_hosts(&provider, &["home"], &mut published), &mut failures, INTERVAL);
+
+        assert_eq!(delay, Some(INTERVAL));
+        assert_eq!(failures, 0);
+        assert_eq!(published, vec![Some("192.0.2.1".parse().unwrap())]);
+
+        // The address did not change, so it is not published again
+        check_hosts(&provider, &["home"], &mut published).unwrap();
+        assert_eq!(provider.updates.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn backs_off_when_throttled() {
+        let provider = Fake { failures: vec![("home", rate_limited)], ..Default::default() };
+        let mut failures = 0;
+
+        let delay = next_check(check_hosts(&provider, &["home"], &mut [None]), &mut failures, INTERVAL);
+        assert_eq!(delay, Some(ERROR_DELAY));
+    }
+
+    #[test]
+    fn stops_when_the_failure_is_not_transient() {
+        let provider = Fake { failures: vec![("home", authentication_failed)], ..Default::default() };
+        let mut failures = 2;
+
+        let outcome = check_hosts(&provider, &["home"], &mut [None]);
+        assert!(!outcome.as_ref().unwrap_err().is_transient());
+        assert_eq!(next_check(outcome, &mut failures, INTERVAL), None);
+    }
+
+    #[test]
+    fn tries_every_host_and_reports_the_failure_which_is_not_transient() {
+        let provider = Fake {
+            failures: vec![("home", server_error), ("office", authentication_failed)],
+            ..Default::default()
+        };
+        let mut published = vec![None; 3];
+
+        let error = check_hosts(&provider, &["home", "office", "lab"], &mut published).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+        assert_eq!(provider.updates.lock().unwrap().len(), 3);
+        assert_eq!(published, vec![None, None, Some("192.0.2.1".parse().unwrap())]);
+    }
+
+    #[test]
+    fn waits_for_the_network_to_settle() {
+        let (sender, signals) = mpsc::channel();
+        let started = Instant::now();
+        thread::spawn(move || {
+            for _ in 0..3 {
+                sender.send(Signal::Changed).unwrap();
+                thread::sleep(SETTLE / 2);
+            }
+            thread::sleep(Duration::from_secs(60));
+        });
+
+        // A burst of changes ends up in a single check, once the last one settled
+        assert_eq!(wait(&signals, Duration::from_secs(60), SETTLE), None);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= SETTLE * 2, "{:?}", elapsed);
+        assert!(elapsed < SETTLE * MAX_SETTLE, "{:?}", elapsed);
+    }
+
+    #[test]
+    fn checks_a_flapping_network_after_a_while() {
+        let (sender, signals) = mpsc::channel();
+        let started = Instant::now();
+        thread::spawn(move || while sender.send(Signal::Changed).is_ok() {
+            thread::sleep(SETTLE / 4);
+        });
+
+        assert_eq!(wait(&signals, Duration::from_secs(60), SETTLE), None);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= SETTLE * MAX_SETTLE, "{:?}", elapsed);
+        assert!(elapsed < SETTLE * MAX_SETTLE * 2, "{:?}", elapsed);
+    }
+
+    #[test]
+    fn returns_other_signals_at_once() {
+        let (sender, signals) = mpsc::channel();
+        sender.send(Signal::Changed).unwrap();
+        sender.send(Signal::Reload).unwrap();
+
+        assert_eq!(wait(&signals, Duration::from_secs(60), SETTLE), Some(Signal::Reload));
+
+        drop(sender);
+        assert_eq!(wait(&signals, Duration::from_secs(60), SETTLE), Some(Signal::Stop));
+    }
+
+    #[test]
+    fn waits_for_the_interval_without_changes() {
+        let (_sender, signals) = mpsc::channel();
+        let started = Instant::now();
+
+        assert_eq!(wait(&signals, SETTLE, Duration::from_secs(60)), None);
+        assert!(started.elapsed() >= SETTLE);
+    }
+}
@@ -0,0 +1,294 @@
+//! Addresses of the local network interfaces, for hosts reachable at them without NAT.
+//!
+//! On Linux the addresses are asked to the kernel over rtnetlink, which tells their scope and
+//! flags: only global addresses are taken, leaving out unique local (fc00::/7), link-local,
+//! deprecated and tentative addresses. IPv6 addresses are then picked by kind (temporary privacy,
+//! stable-privacy, EUI-64 or any other) in the order of preference configured. Other Unix systems
+//! fall back to `getifaddrs`, which does not tell the flags, so only the scope and kind rules which
+//! can be told from the address itself apply.
+use super::Family;
+use crate::providers::{ProviderError, ProviderErrorKind, Result};
+
+use std::net::{IpAddr, Ipv6Addr};
+use serde::{Serialize, Deserialize};
+
+/// Kind of an IPv6 address, by how its interface identifier was made
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddressKind {
+    /// Short-lived privacy address (RFC 8981), changing every day or so
+    Temporary,
+    /// Stable address made from a secret and the prefix (RFC 7217)
+    StablePrivacy,
+    /// Address made from the MAC address of the interface
+    Eui64,
+    /// Any other address, e.g. set by hand or by DHCPv6
+    Other,
+}
+
+/// How far an address reaches, as the kernel tells it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Scope {
+    Global,
+    Site,
+    Link,
+    Host,
+}
+
+/// An address of an interface with what is known about it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InterfaceAddress {
+    pub address: IpAddr,
+    pub scope: Scope,
+    pub temporary: bool,
+    pub stable_privacy: bool,
+    pub deprecated: bool,
+    /// Still being checked for duplicates, or found to be one
+    pub tentative: bool,
+}
+
+
+impl AddressKind {
+    fn of(address: &InterfaceAddress) -> Self {
+        match address.address {
+            _ if address.temporary => AddressKind::Temporary,
+            _ if address.stable_privacy => AddressKind::StablePrivacy,
+            IpAddr::V6(a) if is_eui64(&a) => AddressKind::Eui64,
+            _ => AddressKind::Other,
+        }
+    }
+}
+
+impl Scope {
+    /// The scope of the address, for systems which do not tell it
+    #[cfg_attr(target_os = "linux", allow(dead_code))]
+    pub(crate) fn of(address: &IpAddr) -> Self {
+        match address {
+            a if a.is_loopback() => Scope::Host,
+            IpAddr::V4(a) if a.is_link_local() => Scope::Link,
+            IpAddr::V6(a) if (a.segments()[0] & 0xffc0) == 0xfe80 => Scope::Link,
+            _ => Scope::Global,
+        }
+    }
+}
+
+impl InterfaceAddress {
+    /// Whether the address can be published: global, not unique local, and usable
+    fn is_usable(&self) -> bool {
+        let unique_local = match self.address {
+            IpAddr::V6(a) => (a.segments()[0] & 0xfe00) == 0xfc00,
+            IpAddr::V4(_) => false,
+        };
+
+        self.scope == Scope::Global && !unique_local && !self.deprecated && !self.tentative && !self.address.is_unspecified()
+    }
+}
+
+
+/// Kinds of IPv6 addresses taken when none are configured, most preferred first
+pub(crate) fn default_preference() -> Vec<AddressKind> {
+    vec![AddressKind::StablePrivacy, AddressKind::Other, AddressKind::Eui64]
+}
+
+/// The address of the interface to publish: IPv4 unless the family is given, and IPv6 addresses
+/// of the first kind in `prefer` the interface has (kinds left out are never taken)
+pub(crate) fn address(name: &str, family: Option<Family>, prefer: &[AddressKind]) -> Result<IpAddr> {
+    let addresses = addresses(name)?;
+    log::trace!("Addresses of {}: {:?}", name, &addresses);
+
+    pick(&addresses, family, prefer).ok_or_else(|| ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+        .msg(format!("Unable to detect the public address, the interface {} has no usable address", name)))
+}
+
+fn pick(addresses: &[InterfaceAddress], family: Option<Family>, prefer: &[AddressKind]) -> Option<IpAddr> {
+    let rank = |a: &InterfaceAddress| match a.address {
+        IpAddr::V4(_) => Some(0),
+        IpAddr::V6(_) => prefer.iter().position(|k| *k == AddressKind::of(a)).map(|p| p + 1),
+    };
+
+    addresses
+        .iter()
+        .filter(|a| family.is_none_or(|f| Family::of(&a.address) == f))
+        .filter(|a| a.is_usable())
+        .filter_map(|a| rank(a).map(|r| (r, a.address)))
+        .min_by_key(|(r, _)| *r)
+        .map(|(_, a)| a)
+}
+
+/// Whether the interface identifier was made from a MAC address, with `ff:fe` in the middle
+fn is_eui64(address: &Ipv6Addr) -> bool {
+    let octets = address.octets();
+    octets[11] == 0xff && octets[12] == 0xfe
+}
+
+#[cfg(target_os = "linux")]
+fn addresses(name: &str) -> Result<Vec<InterfaceAddress>> {
+    Ok(super::netlink::addresses(name)?)
+}
+
+/// All the addresses of the interface, as told by `getifaddrs`
+#[cfg(all(unix, not(target_os = "linux")))]
+fn addresses(name: &str) -> Result<Vec<InterfaceAddress>> {
+    use std::ffi::CStr;
+    use std::io;
+    use std::net::Ipv4Addr;
+
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut list) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let mut addresses = Vec::new();
+    let mut current = list;
+    while !current.is_null() {
+        // SAFETY: the entries and their addresses stay valid until the list is freed below
+        let entry = unsafe { &*current };
+        current = entry.ifa_next;
+
+        if entry.ifa_addr.is_null() || unsafe { CStr::from_ptr(entry.ifa_name) }.to_bytes() != name.as_bytes() {
+            continue;
+        }
+
+        let address = match i32::from(unsafe { (*entry.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let address = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)))
+            },
+            libc::AF_INET6 => {
+                let address = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::V6(Ipv6Addr::from(address.sin6_addr.s6_addr))
+            },
+            _ => continue,
+        };
+
+        addresses.push(InterfaceAddress {
+            address,
+            scope: Scope::of(&address),
+            temporary: false,
+            stable_privacy: false,
+            deprecated: false,
+            tentative: false,
+        });
+    }
+
+    unsafe { libc::freeifaddrs(list) };
+
+    Ok(addresses)
+}
+
+#[cfg(not(unix))]
+fn addresses(_name: &str) -> Result<Vec<InterfaceAddress>> {
+    Err(ProviderError::new(ProviderErrorKind::Unsupported)
+        .msg(String::from("Interface addresses can only be detected on Unix systems!")))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STABLE: &str = "2001:db8::5e3a:8f2b:11d4:9c07";
+    const EUI64: &str = "2001:db8::211:22ff:fe33:4455";
+    const TEMPORARY: &str = "2001:db8::8d1c:4b2e:7f90:a3b6";
+    const OTHER: &str = "2001:db8::10";
+
+    fn global(address: &str) -> InterfaceAddress {
+        let address: IpAddr = address.parse().unwrap();
+        InterfaceAddress {
+            address,
+            scope: Scope::of(&address),
+            temporary: false,
+            stable_privacy: false,
+            deprecated: false,
+            tentative: false,
+        }
+    }
+
+    fn stable(address: &str) -> InterfaceAddress {
+        InterfaceAddress { stable_privacy: true, ..global(address) }
+    }
+
+    fn temporary(address: &str) -> InterfaceAddress {
+        InterfaceAddress { temporary: true, ..global(address) }
+    }
+
+    fn pick_v6(addresses: &[InterfaceAddress], prefer: &[AddressKind]) -> Option<String> {
+        pick(addresses, Some(Family::Ipv6), prefer).map(|a| a.to_string())
+    }
+
+    #[test]
+    fn takes_global_addresses_only() {
+        let site = InterfaceAddress { scope: Scope::Site, ..global("2001:db8::1") };
+        let link = InterfaceAddress { scope: Scope::Link, ..global("2001:db8::2") };
+        let host = InterfaceAddress { scope: Scope::Host, ..global("2001:db8::3") };
+        let all = [site, link, host, global(OTHER)];
+
+        assert_eq!(pick_v6(&all, &default_preference()).as_deref(), Some(OTHER));
+        assert_eq!(pick_v6(&all[..3], &default_preference()), None);
+    }
+
+    #[test]
+    fn leaves_out_unique_local_and_link_local_addresses() {
+        let addresses = [global("fd12:3456:789a::1"), global("fc00::1"), global("fe80::1"), global("169.254.1.1"), global("127.0.0.1")];
+
+        assert_eq!(pick(&addresses, None, &default_preference()), None);
+        assert_eq!(Scope::of(&"fe80::1".parse().unwrap()), Scope::Link);
+        assert_eq!(Scope::of(&"169.254.1.1".parse().unwrap()), Scope::Link);
+        assert_eq!(Scope::of(&"::1".parse().unwrap()), Scope::Host);
+    }
+
+    #[test]
+    fn leaves_out_deprecated_and_tentative_addresses() {
+        let deprecated = InterfaceAddress { deprecated: true, ..stable(STABLE) };
+        let tentative = InterfaceAddress { tentative: true, ..stable("2001:db8::6") };
+
+        assert_eq!(pick_v6(&[deprecated.clone(), tentative.clone()], &default_preference()), None);
+        assert_eq!(pick_v6(&[deprecated, tentative, global(EUI64)], &default_preference()).as_deref(), Some(EUI64));
+    }
+
+    #[test]
+    fn prefers_stable_privacy_addresses() {
+        let addresses = [global(EUI64), global(OTHER), stable(STABLE), temporary(TEMPORARY)];
+
+        assert_eq!(pick_v6(&addresses, &default_preference()).as_deref(), Some(STABLE));
+        assert_eq!(pick_v6(&addresses[..2], &default_preference()).as_deref(), Some(OTHER));
+        assert_eq!(pick_v6(&addresses[..1], &default_preference()).as_deref(), Some(EUI64));
+    }
+
+    #[test]
+    fn takes_temporary_addresses_only_when_asked_to() {
+        let addresses = [temporary(TEMPORARY)];
+
+        assert_eq!(pick_v6(&addresses, &default_preference()), None);
+        assert_eq!(pick_v6(&addresses, &[AddressKind::Temporary]).as_deref(), Some(TEMPORARY));
+        // The flags tell the kind, whatever the interface identifier looks like
+        let flagged = [InterfaceAddress { temporary: true, ..global(EUI64) }, global(OTHER)];
+        assert_eq!(pick_v6(&flagged, &[AddressKind::Temporary, AddressKind::Other]).as_deref(), Some(EUI64));
+    }
+
+    #[test]
+    fn follows_the_order_of_preference() {
+        let addresses = [stable(STABLE), global(EUI64)];
+
+        assert_eq!(pick_v6(&addresses, &[AddressKind::Eui64, AddressKind::StablePrivacy]).as_deref(), Some(EUI64));
+        assert_eq!(pick_v6(&addresses, &[AddressKind::Other]), None);
+    }
+
+    #[test]
+    fn prefers_ipv4_unless_the_family_is_given() {
+        let addresses = [stable(STABLE), global("192.0.2.1")];
+
+        assert_eq!(pick(&addresses, None, &default_preference()), Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(pick(&addresses[..1], None, &default_preference()), Some(STABLE.parse().unwrap()));
+        assert_eq!(pick(&addresses, Some(Family::Ipv6), &default_preference()), Some(STABLE.parse().unwrap()));
+        assert_eq!(pick(&addresses[..1], Some(Family::Ipv4), &default_preference()), None);
+    }
+
+    #[test]
+    fn tells_eui64_interface_identifiers() {
+        assert!(is_eui64(&EUI64.parse().unwrap()));
+        assert!(!is_eui64(&STABLE.parse().unwrap()));
+        assert_eq!(AddressKind::of(&global(EUI64)), AddressKind::Eui64);
+        assert_eq!(AddressKind::of(&global(OTHER)), AddressKind::Other);
+    }
+}
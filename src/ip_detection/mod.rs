@@ -0,0 +1,261 @@
+//! Detecting the public address of the client, for keeping hosts pointed at it (DDNS).
+//!
+//! By default the address is asked to an HTTP service which answers with the address the request
+//! came from as plain text, e.g. `192.0.2.1`. Credentials can set another strategy for their hosts
+//! with `ip_detection` in the config file: another HTTP service, an address of a local network
+//! interface, a DNS query answered with the address of the client, or a static address.
+mod interface;
+#[cfg(target_os = "linux")]
+mod netlink;
+
+pub use interface::AddressKind;
+#[cfg(target_os = "linux")]
+pub use netlink::Changes as NetworkChanges;
+
+use crate::config::{Credential, Credentials};
+use crate::providers::{self, http, rfc2136, Record, ProviderError, ProviderErrorKind, Result};
+use crate::providers::retry::{Idempotency, SendRetry};
+
+use std::net::IpAddr;
+use serde::{Serialize, Deserialize};
+
+/// Service answering with the public IPv4 address of the client, unless configured otherwise
+pub const DEFAULT_URL: &str = "https://api.ipify.org";
+
+/// Name answered with the address the query came from by the OpenDNS resolvers
+pub const DEFAULT_DNS_NAME: &str = "myip.opendns.com";
+
+/// Resolver answering `DEFAULT_DNS_NAME`
+pub const DEFAULT_DNS_SERVER: &str = "resolver1.opendns.com";
+
+/// How the address of a host is detected, e.g. in the config file:
+///
+/// ```yaml
+/// ip_detection: {method: http, url: "https://api6.ipify.org"}
+/// ip_detection: {method: interface, name: eth0, family: ipv6, prefer: [eui64, stable-privacy]}
+/// ip_detection: {method: dns, name: o-o.myaddr.l.google.com, server: ns1.google.com, type: TXT}
+/// ip_detection: {method: static, address: 192.0.2.1}
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "method", rename_all = "lowercase")]
+pub enum Strategy {
+    /// Ask an HTTP service answering with the address the request came from
+    Http {
+        #[serde(default = "default_url")]
+        url: String,
+    },
+    /// Take a global address of a local network interface
+    Interface {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        family: Option<Family>,
+        /// Kinds of IPv6 addresses which can be taken, most preferred first
+        #[serde(default = "interface::default_preference")]
+        prefer: Vec<AddressKind>,
+    },
+    /// Look up a name with a DNS server answering with the address the query came from, in an
+    /// A/AAAA record or as the text of a TXT record
+    Dns {
+        #[serde(default = "default_dns_name")]
+        name: String,
+        #[serde(default = "default_dns_server")]
+        server: String,
+        #[serde(default = "default_dns_type")]
+        r#type: String,
+    },
+    /// Always the same address
+    Static {
+        address: IpAddr,
+    },
+}
+
+/// Version of the IP protocol of an address
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Family {
+    Ipv4,
+    Ipv6,
+}
+
+
+impl Default for Strategy {
+    fn default() -> Self {
+        Strategy::Http { url: default_url() }
+    }
+}
+
+impl Family {
+    pub fn of(address: &IpAddr) -> Self {
+        if address.is_ipv4() { Family::Ipv4 } else { Family::Ipv6 }
+    }
+}
+
+
+/// Detect the address of the client with the strategy
+pub fn detect(strategy: &Strategy) -> Result<IpAddr> {
+    let address = match strategy {
+        Strategy::Http { url } => public_address(url)?,
+        Strategy::Interface { name, family, prefer } => interface::address(name, *family, prefer)?,
+        Strategy::Dns { name, server, r#type } => dns_address(name, server, r#type)?,
+        Strategy::Static { address } => *address,
+    };
+    log::debug!("Detected the address {} with {:?}", address, strategy);
+
+    Ok(address)
+}
+
+/// The strategy set on the credential of the host, if any
+pub fn configured(credentials: &[Credential], zone: &str, host: Option<&str>) -> Option<Strategy> {
+    let credentials: Option<Credentials> = Some(credentials.to_vec());
+
+    [Some("A"), Some("AAAA")]
+        .iter()
+        .filter_map(|rtype| providers::get_credential(&credentials, zone, host, *rtype).ok())
+        .find_map(|c| c.ip_detection)
+}
+
+/// The public address of the client, as seen by the service at the URL
+pub fn public_address(url: &str) -> Result<IpAddr> {
+    let response = http::client()
+        .get(url)
+        .send_retrying(Idempotency::Idempotent)?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+            .msg(format!("Unable to detect the public address with {}. Received status {}", url, status)));
+    }
+
+    let text = response.text()?;
+    log::trace!("Received response: {}", &text);
+
+    text.trim().parse().map_err(|_| ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+        .msg(format!("Unable to detect the public address, {} answered with something else than an address: {}", url, text.trim())))
+}
+
+/// Type of the records holding the address
+pub fn record_type(address: &IpAddr) -> &'static str {
+    if address.is_ipv4() { "A" } else { "AAAA" }
+}
+
+/// The address the DNS server answers the name with, from the first A/AAAA record or the first
+/// TXT record holding an address
+fn dns_address(name: &str, server: &str, rtype: &str) -> Result<IpAddr> {
+    let records = rfc2136::query(server, name, rtype)?;
+    log::trace!("Received records: {:?}", &records);
+
+    answered_address(&records).ok_or_else(|| ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+        .msg(format!("Unable to detect the public address, {} did not answer {} {} with an address", server, name, rtype)))
+}
+
+/// The address of the first record holding one, as the data of an A/AAAA record or the (quoted)
+/// text of a TXT record
+fn answered_address(records: &[Record]) -> Option<IpAddr> {
+    records.iter().find_map(|r| r.data.trim().trim_matches('"').parse().ok())
+}
+
+fn default_url() -> String {
+    String::from(DEFAULT_URL)
+}
+
+fn default_dns_name() -> String {
+    String::from(DEFAULT_DNS_NAME)
+}
+
+fn default_dns_server() -> String {
+    String::from(DEFAULT_DNS_SERVER)
+}
+
+fn default_dns_type() -> String {
+    String::from("A")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credential(zone: &str, host: Option<&str>, rtype: Option<&str>, strategy: Option<Strategy>) -> Credential {
+        Credential {
+            provider: String::from("cloudflare"),
+            zone: Some(zone.to_string()),
+            host: host.map(str::to_string),
+            r#type: rtype.map(str::to_string),
+            ip_detection: strategy,
+            ..Default::default()
+        }
+    }
+
+    fn fixed(address: &str) -> Strategy {
+        Strategy::Static { address: address.parse().unwrap() }
+    }
+
+    fn record(rtype: &str, data: &str) -> Record {
+        Record {
+            host: String::from("myip"),
+            r#type: rtype.to_string(),
+            data: data.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn takes_the_strategy_of_the_host() {
+        let credentials = vec![
+            credential("example.com", Some("home"), None, Some(fixed("192.0.2.1"))),
+            credential("example.com", Some("office"), None, Some(Strategy::default())),
+            credential("example.com", Some("lab"), None, None),
+        ];
+
+        assert_eq!(configured(&credentials, "example.com", Some("home")), Some(fixed("192.0.2.1")));
+        assert_eq!(configured(&credentials, "example.com", Some("office")), Some(Strategy::Http { url: String::from(DEFAULT_URL) }));
+        // Without a strategy of their own, hosts are left to the default detection of the caller
+        assert_eq!(configured(&credentials, "example.com", Some("lab")), None);
+        assert_eq!(configured(&credentials, "example.com", Some("nas")), None);
+    }
+
+    #[test]
+    fn falls_back_to_the_strategy_of_the_aaaa_record() {
+        let credentials = vec![
+            credential("example.com", Some("home"), None, None),
+            credential("example.com", Some("home"), Some("AAAA"), Some(fixed("2001:db8::1"))),
+        ];
+        assert_eq!(configured(&credentials, "example.com", Some("home")), Some(fixed("2001:db8::1")));
+
+        let credentials = vec![
+            credential("example.com", Some("home"), Some("A"), Some(fixed("192.0.2.1"))),
+            credential("example.com", Some("home"), Some("AAAA"), Some(fixed("2001:db8::1"))),
+        ];
+        assert_eq!(configured(&credentials, "example.com", Some("home")), Some(fixed("192.0.2.1")));
+    }
+
+    #[test]
+    fn reads_the_strategies_of_the_config_file() {
+        let strategy: Strategy = serde_yaml::from_str("{method: interface, name: eth0}").unwrap();
+        assert_eq!(strategy, Strategy::Interface { name: String::from("eth0"), family: None, prefer: interface::default_preference() });
+
+        let strategy: Strategy = serde_yaml::from_str("{method: dns}").unwrap();
+        assert_eq!(strategy, Strategy::Dns {
+            name: String::from(DEFAULT_DNS_NAME),
+            server: String::from(DEFAULT_DNS_SERVER),
+            r#type: String::from("A"),
+        });
+
+        assert!(serde_yaml::from_str::<Strategy>("{method: interface}").is_err());
+    }
+
+    #[test]
+    fn takes_the_address_of_the_first_record_holding_one() {
+        assert_eq!(answered_address(&[record("A", "192.0.2.1")]), Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(answered_address(&[record("TXT", "\"not an address\""), record("TXT", "\"2001:db8::1\"")]), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(answered_address(&[record("TXT", "\"edns0-client-subnet 192.0.2.0/24\"")]), None);
+        assert_eq!(answered_address(&[]), None);
+    }
+
+    #[test]
+    fn publishes_the_address_in_the_record_of_its_family() {
+        assert_eq!(record_type(&"192.0.2.1".parse().unwrap()), "A");
+        assert_eq!(record_type(&"2001:db8::1".parse().unwrap()), "AAAA");
+        assert_eq!(Family::of(&"2001:db8::1".parse().unwrap()), Family::Ipv6);
+    }
+}
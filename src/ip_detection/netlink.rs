@@ -0,0 +1,544 @@
+//! Asking the Linux kernel for the addresses of the network interfaces over rtnetlink.
+//!
+//! The addresses are dumped with an `RTM_GETADDR` request. Every `RTM_NEWADDR` answer starts with
+//! an `ifaddrmsg` (family, prefix length, flags, scope and index of the interface) followed by
+//! attributes, of which the address (`IFA_LOCAL`, or `IFA_ADDRESS` for IPv6) and the full flags
+//! (`IFA_FLAGS`, as `ifaddrmsg` only has room for the first 8) are read.
+//!
+//! Changes are followed by joining the multicast groups of the address and route events, which
+//! the kernel sends as soon as e.g. a PPPoE link comes back with a new address.
+use super::interface::{InterfaceAddress, Scope};
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::RawFd;
+
+/// Length of the `nlmsghdr` header
+const HEADER_LEN: usize = 16;
+
+/// Length of the `ifaddrmsg` header
+const IFADDRMSG_LEN: usize = 8;
+
+/// Large enough for the messages the kernel sends at once
+const BUFFER_LEN: usize = 32 * 1024;
+
+/// Sequence number of the dump request, which its replies carry
+const DUMP_SEQUENCE: u32 = 1;
+
+/// Multicast groups of the address and route events of both families
+const CHANGE_GROUPS: u32 = (libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR | libc::RTMGRP_IPV4_ROUTE | libc::RTMGRP_IPV6_ROUTE) as u32;
+
+/// A netlink socket of the routing family, closed when dropped
+struct Socket {
+    fd: RawFd,
+}
+
+/// A message received from the kernel
+struct Message<'a> {
+    kind: u16,
+    /// Sequence number of the request the message answers, 0 for events
+    sequence: u32,
+    payload: &'a [u8],
+}
+
+/// Changes of the addresses of the network interfaces and of the default routes, as the kernel
+/// tells them
+pub struct Changes {
+    socket: Socket,
+    buffer: Vec<u8>,
+}
+
+
+impl Socket {
+    /// Open a socket, listening to the multicast groups given (`RTMGRP_*`) on top of answers
+    fn open(groups: u32) -> io::Result<Self> {
+        // SAFETY: plain system call with constant arguments, the descriptor is checked below
+        let fd = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = Socket { fd };
+
+        // SAFETY: sockaddr_nl is a C struct of integers, for which all zeroes is a valid value
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = groups;
+
+        // SAFETY: the address points at a sockaddr_nl of the size given, alive for the whole call
+        let bound = unsafe {
+            libc::bind(fd, &address as *const libc::sockaddr_nl as *const libc::sockaddr, mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t)
+        };
+        if bound < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(socket)
+    }
+
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        // SAFETY: the pointer and length come from a slice which outlives the call
+        let sent = unsafe { libc::send(self.fd, message.as_ptr() as *const libc::c_void, message.len(), 0) };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Receive the next datagram from the kernel, which may hold several messages. Datagrams
+    /// other processes send to the socket are left out.
+    fn receive(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // SAFETY: as for the address in `open`
+            let mut sender: libc::sockaddr_nl = unsafe { mem::zeroed() };
+            let mut sender_len = mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t;
+
+            // SAFETY: the kernel writes at most `buffer.len()` bytes to the buffer, and at most
+            // `sender_len` bytes to the sender, both of which outlive the call
+            let received = unsafe {
+                libc::recvfrom(
+                    self.fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                    &mut sender as *mut libc::sockaddr_nl as *mut libc::sockaddr,
+                    &mut sender_len,
+                )
+            };
+            if received >= 0 {
+                // The kernel sends from port 0
+                if sender.nl_pid != 0 {
+                    log::debug!("Ignoring a netlink message from port {}", sender.nl_pid);
+                    continue;
+                }
+                return Ok(received as usize);
+            }
+
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        // SAFETY: the descriptor was opened by `open` and is only closed here
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+impl Changes {
+    /// Start listening to the changes, which are only told from then on
+    pub fn subscribe() -> io::Result<Self> {
+        Ok(Changes {
+            socket: Socket::open(CHANGE_GROUPS)?,
+            buffer: vec![0u8; BUFFER_LEN],
+        })
+    }
+
+    /// Wait for the next change
+    pub fn wait(&mut self) -> io::Result<()> {
+        let change = self.next()?;
+        log::debug!("Network change: {}", change);
+
+        Ok(())
+    }
+
+    /// Wait for the next change, and tell what it is
+    fn next(&mut self) -> io::Result<String> {
+        loop {
+            let received = match self.socket.receive(&mut self.buffer) {
+                Ok(r) => r,
+                // The kernel dropped events as they came faster than they were read
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => return Ok(String::from("missed events")),
+                Err(e) => return Err(e),
+            };
+
+            if let Some(change) = messages(&self.buffer[..received]).iter().find_map(describe) {
+                return Ok(change);
+            }
+        }
+    }
+}
+
+
+/// All the addresses of the interface
+pub(crate) fn addresses(name: &str) -> io::Result<Vec<InterfaceAddress>> {
+    let index = interface_index(name)?;
+    let socket = Socket::open(0)?;
+    socket.send(&dump_request(DUMP_SEQUENCE))?;
+
+    let mut buffer = vec![0u8; BUFFER_LEN];
+    let mut addresses = Vec::new();
+
+    loop {
+        let received = socket.receive(&mut buffer)?;
+
+        // Only the replies to the request, not e.g. events
+        for message in messages(&buffer[..received]).into_iter().filter(|m| m.sequence == DUMP_SEQUENCE) {
+            match message.kind {
+                kind if kind == libc::NLMSG_DONE as u16 => return Ok(addresses),
+                kind if kind == libc::NLMSG_ERROR as u16 => {
+                    let code = message.payload.get(..4).map_or(0, |b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]));
+                    if code != 0 {
+                        return Err(io::Error::from_raw_os_error(-code));
+                    }
+                },
+                libc::RTM_NEWADDR => {
+                    if let Some((i, address)) = parse_address(message.payload) {
+                        if i == index {
+                            addresses.push(address);
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Index of the interface with the name
+fn interface_index(name: &str) -> io::Result<u32> {
+    let c_name = CString::new(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    // SAFETY: the name is a NUL-terminated string which outlives the call
+    match unsafe { libc::if_nametoindex(c_name.as_ptr()) } {
+        0 => Err(io::Error::new(io::ErrorKind::NotFound, format!("There is no network interface named {}", name))),
+        index => Ok(index),
+    }
+}
+
+/// Request for the addresses of all interfaces, of both families
+fn dump_request(sequence: u32) -> Vec<u8> {
+    let len = HEADER_LEN + IFADDRMSG_LEN;
+    let mut message = Vec::with_capacity(len);
+
+    message.extend_from_slice(&(len as u32).to_ne_bytes());
+    message.extend_from_slice(&libc::RTM_GETADDR.to_ne_bytes());
+    message.extend_from_slice(&((libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16).to_ne_bytes());
+    message.extend_from_slice(&sequence.to_ne_bytes());
+    message.extend_from_slice(&0u32.to_ne_bytes());
+    // ifaddrmsg with AF_UNSPEC and everything else left out
+    message.extend_from_slice(&[0u8; IFADDRMSG_LEN]);
+
+    message
+}
+
+/// The messages in a datagram
+fn messages(mut data: &[u8]) -> Vec<Message<'_>> {
+    let mut messages = Vec::new();
+
+    while data.len() >= HEADER_LEN {
+        let len = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        if len < HEADER_LEN || len > data.len() {
+            break;
+        }
+
+        messages.push(Message {
+            kind: u16::from_ne_bytes([data[4], data[5]]),
+            sequence: u32::from_ne_bytes([data[8], data[9], data[10], data[11]]),
+            payload: &data[HEADER_LEN..len],
+        });
+
+        data = &data[align(len).min(data.len())..];
+    }
+
+    messages
+}
+
+/// What the event changed, unless it is of no interest: routes other than the default ones are
+/// left out as they change along with addresses or have nothing to do with the public address
+fn describe(message: &Message) -> Option<String> {
+    match message.kind {
+        libc::RTM_NEWADDR | libc::RTM_DELADDR => {
+            let (index, address) = parse_address(message.payload)?;
+            let change = if message.kind == libc::RTM_NEWADDR { "added" } else { "removed" };
+            Some(format!("address {} {} on interface {}", address.address, change, index))
+        },
+        libc::RTM_NEWROUTE | libc::RTM_DELROUTE => {
+            // rtmsg starts with the family and the prefix length of the destination
+            let family = if *message.payload.first()? == libc::AF_INET6 as u8 { "IPv6" } else { "IPv4" };
+            let default = *message.payload.get(1)? == 0;
+            let change = if message.kind == libc::RTM_NEWROUTE { "added" } else { "removed" };
+            default.then(|| format!("{} default route {}", family, change))
+        },
+        _ => None,
+    }
+}
+
+/// The index of the interface and the address in the payload of `RTM_NEWADDR` (or `RTM_DELADDR`)
+fn parse_address(payload: &[u8]) -> Option<(u32, InterfaceAddress)> {
+    if payload.len() < IFADDRMSG_LEN {
+        return None;
+    }
+
+    let family = i32::from(payload[0]);
+    let mut flags = u32::from(payload[2]);
+    let scope = payload[3];
+    let index = u32::from_ne_bytes([payload[4], payload[5], payload[6], payload[7]]);
+
+    let mut local = None;
+    let mut address = None;
+    let mut attributes = &payload[IFADDRMSG_LEN..];
+
+    while attributes.len() >= 4 {
+        let len = u16::from_ne_bytes([attributes[0], attributes[1]]) as usize;
+        let kind = u16::from_ne_bytes([attributes[2], attributes[3]]);
+        if len < 4 || len > attributes.len() {
+            break;
+        }
+        let value = &attributes[4..len];
+
+        match kind {
+            libc::IFA_LOCAL => local = ip_address(family, value),
+            libc::IFA_ADDRESS => address = ip_address(family, value),
+            libc::IFA_FLAGS if value.len() >= 4 => flags = u32::from_ne_bytes([value[0], value[1], value[2], value[3]]),
+            _ => (),
+        }
+
+        attributes = &attributes[align(len).min(attributes.len())..];
+    }
+
+    let scope = match scope {
+        libc::RT_SCOPE_UNIVERSE => Scope::Global,
+        libc::RT_SCOPE_SITE => Scope::Site,
+        libc::RT_SCOPE_LINK => Scope::Link,
+        _ => Scope::Host,
+    };
+    let flag = |f: u32| flags & f != 0;
+
+    Some((index, InterfaceAddress {
+        address: local.or(address)?,
+        scope,
+        temporary: flag(libc::IFA_F_TEMPORARY),
+        stable_privacy: flag(libc::IFA_F_STABLE_PRIVACY),
+        deprecated: flag(libc::IFA_F_DEPRECATED),
+        tentative: flag(libc::IFA_F_TENTATIVE) || flag(libc::IFA_F_DADFAILED),
+    }))
+}
+
+fn ip_address(family: i32, value: &[u8]) -> Option<IpAddr> {
+    match family {
+        libc::AF_INET if value.len() >= 4 => Some(IpAddr::V4(Ipv4Addr::new(value[0], value[1], value[2], value[3]))),
+        libc::AF_INET6 if value.len() >= 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&value[..16]);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        },
+        _ => None,
+    }
+}
+
+/// Messages and attributes are padded to 4 bytes
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process::Command;
+    use std::thread;
+
+    /// A message as the kernel lays it out, with the payload padded
+    fn message(kind: u16, sequence: u32, payload: &[u8]) -> Vec<u8> {
+        let mut message = Vec::new();
+        message.extend_from_slice(&((HEADER_LEN + payload.len()) as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(&0u16.to_ne_bytes());
+        message.extend_from_slice(&sequence.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(payload);
+        message.resize(align(message.len()), 0);
+        message
+    }
+
+    #[test]
+    fn splits_datagrams_into_messages() {
+        let mut datagram = message(libc::RTM_NEWADDR, 0, &[1, 2, 3]);
+        datagram.extend(message(libc::NLMSG_DONE as u16, DUMP_SEQUENCE, &[0; 4]));
+
+        let messages = messages(&datagram);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].kind, libc::RTM_NEWADDR);
+        assert_eq!(messages[0].sequence, 0);
+        assert_eq!(messages[0].payload, &[1, 2, 3]);
+        assert_eq!(messages[1].kind, libc::NLMSG_DONE as u16);
+        assert_eq!(messages[1].sequence, DUMP_SEQUENCE);
+    }
+
+    /// The payload of `RTM_NEWADDR` for the address, with the flags in `IFA_FLAGS`
+    fn address_payload(address: IpAddr, scope: u8, flags: u32) -> Vec<u8> {
+        let (family, octets) = match address {
+            IpAddr::V4(a) => (libc::AF_INET, a.octets().to_vec()),
+            IpAddr::V6(a) => (libc::AF_INET6, a.octets().to_vec()),
+        };
+        let mut payload = vec![family as u8, 64, flags as u8, scope];
+        payload.extend_from_slice(&7u32.to_ne_bytes());
+
+        let mut attribute = |kind: u16, value: &[u8]| {
+            payload.extend_from_slice(&((4 + value.len()) as u16).to_ne_bytes());
+            payload.extend_from_slice(&kind.to_ne_bytes());
+            payload.extend_from_slice(value);
+            payload.resize(align(payload.len()), 0);
+        };
+        attribute(libc::IFA_ADDRESS, &octets);
+        attribute(libc::IFA_FLAGS, &flags.to_ne_bytes());
+
+        payload
+    }
+
+    #[test]
+    fn reads_the_scope_and_flags_of_addresses() {
+        let address: IpAddr = "2001:db8::1".parse().unwrap();
+
+        let (index, parsed) = parse_address(&address_payload(address, libc::RT_SCOPE_UNIVERSE, 0)).unwrap();
+        assert_eq!(index, 7);
+        assert_eq!(parsed, InterfaceAddress {
+            address,
+            scope: Scope::Global,
+            temporary: false,
+            stable_privacy: false,
+            deprecated: false,
+            tentative: false,
+        });
+
+        // Flags past the first 8 bits only fit in IFA_FLAGS
+        let flags = libc::IFA_F_TEMPORARY | libc::IFA_F_DEPRECATED | libc::IFA_F_STABLE_PRIVACY;
+        let (_, parsed) = parse_address(&address_payload(address, libc::RT_SCOPE_UNIVERSE, flags)).unwrap();
+        assert!(parsed.temporary && parsed.deprecated && parsed.stable_privacy && !parsed.tentative);
+
+        let (_, parsed) = parse_address(&address_payload(address, libc::RT_SCOPE_UNIVERSE, libc::IFA_F_DADFAILED)).unwrap();
+        assert!(parsed.tentative);
+
+        let (_, parsed) = parse_address(&address_payload("fe80::1".parse().unwrap(), libc::RT_SCOPE_LINK, 0)).unwrap();
+        assert_eq!(parsed.scope, Scope::Link);
+        let (_, parsed) = parse_address(&address_payload("127.0.0.1".parse().unwrap(), libc::RT_SCOPE_HOST, 0)).unwrap();
+        assert_eq!((parsed.address, parsed.scope), (IpAddr::V4(Ipv4Addr::LOCALHOST), Scope::Host));
+
+        assert!(parse_address(&[0; 4]).is_none());
+    }
+
+    /// Run the test on a thread of its own in a network namespace of its own (namespaces are per
+    /// thread, and inherited by the programs it runs). Creating one takes `CAP_SYS_ADMIN` and the
+    /// tests need the `ip` command, so they only run when asked for with `cargo test -- --ignored`.
+    fn in_namespace<F: FnOnce() + Send + 'static>(test: F) {
+        let outcome = thread::spawn(move || {
+            // SAFETY: plain system call, which only changes the namespace of this thread
+            if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+                panic!("Unable to create a network namespace: {}", io::Error::last_os_error());
+            }
+            if let Err(e) = Command::new("ip").arg("-V").output() {
+                panic!("Unable to run the ip command: {}", e);
+            }
+
+            test();
+        }).join();
+
+        if let Err(panic) = outcome {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    fn ip(args: &str) {
+        let output = Command::new("ip").args(args.split_whitespace()).output().unwrap();
+        assert!(output.status.success(), "ip {}: {}", args, String::from_utf8_lossy(&output.stderr));
+    }
+
+    /// Changes which fail instead of blocking forever when the kernel tells nothing
+    fn subscribe() -> Changes {
+        let changes = Changes::subscribe().unwrap();
+        let timeout = libc::timeval { tv_sec: 5, tv_usec: 0 };
+        // SAFETY: the option points at a timeval of the size given, alive for the whole call
+        let set = unsafe {
+            libc::setsockopt(
+                changes.socket.fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const libc::timeval as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(set, 0);
+
+        changes
+    }
+
+    /// A veth pair (as there may be no dummy interfaces), with the peer left down
+    fn interface(name: &str) {
+        ip(&format!("link add {} type veth peer name {}-peer", name, name));
+        ip(&format!("link set {} up", name));
+    }
+
+    #[test]
+    #[ignore = "needs CAP_SYS_ADMIN and the ip command"]
+    fn tells_address_changes() {
+        in_namespace(|| {
+            interface("ddns0");
+            let mut changes = subscribe();
+
+            ip("addr add 192.0.2.1/24 dev ddns0");
+            assert_eq!(changes.next().unwrap(), format!("address 192.0.2.1 added on interface {}", interface_index("ddns0").unwrap()));
+
+            ip("addr del 192.0.2.1/24 dev ddns0");
+            assert!(changes.next().unwrap().starts_with("address 192.0.2.1 removed"));
+        });
+    }
+
+    #[test]
+    #[ignore = "needs CAP_SYS_ADMIN and the ip command"]
+    fn tells_default_route_changes_only() {
+        in_namespace(|| {
+            interface("ddns0");
+            ip("addr add 192.0.2.1/24 dev ddns0");
+            let mut changes = subscribe();
+
+            // Left out, so the default route is the first change told
+            ip("route add 198.51.100.0/24 dev ddns0");
+            ip("route add default via 192.0.2.254 dev ddns0");
+            assert_eq!(changes.next().unwrap(), "IPv4 default route added");
+
+            ip("route del default");
+            assert_eq!(changes.next().unwrap(), "IPv4 default route removed");
+        });
+    }
+
+    #[test]
+    #[ignore = "needs CAP_SYS_ADMIN and the ip command"]
+    fn lists_the_addresses_of_an_interface_with_their_flags() {
+        in_namespace(|| {
+            interface("ddns0");
+            interface("ddns1");
+            ip("addr add 192.0.2.1/24 dev ddns0");
+            ip("addr add 2001:db8::1/64 dev ddns0 nodad");
+            ip("addr add 2001:db8::2/64 dev ddns0 nodad preferred_lft 0");
+            ip("addr add fe80::1/64 dev ddns0 nodad");
+            ip("addr add 198.51.100.1/24 dev ddns1");
+
+            let listed = addresses("ddns0").unwrap();
+            let find = |a: &str| listed.iter().find(|l| l.address == a.parse::<IpAddr>().unwrap());
+
+            assert!(find("198.51.100.1").is_none());
+            assert_eq!(find("192.0.2.1").unwrap().scope, Scope::Global);
+            let global = find("2001:db8::1").unwrap();
+            assert_eq!(global.scope, Scope::Global);
+            assert!(!global.deprecated && !global.tentative && !global.temporary);
+            assert!(find("2001:db8::2").unwrap().deprecated);
+            assert_eq!(find("fe80::1").unwrap().scope, Scope::Link);
+        });
+    }
+
+    #[test]
+    fn lists_the_addresses_of_the_loopback_interface() {
+        let loopback = addresses("lo").unwrap();
+
+        assert!(loopback.iter().any(|a| a.address == IpAddr::V4(Ipv4Addr::LOCALHOST) && a.scope == Scope::Host));
+        assert!(addresses("ddnsclient-missing").is_err());
+    }
+}
@@ -0,0 +1,34 @@
+//! Client for the APIs of DNS providers: search, update and delete DNS records, or point a host
+//! at the address of the client (DDNS).
+//!
+//! Providers are created by name with `init_provider` (see `PROVIDERS`), given their credentials
+//! and then driven through the `Provider` trait:
+//!
+//! ```no_run
+//! use ddnsclient::{Credential, RecordSelector, SearchRequest};
+//!
+//! let mut provider = ddnsclient::init_provider("mythic-beasts")?;
+//! provider.set_credentials(vec![Credential {
+//!     provider: String::from("mythic-beasts"),
+//!     user: String::from("key"),
+//!     pass: String::from("secret"),
+//!     ..Default::default()
+//! }]);
+//!
+//! let selector = RecordSelector::new("example.com", Some("www"), None);
+//! let records = provider.search(&SearchRequest { selector })?;
+//! # Ok::<(), ddnsclient::ProviderError>(())
+//! ```
+pub mod config;
+pub mod ip_detection;
+pub mod providers;
+pub mod state;
+
+pub use config::{Configuration, ConfigError, ConfigErrorKind, Credential, Credentials, HttpSettings};
+pub use providers::http;
+pub use providers::{
+    Provider, Record, RecordSelector, SearchRequest, UpdateRequest, AddRequest, DeleteRequest, DynamicDnsRequest, DynamicDnsResponse, Zone,
+    Template, TemplatesRequest, ApplyTemplateRequest,
+    ProviderError, ProviderErrorKind, Result, PROVIDERS, init_provider, get_provider_credentials,
+    AsyncProvider, BlockingProvider, for_each_zone,
+};
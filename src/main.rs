@@ -1,28 +1,31 @@
-mod config;
-mod providers;
-
 extern crate clap;
 extern crate log;
 extern crate simple_logger;
-extern crate reqwest;
-extern crate serde;
 extern crate serde_json;
 
-use config::{Configuration, Credential};
-use providers::*;
+mod daemon;
+
+use ddnsclient::{Configuration, ConfigError, Credential, Provider, Record, RecordSelector, Zone, SearchRequest, UpdateRequest, AddRequest, DeleteRequest, DynamicDnsRequest, Template, TemplatesRequest, ApplyTemplateRequest, PROVIDERS, init_provider, get_provider_credentials};
+use ddnsclient::{AsyncProvider, BlockingProvider, for_each_zone, http, ip_detection};
+use ddnsclient::state::{self, State, Entry as StateEntry};
 
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::process;
 use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 use std::io::prelude::*;
 
-use clap::{Arg, App, SubCommand};
+use clap::{Arg, App, ArgMatches, SubCommand};
+use serde::Serialize;
 
 
 fn main() {
     let app = App::new("DDNS Client")
         .version(clap::crate_version!())
         .author(clap::crate_authors!())
-        .after_help("SUPPORTED PROVIDERS:\n - Mythic Beasts [mythic-beasts] (https://www.mythic-beasts.com/support/api/dnsv2)")
+        .after_help("SUPPORTED PROVIDERS:\n - Mythic Beasts [mythic-beasts] (https://www.mythic-beasts.com/support/api/dnsv2)\n - No-IP [noip] (https://www.noip.com/integrate/request)\n - Any DynDNS2 compatible server [dyndns2] (requires a configuration file with the api_url of the server)\n - Cloudflare [cloudflare] (https://api.cloudflare.com)\n - RFC 2136 dynamic updates [rfc2136] (requires a configuration file with the address of the DNS server as api_url)\n - PowerDNS Authoritative [powerdns] (https://doc.powerdns.com/authoritative/http-api)\n - DigitalOcean [digitalocean] (https://docs.digitalocean.com/reference/api)\n - Hetzner DNS [hetzner] (https://dns.hetzner.com/api-docs)\n - Linode [linode] (https://www.linode.com/docs/api/domains)\n - AWS Route 53 [route53] (https://docs.aws.amazon.com/Route53/latest/APIReference)\n - deSEC [desec] (https://desec.readthedocs.io)\n - Gandi LiveDNS [gandi] (https://api.gandi.net/docs/livedns)\n - External program [exec] (requires a configuration file with the command of the program, see the README)\n - BIND zone file [zonefile] (requires a configuration file with the path of the zone file)")
         .arg(Arg::with_name("verbosity")
             .short("v")
             .global(true)
@@ -32,7 +35,7 @@ fn main() {
             .global(true)
             .takes_value(true)
             .number_of_values(1)
-            .help("The name of the zone")
+            .help("The name of the zone. Several zones can be given separated by commas (e.g. example.com,example.org), in which case the results of all zones are reported together.")
         )
         .arg(Arg::with_name("host")
             .global(true)
@@ -50,7 +53,7 @@ fn main() {
             .short("u")
             .long("username")
             .required(true)
-            .required_unless("config-path")
+            .required_unless_one(&["config-path", "token"])
             .env("DNSAPICLIENT_USER")
             .takes_value(true)
             .number_of_values(1)
@@ -60,18 +63,29 @@ fn main() {
             .short("p")
             .long("password")
             .required(true)
-            .required_unless("config-path")
+            .required_unless_one(&["config-path", "token"])
             .takes_value(true)
             .number_of_values(1)
             .env("DNSAPICLIENT_PASS")
             .help("Authentication password")
         )
+        .arg(Arg::with_name("token")
+            .short("t")
+            .long("token")
+            .required(true)
+            .required_unless_one(&["config-path", "username"])
+            .conflicts_with_all(&["username", "password"])
+            .takes_value(true)
+            .number_of_values(1)
+            .env("DNSAPICLIENT_TOKEN")
+            .help("Authentication token, for providers using API tokens instead of user-pass")
+        )
         .arg(Arg::with_name("config-path")
             .short("c")
             .long("config")
             .required(true)
-            .required_unless("username")
-            .conflicts_with_all(&["username", "password"])
+            .required_unless_one(&["username", "token"])
+            .conflicts_with_all(&["username", "password", "token"])
             .takes_value(true)
             .number_of_values(1)
             .help("Path to the YAML configuration file.")
@@ -80,18 +94,166 @@ fn main() {
             .long("provider")
             .takes_value(true)
             .number_of_values(1)
-            .possible_values(&["mythic-beasts", "noip"])
+            .possible_values(PROVIDERS)
             .default_value("mythic-beasts")
             .help("Specify DNS provider to use")
         )
+        .arg(Arg::with_name("api-url")
+            .long("api-url")
+            .value_name("URL")
+            .takes_value(true)
+            .number_of_values(1)
+            .env("DNSAPICLIENT_API_URL")
+            .help("Base URL of the provider API, replacing the default one and the api_url of the configuration file")
+        )
+        .arg(Arg::with_name("timeout")
+            .long("timeout")
+            .takes_value(true)
+            .number_of_values(1)
+            .value_name("SECONDS")
+            .validator(is_number)
+            .help("Give up on requests to the API which take longer than this [default: 30]")
+        )
+        .arg(Arg::with_name("connect-timeout")
+            .long("connect-timeout")
+            .takes_value(true)
+            .number_of_values(1)
+            .value_name("SECONDS")
+            .validator(is_number)
+            .help("Give up on connecting to the API after this long [default: 10]")
+        )
+        .arg(Arg::with_name("max-attempts")
+            .long("max-attempts")
+            .takes_value(true)
+            .number_of_values(1)
+            .value_name("N")
+            .validator(is_number)
+            .help("Send requests failing for a temporary reason (throttling, server errors, timeouts) up to N times [default: 4]")
+        )
+        .arg(Arg::with_name("proxy")
+            .long("proxy")
+            .takes_value(true)
+            .number_of_values(1)
+            .value_name("URL")
+            .help("Send all requests through this HTTP(S) or SOCKS5 proxy (e.g. http://proxy:3128, socks5h://proxy:1080)")
+        )
+        .arg(Arg::with_name("ca-cert")
+            .long("ca-cert")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .value_name("FILE")
+            .help("Also trust the certificate authorities of this PEM file. Can be repeated.")
+        )
+        .arg(Arg::with_name("client-cert")
+            .long("client-cert")
+            .takes_value(true)
+            .number_of_values(1)
+            .value_name("FILE")
+            .help("Authenticate with the certificate and key of this PKCS#12 file")
+        )
+        .arg(Arg::with_name("client-cert-password")
+            .long("client-cert-password")
+            .takes_value(true)
+            .number_of_values(1)
+            .env("DNSAPICLIENT_CLIENT_CERT_PASS")
+            .help("Password of the client certificate file")
+        )
+        .arg(Arg::with_name("user-agent")
+            .long("user-agent")
+            .takes_value(true)
+            .number_of_values(1)
+            .help("User agent to send to the API")
+        )
+        .arg(Arg::with_name("wait")
+            .long("wait")
+            .global(true)
+            .takes_value(false)
+            .help("Wait until the changes have propagated to all DNS servers of the provider (Route 53)")
+        )
+        .arg(Arg::with_name("parallel")
+            .long("parallel")
+            .global(true)
+            .takes_value(true)
+            .number_of_values(1)
+            .value_name("N")
+            .validator(is_number)
+            .help("Work on up to N zones at the same time and report the results of all zones in a single document")
+        )
+        .arg(Arg::with_name("exclude-generated")
+            .long("exclude-generated")
+            .global(true)
+            .takes_value(false)
+            .help("Leave out the records the provider generates itself (Mythic Beasts). Updates and deletes leave them out by default.")
+        )
+        .arg(Arg::with_name("include-generated")
+            .long("include-generated")
+            .global(true)
+            .takes_value(false)
+            .conflicts_with("exclude-generated")
+            .help("Also update and delete the records the provider generates itself (Mythic Beasts)")
+        )
+        .arg(Arg::with_name("exclude-template")
+            .long("exclude-template")
+            .global(true)
+            .takes_value(false)
+            .help("Leave out the records which come from DNS templates (Mythic Beasts). Updates and deletes leave them out by default.")
+        )
+        .arg(Arg::with_name("include-template")
+            .long("include-template")
+            .global(true)
+            .takes_value(false)
+            .conflicts_with("exclude-template")
+            .help("Also update and delete the records which come from DNS templates (Mythic Beasts)")
+        )
+        .arg(Arg::with_name("ip-url")
+            .long("ip-url")
+            .global(true)
+            .takes_value(true)
+            .number_of_values(1)
+            .value_name("URL")
+            .env("DNSAPICLIENT_IP_URL")
+            .help("Service answering with the public address of the client as plain text, for DDNS to tell whether the address changed [default: https://api.ipify.org]")
+        )
+        .arg(Arg::with_name("state")
+            .long("state")
+            .global(true)
+            .takes_value(true)
+            .number_of_values(1)
+            .value_name("FILE")
+            .env("DNSAPICLIENT_STATE")
+            .help("File keeping the addresses published last [default: $XDG_STATE_HOME/ddnsclient/state.json]")
+        )
         .arg(Arg::with_name("pretty")
             .long("pretty")
             .takes_value(false)
-            .help("Human readable output with pretty format")
+            .help("Human readable output with pretty format (same as --format pretty)")
+        )
+        .arg(Arg::with_name("format")
+            .long("format")
+            .global(true)
+            .takes_value(true)
+            .number_of_values(1)
+            .possible_values(&["json", "pretty", "table"])
+            .help("Output format: JSON, pretty printed JSON or a table with aligned columns [default: json]")
         )
 
         .subcommand(SubCommand::with_name("ddns")
-            .about("Create or update an A or AAAA record with the specified hostname, with the data set to the IP address of the client using the API.")
+            .about("Create or update an A or AAAA record with the specified hostname, with the data set to the IP address of the client using the API, or to the address detected as configured for the host. Nothing is sent when the address did not change since it was published last.")
+            .arg(Arg::with_name("force")
+                .long("force")
+                .takes_value(false)
+                .help("Publish the address even if the state file tells it is already published")
+            )
+            .arg(Arg::with_name("ttl")
+                .long("ttl")
+                .takes_value(true)
+                .number_of_values(1)
+                .value_name("SECONDS")
+                .validator(is_number)
+                .default_value("300")
+                .help("TTL of the record, for hosts with an address detection configured")
+            )
             // TODO: support this style as well - more user friendly
             // .arg(Arg::with_name("hostname")
                 // .required(true)
@@ -100,6 +262,73 @@ fn main() {
                 // .help("The fully-qualified host name")
             // )
         )
+        .subcommand(SubCommand::with_name("zones")
+            .about("List the zones the credentials can access, with the details the provider knows about them")
+        )
+        .subcommand(SubCommand::with_name("template")
+            .about("List the DNS templates which can be applied to the zone, or apply one")
+            .subcommand(SubCommand::with_name("list")
+                .about("List the DNS templates which can be applied to the zone")
+            )
+            .subcommand(SubCommand::with_name("apply")
+                .about("Apply a DNS template to the zone, adding its records")
+                .arg(Arg::with_name("name")
+                    .required(true)
+                    .takes_value(true)
+                    .number_of_values(1)
+                    .help("The name of the template")
+                )
+            )
+        )
+        .subcommand(SubCommand::with_name("daemon")
+            .about("Keep running and point the host at the public address of the client whenever it changes, with DDNS or else by updating its A/AAAA record")
+            .arg(Arg::with_name("interval")
+                .long("interval")
+                .takes_value(true)
+                .number_of_values(1)
+                .value_name("SECONDS")
+                .validator(is_number)
+                .default_value("300")
+                .help("Check the public address this often")
+            )
+            .arg(Arg::with_name("force")
+                .long("force")
+                .takes_value(false)
+                .help("Publish the address on the first check even if the state file tells it is already published")
+            )
+            .arg(Arg::with_name("ttl")
+                .long("ttl")
+                .takes_value(true)
+                .number_of_values(1)
+                .value_name("SECONDS")
+                .validator(is_number)
+                .default_value("300")
+                .help("TTL of the record, for hosts with an address detection configured and providers which do not support DDNS")
+            )
+            .arg(Arg::with_name("watch")
+                .long("watch")
+                .takes_value(false)
+                .help("Check the public address as soon as an address or a default route of the network interfaces changes as well (Linux only)")
+            )
+            .arg(Arg::with_name("debounce")
+                .long("debounce")
+                .takes_value(true)
+                .number_of_values(1)
+                .value_name("SECONDS")
+                .validator(is_number)
+                .default_value("5")
+                .help("With --watch, wait for the network to stay the same this long before checking the address")
+            )
+        )
+        .subcommand(SubCommand::with_name("state")
+            .about("Show the addresses published last for the zone|host|type (all of them when not given), or forget them")
+            .subcommand(SubCommand::with_name("list")
+                .about("Show the addresses published last")
+            )
+            .subcommand(SubCommand::with_name("clear")
+                .about("Forget the addresses published last, so the next DDNS run publishes the address again. Forgetting all of them also lifts the holds of the providers which asked not to be contacted again.")
+            )
+        )
         .subcommand(SubCommand::with_name("delete")
             .about("Deletes all records selected by the zone|host|type")
         )
@@ -111,6 +340,14 @@ fn main() {
                 .help("Records provided as JSON. If not provided, it will read from stdin")
             )
         )
+        .subcommand(SubCommand::with_name("add")
+            .about("Add records next to the ones selected by the zone|host|type, refusing records which already exist")
+            .arg(Arg::with_name("records")
+                .takes_value(true)
+                .number_of_values(1)
+                .help("Records provided as JSON. If not provided, it will read from stdin")
+            )
+        )
         .get_matches();
 
 
@@ -124,80 +361,609 @@ fn main() {
     };
     simple_logger::init_with_level(log_level).expect("Unable to initialise the logger!");
 
-    let provider = app.value_of("provider").expect("Unable to establish which provider to use");
+    let provider_name = app.value_of("provider").expect("Unable to establish which provider to use");
+
+    let config = match load_configuration(&app, provider_name) {
+        Ok(c) => c,
+        Err(e) => {
+            log::error!("{}", e);
+            process::exit(exitcode::CONFIG);
+        },
+    };
+
+    if let Err(e) = http::configure(&config.http) {
+        log::error!("{}", e);
+        process::exit(exitcode::CONFIG);
+    }
+
+    let mut provider = match init_provider(provider_name) {
+        Ok(p) => p,
+        Err(e) => {
+            log::error!("{}", e);
+            process::exit(exitcode::USAGE);
+        },
+    };
+    let credentials = get_provider_credentials(provider.as_ref(), config);
+    provider.set_credentials(credentials.clone());
+
+    let (operation, matches) = match app.subcommand() {
+        (name, Some(m)) => (name, m),
+        _ => ("search", &app),
+    };
+    let format = output_format(&app, matches);
+
+    let state = if operation == "ddns" || operation == "daemon" { state_file(matches) } else { None };
+    if let Some(state) = &state {
+        provider.set_state(state.clone());
+    }
+
+    if operation == "daemon" {
+        let reload = || {
+            let config = match load_configuration(&app, provider_name) {
+                Ok(c) => c,
+                Err(e) => {
+                    log::error!("Keeping the current configuration. {}", e);
+                    return None;
+                },
+            };
+
+            let mut provider = match init_provider(provider_name) {
+                Ok(p) => p,
+                Err(e) => {
+                    log::error!("Keeping the current configuration. Unable to set up the {} provider: {}", provider_name, e);
+                    return None;
+                },
+            };
+
+            if let Err(e) = http::configure(&config.http) {
+                log::error!("Keeping the current configuration. {}", e);
+                return None;
+            }
+
+            let credentials = get_provider_credentials(provider.as_ref(), config);
+            provider.set_credentials(credentials.clone());
+            if let Some(state) = &state {
+                provider.set_state(state.clone());
+            }
+            Some(daemon::Setup {
+                provider,
+                credentials,
+                selectors: daemon_selectors(matches),
+                settings: daemon_settings(matches),
+            })
+        };
+
+        let setup = daemon::Setup {
+            provider,
+            credentials,
+            selectors: daemon_selectors(matches),
+            settings: daemon_settings(matches),
+        };
+        process::exit(daemon::run(setup, state.clone(), reload));
+    }
+
+    if operation == "state" {
+        run_state(matches, format);
+        return;
+    }
+
+    if operation == "zones" {
+        match provider.list_zones() {
+            Ok(zones) => print_output(&zones, format),
+            Err(e) => {
+                log::error!("{}", e);
+                process::exit(exitcode::UNAVAILABLE);
+            },
+        }
+        return;
+    }
+
+    if operation == "template" {
+        run_template(provider.as_ref(), matches, format);
+        return;
+    }
+
+    let zones: Vec<String> = matches.value_of("zone")
+        .map(|z| z.split(',').map(|z| z.trim().to_string()).filter(|z| !z.is_empty()).collect())
+        .unwrap_or_default();
+
+    if zones.is_empty() && matches.is_present("parallel") {
+        log::error!("The zone is required with --parallel! Give the zones separated by commas.");
+        process::exit(exitcode::USAGE);
+    }
+
+    if zones.len() > 1 || matches.is_present("parallel") {
+        run_parallel(provider, &credentials, state, operation, matches, zones, format);
+        return;
+    }
+
+    let subcommand = match operation {
+        "ddns" => dynamic_dns(provider.as_ref(), &credentials, state.as_ref(), record_selector(matches), matches),
+        "update" => provider.update(&UpdateRequest {
+            selector: record_selector(matches),
+            records: read_records(matches),
+            wait: matches.is_present("wait"),
+        }),
+        "add" => provider.add(&AddRequest {
+            selector: record_selector(matches),
+            records: read_records(matches),
+            wait: matches.is_present("wait"),
+        }),
+        "delete" => provider.delete(&DeleteRequest {
+            selector: record_selector(matches),
+            wait: matches.is_present("wait"),
+        }),
+        _ => Ok(false),
+    };
+
+    match subcommand {
+        Ok(subcmd_ran) => {
+            if subcmd_ran {
+                return;
+            }
+
+            match provider.search(&SearchRequest { selector: record_selector(&app) }) {
+                Ok(records) => print_output(&records, format),
+                Err(e) => {
+                    log::error!("{}", e);
+                    process::exit(exitcode::UNAVAILABLE);
+                },
+            }
+        },
+        Err(e) => {
+            log::error!("{}", e);
+            process::exit(exitcode::UNAVAILABLE);
+        },
+    }
+}
+
+
+/// DDNS, unless the address of the client is the one published last for the host. Hosts with an
+/// address detection configured get their A/AAAA record updated with the detected address instead.
+fn dynamic_dns(provider: &dyn Provider, credentials: &[Credential], state: Option<&State>, selector: RecordSelector, matches: &ArgMatches) -> ddnsclient::Result<bool> {
+    let detected = detect_address(credentials, &selector, matches, &mut None)?;
+    let address = detected.address;
+    let host = selector.host().unwrap_or("@").to_string();
+    let name = provider.get_name();
+
+    if let (Some(state), Some(address)) = (state, address) {
+        if !matches.is_present("force") && state.is_published(&name, selector.zone(), &host, address) {
+            log::info!("{} in {} already points at {}, use --force to publish it again", host, selector.zone(), address);
+            return Ok(true);
+        }
+    }
+
+    let zone = selector.zone().to_string();
+    let outcome = match address {
+        Some(address) if detected.configured => provider
+            .update(&daemon::address_update(&selector, address, ttl(matches)))
+            .map(|_| daemon::address_updated(&selector, address)),
+        _ => provider.dynamic_dns(&DynamicDnsRequest { selector, address }),
+    };
+
+    if let Some(state) = state {
+        state.remember(&name, &zone, &host, address, &outcome);
+    }
+
+    outcome.map(|_| true)
+}
+
+/// The address detected for a host, and whether it was detected as configured for the host (and is
+/// published with an update) rather than by the service at `--ip-url` (and published with DDNS)
+#[derive(Debug, Clone, Copy, Default)]
+struct Detected {
+    address: Option<IpAddr>,
+    configured: bool,
+}
+
+/// Detect the address of the host as configured for it, or else with the service at `--ip-url`,
+/// which is only asked once: `fallback` keeps its answer
+fn detect_address(credentials: &[Credential], selector: &RecordSelector, matches: &ArgMatches, fallback: &mut Option<Option<IpAddr>>) -> ddnsclient::Result<Detected> {
+    match ip_detection::configured(credentials, selector.zone(), selector.host()) {
+        Some(strategy) => Ok(Detected {
+            address: Some(ip_detection::detect(&strategy)?),
+            configured: true,
+        }),
+        None => Ok(Detected {
+            address: *fallback.get_or_insert_with(|| public_address(matches)),
+            configured: false,
+        }),
+    }
+}
+
+/// The public address of the client, if it can be told, to compare with the one published last
+fn public_address(matches: &ArgMatches) -> Option<IpAddr> {
+    match ip_detection::public_address(ip_url(matches)) {
+        Ok(a) => Some(a),
+        Err(e) => {
+            log::warn!("Unable to tell whether the address changed. {}", e);
+            None
+        },
+    }
+}
+
+fn ip_url<'a>(matches: &'a ArgMatches) -> &'a str {
+    matches.value_of("ip-url").unwrap_or(ip_detection::DEFAULT_URL)
+}
+
+fn ttl(matches: &ArgMatches) -> u32 {
+    matches.value_of("ttl").and_then(|t| t.parse().ok()).unwrap_or(300)
+}
+
+/// The state file, unless there is nowhere to keep it
+fn state_file(matches: &ArgMatches) -> Option<State> {
+    let path = matches.value_of("state").map(PathBuf::from).or_else(State::default_path);
+    if path.is_none() {
+        log::warn!("Unable to locate the state file, set --state or $XDG_STATE_HOME to keep one");
+    }
+
+    path.map(State::new)
+}
+
+/// Show the entries of the state file, or forget them
+fn run_state(matches: &ArgMatches, format: Format) {
+    let state = match state_file(matches) {
+        Some(s) => s,
+        None => process::exit(exitcode::CONFIG),
+    };
+    let (zone, host, rtype) = (matches.value_of("zone"), matches.value_of("host"), matches.value_of("type"));
+
+    let result = match matches.subcommand() {
+        ("clear", Some(_)) => state.clear(zone, host, rtype).map(|n| log::info!("Forgot {} entries", n)),
+        _ => state.entries().map(|entries| {
+            let entries: Vec<StateEntry> = entries.into_iter().filter(|e| e.matches(zone, host, rtype)).collect();
+            print_output(&entries, format)
+        }),
+    };
+
+    if let Err(e) = result {
+        log::error!("{}: {}", state.path().display(), e);
+        process::exit(exitcode::IOERR);
+    }
+}
+
+
+/// List the templates of the zone, or apply one of them
+fn run_template(provider: &dyn Provider, matches: &ArgMatches, format: Format) {
+    let zone = record_selector(matches).zone;
 
+    let result = match matches.subcommand() {
+        ("apply", Some(m)) => provider.apply_template(&ApplyTemplateRequest {
+            zone,
+            template: m.value_of("name").expect("The name is required").to_string(),
+        }).map(|_| ()),
+        _ => provider.list_templates(&TemplatesRequest { zone }).map(|templates| print_output(&templates, format)),
+    };
+
+    if let Err(e) = result {
+        log::error!("{}", e);
+        process::exit(exitcode::UNAVAILABLE);
+    }
+}
+
+
+/// The configuration file, or the credentials given on the command line, with the settings of the
+/// command line applied on top
+fn load_configuration(app: &ArgMatches, provider: &str) -> Result<Configuration, ConfigError> {
     let mut config = Configuration::new();
     if let Some(config_path) = app.value_of("config-path") {
-        config = Configuration::from_path(config_path);
+        config = Configuration::from_path(config_path)?;
     }
-    if app.is_present("username") && app.is_present("password") {
+    if (app.is_present("username") && app.is_present("password")) || app.is_present("token") {
         config = Configuration {
             credentials: vec![Credential {
                 provider: provider.to_string(),
-                user: app.value_of("username").unwrap().to_string(),
-                pass: app.value_of("password").unwrap().to_string(),
-                zone: None,
-                host: None,
-                r#type: None,
+                user: app.value_of("username").unwrap_or_default().to_string(),
+                pass: app.value_of("password").unwrap_or_default().to_string(),
+                token: app.value_of("token").map(|t| t.to_string()),
+                ..Default::default()
             }],
+            http: config.http,
         };
     }
 
-    let mut provider = providers::init_provider(provider);
-    provider.set_credentials(get_provider_credentials(&provider, config));
+    // Command line settings take precedence over the configuration file
+    if let Some(url) = app.value_of("api-url") {
+        for credential in config.credentials.iter_mut().filter(|c| c.provider == provider) {
+            credential.api_url = Some(url.to_string());
+        }
+    }
+    if let Some(t) = app.value_of("timeout") {
+        config.http.timeout = t.parse().ok();
+    }
+    if let Some(t) = app.value_of("connect-timeout") {
+        config.http.connect_timeout = t.parse().ok();
+    }
+    if let Some(n) = app.value_of("max-attempts") {
+        config.http.max_attempts = n.parse().ok();
+    }
+    if let Some(p) = app.value_of("proxy") {
+        config.http.proxy = Some(p.to_string());
+    }
+    if let Some(certs) = app.values_of("ca-cert") {
+        config.http.ca_certificates.extend(certs.map(String::from));
+    }
+    if let Some(c) = app.value_of("client-cert") {
+        config.http.client_certificate = Some(c.to_string());
+    }
+    if let Some(p) = app.value_of("client-cert-password") {
+        config.http.client_certificate_password = Some(p.to_string());
+    }
+    if let Some(u) = app.value_of("user-agent") {
+        config.http.user_agent = Some(u.to_string());
+    }
+
+    Ok(config)
+}
 
-    let subcommand = match app.subcommand() {
-        ("ddns", Some(ddns)) => provider.dynamic_dns(ddns),
-        ("update", Some(upd)) => {
-            let records: Vec<providers::Record> = match upd.values_of("records") {
-                Some(rcds) => process_dns_records(rcds.map(|ln| ln.to_string())),
-                None => process_dns_records(io::stdin().lock().lines().map(|ln| ln.unwrap())),
-            };
 
-            provider.update(upd, &records)
+/// Outcome of an operation on one of several zones
+#[derive(Serialize, Debug)]
+struct ZoneReport {
+    zone: String,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    records: Option<Vec<Record>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Run the operation on every zone, up to `--parallel` zones at the same time, and print the outcome
+/// of all zones as a single document
+fn run_parallel(provider: Box<dyn Provider>, credentials: &[Credential], state: Option<State>, operation: &str, matches: &ArgMatches, zones: Vec<String>, format: Format) {
+    let provider = BlockingProvider::new(provider);
+    let parallel = matches.value_of("parallel").and_then(|p| p.parse().ok()).unwrap_or(1);
+    let host = matches.value_of("host");
+    let rtype = matches.value_of("type");
+    let wait = matches.is_present("wait");
+    let ttl = ttl(matches);
+    let records = if operation == "update" || operation == "add" { read_records(matches) } else { vec![] };
+
+    // DDNS detects the address of the host in every zone up front, failing the zones it can not be
+    // detected for, and leaves out the zones the address is already published in
+    let mut detected: HashMap<String, Detected> = HashMap::new();
+    let mut undetected = Vec::new();
+    let zones = if operation == "ddns" {
+        let mut fallback = None;
+        zones.into_iter().filter(|zone| match detect_address(credentials, &RecordSelector::new(zone, host, None), matches, &mut fallback) {
+            Ok(d) => {
+                detected.insert(zone.clone(), d);
+                true
+            },
+            Err(e) => {
+                undetected.push((zone.clone(), Err(e)));
+                false
+            },
+        }).collect()
+    } else {
+        zones
+    };
+    let published: HashSet<String> = match &state {
+        Some(state) if !matches.is_present("force") => detected
+            .iter()
+            .filter(|(z, d)| d.address.is_some_and(|a| state.is_published(&provider.get_name(), z, host.unwrap_or("@"), a)))
+            .map(|(z, _)| z.clone())
+            .collect(),
+        _ => HashSet::new(),
+    };
+
+    let mut runtime = match tokio::runtime::Runtime::new() {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("Unable to start the async runtime: {}", e);
+            process::exit(exitcode::OSERR);
         },
-        ("delete", Some(del)) => provider.delete(del),
-        _ => Ok(false),
     };
 
-    match subcommand {
-        Ok(subcmd_ran) => {
-            if subcmd_ran {
-                return ();
+    let mut results = runtime.block_on(for_each_zone(zones, parallel, |zone| {
+        let provider = provider.clone();
+        let selector = with_filters(RecordSelector::new(&zone, host, rtype), matches);
+        let records = records.clone();
+        let operation = operation.to_string();
+        let skip = published.contains(&zone);
+        let detected = detected.get(&zone).copied().unwrap_or_default();
+        let state = state.clone();
+
+        async move {
+            if skip {
+                log::info!("{} in {} already points at the address, use --force to publish it again", selector.host().unwrap_or("@"), selector.zone());
+                return Ok(None);
             }
 
-            match provider.search(&app) {
-                Ok(records) => {
-                    if app.is_present("pretty") {
-                        match serde_json::to_string_pretty(&records) {
-                            Ok(s) => {
-                                println!("{}", s);
-                                return ();
-                            },
-                            Err(e) => {
-                                log::error!("{}", e);
-                                process::exit(exitcode::UNAVAILABLE);
-                            }
-                        }
-                    }
+            log::info!("Running {} on zone {}", operation, selector.zone());
 
-                    match serde_json::to_string(&records) {
-                        Ok(s) => {
-                            println!("{}", s);
-                            return ();
-                        },
-                        Err(e) => {
-                            log::error!("{}", e);
-                            process::exit(exitcode::UNAVAILABLE);
-                        }
+            match operation.as_str() {
+                "ddns" => {
+                    let (zone, host) = (selector.zone().to_string(), selector.host().unwrap_or("@").to_string());
+                    let outcome = match detected.address {
+                        Some(address) if detected.configured => provider
+                            .update(&daemon::address_update(&selector, address, ttl)).await
+                            .map(|_| daemon::address_updated(&selector, address)),
+                        address => provider.dynamic_dns(&DynamicDnsRequest { selector, address }).await,
+                    };
+                    if let Some(state) = &state {
+                        state.remember(&provider.get_name(), &zone, &host, detected.address, &outcome);
                     }
+                    outcome.map(|_| None)
                 },
-                Err(e) => {
-                    log::error!("{}", e);
-                    process::exit(exitcode::UNAVAILABLE);
-                },
+                "update" => provider.update(&UpdateRequest { selector, records, wait }).await.map(|_| None),
+                "add" => provider.add(&AddRequest { selector, records, wait }).await.map(|_| None),
+                "delete" => provider.delete(&DeleteRequest { selector, wait }).await.map(|_| None),
+                _ => provider.search(&SearchRequest { selector }).await,
             }
-        },
+        }
+    }));
+
+    results.extend(undetected);
+
+    let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+    let reports: Vec<ZoneReport> = results
+        .into_iter()
+        .map(|(zone, result)| match result {
+            Ok(records) => ZoneReport { zone, success: true, records, error: None },
+            Err(e) => {
+                log::error!("{}: {}", zone, e);
+                ZoneReport { zone, success: false, records: None, error: Some(e.to_string()) }
+            },
+        })
+        .collect();
+
+    print_output(&reports, format);
+
+    if failed > 0 {
+        process::exit(exitcode::UNAVAILABLE);
+    }
+}
+
+
+/// How results are printed
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Json,
+    Pretty,
+    Table,
+}
+
+/// Results which can be printed as a table
+trait Tabular {
+    /// The header and then a row for every item
+    fn rows(&self) -> Vec<Vec<String>>;
+}
+
+impl Tabular for Vec<Record> {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let flags = |r: &Record| {
+            let mut flags = Vec::new();
+            if r.template == Some(true) {
+                flags.push("template");
+            }
+            if r.generated == Some(true) {
+                flags.push("generated");
+            }
+            flags.join(",")
+        };
+        // Only providers which tell get the column
+        let flagged = self.iter().any(|r| !flags(r).is_empty());
+
+        let mut header = vec!["HOST".to_string(), "TTL".to_string(), "TYPE".to_string(), "DATA".to_string()];
+        if flagged {
+            header.push("FLAGS".to_string());
+        }
+
+        std::iter::once(header)
+            .chain(self.iter().map(|r| {
+                let mut row = vec![r.host.clone(), r.ttl.to_string(), r.r#type.clone(), r.to_content()];
+                if flagged {
+                    row.push(flags(r));
+                }
+                row
+            }))
+            .collect()
+    }
+}
+
+impl<T: Tabular + Default> Tabular for Option<T> {
+    fn rows(&self) -> Vec<Vec<String>> {
+        match self {
+            Some(t) => t.rows(),
+            None => T::default().rows(),
+        }
+    }
+}
+
+impl Tabular for Vec<Zone> {
+    fn rows(&self) -> Vec<Vec<String>> {
+        named_rows(self.iter().map(|z| (&z.name, &z.metadata)).collect())
+    }
+}
+
+impl Tabular for Vec<Template> {
+    fn rows(&self) -> Vec<Vec<String>> {
+        named_rows(self.iter().map(|t| (&t.name, &t.metadata)).collect())
+    }
+}
+
+impl Tabular for Vec<ZoneReport> {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let header = vec!["ZONE".to_string(), "STATUS".to_string(), "RESULT".to_string()];
+
+        std::iter::once(header)
+            .chain(self.iter().map(|r| {
+                let result = match (&r.records, &r.error) {
+                    (_, Some(e)) => e.clone(),
+                    (Some(records), None) => format!("{} record(s)", records.len()),
+                    (None, None) => String::new(),
+                };
+                vec![r.zone.clone(), if r.success { "ok" } else { "failed" }.to_string(), result]
+            }))
+            .collect()
+    }
+}
+
+impl Tabular for Vec<StateEntry> {
+    fn rows(&self) -> Vec<Vec<String>> {
+        let header = ["PROVIDER", "ZONE", "HOST", "TYPE", "ADDRESS", "PUBLISHED", "UPDATED", "RESPONSE"].iter().map(|h| h.to_string()).collect();
+
+        std::iter::once(header)
+            .chain(self.iter().map(|e| vec![
+                e.provider.clone(),
+                e.zone.clone(),
+                e.host.clone(),
+                e.r#type.clone(),
+                e.address.clone().unwrap_or_default(),
+                e.published_at.map(state::format_time).unwrap_or_default(),
+                state::format_time(e.updated_at),
+                e.response.clone(),
+            ]))
+            .collect()
+    }
+}
+
+/// Rows of items with a name and metadata, with a column for every piece of metadata
+fn named_rows(items: Vec<(&String, &BTreeMap<String, serde_json::Value>)>) -> Vec<Vec<String>> {
+    // Providers know about different details, and not every item has all of them
+    let keys: BTreeSet<&String> = items.iter().flat_map(|(_, m)| m.keys()).collect();
+    let header = std::iter::once("NAME".to_string()).chain(keys.iter().map(|k| k.to_uppercase())).collect();
+
+    std::iter::once(header)
+        .chain(items.iter().map(|(name, metadata)| {
+            std::iter::once(name.to_string())
+                .chain(keys.iter().map(|k| metadata.get(*k).map(cell).unwrap_or_default()))
+                .collect()
+        }))
+        .collect()
+}
+
+/// A metadata value as shown in a table, without the quotes of JSON strings
+fn cell(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::Null => String::new(),
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(a) => a.iter().map(cell).collect::<Vec<_>>().join(","),
+        v => v.to_string(),
+    }
+}
+
+/// The output format, where `--pretty` stands for `--format pretty`
+fn output_format(app: &ArgMatches, matches: &ArgMatches) -> Format {
+    match matches.value_of("format").or_else(|| app.value_of("format")) {
+        Some("table") => Format::Table,
+        Some("pretty") => Format::Pretty,
+        Some(_) => Format::Json,
+        None if app.is_present("pretty") => Format::Pretty,
+        None => Format::Json,
+    }
+}
+
+fn print_output<T: Serialize + Tabular>(value: &T, format: Format) {
+    let output = match format {
+        Format::Json => serde_json::to_string(value),
+        Format::Pretty => serde_json::to_string_pretty(value),
+        Format::Table => Ok(table(&value.rows())),
+    };
+
+    match output {
+        Ok(s) => println!("{}", s),
         Err(e) => {
             log::error!("{}", e);
             process::exit(exitcode::UNAVAILABLE);
@@ -206,12 +972,107 @@ fn main() {
 }
 
 
+/// Lay the rows out in columns as wide as their widest cell
+fn table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(|r| r.len()).max().unwrap_or_default();
+    let widths: Vec<usize> = (0..columns)
+        .map(|c| rows.iter().filter_map(|r| r.get(c)).map(|v| v.chars().count()).max().unwrap_or_default())
+        .collect();
+
+    rows.iter()
+        .map(|row| {
+            let line = row.iter()
+                .zip(&widths)
+                .map(|(v, w)| format!("{:width$}", v, width = w))
+                .collect::<Vec<_>>()
+                .join("  ");
+            line.trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+
+/// Records given on the command line, or else read from stdin
+fn read_records(matches: &ArgMatches) -> Vec<Record> {
+    match matches.values_of("records") {
+        Some(rcds) => process_dns_records(rcds.map(|ln| ln.to_string())),
+        None => process_dns_records(io::stdin().lock().lines().map(|ln| ln.unwrap())),
+    }
+}
+
+
+fn is_number(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(String::from("must be a positive number")),
+    }
+}
+
+
+/// Translate the zone|host|type arguments into the selection of records a provider works on
+fn record_selector(matches: &ArgMatches) -> RecordSelector {
+    let zone = match matches.value_of("zone") {
+        Some(z) => z,
+        None => {
+            log::error!("The zone is required! Use the zones subcommand to list the zones of the credentials.");
+            process::exit(exitcode::USAGE);
+        },
+    };
+
+    with_filters(RecordSelector::new(zone, matches.value_of("host"), matches.value_of("type")), matches)
+}
+
+/// The hosts the daemon keeps up to date, one in every zone given
+fn daemon_selectors(matches: &ArgMatches) -> Vec<RecordSelector> {
+    let selector = record_selector(matches);
+    if selector.host().is_none() {
+        log::error!("The host is required!");
+        process::exit(exitcode::USAGE);
+    }
+
+    selector.zone
+        .split(',')
+        .map(str::trim)
+        .filter(|z| !z.is_empty())
+        .map(|z| RecordSelector::new(z, selector.host(), None))
+        .collect()
+}
+
+fn daemon_settings(matches: &ArgMatches) -> daemon::Settings {
+    let number = |name| matches.value_of(name).and_then(|v| v.parse().ok()).expect("Validated by clap");
+
+    daemon::Settings {
+        interval: Duration::from_secs(number("interval")),
+        ip_url: ip_url(matches).to_string(),
+        ttl: number("ttl") as u32,
+        force: matches.is_present("force"),
+        watch: matches.is_present("watch"),
+        debounce: Duration::from_secs(number("debounce")),
+    }
+}
+
+
+/// Leave generated and template records in or out as the flags ask, or else as the operation does
+fn with_filters(mut selector: RecordSelector, matches: &ArgMatches) -> RecordSelector {
+    let filter = |exclude: &str, include: &str| match (matches.is_present(exclude), matches.is_present(include)) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+
+    selector.exclude_generated = filter("exclude-generated", "include-generated");
+    selector.exclude_template = filter("exclude-template", "include-template");
+    selector
+}
+
+
 /// Process DNS records utility function
-fn process_dns_records<I>(strings: I) -> Vec<providers::Record> where I: IntoIterator<Item = String> + std::fmt::Debug {
-    let mut dns_records: Vec<providers::Record> = Vec::new();
+fn process_dns_records<I>(strings: I) -> Vec<Record> where I: IntoIterator<Item = String> + std::fmt::Debug {
+    let mut dns_records: Vec<Record> = Vec::new();
 
     for string in strings {
-        let result: Result<Vec<providers::Record>, serde_json::Error> = serde_json::from_str(&string);
+        let result: Result<Vec<Record>, serde_json::Error> = serde_json::from_str(&string);
 
         if let Ok(r) = result {
             dns_records.extend(r);
@@ -0,0 +1,227 @@
+//! Async variant of the `Provider` trait, for running operations concurrently on tokio.
+//!
+//! The providers talk to their APIs with blocking calls, so `BlockingProvider` runs them on the
+//! blocking thread pool of tokio and lets the runtime carry on with other operations meanwhile.
+//!
+//! This is a `spawn_blocking` wrapper, not non-blocking I/O: every operation in flight still holds
+//! a thread of the blocking pool while it waits for the API, so bound the concurrency (e.g. with
+//! `for_each_zone`) rather than starting thousands of operations at once.
+use super::{Provider, Record, SearchRequest, UpdateRequest, AddRequest, DeleteRequest, DynamicDnsRequest, DynamicDnsResponse, Zone, Template, TemplatesRequest, ApplyTemplateRequest, ProviderError, Result};
+
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use async_trait::async_trait;
+use tokio::sync::Semaphore;
+
+/// Provider operations returning futures. The only implementation, `BlockingProvider`, wraps a
+/// blocking `Provider` with `spawn_blocking`.
+#[async_trait]
+pub trait AsyncProvider: fmt::Debug + Send + Sync {
+    fn get_name(&self) -> String;
+
+    /// Dynamic DNS capability
+    async fn dynamic_dns(&self, request: &DynamicDnsRequest) -> Result<DynamicDnsResponse>;
+
+    /// Search for DNS record(s) via the API
+    async fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>>;
+
+    /// Update DNS record(s)
+    async fn update(&self, request: &UpdateRequest) -> Result<bool>;
+
+    /// Add DNS record(s), keeping the existing ones
+    async fn add(&self, request: &AddRequest) -> Result<bool>;
+
+    /// Delete DNS record(s)
+    async fn delete(&self, request: &DeleteRequest) -> Result<bool>;
+
+    /// List the zones the credential(s) can access
+    async fn list_zones(&self) -> Result<Vec<Zone>>;
+
+    /// List the DNS templates which can be applied to the zone
+    async fn list_templates(&self, request: &TemplatesRequest) -> Result<Vec<Template>>;
+
+    /// Apply a DNS template to the zone, adding its records
+    async fn apply_template(&self, request: &ApplyTemplateRequest) -> Result<bool>;
+}
+
+/// Runs a blocking provider on the blocking thread pool of tokio, one thread per operation in flight
+#[derive(Debug, Clone)]
+pub struct BlockingProvider {
+    inner: Arc<dyn Provider>,
+}
+
+
+impl BlockingProvider {
+    pub fn new(provider: Box<dyn Provider>) -> Self {
+        BlockingProvider {
+            inner: Arc::from(provider),
+        }
+    }
+
+    async fn run<T, F>(&self, operation: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Provider) -> Result<T> + Send + 'static,
+    {
+        let provider = self.inner.clone();
+
+        tokio::task::spawn_blocking(move || operation(provider.as_ref())).await?
+    }
+}
+
+impl From<Box<dyn Provider>> for BlockingProvider {
+    fn from(provider: Box<dyn Provider>) -> Self {
+        BlockingProvider::new(provider)
+    }
+}
+
+
+#[async_trait]
+impl AsyncProvider for BlockingProvider {
+    fn get_name(&self) -> String {
+        self.inner.get_name()
+    }
+
+    async fn dynamic_dns(&self, request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        let request = request.clone();
+        self.run(move |p| p.dynamic_dns(&request)).await
+    }
+
+    async fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let request = request.clone();
+        self.run(move |p| p.search(&request)).await
+    }
+
+    async fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let request = request.clone();
+        self.run(move |p| p.update(&request)).await
+    }
+
+    async fn add(&self, request: &AddRequest) -> Result<bool> {
+        let request = request.clone();
+        self.run(move |p| p.add(&request)).await
+    }
+
+    async fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let request = request.clone();
+        self.run(move |p| p.delete(&request)).await
+    }
+
+    async fn list_zones(&self) -> Result<Vec<Zone>> {
+        self.run(|p| p.list_zones()).await
+    }
+
+    async fn list_templates(&self, request: &TemplatesRequest) -> Result<Vec<Template>> {
+        let request = request.clone();
+        self.run(move |p| p.list_templates(&request)).await
+    }
+
+    async fn apply_template(&self, request: &ApplyTemplateRequest) -> Result<bool> {
+        let request = request.clone();
+        self.run(move |p| p.apply_template(&request)).await
+    }
+}
+
+
+/// Run an operation for each zone, with at most `parallel` of them running at the same time. The
+/// results are returned in the order of the zones.
+pub async fn for_each_zone<T, F, Fut>(zones: Vec<String>, parallel: usize, operation: F) -> Vec<(String, Result<T>)>
+where
+    T: Send + 'static,
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<T>> + Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(parallel.max(1)));
+    let mut tasks = Vec::new();
+
+    for zone in zones {
+        let semaphore = semaphore.clone();
+        // Futures do nothing until awaited, so the operation only starts once it has a permit
+        let operation = operation(zone.clone());
+
+        tasks.push((zone, tokio::spawn(async move {
+            let _permit = semaphore.acquire().await;
+            operation.await
+        })));
+    }
+
+    let mut results = Vec::new();
+    for (zone, task) in tasks {
+        let result = match task.await {
+            Ok(r) => r,
+            Err(e) => Err(ProviderError::from(e)),
+        };
+
+        results.push((zone, result));
+    }
+
+    results
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::ProviderErrorKind;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    fn zones(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn runs_at_most_the_given_number_of_zones_at_once() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+
+        let results = for_each_zone(zones(&["a", "b", "c", "d", "e", "f", "g", "h"]), 3, |zone| {
+            let running = running.clone();
+            let most = most.clone();
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                tokio::task::spawn_blocking(|| thread::sleep(Duration::from_millis(50))).await?;
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(zone)
+            }
+        }).await;
+
+        assert_eq!(results.len(), 8);
+        assert!(results.iter().all(|(zone, result)| result.as_ref().ok() == Some(zone)));
+        assert_eq!(most.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn reports_a_failing_zone_along_with_the_others() {
+        let results = for_each_zone(zones(&["a.com", "b.com", "c.com"]), 2, |zone| async move {
+            match zone.as_str() {
+                "b.com" => Err(ProviderError::with_message(ProviderErrorKind::DnsApiError, "Zone b.com does not exist")),
+                _ => Ok(zone.len()),
+            }
+        }).await;
+
+        let zones: Vec<&str> = results.iter().map(|(z, _)| z.as_str()).collect();
+        assert_eq!(zones, vec!["a.com", "b.com", "c.com"]);
+        assert_eq!(results[0].1.as_ref().ok(), Some(&5));
+        assert!(matches!(results[1].1.as_ref().unwrap_err().kind(), ProviderErrorKind::DnsApiError));
+        assert_eq!(results[2].1.as_ref().ok(), Some(&5));
+    }
+
+    #[tokio::test(threaded_scheduler)]
+    async fn reports_a_panicking_zone_as_a_failed_task() {
+        let results = for_each_zone(zones(&["a.com", "b.com"]), 0, |zone| async move {
+            if zone == "a.com" {
+                panic!("the operation panicked");
+            }
+            Ok(())
+        }).await;
+
+        assert!(matches!(results[0].1.as_ref().unwrap_err().kind(), ProviderErrorKind::TaskFailed));
+        assert!(results[1].1.is_ok());
+    }
+}
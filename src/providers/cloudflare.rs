@@ -0,0 +1,704 @@
+use super::{Provider, http, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, DeleteRequest, Record, ProviderError, ProviderErrorKind, Result, get_credential, api_url, list_account_zones, fqdn, relative_host, reconcile, status_error_kind};
+use super::retry::{Idempotency, SendRetry};
+use crate::config;
+
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+
+const API_URL: &str = "https://api.cloudflare.com/client/v4";
+
+/// Cloudflare endpoint which echoes back (amongst others) the address of the client
+const TRACE_URL: &str = "https://www.cloudflare.com/cdn-cgi/trace";
+
+/// Cloudflare's TTL value for "automatic"
+const AUTO_TTL: u32 = 1;
+
+#[derive(Deserialize, Debug)]
+struct ApiResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ApiMessage>,
+    result: Option<T>,
+    result_info: Option<ResultInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ApiMessage {
+    code: u32,
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResultInfo {
+    page: u32,
+    total_pages: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct Zone {
+    id: String,
+    #[serde(default)]
+    name: String,
+    status: Option<String>,
+    #[serde(default)]
+    name_servers: Vec<String>,
+}
+
+/// A DNS record as represented by the Cloudflare API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DnsRecord {
+    #[serde(skip_serializing)]
+    id: Option<String>,
+    r#type: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    ttl: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
+}
+
+#[derive(Debug)]
+pub struct Cloudflare {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+
+impl DnsRecord {
+    /// Map a record onto the Cloudflare schema. Records which have structured data in Cloudflare
+    /// (e.g. SRV, CAA) are sent through the `data` field instead of `content`.
+    fn from_record(zone: &str, r: &Record) -> Self {
+        let rtype = r.r#type.to_uppercase();
+        let mut content = Some(r.data.clone());
+        let mut priority = None;
+        let mut data = None;
+
+        match rtype.as_str() {
+            "MX" => priority = r.mx_priority,
+            "SRV" => {
+                content = None;
+                data = Some(json!({
+                    "priority": r.srv_priority.unwrap_or(0),
+                    "weight": r.srv_weight.unwrap_or(0),
+                    "port": r.srv_port.unwrap_or(0),
+                    "target": r.data,
+                }));
+            },
+            "CAA" => {
+                content = None;
+                data = Some(json!({
+                    "flags": r.caa_flags.unwrap_or(0),
+                    "tag": r.caa_property.clone().unwrap_or_default(),
+                    "value": r.data,
+                }));
+            },
+            "SSHFP" => {
+                content = None;
+                data = Some(json!({
+                    "algorithm": r.sshfp_algorithm.unwrap_or(0),
+                    "type": r.sshfp_type.unwrap_or(0),
+                    "fingerprint": r.data,
+                }));
+            },
+            "TLSA" => {
+                content = None;
+                data = Some(json!({
+                    "usage": r.tlsa_usage.unwrap_or(0),
+                    "selector": r.tlsa_selector.unwrap_or(0),
+                    "matching_type": r.tlsa_matching.unwrap_or(0),
+                    "certificate": r.data,
+                }));
+            },
+            _ => (),
+        }
+
+        DnsRecord {
+            id: None,
+            r#type: rtype,
+            name: fqdn(zone, Some(&r.host)),
+            content,
+            ttl: r.ttl,
+            proxied: r.proxied,
+            priority,
+            data,
+        }
+    }
+
+    fn into_record(self, zone: &str) -> Record {
+        let field = |name: &str| -> Option<u32> {
+            self.data.as_ref()
+                .and_then(|d| d.get(name))
+                .and_then(|v| v.as_u64())
+                .map(|v| v as u32)
+        };
+        let text = |name: &str| -> Option<String> {
+            self.data.as_ref()
+                .and_then(|d| d.get(name))
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_string())
+        };
+
+        let mut record = Record {
+            host: relative_host(zone, &self.name),
+            ttl: self.ttl,
+            r#type: self.r#type.clone(),
+            data: self.content.clone().unwrap_or_default(),
+            proxied: self.proxied,
+            ..Default::default()
+        };
+
+        match self.r#type.as_str() {
+            "MX" => record.mx_priority = self.priority,
+            "SRV" => {
+                record.srv_priority = field("priority").or(self.priority);
+                record.srv_weight = field("weight");
+                record.srv_port = field("port");
+                record.data = text("target").unwrap_or(record.data);
+            },
+            "CAA" => {
+                record.caa_flags = field("flags");
+                record.caa_property = text("tag");
+                record.data = text("value").unwrap_or(record.data);
+            },
+            "SSHFP" => {
+                record.sshfp_algorithm = field("algorithm");
+                record.sshfp_type = field("type");
+                record.data = text("fingerprint").unwrap_or(record.data);
+            },
+            "TLSA" => {
+                record.tlsa_usage = field("usage");
+                record.tlsa_selector = field("selector");
+                record.tlsa_matching = field("matching_type");
+                record.data = text("certificate").unwrap_or(record.data);
+            },
+            _ => (),
+        }
+
+        record
+    }
+}
+
+
+impl Cloudflare {
+    pub fn new() -> Self {
+        Cloudflare {
+            name: String::from("cloudflare"),
+            credentials: None,
+        }
+    }
+
+    fn get_credential(&self, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<(String, String)> {
+        Cloudflare::api_access(&get_credential(&self.credentials, zone, host, rtype)?)
+    }
+
+    /// Base URL of the API and the token of the credential
+    fn api_access(credential: &config::Credential) -> Result<(String, String)> {
+        let api_url = api_url(credential, API_URL);
+
+        match &credential.token {
+            Some(t) => Ok((api_url, t.clone())),
+            None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+                .msg(String::from("Cloudflare requires an API token!"))),
+        }
+    }
+
+    /// Send a request to the API and unwrap the Cloudflare response envelope
+    fn request<T: DeserializeOwned>(method: reqwest::Method, url: &str, query: &[(&str, String)], token: &str, body: Option<&DnsRecord>) -> Result<(T, Option<ResultInfo>)> {
+        let idempotency = Idempotency::of(&method);
+        let mut request = http::client()
+            .request(method, url)
+            .query(query)
+            .bearer_auth(token);
+
+        if let Some(b) = body {
+            request = request.json(b);
+        }
+
+        let response = request.send_retrying(idempotency)?;
+        let status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        let result: ApiResponse<T> = match serde_json::from_str(&text) {
+            Ok(r) => r,
+            // e.g. an error page of a proxy in front of the API
+            Err(_) if !status.is_success() => return Err(ProviderError::new(status_error_kind(status))
+                .msg(format!("Cloudflare API request failed with {}", status))),
+            Err(e) => return Err(e.into()),
+        };
+
+        if !result.success || result.result.is_none() {
+            let reasons: Vec<String> = result.errors
+                .iter()
+                .map(|e| format!("{} (code {})", e.message, e.code))
+                .collect();
+            let kind = if status.is_success() { ProviderErrorKind::DnsApiError } else { status_error_kind(status) };
+
+            return Err(ProviderError::new(kind)
+                .msg(format!("Cloudflare API request failed. Reasons: \n - {}", reasons.join("\n - "))));
+        }
+
+        Ok((result.result.unwrap(), result.result_info))
+    }
+
+    /// Resolve the zone name to the identifier used by the API
+    fn get_zone_id(api_url: &str, token: &str, zone: &str) -> Result<String> {
+        let url = format!("{}/zones", api_url);
+        let (zones, _): (Vec<Zone>, _) = Cloudflare::request(reqwest::Method::GET, &url, &[("name", zone.to_string())], token, None)?;
+
+        match zones.into_iter().next() {
+            Some(z) => Ok(z.id),
+            None => Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                .msg(format!("Zone {} does not exist or the token has no access to it!", zone))),
+        }
+    }
+
+    /// Fetch all records (following pagination) which match the zone|host|type selection
+    fn get_records(api_url: &str, token: &str, zone_id: &str, name: Option<&str>, rtype: Option<&str>) -> Result<Vec<DnsRecord>> {
+        let mut records: Vec<DnsRecord> = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let url = format!("{}/zones/{}/dns_records", api_url, zone_id);
+            let mut query = vec![("per_page", String::from("100")), ("page", page.to_string())];
+            if let Some(n) = name {
+                query.push(("name", n.to_string()));
+            }
+            if let Some(t) = rtype {
+                query.push(("type", t.to_uppercase()));
+            }
+
+            let (batch, info): (Vec<DnsRecord>, _) = Cloudflare::request(reqwest::Method::GET, &url, &query, token, None)?;
+            records.extend(batch);
+
+            match info {
+                Some(i) if i.page < i.total_pages => page = i.page + 1,
+                _ => break,
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Fetch all zones the token has access to, following pagination
+    fn get_zones(api_url: &str, token: &str) -> Result<Vec<Zone>> {
+        let mut zones: Vec<Zone> = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let url = format!("{}/zones", api_url);
+            let query = [("per_page", String::from("50")), ("page", page.to_string())];
+            let (batch, info): (Vec<Zone>, _) = Cloudflare::request(reqwest::Method::GET, &url, &query, token, None)?;
+            zones.extend(batch);
+
+            match info {
+                Some(i) if i.page < i.total_pages => page = i.page + 1,
+                _ => break,
+            }
+        }
+
+        Ok(zones)
+    }
+
+    /// Get the address Cloudflare sees the requests coming from
+    fn get_client_address() -> Result<String> {
+        let text = http::client()
+            .get(TRACE_URL)
+            .send_retrying(Idempotency::Idempotent)?
+            .text()?;
+
+        match text.lines().find_map(|l| l.strip_prefix("ip=")) {
+            Some(ip) => Ok(ip.trim().to_string()),
+            None => Err(ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+                .msg(String::from("Unable to establish the address of the client!"))),
+        }
+    }
+}
+
+
+impl Provider for Cloudflare {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn dynamic_dns(&self, request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        let zone = request.selector.zone();
+        let host = match request.selector.host() {
+            Some(h) => h,
+            None => return Err(ProviderError::new(ProviderErrorKind::InvalidInput)
+                .msg(String::from("DDNS requires the host to point at the address!"))),
+        };
+        let name = fqdn(zone, Some(host));
+
+        let (api_url, token) = self.get_credential(zone, Some(host), None)?;
+        let zone_id = Cloudflare::get_zone_id(&api_url, &token, zone)?;
+
+        let address = match request.address {
+            Some(a) => a.to_string(),
+            None => Cloudflare::get_client_address()?,
+        };
+        let rtype = if address.contains(':') { "AAAA" } else { "A" };
+
+        let existing = Cloudflare::get_records(&api_url, &token, &zone_id, Some(&name), Some(rtype))?;
+
+        match existing.into_iter().next() {
+            Some(mut record) => {
+                if record.content.as_deref() == Some(address.as_str()) {
+                    let message = format!("No change, {} is already set to {}", name, address);
+                    log::info!("{}", message);
+                    return Ok(DynamicDnsResponse { address: address.parse().ok(), message });
+                }
+
+                // Keep TTL and proxied flag of the existing record, only the address changes
+                let url = format!("{}/zones/{}/dns_records/{}", api_url, zone_id, record.id.clone().unwrap_or_default());
+                record.content = Some(address.clone());
+                let _: (DnsRecord, _) = Cloudflare::request(reqwest::Method::PUT, &url, &[], &token, Some(&record))?;
+            },
+            None => {
+                let url = format!("{}/zones/{}/dns_records", api_url, zone_id);
+                let record = DnsRecord {
+                    id: None,
+                    r#type: rtype.to_string(),
+                    name: name.clone(),
+                    content: Some(address.clone()),
+                    ttl: AUTO_TTL,
+                    proxied: Some(false),
+                    priority: None,
+                    data: None,
+                };
+                let _: (DnsRecord, _) = Cloudflare::request(reqwest::Method::POST, &url, &[], &token, Some(&record))?;
+            },
+        }
+
+        let message = format!("Updated {} {} record to {}", name, rtype, address);
+        log::info!("{}", message);
+
+        Ok(DynamicDnsResponse { address: address.parse().ok(), message })
+    }
+
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let zone_id = Cloudflare::get_zone_id(&api_url, &token, zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+        let records = Cloudflare::get_records(&api_url, &token, &zone_id, name.as_deref(), rtype)?;
+
+        Ok(Some(records.into_iter().map(|r| r.into_record(zone)).collect()))
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let zone_id = Cloudflare::get_zone_id(&api_url, &token, zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+        let records = Cloudflare::get_records(&api_url, &token, &zone_id, name.as_deref(), rtype)?;
+
+        for record in &records {
+            let url = format!("{}/zones/{}/dns_records/{}", api_url, zone_id, record.id.clone().unwrap_or_default());
+            let _: (serde_json::Value, _) = Cloudflare::request(reqwest::Method::DELETE, &url, &[], &token, None)?;
+        }
+
+        log::info!("Deleted {} record(s)", records.len());
+
+        Ok(true)
+    }
+
+    /// Replace all records selected by zone|host|type with the given ones. Records which already
+    /// exist with the same data are kept (and only updated if the TTL or proxied flag changed),
+    /// a changed record is written over the one of the same name and type, and the rest are
+    /// deleted before the new ones are created, as a CNAME cannot sit next to other records.
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let records = &request.records;
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let zone_id = Cloudflare::get_zone_id(&api_url, &token, zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+        let existing: Vec<(String, Record)> = Cloudflare::get_records(&api_url, &token, &zone_id, name.as_deref(), rtype)?
+            .into_iter()
+            .map(|r| (r.id.clone().unwrap_or_default(), r.into_record(zone)))
+            .collect();
+
+        let mut changes = reconcile(zone, existing.clone(), records);
+        changes.replace_in_place(|id, record| existing.iter().any(|(i, e)| i == id
+            && e.r#type.eq_ignore_ascii_case(&record.r#type)
+            && fqdn(zone, Some(&e.host)).eq_ignore_ascii_case(&fqdn(zone, Some(&record.host)))));
+        let url = format!("{}/zones/{}/dns_records", api_url, zone_id);
+
+        for id in &changes.delete {
+            let _: (serde_json::Value, _) = Cloudflare::request(reqwest::Method::DELETE, &format!("{}/{}", url, id), &[], &token, None)?;
+        }
+
+        for (id, record) in &changes.update {
+            let body = DnsRecord::from_record(zone, record);
+            let _: (DnsRecord, _) = Cloudflare::request(reqwest::Method::PUT, &format!("{}/{}", url, id), &[], &token, Some(&body))?;
+        }
+
+        for record in &changes.create {
+            let body = DnsRecord::from_record(zone, record);
+            let _: (DnsRecord, _) = Cloudflare::request(reqwest::Method::POST, &url, &[], &token, Some(&body))?;
+        }
+
+        log::info!("Updated record(s)!");
+        log::debug!("Added {} record(s). Changed {} record(s). Removed {} record(s)", changes.create.len(), changes.update.len(), changes.delete.len());
+
+        Ok(true)
+    }
+
+    fn list_zones(&self) -> Result<Vec<super::Zone>> {
+        list_account_zones(&self.credentials, |credential| {
+            let (api_url, token) = Cloudflare::api_access(credential)?;
+
+            Ok(Cloudflare::get_zones(&api_url, &token)?
+                .into_iter()
+                .map(|z| super::Zone::new(&z.name)
+                    .with("id", z.id)
+                    .with("status", z.status)
+                    .with("name_servers", z.name_servers))
+                .collect())
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+    use crate::providers::test_server::{Reply, Request, Server};
+
+    const ZONES: &str = include_str!("../../tests/fixtures/cloudflare/zones.json");
+    const DNS_RECORDS: &str = include_str!("../../tests/fixtures/cloudflare/dns_records.json");
+    const ZONE_ID: &str = "023e105f4ecef8ad9ca31a8372d0c353";
+
+    /// The recorded records, filtered by name and type as the API does
+    fn records(request: &Request) -> Reply {
+        let name = request.query("name");
+        let rtype = request.query("type");
+        let mut body: serde_json::Value = serde_json::from_str(DNS_RECORDS).unwrap();
+
+        if let Some(records) = body["result"].as_array_mut() {
+            records.retain(|r| {
+                name.as_ref().is_none_or(|n| n == r["name"].as_str().unwrap_or_default())
+                    && rtype.as_ref().is_none_or(|t| t == r["type"].as_str().unwrap_or_default())
+            });
+        }
+
+        Reply::new(200, &body.to_string())
+    }
+
+    fn api() -> Server {
+        Server::start(|request| match (request.method.as_str(), request.path()) {
+            ("GET", "/zones") => Reply::new(200, ZONES),
+            ("GET", p) if p.ends_with("/dns_records") => records(request),
+            ("POST", _) | ("PUT", _) => Reply::new(200, &format!(r#"{{"success": true, "errors": [], "result": {}}}"#, request.body)),
+            ("DELETE", _) => Reply::new(200, r#"{"success": true, "errors": [], "result": {"id": "deleted"}}"#),
+            _ => Reply::new(404, r#"{"success": false, "errors": [{"code": 7003, "message": "Could not route to /nowhere"}], "result": null}"#),
+        })
+    }
+
+    fn provider(server: &Server) -> Cloudflare {
+        let mut provider = Cloudflare::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("cloudflare"),
+            token: Some(String::from("secret")),
+            api_url: Some(server.url().to_string()),
+            ..Default::default()
+        }]);
+
+        provider
+    }
+
+    fn record(host: &str, rtype: &str, data: &str, ttl: u32) -> Record {
+        Record {
+            host: host.to_string(),
+            ttl,
+            r#type: rtype.to_string(),
+            data: data.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn update(server: &Server, host: &str, rtype: Option<&str>, records: Vec<Record>) -> Vec<Request> {
+        provider(server).update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some(host), rtype),
+            records,
+            wait: false,
+        }).unwrap();
+
+        server.requests().into_iter().filter(|r| r.method != "GET").collect()
+    }
+
+    #[test]
+    fn looks_the_zone_up_by_its_name() {
+        let server = api();
+        let records = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", Some("www"), Some("a")),
+        }).unwrap().unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!((records[0].host.as_str(), records[0].data.as_str(), records[0].proxied), ("www", "192.0.2.1", Some(true)));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path(), "/zones");
+        assert_eq!(requests[0].query("name").as_deref(), Some("example.com"));
+        assert_eq!(requests[0].header("Authorization"), Some("Bearer secret"));
+        assert_eq!(requests[1].path(), format!("/zones/{}/dns_records", ZONE_ID));
+        assert_eq!(requests[1].query("name").as_deref(), Some("www.example.com"));
+        assert_eq!(requests[1].query("type").as_deref(), Some("A"));
+    }
+
+    #[test]
+    fn encodes_the_names_in_the_query() {
+        let server = api();
+        provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", Some("a&type=MX"), None),
+        }).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[1].query("name").as_deref(), Some("a&type=MX.example.com"));
+        assert_eq!(requests[1].query("type"), None);
+    }
+
+    #[test]
+    fn maps_the_priority_and_structured_data() {
+        let server = api();
+        let records = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap().unwrap();
+
+        let mx = records.iter().find(|r| r.r#type == "MX").unwrap();
+        assert_eq!((mx.host.as_str(), mx.data.as_str(), mx.mx_priority), ("@", "mail.example.com", Some(10)));
+
+        let srv = records.iter().find(|r| r.r#type == "SRV").unwrap();
+        assert_eq!((srv.host.as_str(), srv.data.as_str()), ("_sip._tcp", "sip.example.com"));
+        assert_eq!((srv.srv_priority, srv.srv_weight, srv.srv_port), (Some(10), Some(60), Some(5060)));
+    }
+
+    #[test]
+    fn creates_updates_and_keeps_records() {
+        let server = api();
+        let changes = update(&server, "www", None, vec![
+            record("www", "A", "192.0.2.1", 300),
+            record("www", "AAAA", "2001:db8::1", 1),
+            record("www", "TXT", "hello", 300),
+        ]);
+
+        assert_eq!(changes.len(), 2);
+        assert_eq!((changes[0].method.as_str(), changes[0].path()), ("PUT", "/zones/023e105f4ecef8ad9ca31a8372d0c353/dns_records/372e67954025e0ba6aaa6d586b9e0b59"));
+        let updated: serde_json::Value = serde_json::from_str(&changes[0].body).unwrap();
+        // The proxied flag of the existing record is kept
+        assert_eq!((&updated["ttl"], &updated["proxied"]), (&serde_json::json!(300), &serde_json::json!(true)));
+
+        assert_eq!((changes[1].method.as_str(), changes[1].path()), ("POST", "/zones/023e105f4ecef8ad9ca31a8372d0c353/dns_records"));
+        let created: serde_json::Value = serde_json::from_str(&changes[1].body).unwrap();
+        assert_eq!((&created["type"], &created["name"], &created["content"]), (&serde_json::json!("TXT"), &serde_json::json!("www.example.com"), &serde_json::json!("hello")));
+    }
+
+    #[test]
+    fn writes_a_changed_record_over_the_existing_one() {
+        let server = api();
+        let changes = update(&server, "ftp", Some("CNAME"), vec![record("ftp", "CNAME", "mail.example.com", 3600)]);
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].method.as_str(), changes[0].path()), ("PUT", "/zones/023e105f4ecef8ad9ca31a8372d0c353/dns_records/9a7806061c88ada191ed06f989cc3dac"));
+        let updated: serde_json::Value = serde_json::from_str(&changes[0].body).unwrap();
+        assert_eq!(updated["content"], "mail.example.com");
+    }
+
+    #[test]
+    fn deletes_records_before_creating_new_ones() {
+        let server = api();
+        let changes = update(&server, "ftp", None, vec![record("ftp", "A", "192.0.2.5", 3600)]);
+
+        let calls: Vec<(&str, &str)> = changes.iter().map(|r| (r.method.as_str(), r.path())).collect();
+        assert_eq!(calls, vec![
+            ("DELETE", "/zones/023e105f4ecef8ad9ca31a8372d0c353/dns_records/9a7806061c88ada191ed06f989cc3dac"),
+            ("POST", "/zones/023e105f4ecef8ad9ca31a8372d0c353/dns_records"),
+        ]);
+    }
+
+    #[test]
+    fn deletes_the_selected_records() {
+        let server = api();
+        provider(&server).delete(&DeleteRequest {
+            selector: RecordSelector::new("example.com", Some("www"), None),
+            wait: false,
+        }).unwrap();
+
+        let deleted: Vec<String> = server.requests().into_iter().filter(|r| r.method == "DELETE").map(|r| r.path().to_string()).collect();
+        assert_eq!(deleted, vec![
+            "/zones/023e105f4ecef8ad9ca31a8372d0c353/dns_records/372e67954025e0ba6aaa6d586b9e0b59",
+            "/zones/023e105f4ecef8ad9ca31a8372d0c353/dns_records/5f0ae4e8d2f5b61c52b0ce1f0c2f6a48",
+        ]);
+    }
+
+    #[test]
+    fn reports_an_unknown_zone() {
+        let server = Server::start(|_| Reply::new(200, r#"{"success": true, "errors": [], "result": [], "result_info": {"page": 1, "total_pages": 0}}"#));
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.org", None, None),
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DnsApiError));
+        assert!(error.to_string().contains("example.org"));
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_envelope() {
+        let server = Server::start(|_| Reply::new(400, r#"{"success": false, "errors": [{"code": 81057, "message": "Record already exists."}], "result": null}"#));
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DnsApiError));
+        assert!(error.to_string().contains("Record already exists. (code 81057)"));
+
+        let server = Server::start(|_| Reply::new(403, r#"{"success": false, "errors": [{"code": 10000, "message": "Authentication error"}], "result": null}"#));
+        let error = provider(&server).list_zones().unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+        assert!(error.to_string().contains("Authentication error (code 10000)"));
+    }
+
+    #[test]
+    fn reports_an_unsuccessful_answer_as_an_error() {
+        let server = Server::start(|_| Reply::new(200, r#"{"success": false, "errors": [{"code": 1003, "message": "Invalid or missing zone id."}], "result": null}"#));
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DnsApiError));
+        assert!(error.to_string().contains("Invalid or missing zone id."));
+    }
+
+    #[test]
+    fn lists_the_zones_of_the_account() {
+        let server = api();
+        let zones = provider(&server).list_zones().unwrap();
+
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].name, "example.com");
+        assert_eq!(zones[0].metadata["id"], ZONE_ID);
+        assert_eq!(zones[0].metadata["name_servers"][0], "ada.ns.cloudflare.com");
+        assert_eq!(server.requests()[0].query("per_page").as_deref(), Some("50"));
+    }
+}
@@ -0,0 +1,398 @@
+use super::{Provider, http, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, AddRequest, DeleteRequest, Record, Zone, ProviderError, ProviderErrorKind, Result, get_credential, api_url, check_duplicates, list_account_zones, fqdn, relative_host, status_error_kind};
+use super::retry::{Idempotency, SendRetry};
+use crate::config;
+
+use serde::{Serialize, Deserialize};
+
+const API_URL: &str = "https://desec.io/api/v1";
+
+/// A set of records sharing the same name and type. The subname is relative to the domain, with an
+/// empty subname for the apex.
+#[derive(Serialize, Deserialize, Debug)]
+struct RRSet {
+    subname: String,
+    r#type: String,
+    ttl: u32,
+    records: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Domain {
+    name: String,
+    minimum_ttl: Option<u32>,
+    published: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Desec {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+
+impl RRSet {
+    fn is_selected(&self, subname: Option<&str>, rtype: Option<&str>) -> bool {
+        // The name servers of the zone are managed by deSEC
+        if self.subname.is_empty() && self.r#type == "NS" {
+            return false;
+        }
+
+        subname.is_none_or(|s| s.eq_ignore_ascii_case(&self.subname))
+            && rtype.is_none_or(|t| t.eq_ignore_ascii_case(&self.r#type))
+    }
+
+    /// Deleting a RRset in a bulk request is done by sending it without any records
+    fn emptied(self) -> Self {
+        RRSet {
+            records: vec![],
+            ..self
+        }
+    }
+}
+
+
+impl Desec {
+    pub fn new() -> Self {
+        Desec {
+            name: String::from("desec"),
+            credentials: None,
+        }
+    }
+
+    fn get_credential(&self, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<(String, String)> {
+        Desec::api_access(&get_credential(&self.credentials, zone, host, rtype)?)
+    }
+
+    /// Base URL of the API and the token of the credential
+    fn api_access(credential: &config::Credential) -> Result<(String, String)> {
+        let api_url = api_url(credential, API_URL);
+
+        match &credential.token {
+            Some(t) => Ok((api_url, t.clone())),
+            None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+                .msg(String::from("deSEC requires an API token!"))),
+        }
+    }
+
+    /// deSEC names the records relative to the domain, with an empty name for the apex
+    fn subname(zone: &str, host: &str) -> String {
+        match relative_host(zone, &fqdn(zone, Some(host))).as_str() {
+            "@" => String::new(),
+            h => h.to_string(),
+        }
+    }
+
+    /// Turn an error response of the API into a `ProviderError`
+    fn check_response(response: reqwest::blocking::Response, action: &str) -> Result<(Option<String>, String)> {
+        let status = response.status();
+        let next = next_link(&response);
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        if status.is_success() {
+            return Ok((next, text));
+        }
+
+        let kind = status_error_kind(status);
+
+        // Errors are either a detail message or the fields which failed validation
+        let reason = match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(serde_json::Value::Object(o)) if o.contains_key("detail") => o["detail"].as_str().unwrap_or_default().to_string(),
+            Ok(v) => v.to_string(),
+            Err(_) => format!("Received status {}", status),
+        };
+
+        Err(ProviderError::new(kind)
+            .msg(format!("Unable to {}. Reason: {}", action, reason)))
+    }
+
+    /// Fetch the RRsets of the domain matching the subname|type selection, following pagination
+    fn get_rrsets(api_url: &str, token: &str, zone: &str, subname: Option<&str>, rtype: Option<&str>) -> Result<Vec<RRSet>> {
+        let mut url = format!("{}/domains/{}/rrsets/?cursor=", api_url, zone);
+        if let Some(s) = subname {
+            url.push_str(&format!("&subname={}", s));
+        }
+        if let Some(t) = rtype {
+            url.push_str(&format!("&type={}", t.to_uppercase()));
+        }
+
+        let mut rrsets: Vec<RRSet> = Vec::new();
+        let mut next = Some(url);
+
+        while let Some(url) = next {
+            let request = http::client()
+                .get(&url)
+                .header("Authorization", format!("Token {}", token));
+
+            let (link, text) = Desec::check_response(request.send_retrying(Idempotency::Idempotent)?, "get the records of the domain")?;
+            let page: Vec<RRSet> = serde_json::from_str(&text)?;
+
+            rrsets.extend(page);
+            next = link;
+        }
+
+        Ok(rrsets
+            .into_iter()
+            .filter(|r| r.is_selected(subname, rtype))
+            .collect())
+    }
+
+    /// Replace the given RRsets in a single request, leaving all others alone
+    fn put(api_url: &str, token: &str, zone: &str, rrsets: &[RRSet], action: &str) -> Result<()> {
+        let request = http::client()
+            .put(&format!("{}/domains/{}/rrsets/", api_url, zone))
+            .header("Authorization", format!("Token {}", token))
+            .json(rrsets);
+
+        Desec::check_response(request.send_retrying(Idempotency::Idempotent)?, action)?;
+
+        Ok(())
+    }
+}
+
+
+impl Provider for Desec {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn dynamic_dns(&self, _request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(String::from("deSEC can not detect the address of the client, use update with an A/AAAA record instead!")))
+    }
+
+    /// Search the RRsets of the domain, flattened into one record per value
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let subname = host.map(|h| Desec::subname(zone, h));
+        let mut records: Vec<Record> = Vec::new();
+
+        for rrset in Desec::get_rrsets(&api_url, &token, zone, subname.as_deref(), rtype)? {
+            let host = if rrset.subname.is_empty() { "@" } else { &rrset.subname };
+            for content in &rrset.records {
+                records.push(Record::from_content(host, rrset.ttl, &rrset.r#type, content)?);
+            }
+        }
+
+        Ok(Some(records))
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let subname = host.map(|h| Desec::subname(zone, h));
+        let deletions: Vec<RRSet> = Desec::get_rrsets(&api_url, &token, zone, subname.as_deref(), rtype)?
+            .into_iter()
+            .map(|r| r.emptied())
+            .collect();
+
+        if deletions.is_empty() {
+            log::info!("Nothing to delete");
+            return Ok(true);
+        }
+
+        Desec::put(&api_url, &token, zone, &deletions, "delete selected record(s)")?;
+        log::info!("Deleted {} record set(s)", deletions.len());
+
+        Ok(true)
+    }
+
+    /// Replace all records selected by zone|host|type with the given ones. The records are grouped
+    /// into RRsets, and the selected RRsets which are not part of the new records are sent without
+    /// any records so they are deleted, all in a single bulk PUT.
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let records = &request.records;
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let mut rrsets: Vec<RRSet> = Vec::new();
+
+        for record in records {
+            let subname = Desec::subname(zone, &record.host);
+            let rtype = record.r#type.to_uppercase();
+
+            match rrsets.iter_mut().find(|r| r.subname.eq_ignore_ascii_case(&subname) && r.r#type == rtype) {
+                Some(rrset) => {
+                    // A RRset has a single TTL, use the lowest one of its records
+                    rrset.ttl = rrset.ttl.min(record.ttl);
+                    rrset.records.push(record.to_content());
+                },
+                None => rrsets.push(RRSet {
+                    subname,
+                    r#type: rtype,
+                    ttl: record.ttl,
+                    records: vec![record.to_content()],
+                }),
+            }
+        }
+
+        let replaced = rrsets.len();
+        let subname = host.map(|h| Desec::subname(zone, h));
+
+        for existing in Desec::get_rrsets(&api_url, &token, zone, subname.as_deref(), rtype)? {
+            if !rrsets.iter().any(|r| r.subname.eq_ignore_ascii_case(&existing.subname) && r.r#type == existing.r#type) {
+                rrsets.push(existing.emptied());
+            }
+        }
+
+        let removed = rrsets.len() - replaced;
+        Desec::put(&api_url, &token, zone, &rrsets, "update selected record(s)")?;
+
+        log::info!("Updated record(s)!");
+        log::debug!("Replaced {} record set(s). Removed {} record set(s)", replaced, removed);
+
+        Ok(true)
+    }
+
+    /// Add the records to their RRsets, which are sent with their current records plus the new
+    /// ones in a single bulk PUT. The other RRsets of the selection are left alone.
+    fn add(&self, request: &AddRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let (api_url, token) = self.get_credential(zone, request.selector.host(), request.selector.rtype())?;
+
+        let mut rrsets: Vec<RRSet> = Vec::new();
+        let mut current: Vec<Record> = Vec::new();
+
+        for record in &request.records {
+            let subname = Desec::subname(zone, &record.host);
+            let rtype = record.r#type.to_uppercase();
+
+            let index = match rrsets.iter().position(|r| r.subname.eq_ignore_ascii_case(&subname) && r.r#type == rtype) {
+                Some(i) => i,
+                None => {
+                    let existing = Desec::get_rrsets(&api_url, &token, zone, Some(&subname), Some(&rtype))?.pop();
+                    if let Some(e) = &existing {
+                        for content in &e.records {
+                            current.push(Record::from_content(&record.host, e.ttl, &e.r#type, content)?);
+                        }
+                    }
+
+                    rrsets.push(existing.unwrap_or(RRSet { subname, r#type: rtype, ttl: record.ttl, records: vec![] }));
+                    rrsets.len() - 1
+                },
+            };
+
+            let rrset = &mut rrsets[index];
+            // A RRset has a single TTL, use the lowest one of its records
+            rrset.ttl = rrset.ttl.min(record.ttl);
+            rrset.records.push(record.to_content());
+        }
+
+        check_duplicates(zone, &current, &request.records)?;
+        Desec::put(&api_url, &token, zone, &rrsets, "add record(s)")?;
+        log::info!("Added {} record(s)", request.records.len());
+
+        Ok(true)
+    }
+
+    fn list_zones(&self) -> Result<Vec<Zone>> {
+        list_account_zones(&self.credentials, |credential| {
+            let (api_url, token) = Desec::api_access(credential)?;
+
+            let mut zones: Vec<Zone> = Vec::new();
+            let mut next = Some(format!("{}/domains/?cursor=", api_url));
+
+            while let Some(url) = next {
+                let request = http::client()
+                    .get(&url)
+                    .header("Authorization", format!("Token {}", token));
+
+                let (link, text) = Desec::check_response(request.send_retrying(Idempotency::Idempotent)?, "list the domains")?;
+                let page: Vec<Domain> = serde_json::from_str(&text)?;
+
+                zones.extend(page.into_iter().map(|d| Zone::new(&d.name)
+                    .with("minimum_ttl", d.minimum_ttl)
+                    .with("published", d.published)));
+                next = link;
+            }
+
+            Ok(zones)
+        })
+    }
+}
+
+
+/// The URL of the next page, from the Link header of a paginated response
+fn next_link(response: &reqwest::blocking::Response) -> Option<String> {
+    let link = response.headers().get(reqwest::header::LINK)?.to_str().ok()?;
+
+    link.split(',')
+        .find(|l| l.contains("rel=\"next\""))
+        .and_then(|l| l.split(';').next())
+        .map(|url| url.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+    use crate::providers::test_server::{Reply, Server};
+
+    /// A deSEC API with a www A RRset, accepting every change
+    fn api() -> Server {
+        Server::start(|request| match (request.method.as_str(), request.query("subname").as_deref(), request.query("type").as_deref()) {
+            ("GET", Some("www"), Some("A")) => Reply::new(200, r#"[{"subname": "www", "type": "A", "ttl": 3600, "records": ["192.0.2.1"]}]"#),
+            _ => Reply::new(200, "[]"),
+        })
+    }
+
+    fn provider(server: &Server) -> Desec {
+        let mut provider = Desec::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("desec"),
+            token: Some(String::from("secret")),
+            api_url: Some(server.url().to_string()),
+            ..Default::default()
+        }]);
+
+        provider
+    }
+
+    fn a(host: &str, data: &str) -> Record {
+        Record { host: host.to_string(), ttl: 3600, r#type: String::from("A"), data: data.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn adds_to_the_record_sets_of_the_new_records_only() {
+        let server = api();
+        provider(&server).add(&AddRequest {
+            selector: RecordSelector::new("example.com", Some("www"), None),
+            records: vec![a("www", "192.0.2.2"), a("@", "192.0.2.3")],
+            wait: false,
+        }).unwrap();
+
+        let put = server.requests().into_iter().find(|r| r.method == "PUT").unwrap();
+        let rrsets: serde_json::Value = serde_json::from_str(&put.body).unwrap();
+        assert_eq!(rrsets, serde_json::json!([
+            {"subname": "www", "type": "A", "ttl": 3600, "records": ["192.0.2.1", "192.0.2.2"]},
+            {"subname": "", "type": "A", "ttl": 3600, "records": ["192.0.2.3"]},
+        ]));
+    }
+
+    #[test]
+    fn refuses_to_add_records_which_exist() {
+        let server = api();
+        let error = provider(&server).add(&AddRequest {
+            selector: RecordSelector::new("example.com", Some("www"), Some("A")),
+            records: vec![a("www", "192.0.2.1")],
+            wait: false,
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DuplicateRecord));
+        assert!(server.requests().iter().all(|r| r.method == "GET"));
+    }
+}
@@ -0,0 +1,468 @@
+use super::{Provider, http, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, DeleteRequest, Record, Zone, ProviderError, ProviderErrorKind, Result, get_credential, api_url, list_account_zones, fqdn, relative_host, reconcile, status_error_kind};
+use super::retry::{Idempotency, SendRetry};
+use crate::config;
+
+use serde::{Serialize, Deserialize};
+
+const API_URL: &str = "https://api.digitalocean.com/v2";
+
+/// Largest page size allowed by the API
+const PAGE_SIZE: u32 = 200;
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    id: Option<String>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecordList {
+    domain_records: Vec<DomainRecord>,
+    links: Option<Links>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DomainList {
+    domains: Vec<Domain>,
+    links: Option<Links>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Domain {
+    name: String,
+    ttl: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Links {
+    pages: Option<Pages>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Pages {
+    next: Option<String>,
+}
+
+/// A DNS record as represented by the DigitalOcean API
+#[derive(Serialize, Deserialize, Debug)]
+struct DomainRecord {
+    #[serde(skip_serializing)]
+    id: Option<u64>,
+    r#type: String,
+    name: String,
+    data: String,
+    ttl: Option<u32>,
+    priority: Option<u32>,
+    port: Option<u32>,
+    weight: Option<u32>,
+    flags: Option<u32>,
+    tag: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct DigitalOcean {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+
+impl DomainRecord {
+    fn from_record(zone: &str, r: &Record) -> Self {
+        let rtype = r.r#type.to_uppercase();
+
+        // Targets have to be fully-qualified, otherwise they are taken as relative to the domain
+        let data = match rtype.as_str() {
+            "CNAME" | "MX" | "NS" | "SRV" => format!("{}.", r.data.trim_end_matches('.')),
+            _ => r.data.clone(),
+        };
+
+        DomainRecord {
+            id: None,
+            name: relative_host(zone, &fqdn(zone, Some(&r.host))),
+            data,
+            ttl: Some(r.ttl),
+            priority: r.mx_priority.or(r.srv_priority),
+            port: r.srv_port,
+            weight: r.srv_weight,
+            flags: r.caa_flags,
+            tag: r.caa_property.clone(),
+            r#type: rtype,
+        }
+    }
+
+    fn into_record(self) -> Record {
+        let mut record = Record {
+            host: self.name,
+            ttl: self.ttl.unwrap_or(0),
+            data: self.data.trim_end_matches('.').to_string(),
+            ..Default::default()
+        };
+
+        match self.r#type.as_str() {
+            "MX" => record.mx_priority = self.priority,
+            "SRV" => {
+                record.srv_priority = self.priority;
+                record.srv_weight = self.weight;
+                record.srv_port = self.port;
+            },
+            "CAA" => {
+                record.caa_flags = self.flags;
+                record.caa_property = self.tag;
+            },
+            _ => (),
+        }
+
+        record.r#type = self.r#type;
+        record
+    }
+}
+
+
+impl DigitalOcean {
+    pub fn new() -> Self {
+        DigitalOcean {
+            name: String::from("digitalocean"),
+            credentials: None,
+        }
+    }
+
+    fn get_credential(&self, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<(String, String)> {
+        DigitalOcean::api_access(&get_credential(&self.credentials, zone, host, rtype)?)
+    }
+
+    /// Base URL of the API and the token of the credential
+    fn api_access(credential: &config::Credential) -> Result<(String, String)> {
+        let api_url = api_url(credential, API_URL);
+
+        match &credential.token {
+            Some(t) => Ok((api_url, t.clone())),
+            None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+                .msg(String::from("DigitalOcean requires an API token!"))),
+        }
+    }
+
+    /// Send a request to the API and return the body of a successful response
+    fn request(method: reqwest::Method, url: &str, token: &str, body: Option<&DomainRecord>) -> Result<String> {
+        let idempotency = Idempotency::of(&method);
+        let mut request = http::client()
+            .request(method, url)
+            .bearer_auth(token);
+
+        if let Some(b) = body {
+            request = request.json(b);
+        }
+
+        let response = request.send_retrying(idempotency)?;
+        let status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        if status.is_success() {
+            return Ok(text);
+        }
+
+        let kind = status_error_kind(status);
+
+        match serde_json::from_str::<ApiError>(&text) {
+            Ok(ApiError { id, message: Some(m) }) => Err(ProviderError::new(kind)
+                .msg(format!("DigitalOcean API request failed. Reason: {} ({})", m, id.unwrap_or_default()))),
+            _ => Err(ProviderError::new(kind)
+                .msg(format!("DigitalOcean API request failed. Received status {}", status))),
+        }
+    }
+
+    /// Fetch all records (following pagination) which match the zone|host|type selection
+    fn get_records(api_url: &str, token: &str, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<Vec<DomainRecord>> {
+        let mut url = format!("{}/domains/{}/records?per_page={}", api_url, zone, PAGE_SIZE);
+        if let Some(h) = host {
+            url.push_str(&format!("&name={}", fqdn(zone, Some(h))));
+        }
+        if let Some(t) = rtype {
+            url.push_str(&format!("&type={}", t.to_uppercase()));
+        }
+
+        let mut records: Vec<DomainRecord> = Vec::new();
+        let mut next = Some(url);
+
+        while let Some(url) = next {
+            let text = DigitalOcean::request(reqwest::Method::GET, &url, token, None)?;
+            let page: RecordList = serde_json::from_str(&text)?;
+
+            records.extend(page.domain_records);
+            next = page.links.and_then(|l| l.pages).and_then(|p| p.next);
+        }
+
+        Ok(records)
+    }
+}
+
+
+impl Provider for DigitalOcean {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn dynamic_dns(&self, _request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(String::from("DigitalOcean can not detect the address of the client, use update with an A/AAAA record instead!")))
+    }
+
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let records = DigitalOcean::get_records(&api_url, &token, zone, host, rtype)?;
+
+        Ok(Some(records.into_iter().map(|r| r.into_record()).collect()))
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let records = DigitalOcean::get_records(&api_url, &token, zone, host, rtype)?;
+
+        for record in &records {
+            let url = format!("{}/domains/{}/records/{}", api_url, zone, record.id.unwrap_or_default());
+            DigitalOcean::request(reqwest::Method::DELETE, &url, &token, None)?;
+        }
+
+        log::info!("Deleted {} record(s)", records.len());
+
+        Ok(true)
+    }
+
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let records = &request.records;
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let existing = DigitalOcean::get_records(&api_url, &token, zone, host, rtype)?
+            .into_iter()
+            .map(|r| (r.id.unwrap_or_default(), r.into_record()))
+            .collect();
+
+        let changes = reconcile(zone, existing, records);
+        let url = format!("{}/domains/{}/records", api_url, zone);
+
+        for record in &changes.create {
+            DigitalOcean::request(reqwest::Method::POST, &url, &token, Some(&DomainRecord::from_record(zone, record)))?;
+        }
+
+        for (id, record) in &changes.update {
+            DigitalOcean::request(reqwest::Method::PUT, &format!("{}/{}", url, id), &token, Some(&DomainRecord::from_record(zone, record)))?;
+        }
+
+        for id in &changes.delete {
+            DigitalOcean::request(reqwest::Method::DELETE, &format!("{}/{}", url, id), &token, None)?;
+        }
+
+        log::info!("Updated record(s)!");
+        log::debug!("Added {} record(s). Changed {} record(s). Removed {} record(s)", changes.create.len(), changes.update.len(), changes.delete.len());
+
+        Ok(true)
+    }
+
+    fn list_zones(&self) -> Result<Vec<Zone>> {
+        list_account_zones(&self.credentials, |credential| {
+            let (api_url, token) = DigitalOcean::api_access(credential)?;
+
+            let mut zones: Vec<Zone> = Vec::new();
+            let mut next = Some(format!("{}/domains?per_page={}", api_url, PAGE_SIZE));
+
+            while let Some(url) = next {
+                let text = DigitalOcean::request(reqwest::Method::GET, &url, &token, None)?;
+                let page: DomainList = serde_json::from_str(&text)?;
+
+                zones.extend(page.domains.into_iter().map(|d| Zone::new(&d.name).with("ttl", d.ttl)));
+                next = page.links.and_then(|l| l.pages).and_then(|p| p.next);
+            }
+
+            Ok(zones)
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+    use crate::providers::test_server::{Reply, Request, Server};
+
+    const RECORDS_PAGE_1: &str = include_str!("../../tests/fixtures/digitalocean/records_page_1.json");
+    const RECORDS_PAGE_2: &str = include_str!("../../tests/fixtures/digitalocean/records_page_2.json");
+    const DOMAINS: &str = include_str!("../../tests/fixtures/digitalocean/domains.json");
+
+    /// A recorded response, with the records filtered by name and type as the API does and the
+    /// links pointing at the stand-in server, keeping the filters
+    fn recorded(request: &Request, fixture: &str) -> Reply {
+        let name = request.query("name");
+        let rtype = request.query("type");
+        let mut filters = String::new();
+        if let Some(n) = &name {
+            filters.push_str(&format!("name={}&", n));
+        }
+        if let Some(t) = &rtype {
+            filters.push_str(&format!("type={}&", t));
+        }
+
+        let base = format!("http://{}", request.header("Host").unwrap_or_default());
+        let fixture = fixture.replace(API_URL, &base).replace("records?page=", &format!("records?{}page=", filters));
+        let mut body: serde_json::Value = serde_json::from_str(&fixture).unwrap();
+
+        if let Some(records) = body["domain_records"].as_array_mut() {
+            records.retain(|r| {
+                name.as_ref().is_none_or(|n| *n == fqdn("example.com", r["name"].as_str()))
+                    && rtype.as_ref().is_none_or(|t| t == r["type"].as_str().unwrap_or_default())
+            });
+        }
+
+        Reply::new(200, &body.to_string())
+    }
+
+    fn api() -> Server {
+        Server::start(|request| match (request.method.as_str(), request.path()) {
+            ("GET", "/domains") => recorded(request, DOMAINS),
+            ("GET", "/domains/example.com/records") if request.query("page").as_deref() == Some("2") => recorded(request, RECORDS_PAGE_2),
+            ("GET", "/domains/example.com/records") => recorded(request, RECORDS_PAGE_1),
+            ("POST", "/domains/example.com/records") => Reply::new(201, r#"{"domain_record": {}}"#),
+            ("DELETE", _) => Reply::new(204, ""),
+            _ => Reply::new(404, r#"{"id": "not_found", "message": "The resource you were accessing could not be found."}"#),
+        })
+    }
+
+    fn provider(server: &Server) -> DigitalOcean {
+        let mut provider = DigitalOcean::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("digitalocean"),
+            token: Some(String::from("secret")),
+            api_url: Some(server.url().to_string()),
+            ..Default::default()
+        }]);
+
+        provider
+    }
+
+    fn search(provider: &DigitalOcean, host: Option<&str>, rtype: Option<&str>) -> Vec<Record> {
+        provider.search(&SearchRequest {
+            selector: RecordSelector::new("example.com", host, rtype),
+        }).unwrap().unwrap()
+    }
+
+    fn srv(port: u32) -> Record {
+        Record {
+            host: String::from("_sip._tcp"),
+            ttl: 3600,
+            r#type: String::from("SRV"),
+            data: String::from("sip.example.com"),
+            srv_priority: Some(10),
+            srv_weight: Some(60),
+            srv_port: Some(port),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn follows_the_pages_of_records() {
+        let server = api();
+        let records = search(&provider(&server), None, None);
+
+        assert_eq!(records.len(), 5);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].query("per_page").as_deref(), Some("200"));
+        assert_eq!(requests[1].query("page").as_deref(), Some("2"));
+        assert_eq!(requests[1].header("Authorization"), Some("Bearer secret"));
+    }
+
+    #[test]
+    fn maps_the_priority_weight_and_port() {
+        let server = api();
+        let records = search(&provider(&server), None, None);
+
+        let mx = records.iter().find(|r| r.r#type == "MX").unwrap();
+        assert_eq!((mx.host.as_str(), mx.data.as_str(), mx.mx_priority), ("@", "mail.example.com", Some(10)));
+        assert_eq!(mx.srv_priority, None);
+
+        let srv = records.iter().find(|r| r.r#type == "SRV").unwrap();
+        assert_eq!((srv.host.as_str(), srv.data.as_str()), ("_sip._tcp", "sip.example.com"));
+        assert_eq!((srv.srv_priority, srv.srv_weight, srv.srv_port), (Some(10), Some(60), Some(5060)));
+        assert_eq!(srv.mx_priority, None);
+
+        let caa = records.iter().find(|r| r.r#type == "CAA").unwrap();
+        assert_eq!((caa.caa_flags, caa.caa_property.as_deref()), (Some(0), Some("issue")));
+    }
+
+    #[test]
+    fn keeps_the_records_which_did_not_change() {
+        let server = api();
+
+        provider(&server).update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("_sip._tcp"), Some("SRV")),
+            records: vec![srv(5060)],
+            wait: false,
+        }).unwrap();
+
+        let requests = server.requests();
+        assert!(requests.iter().all(|r| r.method == "GET"));
+        assert_eq!(requests[0].query("name").as_deref(), Some("_sip._tcp.example.com"));
+        assert_eq!(requests[0].query("type").as_deref(), Some("SRV"));
+    }
+
+    #[test]
+    fn sends_the_priority_weight_and_port() {
+        let server = api();
+
+        provider(&server).update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("_sip._tcp"), Some("SRV")),
+            records: vec![srv(5061)],
+            wait: false,
+        }).unwrap();
+
+        let changes: Vec<Request> = server.requests().into_iter().filter(|r| r.method != "GET").collect();
+        assert_eq!(changes.len(), 2);
+
+        assert_eq!(changes[0].method, "POST");
+        let created: serde_json::Value = serde_json::from_str(&changes[0].body).unwrap();
+        assert_eq!(created["type"], "SRV");
+        assert_eq!(created["name"], "_sip._tcp");
+        // Targets are sent fully-qualified
+        assert_eq!(created["data"], "sip.example.com.");
+        assert_eq!((&created["priority"], &created["weight"], &created["port"]), (&serde_json::json!(10), &serde_json::json!(60), &serde_json::json!(5061)));
+
+        assert_eq!(changes[1].method, "DELETE");
+        assert_eq!(changes[1].path(), "/domains/example.com/records/3352898");
+    }
+
+    #[test]
+    fn lists_the_domains_of_the_account() {
+        let server = api();
+        let zones = provider(&server).list_zones().unwrap();
+
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].name, "example.com");
+        assert_eq!(zones[0].metadata["ttl"], 1800);
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_api() {
+        let server = Server::start(|_| Reply::new(401, r#"{"id": "Unauthorized", "message": "Unable to authenticate you"}"#));
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+        assert!(error.to_string().contains("Unable to authenticate you"));
+    }
+}
@@ -0,0 +1,536 @@
+use super::{Provider, http, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, AddRequest, DeleteRequest, Record, ProviderError, ProviderErrorKind, Result, get_credential, fqdn};
+use super::retry::{Idempotency, SendRetry};
+use crate::config;
+use crate::state::State;
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long the protocol asks clients to back off after a `911` or `dnserr` response
+const SERVER_ERROR_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// Return codes of the update endpoint
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReturnCode {
+    Good,
+    NoChange,
+    NoHost,
+    BadAuth,
+    BadAgent,
+    NotDonator,
+    NotFqdn,
+    NumHost,
+    Abuse,
+    ServerError,
+    DnsError,
+    Unknown,
+}
+
+impl ReturnCode {
+    fn parse(code: &str) -> Self {
+        match code {
+            "good" => ReturnCode::Good,
+            "nochg" => ReturnCode::NoChange,
+            "nohost" => ReturnCode::NoHost,
+            "badauth" => ReturnCode::BadAuth,
+            "badagent" => ReturnCode::BadAgent,
+            "!donator" => ReturnCode::NotDonator,
+            "notfqdn" => ReturnCode::NotFqdn,
+            "numhost" => ReturnCode::NumHost,
+            "abuse" => ReturnCode::Abuse,
+            "911" => ReturnCode::ServerError,
+            "dnserr" => ReturnCode::DnsError,
+            _ => ReturnCode::Unknown,
+        }
+    }
+
+    /// Codes which are about the account (or the client) rather than the host of the request, so
+    /// the server is not contacted for any host of the account while they hold
+    fn applies_to_the_account(self) -> bool {
+        matches!(self, ReturnCode::BadAuth | ReturnCode::BadAgent | ReturnCode::ServerError)
+    }
+
+    /// Codes after which the protocol forbids contacting the server again without user intervention
+    fn requires_intervention(self) -> bool {
+        matches!(self, ReturnCode::NoHost
+            | ReturnCode::BadAuth
+            | ReturnCode::BadAgent
+            | ReturnCode::NotDonator
+            | ReturnCode::NotFqdn
+            | ReturnCode::NumHost
+            | ReturnCode::Abuse)
+    }
+
+    fn error(self, hostname: &str, response: &str) -> ProviderError {
+        match self {
+            ReturnCode::NoHost => ProviderError::new(ProviderErrorKind::HostNotFound)
+                .msg(format!("Hostname {} does not exist under the account!", hostname)),
+            ReturnCode::BadAuth => ProviderError::new(ProviderErrorKind::AuthenticationFailed),
+            ReturnCode::BadAgent => ProviderError::new(ProviderErrorKind::BadAgent),
+            ReturnCode::NotDonator => ProviderError::new(ProviderErrorKind::NotDonator),
+            ReturnCode::NotFqdn => ProviderError::new(ProviderErrorKind::NotFqdn)
+                .msg(format!("Hostname {} is not a fully-qualified domain name!", hostname)),
+            ReturnCode::NumHost => ProviderError::new(ProviderErrorKind::TooManyHosts),
+            ReturnCode::Abuse => ProviderError::new(ProviderErrorKind::Abuse)
+                .msg(format!("Hostname {} has been blocked for abuse!", hostname)),
+            ReturnCode::ServerError => ProviderError::new(ProviderErrorKind::ServerError),
+            ReturnCode::DnsError => ProviderError::new(ProviderErrorKind::DnsError),
+            _ => ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+                .msg(format!("Received unexpected response: {}", response.trim())),
+        }
+    }
+}
+
+
+/// Generic DynDNS2 (`/nic/update?hostname=&myip=`) provider.
+///
+/// The server is taken from the `api_url` of the credential, which makes this provider usable
+/// against any compatible service (e.g. Dynu, ChangeIP, OVH DynHost). Providers speaking the same
+/// protocol on a well-known server (e.g. No-IP) are built on top of it using `with_server`.
+///
+/// When the server asks not to be contacted again, for a while or until the problem is fixed, the
+/// hold is kept in the state file (when there is one) so later runs keep to it as well. Answers
+/// about the account or the server (`badauth`, `badagent`, `911`) hold every host of the account,
+/// the others only the host of the request.
+#[derive(Debug)]
+pub struct Dyndns2 {
+    name: String,
+    default_api_url: Option<&'static str>,
+    /// Sent instead of the user agent of the shared client
+    user_agent: Option<&'static str>,
+    credentials: Option<config::Credentials>,
+    state: Option<State>,
+    /// What the server asked during this run: not to be contacted again for the account or a host,
+    /// until the problem is fixed or for a while
+    holds: Mutex<Vec<Hold>>,
+}
+
+/// An answer of the server keeping the account, or only the host, from being updated
+#[derive(Debug)]
+struct Hold {
+    host: Option<String>,
+    code: ReturnCode,
+    until: Option<Instant>,
+}
+
+
+impl Dyndns2 {
+    pub fn new() -> Self {
+        Dyndns2 {
+            name: String::from("dyndns2"),
+            default_api_url: None,
+            user_agent: None,
+            credentials: None,
+            state: None,
+            holds: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Dyndns2 provider with a different name and a default server
+    pub fn with_server(name: &str, api_url: &'static str) -> Self {
+        Dyndns2 {
+            name: name.to_string(),
+            default_api_url: Some(api_url),
+            ..Dyndns2::new()
+        }
+    }
+
+    /// Identify to the server with this user agent, whatever the one configured for the shared
+    /// client, for servers which block clients that do not identify themselves as they ask
+    pub fn with_user_agent(self, user_agent: &'static str) -> Self {
+        Dyndns2 {
+            user_agent: Some(user_agent),
+            ..self
+        }
+    }
+
+    /// Build the update endpoint from the base URL of the server
+    fn build_api_endpoint(api_url: &str) -> String {
+        let api_url = api_url.trim_end_matches('/');
+
+        if api_url.ends_with("/nic/update") {
+            return api_url.to_string();
+        }
+
+        format!("{}/nic/update", api_url)
+    }
+
+    /// Enforce the protocol rules before contacting the server again, as told by the earlier
+    /// answers of this run and by the holds of the state file
+    fn check_allowed(&self, credential: &config::Credential, hostname: &str) -> Result<()> {
+        {
+            let mut holds = self.holds.lock().expect("Lock is never poisoned");
+            let now = Instant::now();
+            holds.retain(|h| h.until.is_none_or(|u| now < u));
+
+            let hold = holds.iter().find(|h| h.host.as_deref().is_none_or(|host| host.eq_ignore_ascii_case(hostname)));
+            if let Some(hold) = hold {
+                return Err(match hold.until {
+                    Some(until) => Dyndns2::backing_off((until - now).as_secs()),
+                    None => Dyndns2::halted(hold.code, hostname),
+                });
+            }
+        }
+
+        let hold = match &self.state {
+            Some(state) => state.hold(&self.name, &credential.user, hostname).unwrap_or_else(|e| {
+                log::warn!("Unable to read the state file {}: {}", state.path().display(), e);
+                None
+            }),
+            None => None,
+        };
+
+        match hold {
+            Some(hold) => match hold.until {
+                Some(until) => {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+                    Err(Dyndns2::backing_off(until.saturating_sub(now)))
+                },
+                None => Err(Dyndns2::halted(ReturnCode::parse(&hold.response), hostname)),
+            },
+            None => Ok(()),
+        }
+    }
+
+    fn halted(code: ReturnCode, hostname: &str) -> ProviderError {
+        let err = code.error(hostname, "");
+        let message = format!("{} Refusing to contact the server again until the problem is fixed and the state is cleared (state clear).", err);
+        err.msg(message)
+    }
+
+    fn backing_off(seconds: u64) -> ProviderError {
+        ProviderError::new(ProviderErrorKind::ServerError)
+            .msg(format!("Server asked to back off, retry in {} seconds.", seconds))
+    }
+
+    /// Keep to the server asking not to be contacted again for the account or the host, for a
+    /// while or until the problem is fixed, in this run and the following ones
+    fn hold(&self, credential: &config::Credential, hostname: &str, answer: &str, duration: Option<Duration>) {
+        let code = ReturnCode::parse(answer);
+        let host = if code.applies_to_the_account() { None } else { Some(hostname) };
+
+        self.holds.lock().expect("Lock is never poisoned").push(Hold {
+            host: host.map(str::to_string),
+            code,
+            until: duration.map(|d| Instant::now() + d),
+        });
+
+        if let Some(state) = &self.state {
+            if let Err(e) = state.put_on_hold(&self.name, &credential.user, host, answer, duration) {
+                log::warn!("Unable to update the state file {}: {}", state.path().display(), e);
+            }
+        }
+    }
+
+    /// Send an update request for a single host and return the response, with the address the
+    /// server reports.
+    ///
+    /// When `myip` is not provided the server will use the source address of the request.
+    fn send_update(&self, endpoint: &str, credential: &config::Credential, hostname: &str, myip: Option<&str>) -> Result<DynamicDnsResponse> {
+        self.check_allowed(credential, hostname)?;
+
+        let mut query = vec![("hostname", hostname)];
+        if let Some(ip) = myip {
+            query.push(("myip", ip));
+        }
+
+        let mut request = http::client()
+            .get(endpoint)
+            .basic_auth(&credential.user, Some(&credential.pass))
+            .query(&query);
+        if let Some(user_agent) = self.user_agent {
+            request = request.header(reqwest::header::USER_AGENT, user_agent);
+        }
+
+        let response = request.send_retrying(Idempotency::Idempotent)?;
+
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        // The server answers with one line per hostname. We always update a single host.
+        let line = text.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+        let mut parts = line.split_whitespace();
+        let answer = parts.next().unwrap_or("");
+        let code = ReturnCode::parse(answer);
+        let address = parts.next().unwrap_or("");
+
+        match code {
+            ReturnCode::Good | ReturnCode::NoChange => {
+                if code == ReturnCode::Good {
+                    log::info!("Updated {} to {}", hostname, address);
+                } else {
+                    log::info!("No change, {} is already set to {}", hostname, address);
+                }
+                Ok(DynamicDnsResponse { address: address.parse().ok(), message: line.trim().to_string() })
+            },
+            ReturnCode::ServerError | ReturnCode::DnsError => {
+                self.hold(credential, hostname, answer, Some(SERVER_ERROR_BACKOFF));
+                Err(code.error(hostname, &text))
+            },
+            _ => {
+                if code.requires_intervention() {
+                    self.hold(credential, hostname, answer, None);
+                }
+                Err(code.error(hostname, &text))
+            },
+        }
+    }
+}
+
+
+impl Provider for Dyndns2 {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn set_state(&mut self, state: State) {
+        self.state = Some(state);
+    }
+
+    /// Hosts are identified by their fully-qualified name. Either pass the FQDN as the zone
+    /// (e.g. `ddns myhost.ddns.net`) or split it into zone and host (e.g. `ddns ddns.net myhost`).
+    /// The address of the request is sent as `myip`, when given.
+    fn dynamic_dns(&self, request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let hostname = fqdn(zone, host);
+
+        let credentials = get_credential(&self.credentials, zone, host, None)?;
+        let endpoint = match credentials.api_url.as_deref().or(self.default_api_url) {
+            Some(url) => Dyndns2::build_api_endpoint(url),
+            None => return Err(ProviderError::new(ProviderErrorKind::InvalidInput)
+                .msg(format!("The {} provider requires the api_url of the server!", self.name))),
+        };
+
+        let myip = request.address.map(|a| a.to_string());
+        self.send_update(&endpoint, &credentials, &hostname, myip.as_deref())
+    }
+
+    fn search(&self, _request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(format!("The {} provider does not support searching DNS records!", self.name)))
+    }
+
+    fn update(&self, _request: &UpdateRequest) -> Result<bool> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(format!("The {} provider only supports updating records through DDNS!", self.name)))
+    }
+
+    fn add(&self, _request: &AddRequest) -> Result<bool> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(format!("The {} provider only supports updating records through DDNS!", self.name)))
+    }
+
+    fn delete(&self, _request: &DeleteRequest) -> Result<bool> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(format!("The {} provider does not support deleting DNS records!", self.name)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+    use crate::providers::test_server::{Reply, Server};
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn provider(server: &Server, state: Option<&PathBuf>) -> Dyndns2 {
+        let mut provider = Dyndns2::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("dyndns2"),
+            user: String::from("user"),
+            pass: String::from("secret"),
+            api_url: Some(server.url().to_string()),
+            ..Default::default()
+        }]);
+        if let Some(path) = state {
+            provider.set_state(State::new(path));
+        }
+
+        provider
+    }
+
+    fn ddns(provider: &Dyndns2, address: Option<&str>) -> Result<DynamicDnsResponse> {
+        ddns_host(provider, "home", address)
+    }
+
+    fn ddns_host(provider: &Dyndns2, host: &str, address: Option<&str>) -> Result<DynamicDnsResponse> {
+        provider.dynamic_dns(&DynamicDnsRequest {
+            selector: RecordSelector::new("example.com", Some(host), None),
+            address: address.map(|a| a.parse().unwrap()),
+        })
+    }
+
+    fn state_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ddnsclient-dyndns2-{}-{}.json", process::id(), name));
+        remove(&path);
+        path
+    }
+
+    fn remove(path: &PathBuf) {
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(path.with_extension("json.lock"));
+    }
+
+    #[test]
+    fn sends_the_address_as_myip() {
+        let server = Server::start(|_| Reply::new(200, "good 2001:db8::1"));
+        let provider = provider(&server, None);
+
+        let response = ddns(&provider, Some("2001:db8::1")).unwrap();
+        assert_eq!(response.address, Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(response.message, "good 2001:db8::1");
+        ddns(&provider, None).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path(), "/nic/update");
+        assert_eq!(requests[0].query("hostname").as_deref(), Some("home.example.com"));
+        assert_eq!(requests[0].query("myip").as_deref(), Some("2001:db8::1"));
+        assert_eq!(requests[1].query("myip"), None);
+    }
+
+    #[test]
+    fn reads_the_return_codes() {
+        assert_eq!(ReturnCode::parse("good"), ReturnCode::Good);
+        assert_eq!(ReturnCode::parse("nochg"), ReturnCode::NoChange);
+        assert_eq!(ReturnCode::parse("!donator"), ReturnCode::NotDonator);
+        assert_eq!(ReturnCode::parse("911"), ReturnCode::ServerError);
+        assert_eq!(ReturnCode::parse("<html>"), ReturnCode::Unknown);
+    }
+
+    #[test]
+    fn halts_only_on_the_codes_requiring_intervention() {
+        assert!(ReturnCode::NoHost.requires_intervention());
+        assert!(ReturnCode::BadAuth.requires_intervention());
+        assert!(ReturnCode::Abuse.requires_intervention());
+        assert!(!ReturnCode::ServerError.requires_intervention());
+        assert!(!ReturnCode::DnsError.requires_intervention());
+        assert!(!ReturnCode::Unknown.requires_intervention());
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_server() {
+        let kind = |text: &str| ReturnCode::parse(text).error("myhost.ddns.net", text).kind;
+
+        assert!(matches!(kind("nohost"), ProviderErrorKind::HostNotFound));
+        assert!(matches!(kind("badauth"), ProviderErrorKind::AuthenticationFailed));
+        assert!(matches!(kind("badagent"), ProviderErrorKind::BadAgent));
+        assert!(matches!(kind("!donator"), ProviderErrorKind::NotDonator));
+        assert!(matches!(kind("abuse"), ProviderErrorKind::Abuse));
+        assert!(matches!(kind("911"), ProviderErrorKind::ServerError));
+        assert!(matches!(kind("<html>"), ProviderErrorKind::UnexpectedResponse));
+        assert!(ReturnCode::NoHost.error("myhost.ddns.net", "nohost").to_string().contains("myhost.ddns.net"));
+    }
+
+    #[test]
+    fn keeps_to_a_halt_in_the_following_runs() {
+        let path = state_path("halt");
+        let server = Server::start(|_| Reply::new(200, "badagent"));
+
+        let error = ddns(&provider(&server, Some(&path)), None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::BadAgent));
+
+        // A later run, e.g. from cron
+        let error = ddns(&provider(&server, Some(&path)), None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::BadAgent));
+        assert!(error.to_string().contains("Refusing to contact the server again"));
+        assert_eq!(server.requests().len(), 1);
+
+        State::new(&path).clear(None, None, None).unwrap();
+        let _ = ddns(&provider(&server, Some(&path)), None);
+        assert_eq!(server.requests().len(), 2);
+
+        remove(&path);
+    }
+
+    #[test]
+    fn keeps_backing_off_in_the_following_runs() {
+        let path = state_path("backoff");
+        let server = Server::start(|_| Reply::new(200, "dnserr"));
+
+        let error = ddns(&provider(&server, Some(&path)), None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::DnsError));
+
+        let error = ddns(&provider(&server, Some(&path)), None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::ServerError));
+        assert!(error.to_string().contains("back off"));
+        assert_eq!(server.requests().len(), 1);
+
+        let hold = State::new(&path).hold("dyndns2", "user", "home.example.com").unwrap().unwrap();
+        assert_eq!(hold.response, "dnserr");
+        assert_eq!(hold.host.as_deref(), Some("home.example.com"));
+        assert!(hold.until.is_some());
+
+        remove(&path);
+    }
+
+    #[test]
+    fn holds_only_the_host_the_answer_is_about() {
+        let path = state_path("host");
+        let server = Server::start(|request| match request.query("hostname").as_deref() {
+            Some("home.example.com") => Reply::new(200, "nohost"),
+            _ => Reply::new(200, "good 192.0.2.1"),
+        });
+        let provider = provider(&server, Some(&path));
+
+        let error = ddns_host(&provider, "home", None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::HostNotFound));
+        ddns_host(&provider, "office", None).unwrap();
+
+        let error = ddns_host(&provider, "home", None).unwrap_err();
+        assert!(error.to_string().contains("Refusing to contact the server again"));
+        assert_eq!(server.requests().len(), 2);
+
+        // A later run keeps to the hold of the host only
+        let provider = self::provider(&server, Some(&path));
+        assert!(ddns_host(&provider, "home", None).is_err());
+        ddns_host(&provider, "office", None).unwrap();
+        assert_eq!(server.requests().len(), 3);
+
+        remove(&path);
+    }
+
+    #[test]
+    fn holds_every_host_of_the_account_after_badauth() {
+        let path = state_path("account");
+        let server = Server::start(|_| Reply::new(200, "badauth"));
+
+        let error = ddns_host(&provider(&server, Some(&path)), "home", None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+
+        let error = ddns_host(&provider(&server, Some(&path)), "office", None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+        assert!(error.to_string().contains("Refusing to contact the server again"));
+        assert_eq!(server.requests().len(), 1);
+
+        let hold = State::new(&path).hold("dyndns2", "user", "office.example.com").unwrap().unwrap();
+        assert_eq!(hold.host, None);
+
+        remove(&path);
+    }
+
+    #[test]
+    fn requires_the_server() {
+        let mut provider = Dyndns2::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("dyndns2"),
+            user: String::from("user"),
+            pass: String::from("secret"),
+            api_url: None,
+            ..Default::default()
+        }]);
+
+        let error = ddns(&provider, None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::InvalidInput));
+        assert!(error.to_string().contains("api_url"));
+    }
+}
@@ -0,0 +1,523 @@
+//! Provider backed by an external program.
+//!
+//! The program named by the `command` of the credential (with its `args`) is run once per
+//! operation and talks JSON over its standard input and output, one message per line:
+//!
+//! 1. the client sends `{"type":"hello","version":1,"client":"ddnsclient x.y.z"}`
+//! 2. the program answers `{"type":"hello","version":1,"capabilities":["search","update",...]}`
+//! 3. the client sends the request, e.g. `{"type":"request","operation":"update","zone":...,
+//!    "host":...,"rtype":...,"credential":{...},"records":[...]}`. Requests to list the zones
+//!    (`list_zones`) come without a zone.
+//! 4. the program answers `{"type":"result","records":[...]}` for a search,
+//!    `{"type":"result","zones":[{"name":...}]}` for a zone listing or
+//!    `{"type":"result","changed":true}` otherwise, or reports a failure with
+//!    `{"type":"error","kind":"authentication_failed","message":"..."}`
+//!
+//! Before answering, the program may send `{"type":"log","level":"info","message":"..."}` messages,
+//! which are logged by the client. Anything written to standard error is passed through.
+//!
+//! The program has the `timeout` of the credential (60 seconds by default) to answer and exit, and
+//! is killed once it runs out of time or the operation fails.
+use super::{Provider, RecordSelector, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, DeleteRequest, Record, Zone, ProviderError, ProviderErrorKind, Result, get_credential, list_account_zones};
+use crate::config;
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+/// Version of the protocol spoken with the program
+const PROTOCOL_VERSION: u32 = 1;
+
+/// Time the program has to answer and exit, unless the credential sets its own
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How often to check whether the program exited, once it has answered
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Operation {
+    Search,
+    Update,
+    Delete,
+    DynamicDns,
+    ListZones,
+}
+
+impl Operation {
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Search => "search",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+            Operation::DynamicDns => "dynamic_dns",
+            Operation::ListZones => "list_zones",
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage<'a> {
+    Hello {
+        version: u32,
+        client: String,
+    },
+    Request {
+        operation: Operation,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        zone: Option<&'a str>,
+        host: Option<&'a str>,
+        rtype: Option<&'a str>,
+        credential: &'a config::Credential,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        records: Option<&'a [Record]>,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PluginMessage {
+    Hello {
+        version: u32,
+        #[serde(default)]
+        capabilities: Vec<Operation>,
+    },
+    Log {
+        level: Option<String>,
+        message: String,
+    },
+    Result {
+        records: Option<Vec<Record>>,
+        changed: Option<bool>,
+        zones: Option<Vec<Zone>>,
+    },
+    Error {
+        kind: Option<String>,
+        message: Option<String>,
+    },
+}
+
+#[derive(Debug)]
+pub struct Exec {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+/// What the program answered to a request
+struct Answer {
+    records: Option<Vec<Record>>,
+    changed: bool,
+    zones: Option<Vec<Zone>>,
+}
+
+/// A running program. It is killed when dropped before it exited, so it never outlives the
+/// operation nor is left as a zombie.
+struct Plugin {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    /// Lines of the standard output, read on their own thread so waiting for them can time out
+    lines: Receiver<io::Result<String>>,
+    command: String,
+    timeout: Duration,
+    deadline: Instant,
+    exited: bool,
+}
+
+
+impl Plugin {
+    fn start(credential: &config::Credential) -> Result<Self> {
+        let command = match &credential.command {
+            Some(c) => c.clone(),
+            None => return Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+                .msg(String::from("The exec provider requires the command of the program to run!"))),
+        };
+
+        let mut child = Command::new(&command)
+            .args(&credential.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| {
+                let message = format!("Unable to run {}: {}", command, e);
+                ProviderError::new(ProviderErrorKind::IoError(e)).msg(message)
+            })?;
+
+        log::debug!("Started {} (pid {})", command, child.id());
+
+        let stdin = child.stdin.take().expect("Standard input of the program is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("Standard output of the program is piped"));
+        let (sender, lines) = mpsc::channel();
+
+        thread::spawn(move || {
+            for line in stdout.lines() {
+                let failed = line.is_err();
+                if sender.send(line).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let timeout = credential.timeout.map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+
+        Ok(Plugin {
+            child,
+            stdin: Some(stdin),
+            lines,
+            command,
+            timeout,
+            deadline: Instant::now() + timeout,
+            exited: false,
+        })
+    }
+
+    fn send(&mut self, message: &ClientMessage) -> Result<()> {
+        let line = serde_json::to_string(message)?;
+        log::trace!("Sending to {}: {}", self.command, line);
+
+        let stdin = self.stdin.as_mut().expect("Input of the program is only closed when finishing");
+        writeln!(stdin, "{}", line)?;
+        stdin.flush()?;
+
+        Ok(())
+    }
+
+    /// Read the next message from the program, logging any log messages on the way
+    fn receive(&mut self) -> Result<PluginMessage> {
+        loop {
+            let line = match self.lines.recv_timeout(self.deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) => line?,
+                Err(RecvTimeoutError::Timeout) => return Err(self.kill()),
+                Err(RecvTimeoutError::Disconnected) => {
+                    let status = self.wait()?;
+                    return Err(ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+                        .msg(format!("{} exited ({}) without answering!", self.command, status)));
+                },
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            log::trace!("Received from {}: {}", self.command, line.trim());
+            let message: PluginMessage = serde_json::from_str(&line).map_err(|e| ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+                .msg(format!("{} sent an invalid message: {}", self.command, e)))?;
+
+            match message {
+                PluginMessage::Log { level, message } => match level.as_deref().unwrap_or("info") {
+                    "error" => log::error!("{}: {}", self.command, message),
+                    "warn" => log::warn!("{}: {}", self.command, message),
+                    "debug" => log::debug!("{}: {}", self.command, message),
+                    "trace" => log::trace!("{}: {}", self.command, message),
+                    _ => log::info!("{}: {}", self.command, message),
+                },
+                m => return Ok(m),
+            }
+        }
+    }
+
+    /// Agree on the version of the protocol and learn what the program can do
+    fn handshake(&mut self) -> Result<Vec<Operation>> {
+        self.send(&ClientMessage::Hello {
+            version: PROTOCOL_VERSION,
+            client: format!("ddnsclient {}", clap::crate_version!()),
+        })?;
+
+        match self.receive()? {
+            PluginMessage::Hello { version, capabilities } if version == PROTOCOL_VERSION => Ok(capabilities),
+            PluginMessage::Hello { version, .. } => Err(ProviderError::new(ProviderErrorKind::Unsupported)
+                .msg(format!("{} speaks version {} of the protocol, version {} is required!", self.command, version, PROTOCOL_VERSION))),
+            PluginMessage::Error { kind, message } => Err(plugin_error(kind, message)),
+            _ => Err(ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+                .msg(format!("{} did not answer the handshake!", self.command))),
+        }
+    }
+
+    /// Close the input of the program and wait for it to exit
+    fn finish(mut self) -> Result<()> {
+        drop(self.stdin.take());
+
+        let status = self.wait()?;
+        if !status.success() {
+            log::warn!("{} exited with {}", self.command, status);
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the program to exit, until it runs out of time
+    fn wait(&mut self) -> Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.child.try_wait()? {
+                self.exited = true;
+                return Ok(status);
+            }
+
+            if Instant::now() >= self.deadline {
+                return Err(self.kill());
+            }
+
+            thread::sleep(EXIT_POLL_INTERVAL);
+        }
+    }
+
+    /// Kill the program which ran out of time, and return the error to report
+    fn kill(&mut self) -> ProviderError {
+        log::debug!("Killing {} (pid {})", self.command, self.child.id());
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        self.exited = true;
+
+        let message = format!("{} did not finish within {} seconds!", self.command, self.timeout.as_secs());
+        ProviderError::new(ProviderErrorKind::IoError(io::Error::new(io::ErrorKind::TimedOut, message.clone()))).msg(message)
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        if !self.exited {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+
+impl Exec {
+    pub fn new() -> Self {
+        Exec {
+            name: String::from("exec"),
+            credentials: None,
+        }
+    }
+
+    /// Run the program for a single operation on the selected records and return its answer
+    fn run(&self, operation: Operation, selector: &RecordSelector, records: Option<&[Record]>) -> Result<Answer> {
+        let credential = get_credential(&self.credentials, selector.zone(), selector.host(), selector.rtype())?;
+
+        Exec::call(&credential, operation, Some(selector), records)
+    }
+
+    /// Run the program of the credential for a single operation and return its answer
+    fn call(credential: &config::Credential, operation: Operation, selector: Option<&RecordSelector>, records: Option<&[Record]>) -> Result<Answer> {
+        let mut plugin = Plugin::start(credential)?;
+        let capabilities = plugin.handshake()?;
+
+        if !capabilities.contains(&operation) {
+            let message = format!("{} does not support the {} operation!", plugin.command, operation.name());
+            plugin.finish()?;
+            return Err(ProviderError::new(ProviderErrorKind::Unsupported).msg(message));
+        }
+
+        plugin.send(&ClientMessage::Request {
+            operation,
+            zone: selector.map(|s| s.zone()),
+            host: selector.and_then(|s| s.host()),
+            rtype: selector.and_then(|s| s.rtype()),
+            credential,
+            records,
+        })?;
+
+        let result = match plugin.receive()? {
+            PluginMessage::Result { records, changed, zones } => Ok(Answer { records, changed: changed.unwrap_or(true), zones }),
+            PluginMessage::Error { kind, message } => Err(plugin_error(kind, message)),
+            _ => Err(ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+                .msg(format!("{} did not answer the request!", plugin.command))),
+        };
+
+        plugin.finish()?;
+
+        result
+    }
+}
+
+
+impl Provider for Exec {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn dynamic_dns(&self, request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        let answer = self.run(Operation::DynamicDns, &request.selector, None)?;
+        let message = String::from(if answer.changed { "Updated record(s)!" } else { "Record(s) already up to date" });
+        log::info!("{}", message);
+
+        // The program is not told the address, nor does it tell which one it published
+        Ok(DynamicDnsResponse { address: None, message })
+    }
+
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let answer = self.run(Operation::Search, &request.selector, None)?;
+
+        Ok(answer.records)
+    }
+
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let answer = self.run(Operation::Update, &request.selector, Some(&request.records))?;
+        log::info!("{}", if answer.changed { "Updated record(s)!" } else { "Record(s) already up to date" });
+
+        Ok(true)
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        self.run(Operation::Delete, &request.selector, None)?;
+        log::info!("Deleted record(s)");
+
+        Ok(true)
+    }
+
+    fn list_zones(&self) -> Result<Vec<Zone>> {
+        list_account_zones(&self.credentials, |credential| {
+            let answer = Exec::call(credential, Operation::ListZones, None, None)?;
+
+            // Programs may answer with fully-qualified names
+            Ok(answer.zones
+                .unwrap_or_default()
+                .into_iter()
+                .map(|z| Zone { name: z.name.trim_end_matches('.').to_string(), ..z })
+                .collect())
+        })
+    }
+}
+
+
+/// Map an error reported by the program onto a `ProviderError`
+fn plugin_error(kind: Option<String>, message: Option<String>) -> ProviderError {
+    let kind = match kind.as_deref().unwrap_or_default() {
+        "credential_not_found" => ProviderErrorKind::CredentialNotFound,
+        "dns_api_error" => ProviderErrorKind::DnsApiError,
+        "invalid_record" => ProviderErrorKind::InvalidRecord,
+        "invalid_input" => ProviderErrorKind::InvalidInput,
+        "unsupported" => ProviderErrorKind::Unsupported,
+        "authentication_failed" => ProviderErrorKind::AuthenticationFailed,
+        "host_not_found" => ProviderErrorKind::HostNotFound,
+        "not_fqdn" => ProviderErrorKind::NotFqdn,
+        "too_many_hosts" => ProviderErrorKind::TooManyHosts,
+        "bad_agent" => ProviderErrorKind::BadAgent,
+        "not_donator" => ProviderErrorKind::NotDonator,
+        "abuse" => ProviderErrorKind::Abuse,
+        "server_error" => ProviderErrorKind::ServerError,
+        "dns_error" => ProviderErrorKind::DnsError,
+        "rate_limited" => ProviderErrorKind::RateLimited,
+        _ => ProviderErrorKind::UnexpectedResponse,
+    };
+
+    match message {
+        Some(m) => ProviderError::new(kind).msg(m),
+        None => ProviderError::new(kind),
+    }
+}
+
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    const HELLO: &str = r#"{"type":"hello","version":1,"capabilities":["search","update","delete"]}"#;
+
+    /// A credential running the shell script as the program
+    fn script(script: &str, timeout: Option<u64>) -> config::Credential {
+        config::Credential {
+            provider: String::from("exec"),
+            command: Some(String::from("sh")),
+            args: vec![String::from("-c"), script.to_string()],
+            timeout,
+            ..Default::default()
+        }
+    }
+
+    fn provider(credential: config::Credential) -> Exec {
+        let mut provider = Exec::new();
+        provider.set_credentials(vec![credential]);
+
+        provider
+    }
+
+    fn search(provider: &Exec) -> Result<Option<Vec<Record>>> {
+        provider.search(&SearchRequest {
+            selector: RecordSelector::new("example.com", Some("www"), None),
+        })
+    }
+
+    /// Whether the process is gone, and not left as a zombie either
+    fn is_gone(pid: &str) -> bool {
+        !std::path::Path::new(&format!("/proc/{}", pid)).exists()
+    }
+
+    #[test]
+    fn returns_the_records_of_the_program() {
+        let credential = script(&format!(r#"read hello; echo '{}'; read request; echo '{{"type":"log","message":"searching"}}'; echo '{{"type":"result","records":[{{"host":"www","ttl":300,"type":"A","data":"192.0.2.1"}}]}}'"#, HELLO), None);
+
+        let records = search(&provider(credential)).unwrap().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data, "192.0.2.1");
+    }
+
+    #[test]
+    fn maps_the_errors_of_the_program() {
+        let credential = script(&format!(r#"read hello; echo '{}'; read request; echo '{{"type":"error","kind":"authentication_failed","message":"Invalid API key"}}'"#, HELLO), None);
+
+        let error = search(&provider(credential)).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+        assert!(error.to_string().contains("Invalid API key"));
+    }
+
+    #[test]
+    fn refuses_operations_the_program_does_not_support() {
+        let credential = script(&format!(r#"read hello; echo '{}'; read nothing"#, HELLO), None);
+
+        let error = provider(credential).list_zones().unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn kills_a_program_which_does_not_answer() {
+        let pid_file = std::env::temp_dir().join(format!("ddnsclient-exec-test-{}", std::process::id()));
+        let credential = script(&format!("echo $$ > {}; exec sleep 30", pid_file.display()), Some(1));
+
+        let started = Instant::now();
+        let error = search(&provider(credential)).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(matches!(error.kind(), ProviderErrorKind::IoError(e) if e.kind() == io::ErrorKind::TimedOut));
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let _ = std::fs::remove_file(&pid_file);
+        assert!(is_gone(pid.trim()));
+    }
+
+    #[test]
+    fn kills_a_program_which_does_not_exit() {
+        let credential = script(&format!(r#"trap '' TERM; read hello; echo '{}'; read request; echo '{{"type":"result","changed":true}}'; exec sleep 30"#, HELLO), Some(1));
+
+        let started = Instant::now();
+        let error = provider(credential).delete(&DeleteRequest {
+            selector: RecordSelector::new("example.com", Some("www"), None),
+            wait: false,
+        }).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(error.to_string().contains("did not finish within 1 seconds"));
+    }
+
+    #[test]
+    fn kills_the_program_when_the_answer_is_invalid() {
+        let pid_file = std::env::temp_dir().join(format!("ddnsclient-exec-invalid-{}", std::process::id()));
+        let credential = script(&format!("echo $$ > {}; echo 'not json'; exec sleep 30", pid_file.display()), None);
+
+        let started = Instant::now();
+        let error = search(&provider(credential)).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(matches!(error.kind(), ProviderErrorKind::UnexpectedResponse));
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let _ = std::fs::remove_file(&pid_file);
+        assert!(is_gone(pid.trim()));
+    }
+}
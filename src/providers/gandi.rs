@@ -0,0 +1,413 @@
+use super::{Provider, http, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, AddRequest, DeleteRequest, Record, ProviderError, ProviderErrorKind, Result, get_credential, api_url, check_duplicates, list_account_zones, fqdn, relative_host, status_error_kind};
+use super::retry::{Idempotency, SendRetry};
+use crate::config;
+
+use serde::{Serialize, Deserialize};
+
+const API_URL: &str = "https://api.gandi.net/v5/livedns";
+
+/// Page size used when listing the domains
+const PAGE_SIZE: usize = 100;
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    message: Option<String>,
+    #[serde(default)]
+    errors: Vec<ErrorDetail>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorDetail {
+    name: Option<String>,
+    description: String,
+}
+
+/// A set of records sharing the same name and type. Names are relative to the domain, with @ for
+/// the apex.
+#[derive(Serialize, Deserialize, Debug)]
+struct RRSet {
+    rrset_name: String,
+    rrset_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rrset_ttl: Option<u32>,
+    rrset_values: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Domain {
+    fqdn: String,
+    automatic_snapshots: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct Zone<'a> {
+    items: Vec<&'a RRSet>,
+}
+
+#[derive(Debug)]
+pub struct Gandi {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+
+impl RRSet {
+    fn is_selected(&self, name: Option<&str>, rtype: Option<&str>) -> bool {
+        // Never touch the records which keep the zone working
+        if self.rrset_type == "SOA" || (self.rrset_name == "@" && self.rrset_type == "NS") {
+            return false;
+        }
+
+        name.is_none_or(|n| n.eq_ignore_ascii_case(&self.rrset_name))
+            && rtype.is_none_or(|t| t.eq_ignore_ascii_case(&self.rrset_type))
+    }
+}
+
+
+impl Gandi {
+    pub fn new() -> Self {
+        Gandi {
+            name: String::from("gandi"),
+            credentials: None,
+        }
+    }
+
+    fn get_credential(&self, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<(String, String)> {
+        Gandi::api_access(&get_credential(&self.credentials, zone, host, rtype)?)
+    }
+
+    /// Base URL of the API and the token of the credential
+    fn api_access(credential: &config::Credential) -> Result<(String, String)> {
+        let api_url = api_url(credential, API_URL);
+
+        match &credential.token {
+            Some(t) => Ok((api_url, t.clone())),
+            None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+                .msg(String::from("Gandi requires a personal access token!"))),
+        }
+    }
+
+    /// Turn an error response of the API into a `ProviderError`
+    fn check_response(response: reqwest::blocking::Response, action: &str) -> Result<String> {
+        let status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        if status.is_success() {
+            return Ok(text);
+        }
+
+        let kind = status_error_kind(status);
+
+        match serde_json::from_str::<ApiError>(&text) {
+            Ok(e) if !e.errors.is_empty() => {
+                let reasons: Vec<String> = e.errors
+                    .into_iter()
+                    .map(|e| match e.name {
+                        Some(n) => format!("{}: {}", n, e.description),
+                        None => e.description,
+                    })
+                    .collect();
+
+                Err(ProviderError::new(kind)
+                    .msg(format!("Unable to {}. Reasons: \n - {}", action, reasons.join("\n - "))))
+            },
+            Ok(ApiError { message: Some(m), .. }) => Err(ProviderError::new(kind)
+                .msg(format!("Unable to {}. Reason: {}", action, m))),
+            _ => Err(ProviderError::new(kind)
+                .msg(format!("Unable to {}. Received status {}", action, status))),
+        }
+    }
+
+    fn get_rrsets(api_url: &str, token: &str, zone: &str) -> Result<Vec<RRSet>> {
+        let request = http::client()
+            .get(&format!("{}/domains/{}/records", api_url, zone))
+            .bearer_auth(token);
+
+        let text = Gandi::check_response(request.send_retrying(Idempotency::Idempotent)?, "get the records of the domain")?;
+
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// Replace all records of the domain in a single request. The SOA record is managed by Gandi.
+    fn put(api_url: &str, token: &str, zone: &str, rrsets: &[RRSet], action: &str) -> Result<()> {
+        let request = http::client()
+            .put(&format!("{}/domains/{}/records", api_url, zone))
+            .bearer_auth(token)
+            .json(&Zone { items: rrsets.iter().filter(|r| r.rrset_type != "SOA").collect() });
+
+        Gandi::check_response(request.send_retrying(Idempotency::Idempotent)?, action)?;
+
+        Ok(())
+    }
+
+    /// Create the RRset, which the API refuses when it exists already, or replace it (alone)
+    fn put_rrset(api_url: &str, token: &str, zone: &str, rrset: &RRSet, create: bool) -> Result<()> {
+        let url = format!("{}/domains/{}/records/{}/{}", api_url, zone, rrset.rrset_name, rrset.rrset_type);
+        let request = if create { http::client().post(&url) } else { http::client().put(&url) };
+        let request = request
+            .bearer_auth(token)
+            .json(&serde_json::json!({ "rrset_ttl": rrset.rrset_ttl, "rrset_values": rrset.rrset_values }));
+
+        // Sending the creation twice would fail as the RRset exists, so it is not repeated
+        let idempotency = if create { Idempotency::NonIdempotent } else { Idempotency::Idempotent };
+        Gandi::check_response(request.send_retrying(idempotency)?, "add record(s)")?;
+
+        Ok(())
+    }
+}
+
+
+impl Provider for Gandi {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn dynamic_dns(&self, _request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(String::from("Gandi can not detect the address of the client, use update with an A/AAAA record instead!")))
+    }
+
+    /// Search the RRsets of the domain, flattened into one record per value
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let name = host.map(|h| relative_host(zone, &fqdn(zone, Some(h))));
+        let mut records: Vec<Record> = Vec::new();
+
+        for rrset in Gandi::get_rrsets(&api_url, &token, zone)? {
+            if name.as_ref().is_some_and(|n| !n.eq_ignore_ascii_case(&rrset.rrset_name)) {
+                continue;
+            }
+
+            if rtype.is_some_and(|t| !t.eq_ignore_ascii_case(&rrset.rrset_type)) {
+                continue;
+            }
+
+            for content in &rrset.rrset_values {
+                records.push(Record::from_content(&rrset.rrset_name, rrset.rrset_ttl.unwrap_or(0), &rrset.rrset_type, content)?);
+            }
+        }
+
+        Ok(Some(records))
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let name = host.map(|h| relative_host(zone, &fqdn(zone, Some(h))));
+        let (deleted, kept): (Vec<RRSet>, Vec<RRSet>) = Gandi::get_rrsets(&api_url, &token, zone)?
+            .into_iter()
+            .partition(|r| r.is_selected(name.as_deref(), rtype));
+
+        if deleted.is_empty() {
+            log::info!("Nothing to delete");
+            return Ok(true);
+        }
+
+        Gandi::put(&api_url, &token, zone, &kept, "delete selected record(s)")?;
+        log::info!("Deleted {} record set(s)", deleted.len());
+
+        Ok(true)
+    }
+
+    /// Replace all records selected by zone|host|type with the given ones. The records are grouped
+    /// into RRsets which, along with the records of the domain which are not selected, replace the
+    /// whole domain in a single bulk PUT.
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let records = &request.records;
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let name = host.map(|h| relative_host(zone, &fqdn(zone, Some(h))));
+        let (replaced, mut rrsets): (Vec<RRSet>, Vec<RRSet>) = Gandi::get_rrsets(&api_url, &token, zone)?
+            .into_iter()
+            .partition(|r| r.is_selected(name.as_deref(), rtype));
+
+        let kept = rrsets.len();
+
+        for record in records {
+            let name = relative_host(zone, &fqdn(zone, Some(&record.host)));
+            let rtype = record.r#type.to_uppercase();
+
+            match rrsets.iter_mut().skip(kept).find(|r| r.rrset_name.eq_ignore_ascii_case(&name) && r.rrset_type == rtype) {
+                Some(rrset) => {
+                    // A RRset has a single TTL, use the lowest one of its records
+                    rrset.rrset_ttl = rrset.rrset_ttl.map(|t| t.min(record.ttl));
+                    rrset.rrset_values.push(record.to_content());
+                },
+                None => rrsets.push(RRSet {
+                    rrset_name: name,
+                    rrset_type: rtype,
+                    rrset_ttl: Some(record.ttl),
+                    rrset_values: vec![record.to_content()],
+                }),
+            }
+        }
+
+        let added = rrsets.len() - kept;
+        Gandi::put(&api_url, &token, zone, &rrsets, "update selected record(s)")?;
+
+        log::info!("Updated record(s)!");
+        log::debug!("Replaced {} record set(s) with {} record set(s)", replaced.len(), added);
+
+        Ok(true)
+    }
+
+    /// Add the records to their RRsets. New RRsets are created, so a RRset created by someone else
+    /// meanwhile is not overwritten, and existing ones are replaced with their current values plus
+    /// the new ones. The rest of the domain is left alone.
+    fn add(&self, request: &AddRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let (api_url, token) = self.get_credential(zone, request.selector.host(), request.selector.rtype())?;
+
+        let existing = Gandi::get_rrsets(&api_url, &token, zone)?;
+        let mut rrsets: Vec<(RRSet, bool)> = Vec::new();
+        let mut current: Vec<Record> = Vec::new();
+
+        for record in &request.records {
+            let name = relative_host(zone, &fqdn(zone, Some(&record.host)));
+            let rtype = record.r#type.to_uppercase();
+            let is_same = |r: &RRSet| r.rrset_name.eq_ignore_ascii_case(&name) && r.rrset_type == rtype;
+
+            let index = match rrsets.iter().position(|(r, _)| is_same(r)) {
+                Some(i) => i,
+                None => {
+                    let rrset = match existing.iter().find(|r| is_same(r)) {
+                        Some(e) => {
+                            for value in &e.rrset_values {
+                                current.push(Record::from_content(&e.rrset_name, e.rrset_ttl.unwrap_or(0), &e.rrset_type, value)?);
+                            }
+                            (RRSet { rrset_name: e.rrset_name.clone(), rrset_type: rtype, rrset_ttl: e.rrset_ttl, rrset_values: e.rrset_values.clone() }, false)
+                        },
+                        None => (RRSet { rrset_name: name, rrset_type: rtype, rrset_ttl: None, rrset_values: vec![] }, true),
+                    };
+
+                    rrsets.push(rrset);
+                    rrsets.len() - 1
+                },
+            };
+
+            let rrset = &mut rrsets[index].0;
+            // A RRset has a single TTL, use the lowest one of its records
+            rrset.rrset_ttl = Some(rrset.rrset_ttl.map_or(record.ttl, |t| t.min(record.ttl)));
+            rrset.rrset_values.push(record.to_content());
+        }
+
+        check_duplicates(zone, &current, &request.records)?;
+
+        for (rrset, create) in &rrsets {
+            Gandi::put_rrset(&api_url, &token, zone, rrset, *create)?;
+        }
+        log::info!("Added {} record(s)", request.records.len());
+
+        Ok(true)
+    }
+
+    fn list_zones(&self) -> Result<Vec<super::Zone>> {
+        list_account_zones(&self.credentials, |credential| {
+            let (api_url, token) = Gandi::api_access(credential)?;
+
+            let mut zones: Vec<super::Zone> = Vec::new();
+            let mut page = 1;
+
+            loop {
+                let request = http::client()
+                    .get(&format!("{}/domains?page={}&per_page={}", api_url, page, PAGE_SIZE))
+                    .bearer_auth(&token);
+
+                let text = Gandi::check_response(request.send_retrying(Idempotency::Idempotent)?, "list the domains")?;
+                let domains: Vec<Domain> = serde_json::from_str(&text)?;
+                let last = domains.len() < PAGE_SIZE;
+
+                zones.extend(domains.into_iter().map(|d| super::Zone::new(&d.fqdn)
+                    .with("automatic_snapshots", d.automatic_snapshots)));
+
+                if last {
+                    break;
+                }
+                page += 1;
+            }
+
+            Ok(zones)
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+    use crate::providers::test_server::{Reply, Server};
+
+    /// A Gandi LiveDNS API with a domain holding a www A RRset
+    fn api() -> Server {
+        Server::start(|request| match (request.method.as_str(), request.path()) {
+            ("GET", "/domains/example.com/records") => Reply::new(200, r#"[
+                {"rrset_name": "@", "rrset_type": "SOA", "rrset_ttl": 10800, "rrset_values": ["ns1.gandi.net. hostmaster.gandi.net. 1 10800 3600 604800 10800"]},
+                {"rrset_name": "www", "rrset_type": "A", "rrset_ttl": 300, "rrset_values": ["192.0.2.1"]}
+            ]"#),
+            ("POST", _) => Reply::new(201, r#"{"message": "DNS Record Created"}"#),
+            ("PUT", _) => Reply::new(201, r#"{"message": "DNS Record Created"}"#),
+            _ => Reply::new(404, r#"{"message": "Not Found"}"#),
+        })
+    }
+
+    fn provider(server: &Server) -> Gandi {
+        let mut provider = Gandi::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("gandi"),
+            token: Some(String::from("secret")),
+            api_url: Some(server.url().to_string()),
+            ..Default::default()
+        }]);
+
+        provider
+    }
+
+    fn a(host: &str, data: &str) -> Record {
+        Record { host: host.to_string(), ttl: 300, r#type: String::from("A"), data: data.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn creates_new_record_sets_and_replaces_only_the_existing_ones() {
+        let server = api();
+        provider(&server).add(&AddRequest {
+            selector: RecordSelector::new("example.com", None, None),
+            records: vec![a("www", "192.0.2.2"), a("ftp", "192.0.2.3")],
+            wait: false,
+        }).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!((requests[1].method.as_str(), requests[1].path()), ("PUT", "/domains/example.com/records/www/A"));
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&requests[1].body).unwrap(), serde_json::json!({"rrset_ttl": 300, "rrset_values": ["192.0.2.1", "192.0.2.2"]}));
+        assert_eq!((requests[2].method.as_str(), requests[2].path()), ("POST", "/domains/example.com/records/ftp/A"));
+    }
+
+    #[test]
+    fn refuses_to_add_records_which_exist() {
+        let server = api();
+        let error = provider(&server).add(&AddRequest {
+            selector: RecordSelector::new("example.com", Some("www"), None),
+            records: vec![a("www", "192.0.2.1")],
+            wait: false,
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DuplicateRecord));
+        assert_eq!(server.requests().len(), 1);
+    }
+}
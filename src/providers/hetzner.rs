@@ -0,0 +1,526 @@
+use super::{Provider, http, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, DeleteRequest, Record, ProviderError, ProviderErrorKind, Result, get_credential, api_url, list_account_zones, fqdn, relative_host, reconcile, status_error_kind};
+use super::retry::{Idempotency, SendRetry};
+use crate::config;
+
+use serde::{Serialize, Deserialize};
+
+const API_URL: &str = "https://dns.hetzner.com/api/v1";
+
+/// Largest page size allowed by the API
+const PAGE_SIZE: u32 = 100;
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    error: Option<ErrorDetail>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorDetail {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ZoneList {
+    #[serde(default)]
+    zones: Vec<Zone>,
+    meta: Option<Meta>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Zone {
+    id: String,
+    name: String,
+    ttl: Option<u32>,
+    status: Option<String>,
+    records_count: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecordList {
+    #[serde(default)]
+    records: Vec<ZoneRecord>,
+    meta: Option<Meta>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Meta {
+    pagination: Option<Pagination>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Pagination {
+    last_page: u32,
+}
+
+/// A DNS record as represented by the Hetzner DNS API. The value is in presentation format.
+#[derive(Serialize, Deserialize, Debug)]
+struct ZoneRecord {
+    #[serde(skip_serializing)]
+    id: Option<String>,
+    zone_id: String,
+    r#type: String,
+    name: String,
+    value: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct Hetzner {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+
+impl ZoneRecord {
+    fn from_record(zone: &str, zone_id: &str, r: &Record) -> Self {
+        ZoneRecord {
+            id: None,
+            zone_id: zone_id.to_string(),
+            r#type: r.r#type.to_uppercase(),
+            name: relative_host(zone, &fqdn(zone, Some(&r.host))),
+            value: r.to_content(),
+            ttl: Some(r.ttl),
+        }
+    }
+
+    fn is_selected(&self, zone: &str, name: Option<&str>, rtype: Option<&str>) -> bool {
+        name.is_none_or(|n| n.eq_ignore_ascii_case(&fqdn(zone, Some(&self.name))))
+            && rtype.is_none_or(|t| t.eq_ignore_ascii_case(&self.r#type))
+    }
+
+    fn to_record(&self) -> Result<Record> {
+        Record::from_content(&self.name, self.ttl.unwrap_or(0), &self.r#type, &self.value)
+    }
+}
+
+
+impl Hetzner {
+    pub fn new() -> Self {
+        Hetzner {
+            name: String::from("hetzner"),
+            credentials: None,
+        }
+    }
+
+    fn get_credential(&self, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<(String, String)> {
+        Hetzner::api_access(&get_credential(&self.credentials, zone, host, rtype)?)
+    }
+
+    /// Base URL of the API and the token of the credential
+    fn api_access(credential: &config::Credential) -> Result<(String, String)> {
+        let api_url = api_url(credential, API_URL);
+
+        match &credential.token {
+            Some(t) => Ok((api_url, t.clone())),
+            None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+                .msg(String::from("Hetzner DNS requires an API token!"))),
+        }
+    }
+
+    /// Send a request to the API and return the body of a successful response
+    fn request(method: reqwest::Method, url: &str, token: &str, body: Option<&ZoneRecord>) -> Result<String> {
+        let idempotency = Idempotency::of(&method);
+        let mut request = http::client()
+            .request(method, url)
+            .header("Auth-API-Token", token);
+
+        if let Some(b) = body {
+            request = request.json(b);
+        }
+
+        let response = request.send_retrying(idempotency)?;
+        let status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        if status.is_success() {
+            return Ok(text);
+        }
+
+        let kind = status_error_kind(status);
+
+        let reason = match serde_json::from_str::<ApiError>(&text) {
+            Ok(ApiError { error: Some(e), .. }) if !e.message.is_empty() => e.message,
+            Ok(ApiError { message: Some(m), .. }) => m,
+            _ => format!("Received status {}", status),
+        };
+
+        Err(ProviderError::new(kind)
+            .msg(format!("Hetzner DNS API request failed. Reason: {}", reason)))
+    }
+
+    fn get_zone_id(api_url: &str, token: &str, zone: &str) -> Result<String> {
+        let text = Hetzner::request(reqwest::Method::GET, &format!("{}/zones?name={}", api_url, zone), token, None)?;
+        let zones: ZoneList = serde_json::from_str(&text)?;
+
+        match zones.zones.into_iter().find(|z| z.name.eq_ignore_ascii_case(zone)) {
+            Some(z) => Ok(z.id),
+            None => Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                .msg(format!("Zone {} not found in the Hetzner DNS account", zone))),
+        }
+    }
+
+    /// Fetch all records of the zone, following pagination
+    fn get_records(api_url: &str, token: &str, zone_id: &str) -> Result<Vec<ZoneRecord>> {
+        let mut records: Vec<ZoneRecord> = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let url = format!("{}/records?zone_id={}&page={}&per_page={}", api_url, zone_id, page, PAGE_SIZE);
+            let text = Hetzner::request(reqwest::Method::GET, &url, token, None)?;
+            let list: RecordList = serde_json::from_str(&text)?;
+
+            records.extend(list.records);
+
+            let last_page = list.meta.and_then(|m| m.pagination).map(|p| p.last_page).unwrap_or(1);
+            if page >= last_page {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(records)
+    }
+
+    /// Fetch all zones of the account, following pagination
+    fn get_zones(api_url: &str, token: &str) -> Result<Vec<Zone>> {
+        let mut zones: Vec<Zone> = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let url = format!("{}/zones?page={}&per_page={}", api_url, page, PAGE_SIZE);
+            let text = Hetzner::request(reqwest::Method::GET, &url, token, None)?;
+            let list: ZoneList = serde_json::from_str(&text)?;
+
+            zones.extend(list.zones);
+
+            let last_page = list.meta.and_then(|m| m.pagination).map(|p| p.last_page).unwrap_or(1);
+            if page >= last_page {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(zones)
+    }
+}
+
+
+impl Provider for Hetzner {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn dynamic_dns(&self, _request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(String::from("Hetzner DNS can not detect the address of the client, use update with an A/AAAA record instead!")))
+    }
+
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let zone_id = Hetzner::get_zone_id(&api_url, &token, zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+
+        let records = Hetzner::get_records(&api_url, &token, &zone_id)?
+            .iter()
+            .filter(|r| r.is_selected(zone, name.as_deref(), rtype))
+            .map(|r| r.to_record())
+            .collect::<Result<Vec<Record>>>()?;
+
+        Ok(Some(records))
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let zone_id = Hetzner::get_zone_id(&api_url, &token, zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+        let mut count = 0;
+
+        for record in Hetzner::get_records(&api_url, &token, &zone_id)? {
+            // The SOA record belongs to the zone and can not be removed
+            if record.r#type == "SOA" || !record.is_selected(zone, name.as_deref(), rtype) {
+                continue;
+            }
+
+            let url = format!("{}/records/{}", api_url, record.id.unwrap_or_default());
+            Hetzner::request(reqwest::Method::DELETE, &url, &token, None)?;
+            count += 1;
+        }
+
+        log::info!("Deleted {} record(s)", count);
+
+        Ok(true)
+    }
+
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let records = &request.records;
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let zone_id = Hetzner::get_zone_id(&api_url, &token, zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+
+        let mut existing: Vec<(String, Record)> = Vec::new();
+        for record in Hetzner::get_records(&api_url, &token, &zone_id)? {
+            if record.r#type == "SOA" || !record.is_selected(zone, name.as_deref(), rtype) {
+                continue;
+            }
+
+            existing.push((record.id.clone().unwrap_or_default(), record.to_record()?));
+        }
+
+        let changes = reconcile(zone, existing, records);
+        let url = format!("{}/records", api_url);
+
+        for record in &changes.create {
+            Hetzner::request(reqwest::Method::POST, &url, &token, Some(&ZoneRecord::from_record(zone, &zone_id, record)))?;
+        }
+
+        for (id, record) in &changes.update {
+            Hetzner::request(reqwest::Method::PUT, &format!("{}/{}", url, id), &token, Some(&ZoneRecord::from_record(zone, &zone_id, record)))?;
+        }
+
+        for id in &changes.delete {
+            Hetzner::request(reqwest::Method::DELETE, &format!("{}/{}", url, id), &token, None)?;
+        }
+
+        log::info!("Updated record(s)!");
+        log::debug!("Added {} record(s). Changed {} record(s). Removed {} record(s)", changes.create.len(), changes.update.len(), changes.delete.len());
+
+        Ok(true)
+    }
+
+    fn list_zones(&self) -> Result<Vec<super::Zone>> {
+        list_account_zones(&self.credentials, |credential| {
+            let (api_url, token) = Hetzner::api_access(credential)?;
+
+            Ok(Hetzner::get_zones(&api_url, &token)?
+                .into_iter()
+                .map(|z| super::Zone::new(&z.name)
+                    .with("id", z.id)
+                    .with("ttl", z.ttl)
+                    .with("status", z.status)
+                    .with("records_count", z.records_count))
+                .collect())
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+    use crate::providers::test_server::{Reply, Request, Server};
+
+    const ZONES: &str = include_str!("../../tests/fixtures/hetzner/zones.json");
+    const RECORDS_PAGE_1: &str = include_str!("../../tests/fixtures/hetzner/records_page_1.json");
+    const RECORDS_PAGE_2: &str = include_str!("../../tests/fixtures/hetzner/records_page_2.json");
+
+    fn api() -> Server {
+        Server::start(|request| match (request.method.as_str(), request.path()) {
+            ("GET", "/zones") => Reply::new(200, ZONES),
+            ("GET", "/records") if request.query("page").as_deref() == Some("2") => Reply::new(200, RECORDS_PAGE_2),
+            ("GET", "/records") => Reply::new(200, RECORDS_PAGE_1),
+            ("POST", "/records") => Reply::new(200, r#"{"record": {}}"#),
+            ("PUT", _) => Reply::new(200, r#"{"record": {}}"#),
+            ("DELETE", _) => Reply::new(200, ""),
+            _ => Reply::new(404, r#"{"error": {"message": "record not found", "code": 404}}"#),
+        })
+    }
+
+    fn provider(server: &Server) -> Hetzner {
+        let mut provider = Hetzner::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("hetzner"),
+            token: Some(String::from("secret")),
+            api_url: Some(server.url().to_string()),
+            ..Default::default()
+        }]);
+
+        provider
+    }
+
+    fn search(provider: &Hetzner, host: Option<&str>, rtype: Option<&str>) -> Vec<Record> {
+        provider.search(&SearchRequest {
+            selector: RecordSelector::new("example.com", host, rtype),
+        }).unwrap().unwrap()
+    }
+
+    fn srv(port: u32) -> Record {
+        Record {
+            host: String::from("_sip._tcp"),
+            ttl: 3600,
+            r#type: String::from("SRV"),
+            data: String::from("sip.example.com"),
+            srv_priority: Some(10),
+            srv_weight: Some(60),
+            srv_port: Some(port),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn follows_the_pages_of_records() {
+        let server = api();
+        let records = search(&provider(&server), None, None);
+
+        assert_eq!(records.len(), 5);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].query("name").as_deref(), Some("example.com"));
+        assert_eq!(requests[1].query("zone_id").as_deref(), Some("rMu2waTJPbHr4"));
+        assert_eq!(requests[1].query("page").as_deref(), Some("1"));
+        assert_eq!(requests[2].query("page").as_deref(), Some("2"));
+        assert_eq!(requests[2].header("Auth-API-Token"), Some("secret"));
+    }
+
+    #[test]
+    fn maps_the_priority_weight_and_port() {
+        let server = api();
+        let records = search(&provider(&server), None, None);
+
+        let mx = records.iter().find(|r| r.r#type == "MX").unwrap();
+        assert_eq!((mx.host.as_str(), mx.data.as_str(), mx.mx_priority), ("@", "mail.example.com", Some(10)));
+
+        let srv = records.iter().find(|r| r.r#type == "SRV").unwrap();
+        assert_eq!((srv.host.as_str(), srv.data.as_str()), ("_sip._tcp", "sip.example.com"));
+        assert_eq!((srv.srv_priority, srv.srv_weight, srv.srv_port), (Some(10), Some(60), Some(5060)));
+
+        let txt = records.iter().find(|r| r.r#type == "TXT").unwrap();
+        assert_eq!(txt.data, "v=spf1 mx -all");
+    }
+
+    #[test]
+    fn selects_the_records_of_the_host() {
+        let server = api();
+        let records = search(&provider(&server), Some("@"), Some("MX"));
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].r#type, "MX");
+    }
+
+    #[test]
+    fn keeps_the_records_which_did_not_change() {
+        let server = api();
+
+        provider(&server).update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("_sip._tcp"), Some("SRV")),
+            records: vec![srv(5060)],
+            wait: false,
+        }).unwrap();
+
+        assert!(server.requests().iter().all(|r| r.method == "GET"));
+    }
+
+    #[test]
+    fn sends_the_priority_weight_and_port() {
+        let server = api();
+
+        provider(&server).update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("_sip._tcp"), Some("SRV")),
+            records: vec![srv(5061)],
+            wait: false,
+        }).unwrap();
+
+        let changes: Vec<Request> = server.requests().into_iter().filter(|r| r.method != "GET").collect();
+        assert_eq!(changes.len(), 2);
+
+        assert_eq!(changes[0].method, "POST");
+        let created: serde_json::Value = serde_json::from_str(&changes[0].body).unwrap();
+        assert_eq!(created["zone_id"], "rMu2waTJPbHr4");
+        assert_eq!(created["type"], "SRV");
+        assert_eq!(created["name"], "_sip._tcp");
+        assert_eq!(created["value"], "10 60 5061 sip.example.com.");
+
+        assert_eq!(changes[1].method, "DELETE");
+        assert_eq!(changes[1].path(), "/records/e28f4a7b9c0d1366");
+    }
+
+    #[test]
+    fn never_deletes_the_soa_record() {
+        let server = api();
+
+        provider(&server).delete(&DeleteRequest {
+            selector: RecordSelector::new("example.com", Some("@"), None),
+            wait: false,
+        }).unwrap();
+
+        let deleted: Vec<String> = server.requests().into_iter()
+            .filter(|r| r.method == "DELETE")
+            .map(|r| r.path().to_string())
+            .collect();
+        assert_eq!(deleted, vec!["/records/c51e2d9a40b8f713", "/records/f9d3c2b1a0e48857"]);
+    }
+
+    #[test]
+    fn lists_the_zones_of_the_account() {
+        let server = api();
+        let zones = provider(&server).list_zones().unwrap();
+
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].name, "example.com");
+        assert_eq!(zones[0].metadata["id"], "rMu2waTJPbHr4");
+        assert_eq!(zones[0].metadata["records_count"], 6);
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_api() {
+        let server = Server::start(|_| Reply::new(401, r#"{"message": "Invalid authentication credentials"}"#));
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+        assert!(error.to_string().contains("Invalid authentication credentials"));
+    }
+
+    #[test]
+    fn reports_rejected_requests_as_permanent() {
+        let server = Server::start(|_| Reply::new(422, r#"{"error": {"message": "invalid value", "code": 422}}"#));
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DnsApiError));
+        assert!(!error.is_transient());
+
+        let server = Server::start(|_| Reply::new(200, "<html>maintenance</html>"));
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+
+        assert!(!error.is_transient());
+    }
+
+    #[test]
+    fn reports_throttling_as_transient() {
+        // Longer than the client waits for, so the request is not sent again
+        let server = Server::start(|_| Reply {
+            headers: vec![(String::from("Retry-After"), String::from("3600"))],
+            ..Reply::new(429, r#"{"message": "rate limit exceeded"}"#)
+        });
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::RateLimited));
+        assert!(error.is_transient());
+    }
+}
@@ -0,0 +1,505 @@
+use super::{Provider, http, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, DeleteRequest, Record, Zone, ProviderError, ProviderErrorKind, Result, get_credential, api_url, list_account_zones, fqdn, relative_host, reconcile, status_error_kind};
+use super::retry::{Idempotency, SendRetry};
+use crate::config;
+
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+
+const API_URL: &str = "https://api.linode.com/v4";
+
+/// Largest page size allowed by the API
+const PAGE_SIZE: u32 = 500;
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    errors: Vec<ErrorDetail>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorDetail {
+    reason: String,
+    field: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Page<T> {
+    data: Vec<T>,
+    page: u32,
+    pages: u32,
+}
+
+#[derive(Deserialize, Debug)]
+struct Domain {
+    id: u64,
+    domain: String,
+    r#type: Option<String>,
+    status: Option<String>,
+}
+
+/// A DNS record as represented by the Linode API. Names are relative to the domain, with an empty
+/// name for the apex.
+#[derive(Serialize, Deserialize, Debug)]
+struct DomainRecord {
+    #[serde(skip_serializing)]
+    id: Option<u64>,
+    r#type: String,
+    name: String,
+    target: String,
+    ttl_sec: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weight: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    port: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    service: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    protocol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct Linode {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+
+impl DomainRecord {
+    fn from_record(zone: &str, r: &Record) -> Self {
+        let rtype = r.r#type.to_uppercase();
+        let host = relative_host(zone, &fqdn(zone, Some(&r.host)));
+        let mut name = if host == "@" { String::new() } else { host };
+
+        // SRV records carry the service and protocol labels in dedicated fields
+        let (mut service, mut protocol) = (None, None);
+        if rtype == "SRV" {
+            let labels: Vec<&str> = name.splitn(3, '.').collect();
+            if labels.len() >= 2 && labels[0].starts_with('_') && labels[1].starts_with('_') {
+                service = Some(labels[0].trim_start_matches('_').to_string());
+                protocol = Some(labels[1].trim_start_matches('_').to_string());
+                name = labels.get(2).map(|l| l.to_string()).unwrap_or_default();
+            }
+        }
+
+        DomainRecord {
+            id: None,
+            name,
+            target: r.data.clone(),
+            ttl_sec: Some(r.ttl),
+            priority: r.mx_priority.or(r.srv_priority),
+            weight: r.srv_weight,
+            port: r.srv_port,
+            service,
+            protocol,
+            tag: r.caa_property.clone(),
+            r#type: rtype,
+        }
+    }
+
+    fn into_record(self) -> Record {
+        let mut record = Record {
+            host: if self.name.is_empty() { String::from("@") } else { self.name },
+            ttl: self.ttl_sec.unwrap_or(0),
+            data: self.target.trim_end_matches('.').to_string(),
+            ..Default::default()
+        };
+
+        match self.r#type.as_str() {
+            "MX" => record.mx_priority = self.priority,
+            "SRV" => {
+                record.srv_priority = self.priority;
+                record.srv_weight = self.weight;
+                record.srv_port = self.port;
+            },
+            "CAA" => {
+                record.caa_flags = Some(0);
+                record.caa_property = self.tag;
+            },
+            _ => (),
+        }
+
+        record.r#type = self.r#type;
+        record
+    }
+
+    fn is_selected(&self, zone: &str, name: Option<&str>, rtype: Option<&str>) -> bool {
+        let host = if self.name.is_empty() { "@" } else { &self.name };
+
+        name.is_none_or(|n| n.eq_ignore_ascii_case(&fqdn(zone, Some(host))))
+            && rtype.is_none_or(|t| t.eq_ignore_ascii_case(&self.r#type))
+    }
+}
+
+
+impl Linode {
+    pub fn new() -> Self {
+        Linode {
+            name: String::from("linode"),
+            credentials: None,
+        }
+    }
+
+    fn get_credential(&self, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<(String, String)> {
+        Linode::api_access(&get_credential(&self.credentials, zone, host, rtype)?)
+    }
+
+    /// Base URL of the API and the token of the credential
+    fn api_access(credential: &config::Credential) -> Result<(String, String)> {
+        let api_url = api_url(credential, API_URL);
+
+        match &credential.token {
+            Some(t) => Ok((api_url, t.clone())),
+            None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+                .msg(String::from("Linode requires a personal access token!"))),
+        }
+    }
+
+    /// Send a request to the API and return the body of a successful response
+    fn request(method: reqwest::Method, url: &str, token: &str, body: Option<&DomainRecord>) -> Result<String> {
+        let idempotency = Idempotency::of(&method);
+        let mut request = http::client()
+            .request(method, url)
+            .bearer_auth(token);
+
+        if let Some(b) = body {
+            request = request.json(b);
+        }
+
+        let response = request.send_retrying(idempotency)?;
+        let status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        if status.is_success() {
+            return Ok(text);
+        }
+
+        let kind = status_error_kind(status);
+
+        match serde_json::from_str::<ApiError>(&text) {
+            Ok(e) if !e.errors.is_empty() => {
+                let reasons: Vec<String> = e.errors
+                    .into_iter()
+                    .map(|e| match e.field {
+                        Some(f) => format!("{}: {}", f, e.reason),
+                        None => e.reason,
+                    })
+                    .collect();
+
+                Err(ProviderError::new(kind)
+                    .msg(format!("Linode API request failed. Reasons: \n - {}", reasons.join("\n - "))))
+            },
+            _ => Err(ProviderError::new(kind)
+                .msg(format!("Linode API request failed. Received status {}", status))),
+        }
+    }
+
+    /// Fetch every item of a paginated collection
+    fn get_all<T: DeserializeOwned>(url: &str, token: &str) -> Result<Vec<T>> {
+        let mut items: Vec<T> = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let text = Linode::request(reqwest::Method::GET, &format!("{}?page={}&page_size={}", url, page, PAGE_SIZE), token, None)?;
+            let list: Page<T> = serde_json::from_str(&text)?;
+
+            items.extend(list.data);
+
+            if list.page >= list.pages {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(items)
+    }
+
+    fn get_domain_id(api_url: &str, token: &str, zone: &str) -> Result<u64> {
+        let domains: Vec<Domain> = Linode::get_all(&format!("{}/domains", api_url), token)?;
+
+        match domains.into_iter().find(|d| d.domain.eq_ignore_ascii_case(zone)) {
+            Some(d) => Ok(d.id),
+            None => Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                .msg(format!("Domain {} not found in the Linode account", zone))),
+        }
+    }
+
+    fn get_records(api_url: &str, token: &str, domain_id: u64, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<Vec<DomainRecord>> {
+        let name = host.map(|h| fqdn(zone, Some(h)));
+        let records: Vec<DomainRecord> = Linode::get_all(&format!("{}/domains/{}/records", api_url, domain_id), token)?;
+
+        Ok(records
+            .into_iter()
+            .filter(|r| r.is_selected(zone, name.as_deref(), rtype))
+            .collect())
+    }
+}
+
+
+impl Provider for Linode {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn dynamic_dns(&self, _request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(String::from("Linode can not detect the address of the client, use update with an A/AAAA record instead!")))
+    }
+
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let domain_id = Linode::get_domain_id(&api_url, &token, zone)?;
+        let records = Linode::get_records(&api_url, &token, domain_id, zone, host, rtype)?;
+
+        Ok(Some(records.into_iter().map(|r| r.into_record()).collect()))
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let domain_id = Linode::get_domain_id(&api_url, &token, zone)?;
+        let records = Linode::get_records(&api_url, &token, domain_id, zone, host, rtype)?;
+
+        for record in &records {
+            let url = format!("{}/domains/{}/records/{}", api_url, domain_id, record.id.unwrap_or_default());
+            Linode::request(reqwest::Method::DELETE, &url, &token, None)?;
+        }
+
+        log::info!("Deleted {} record(s)", records.len());
+
+        Ok(true)
+    }
+
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let records = &request.records;
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let domain_id = Linode::get_domain_id(&api_url, &token, zone)?;
+        let existing = Linode::get_records(&api_url, &token, domain_id, zone, host, rtype)?
+            .into_iter()
+            .map(|r| (r.id.unwrap_or_default(), r.into_record()))
+            .collect();
+
+        let changes = reconcile(zone, existing, records);
+        let url = format!("{}/domains/{}/records", api_url, domain_id);
+
+        for record in &changes.create {
+            Linode::request(reqwest::Method::POST, &url, &token, Some(&DomainRecord::from_record(zone, record)))?;
+        }
+
+        for (id, record) in &changes.update {
+            Linode::request(reqwest::Method::PUT, &format!("{}/{}", url, id), &token, Some(&DomainRecord::from_record(zone, record)))?;
+        }
+
+        for id in &changes.delete {
+            Linode::request(reqwest::Method::DELETE, &format!("{}/{}", url, id), &token, None)?;
+        }
+
+        log::info!("Updated record(s)!");
+        log::debug!("Added {} record(s). Changed {} record(s). Removed {} record(s)", changes.create.len(), changes.update.len(), changes.delete.len());
+
+        Ok(true)
+    }
+
+    fn list_zones(&self) -> Result<Vec<Zone>> {
+        list_account_zones(&self.credentials, |credential| {
+            let (api_url, token) = Linode::api_access(credential)?;
+            let domains: Vec<Domain> = Linode::get_all(&format!("{}/domains", api_url), &token)?;
+
+            Ok(domains
+                .into_iter()
+                .map(|d| Zone::new(&d.domain)
+                    .with("id", d.id)
+                    .with("type", d.r#type)
+                    .with("status", d.status))
+                .collect())
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+    use crate::providers::test_server::{Reply, Request, Server};
+
+    const DOMAINS: &str = include_str!("../../tests/fixtures/linode/domains.json");
+    const RECORDS_PAGE_1: &str = include_str!("../../tests/fixtures/linode/records_page_1.json");
+    const RECORDS_PAGE_2: &str = include_str!("../../tests/fixtures/linode/records_page_2.json");
+
+    fn api() -> Server {
+        Server::start(|request| match (request.method.as_str(), request.path()) {
+            ("GET", "/domains") => Reply::new(200, DOMAINS),
+            ("GET", "/domains/1234567/records") if request.query("page").as_deref() == Some("2") => Reply::new(200, RECORDS_PAGE_2),
+            ("GET", "/domains/1234567/records") => Reply::new(200, RECORDS_PAGE_1),
+            ("POST", "/domains/1234567/records") | ("PUT", _) => Reply::new(200, "{}"),
+            ("DELETE", _) => Reply::new(200, "{}"),
+            _ => Reply::new(404, r#"{"errors": [{"reason": "Not found"}]}"#),
+        })
+    }
+
+    fn provider(server: &Server) -> Linode {
+        let mut provider = Linode::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("linode"),
+            token: Some(String::from("secret")),
+            api_url: Some(server.url().to_string()),
+            ..Default::default()
+        }]);
+
+        provider
+    }
+
+    fn search(provider: &Linode, host: Option<&str>, rtype: Option<&str>) -> Vec<Record> {
+        provider.search(&SearchRequest {
+            selector: RecordSelector::new("example.com", host, rtype),
+        }).unwrap().unwrap()
+    }
+
+    fn srv(host: &str, port: u32) -> Record {
+        Record {
+            host: host.to_string(),
+            ttl: 3600,
+            r#type: String::from("SRV"),
+            data: String::from("sip.example.com"),
+            srv_priority: Some(10),
+            srv_weight: Some(60),
+            srv_port: Some(port),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn follows_the_pages_of_records() {
+        let server = api();
+        let records = search(&provider(&server), None, None);
+
+        assert_eq!(records.len(), 4);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].query("page").as_deref(), Some("1"));
+        assert_eq!(requests[1].query("page_size").as_deref(), Some("500"));
+        assert_eq!(requests[2].query("page").as_deref(), Some("2"));
+        assert_eq!(requests[2].header("Authorization"), Some("Bearer secret"));
+    }
+
+    #[test]
+    fn maps_the_priority_weight_and_port() {
+        let server = api();
+        let records = search(&provider(&server), None, None);
+
+        // The apex has an empty name
+        let mx = records.iter().find(|r| r.r#type == "MX").unwrap();
+        assert_eq!((mx.host.as_str(), mx.data.as_str(), mx.mx_priority), ("@", "mail.example.com", Some(10)));
+
+        let srv = records.iter().find(|r| r.r#type == "SRV").unwrap();
+        assert_eq!((srv.host.as_str(), srv.data.as_str()), ("_sip._tcp", "sip.example.com"));
+        assert_eq!((srv.srv_priority, srv.srv_weight, srv.srv_port), (Some(10), Some(60), Some(5060)));
+
+        let a = records.iter().find(|r| r.r#type == "A").unwrap();
+        assert_eq!((a.mx_priority, a.srv_priority), (None, None));
+    }
+
+    #[test]
+    fn keeps_the_records_which_did_not_change() {
+        let server = api();
+
+        provider(&server).update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("_sip._tcp"), Some("SRV")),
+            records: vec![srv("_sip._tcp", 5060)],
+            wait: false,
+        }).unwrap();
+
+        assert!(server.requests().iter().all(|r| r.method == "GET"));
+    }
+
+    #[test]
+    fn sends_the_service_and_protocol_of_srv_records() {
+        let server = api();
+
+        provider(&server).update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("_sip._tcp"), Some("SRV")),
+            records: vec![srv("_sip._tcp", 5061)],
+            wait: false,
+        }).unwrap();
+
+        let changes: Vec<Request> = server.requests().into_iter().filter(|r| r.method != "GET").collect();
+        assert_eq!(changes.len(), 2);
+
+        assert_eq!(changes[0].method, "POST");
+        let created: serde_json::Value = serde_json::from_str(&changes[0].body).unwrap();
+        assert_eq!(created["type"], "SRV");
+        assert_eq!(created["service"], "sip");
+        assert_eq!(created["protocol"], "tcp");
+        assert_eq!(created["name"], "");
+        assert_eq!(created["target"], "sip.example.com");
+        assert_eq!((&created["priority"], &created["weight"], &created["port"]), (&serde_json::json!(10), &serde_json::json!(60), &serde_json::json!(5061)));
+
+        assert_eq!(changes[1].method, "DELETE");
+        assert_eq!(changes[1].path(), "/domains/1234567/records/28861003");
+    }
+
+    #[test]
+    fn sends_the_priority_of_mx_records_only() {
+        let server = api();
+
+        provider(&server).update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("@"), Some("MX")),
+            records: vec![Record {
+                host: String::from("@"),
+                ttl: 3600,
+                r#type: String::from("MX"),
+                data: String::from("mx2.example.com"),
+                mx_priority: Some(20),
+                ..Default::default()
+            }],
+            wait: false,
+        }).unwrap();
+
+        let created = server.requests().into_iter().find(|r| r.method == "POST").unwrap();
+        let created: serde_json::Value = serde_json::from_str(&created.body).unwrap();
+        assert_eq!(created["priority"], 20);
+        assert!(created.get("weight").is_none());
+        assert!(created.get("port").is_none());
+    }
+
+    #[test]
+    fn lists_the_domains_of_the_account() {
+        let server = api();
+        let zones = provider(&server).list_zones().unwrap();
+
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].name, "example.com");
+        assert_eq!(zones[0].metadata["id"], 1234567);
+        assert_eq!(zones[0].metadata["type"], "master");
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_api() {
+        let server = Server::start(|_| Reply::new(400, r#"{"errors": [{"reason": "Invalid port", "field": "port"}]}"#));
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DnsApiError));
+        assert!(error.to_string().contains("port: Invalid port"));
+    }
+}
@@ -1,15 +1,37 @@
+mod async_provider;
+mod dyndns2;
+mod cloudflare;
+mod desec;
+mod digitalocean;
+mod exec;
+mod gandi;
+mod hetzner;
+pub mod http;
+mod linode;
 mod mythic_beasts;
+mod noip;
+mod powerdns;
+pub(crate) mod retry;
+pub(crate) mod rfc2136;
+mod route53;
+mod zonefile;
+#[cfg(test)]
+pub(crate) mod test_server;
 
 use crate::config;
+use crate::state::State;
 
+pub use async_provider::{AsyncProvider, BlockingProvider, for_each_zone};
+
+use std::collections::BTreeMap;
 use std::fmt;
 use std::error;
+use std::net::IpAddr;
 use serde::{Serialize, Deserialize};
-use clap::{ArgMatches};
 
 
 /// A DNS record structure
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Record {
     pub host: String,
     pub ttl: u32,
@@ -38,21 +60,331 @@ pub struct Record {
     pub tlsa_selector: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tlsa_matching: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxied: Option<bool>,
 
-    #[serde(skip)]
-    pub _template: Option<bool>,
+    /// The record comes from a DNS template applied to the zone (Mythic Beasts)
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "flag")]
+    pub template: Option<bool>,
+    /// The provider generates the record itself, e.g. for a service it hosts (Mythic Beasts)
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "flag")]
+    pub generated: Option<bool>,
 }
 
-/// Initialize provider based on the providers' name-id (e.g mythic-beasts)
-pub fn init_provider(name: &str) -> Box<dyn Provider> {
-    match name {
-        "mythic-beasts" => mythic_beasts::MythicBeasts::new(),
-        _ => unimplemented!(),
+impl Record {
+    /// Build a record from the presentation (zone file) format of its data, e.g. `10 mail.example.com.`
+    /// for an MX record. Names within the data are stored without the trailing dot.
+    pub fn from_content(host: &str, ttl: u32, rtype: &str, content: &str) -> Result<Record> {
+        let rtype = rtype.to_uppercase();
+        let invalid = || ProviderError::new(ProviderErrorKind::InvalidRecord)
+            .msg(format!("Invalid {} record data: {}", rtype, content));
+        let number = |s: Option<&str>| -> Result<u32> {
+            s.and_then(|n| n.parse().ok()).ok_or_else(invalid)
+        };
+        let name = |s: Option<&str>| -> Result<String> {
+            s.map(|n| n.trim_end_matches('.').to_string()).ok_or_else(invalid)
+        };
+
+        let mut record = Record {
+            host: host.to_string(),
+            ttl,
+            r#type: rtype.clone(),
+            ..Default::default()
+        };
+        let mut fields = content.split_whitespace();
+
+        match rtype.as_str() {
+            "CNAME" | "NS" | "PTR" => record.data = name(fields.next())?,
+            "MX" => {
+                record.mx_priority = Some(number(fields.next())?);
+                record.data = name(fields.next())?;
+            },
+            "SRV" => {
+                record.srv_priority = Some(number(fields.next())?);
+                record.srv_weight = Some(number(fields.next())?);
+                record.srv_port = Some(number(fields.next())?);
+                record.data = name(fields.next())?;
+            },
+            "CAA" => {
+                record.caa_flags = Some(number(fields.next())?);
+                record.caa_property = Some(fields.next().ok_or_else(invalid)?.to_string());
+                record.data = unquote(&fields.collect::<Vec<&str>>().join(" "));
+            },
+            "SSHFP" => {
+                record.sshfp_algorithm = Some(number(fields.next())?);
+                record.sshfp_type = Some(number(fields.next())?);
+                record.data = fields.collect::<Vec<&str>>().join("");
+            },
+            "TLSA" => {
+                record.tlsa_usage = Some(number(fields.next())?);
+                record.tlsa_selector = Some(number(fields.next())?);
+                record.tlsa_matching = Some(number(fields.next())?);
+                record.data = fields.collect::<Vec<&str>>().join("");
+            },
+            "TXT" | "SPF" => record.data = unquote(content),
+            _ => record.data = content.trim().to_string(),
+        }
+
+        Ok(record)
+    }
+
+    /// The data of the record in presentation (zone file) format. Names are fully-qualified
+    /// (with the trailing dot) and text is quoted.
+    pub fn to_content(&self) -> String {
+        let name = |n: &str| format!("{}.", n.trim_end_matches('.'));
+
+        match self.r#type.to_uppercase().as_str() {
+            "CNAME" | "NS" | "PTR" => name(&self.data),
+            "MX" => format!("{} {}", self.mx_priority.unwrap_or(0), name(&self.data)),
+            "SRV" => format!("{} {} {} {}",
+                self.srv_priority.unwrap_or(0),
+                self.srv_weight.unwrap_or(0),
+                self.srv_port.unwrap_or(0),
+                name(&self.data)),
+            "CAA" => format!("{} {} {}",
+                self.caa_flags.unwrap_or(0),
+                self.caa_property.as_deref().unwrap_or("issue"),
+                quote(&self.data)),
+            "SSHFP" => format!("{} {} {}", self.sshfp_algorithm.unwrap_or(0), self.sshfp_type.unwrap_or(0), self.data),
+            "TLSA" => format!("{} {} {} {}",
+                self.tlsa_usage.unwrap_or(0),
+                self.tlsa_selector.unwrap_or(0),
+                self.tlsa_matching.unwrap_or(0),
+                self.data),
+            "TXT" | "SPF" => {
+                // Character strings are limited to 255 bytes, longer text is split in several strings
+                let chunks: Vec<String> = split_text(&self.data)
+                    .iter()
+                    .map(|c| quote(c))
+                    .collect();
+                chunks.join(" ")
+            },
+            _ => self.data.clone(),
+        }
+    }
+}
+
+/// A DNS template which can be applied to a zone, adding a set of records (e.g. those of a mail
+/// service)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Template {
+    pub name: String,
+    /// Whatever else the provider tells about the template (e.g. its description)
+    #[serde(flatten)]
+    pub metadata: BTreeMap<String, serde_json::Value>,
+}
+
+/// A DNS zone the credential can access
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Zone {
+    pub name: String,
+    /// Whatever else the provider tells about the zone (e.g. its identifier, status or serial)
+    #[serde(flatten)]
+    pub metadata: BTreeMap<String, serde_json::Value>,
+}
+
+impl Zone {
+    pub fn new(name: &str) -> Self {
+        Zone {
+            name: name.trim_end_matches('.').to_string(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Add a piece of metadata, unless the provider did not tell (null)
+    pub fn with<V: Into<serde_json::Value>>(mut self, key: &str, value: V) -> Self {
+        let value = value.into();
+        if !value.is_null() {
+            self.metadata.insert(key.to_string(), value);
+        }
+
+        self
+    }
+}
+
+/// Flags of records, which providers give either as a boolean or as the name of what the record
+/// comes from (e.g. the template)
+fn flag<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<bool>, D::Error> {
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Null => None,
+        serde_json::Value::Bool(b) => Some(b),
+        serde_json::Value::String(s) => Some(!s.is_empty()),
+        _ => Some(true),
+    })
+}
+
+/// Changes needed to replace the existing records (along with their API identifiers) with the
+/// wanted ones, for APIs which manage records one by one
+#[derive(Debug)]
+pub(crate) struct Changes<Id> {
+    pub create: Vec<Record>,
+    /// Records whose TTL or proxied flag changed, or which take the place of another record
+    pub update: Vec<(Id, Record)>,
+    pub delete: Vec<Id>,
+}
+
+
+impl<Id> Changes<Id> {
+    /// Turn a record to create into an update of a record to delete which it `replaces`, so the
+    /// name is never left without a record of the type while the changes are sent
+    pub fn replace_in_place<F: Fn(&Id, &Record) -> bool>(&mut self, replaces: F) {
+        for record in std::mem::take(&mut self.create) {
+            match self.delete.iter().position(|id| replaces(id, &record)) {
+                Some(p) => self.update.push((self.delete.remove(p), record)),
+                None => self.create.push(record),
+            }
+        }
+    }
+}
+
+/// Work out which records to create, update and delete. Records holding the same data are kept
+/// so they keep their identifiers.
+pub(crate) fn reconcile<Id>(zone: &str, existing: Vec<(Id, Record)>, wanted: &[Record]) -> Changes<Id> {
+    let mut existing = existing;
+    let mut changes = Changes {
+        create: vec![],
+        update: vec![],
+        delete: vec![],
+    };
+
+    for record in wanted {
+        let found = existing.iter().position(|(_, e)| same_record(zone, e, record));
+
+        match found {
+            Some(i) => {
+                let (id, current) = existing.remove(i);
+                let proxied = record.proxied.or(current.proxied);
+
+                if current.ttl != record.ttl || current.proxied != proxied {
+                    let mut changed = record.clone();
+                    changed.proxied = proxied;
+                    changes.update.push((id, changed));
+                }
+            },
+            None => changes.create.push(record.clone()),
+        }
+    }
+
+    changes.delete = existing.into_iter().map(|(id, _)| id).collect();
+
+    changes
+}
+
+/// Whether two records hold the same data for the same name, regardless of their TTL
+pub(crate) fn same_record(zone: &str, a: &Record, b: &Record) -> bool {
+    a.r#type.eq_ignore_ascii_case(&b.r#type)
+        && fqdn(zone, Some(&a.host)).eq_ignore_ascii_case(&fqdn(zone, Some(&b.host)))
+        && a.to_content().eq_ignore_ascii_case(&b.to_content())
+}
+
+/// Refuse to add records which already exist, or which are given more than once
+pub(crate) fn check_duplicates(zone: &str, existing: &[Record], added: &[Record]) -> Result<()> {
+    for (i, record) in added.iter().enumerate() {
+        let duplicate = existing.iter().chain(&added[..i]).any(|e| same_record(zone, e, record));
+
+        if duplicate {
+            return Err(ProviderError::new(ProviderErrorKind::DuplicateRecord)
+                .msg(format!("Record {} {} {} already exists!", fqdn(zone, Some(&record.host)), record.r#type.to_uppercase(), record.to_content())));
+        }
+    }
+
+    Ok(())
+}
+
+/// Join the quoted strings of a TXT-like value (e.g. `"v=spf1 " "-all"`) into plain text
+fn unquote(content: &str) -> String {
+    let content = content.trim();
+    if !content.starts_with('"') {
+        return content.to_string();
+    }
+
+    let mut text = String::new();
+    let mut chars = content.chars();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' if quoted => {
+                if let Some(escaped) = chars.next() {
+                    text.push(escaped);
+                }
+            },
+            _ if quoted => text.push(c),
+            _ => (),
+        }
+    }
+
+    text
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Split text into chunks of at most 255 bytes, without splitting characters
+fn split_text(text: &str) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+
+    while rest.len() > 255 {
+        let mut at = 255;
+        while !rest.is_char_boundary(at) {
+            at -= 1;
+        }
+
+        chunks.push(&rest[..at]);
+        rest = &rest[at..];
     }
+    chunks.push(rest);
+
+    chunks
+}
+
+/// Names of the supported providers, as accepted by `init_provider`
+pub const PROVIDERS: &[&str] = &[
+    "mythic-beasts",
+    "noip",
+    "dyndns2",
+    "cloudflare",
+    "rfc2136",
+    "powerdns",
+    "digitalocean",
+    "hetzner",
+    "linode",
+    "route53",
+    "desec",
+    "gandi",
+    "exec",
+    "zonefile",
+];
+
+/// Initialize provider based on the providers' name-id (e.g mythic-beasts)
+pub fn init_provider(name: &str) -> Result<Box<dyn Provider>> {
+    let provider: Box<dyn Provider> = match name {
+        "mythic-beasts" => Box::new(mythic_beasts::MythicBeasts::new()),
+        "noip" => Box::new(noip::new()),
+        "dyndns2" => Box::new(dyndns2::Dyndns2::new()),
+        "cloudflare" => Box::new(cloudflare::Cloudflare::new()),
+        "rfc2136" => Box::new(rfc2136::Rfc2136::new()),
+        "powerdns" => Box::new(powerdns::PowerDns::new()),
+        "digitalocean" => Box::new(digitalocean::DigitalOcean::new()),
+        "hetzner" => Box::new(hetzner::Hetzner::new()),
+        "linode" => Box::new(linode::Linode::new()),
+        "route53" => Box::new(route53::Route53::new()),
+        "desec" => Box::new(desec::Desec::new()),
+        "gandi" => Box::new(gandi::Gandi::new()),
+        "exec" => Box::new(exec::Exec::new()),
+        "zonefile" => Box::new(zonefile::ZoneFileProvider::new()),
+        _ => return Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(format!("Unknown provider {}, expected one of: {}", name, PROVIDERS.join(", ")))),
+    };
+
+    Ok(provider)
 }
 
 /// Get (filter) a provider credentials from configuration
-pub fn get_provider_credentials(provider: &Box<dyn Provider>, c: config::Configuration) -> config::Credentials {
+pub fn get_provider_credentials(provider: &dyn Provider, c: config::Configuration) -> config::Credentials {
     let creds: config::Credentials = c
     .credentials
     .into_iter()
@@ -62,23 +394,367 @@ pub fn get_provider_credentials(provider: &Box<dyn Provider>, c: config::Configu
     creds
 }
 
+/// Find the credential to use for the given zone|host|type selection
+///
+/// A credential matches either on its zone|host|type fields or, when its host is set, on the
+/// fully-qualified host name (e.g. a credential with `host: home.example.com` and no zone). When
+/// no credential is set up for the type, the one of the host without a type is used.
+pub(crate) fn get_credential(credentials: &Option<config::Credentials>, zone: &str, host: Option<&str>, r#type: Option<&str>) -> Result<config::Credential> {
+    // We either have one authentication credential configured -OR- user has used user-pass approach
+    if let Some(credential) = credentials {
+        if credential.len() == 1 {
+            return Ok(credential[0].clone());
+        }
+    }
+
+    let credential_filter = |c: &&config::Credential| -> bool {
+        let rtype_check = match r#type {
+            None => c.r#type.is_none(),
+            Some(rtype) => c.r#type.as_deref() == Some(rtype),
+        };
+
+        if !rtype_check {
+            return false;
+        }
+
+        let host_check = match host {
+            None => c.host.is_none(),
+            Some(h) => c.host.as_deref() == Some(h),
+        };
+
+        if c.zone.as_deref() == Some(zone) && host_check {
+            return true;
+        }
+
+        match &c.host {
+            Some(h) => fqdn(c.zone.as_deref().unwrap_or(""), Some(h)) == fqdn(zone, host),
+            None => false,
+        }
+    };
+
+    let credential: Option<&config::Credential> = credentials
+        .iter()
+        .flatten()
+        .find(credential_filter);
+
+    match credential {
+        Some(c) => Ok(c.clone()),
+        None if r#type.is_some() => get_credential(credentials, zone, host, None),
+        None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)),
+    }
+}
+
+/// List the zones of every account among the credentials and merge them by name. Credentials are
+/// usually set up per zone, so the same account is only asked once.
+pub(crate) fn list_account_zones<F>(credentials: &Option<config::Credentials>, list: F) -> Result<Vec<Zone>>
+where
+    F: Fn(&config::Credential) -> Result<Vec<Zone>>,
+{
+    let mut accounts: Vec<&config::Credential> = Vec::new();
+    for credential in credentials.iter().flatten() {
+        let known = accounts.iter().any(|a| a.user == credential.user
+            && a.pass == credential.pass
+            && a.token == credential.token
+            && a.api_url == credential.api_url
+            && a.command == credential.command
+            && a.args == credential.args);
 
+        if !known {
+            accounts.push(credential);
+        }
+    }
+
+    if accounts.is_empty() {
+        return Err(ProviderError::new(ProviderErrorKind::CredentialNotFound));
+    }
+
+    let mut zones: Vec<Zone> = Vec::new();
+    for account in accounts {
+        for zone in list(account)? {
+            if !zones.iter().any(|z| z.name.eq_ignore_ascii_case(&zone.name)) {
+                zones.push(zone);
+            }
+        }
+    }
+
+    zones.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(zones)
+}
 
-pub trait Provider: fmt::Debug {
+/// Kind of the error for a response with an unsuccessful status: whether the credential was
+/// refused, the client throttled or the server failing (all of which may go away), or else the
+/// request rejected
+pub(crate) fn status_error_kind(status: reqwest::StatusCode) -> ProviderErrorKind {
+    match status {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => ProviderErrorKind::AuthenticationFailed,
+        reqwest::StatusCode::TOO_MANY_REQUESTS => ProviderErrorKind::RateLimited,
+        s if s.is_server_error() => ProviderErrorKind::ServerError,
+        _ => ProviderErrorKind::DnsApiError,
+    }
+}
+
+/// Whether reqwest failed to send the request or read the response, as opposed to building the
+/// request, following redirects or decoding a response which came through. reqwest 0.10 has no
+/// `is_connect`, so decoding errors are told apart by the serde error they wrap.
+fn is_network_error(err: &reqwest::Error) -> bool {
+    if err.is_timeout() {
+        return true;
+    }
+    if err.is_builder() || err.is_redirect() || err.is_status() {
+        return false;
+    }
+    let mut source = error::Error::source(err);
+    while let Some(e) = source {
+        if e.is::<serde_json::Error>() {
+            return false;
+        }
+        source = e.source();
+    }
+    true
+}
+
+/// Base URL of the API, the `api_url` of the credential when set (e.g. a mock server or a proxy)
+/// or the default one of the provider, without the trailing slash
+pub(crate) fn api_url(credential: &config::Credential, default: &str) -> String {
+    credential.api_url.as_deref().unwrap_or(default).trim_end_matches('/').to_string()
+}
+
+/// Build the fully-qualified domain name of a host within a zone
+///
+/// The host can be relative to the zone (e.g. www), the bare domain (@) or an already
+/// fully-qualified host name (e.g. www.example.com), in which case it is returned as it is.
+pub(crate) fn fqdn(zone: &str, host: Option<&str>) -> String {
+    let zone = zone.trim_end_matches('.');
+
+    match host {
+        None | Some("") | Some("@") => zone.to_string(),
+        Some(h) => {
+            let h = h.trim_end_matches('.');
+
+            if zone.is_empty() || h == zone || h.ends_with(&format!(".{}", zone)) {
+                h.to_string()
+            } else {
+                format!("{}.{}", h, zone)
+            }
+        },
+    }
+}
+
+
+/// Strip the zone from a fully-qualified host name. The bare domain (apex) is returned as @
+pub(crate) fn relative_host(zone: &str, name: &str) -> String {
+    let zone = zone.trim_end_matches('.');
+    let name = name.trim_end_matches('.');
+
+    if name.eq_ignore_ascii_case(zone) {
+        return String::from("@");
+    }
+
+    let split_at = name.len().saturating_sub(zone.len() + 1);
+    if split_at > 0 && name.is_char_boundary(split_at) && name[split_at..].eq_ignore_ascii_case(&format!(".{}", zone)) {
+        return name[..split_at].to_string();
+    }
+
+    name.to_string()
+}
+
+
+/// The (UTC) year, month and day of a number of seconds since the Unix epoch
+pub(crate) fn civil_date(secs: u64) -> (i64, u32, u32) {
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = (secs / 86400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month as u32, day as u32)
+}
+
+
+/// Days since 1970-01-01 of a date of the proleptic Gregorian calendar, the inverse of `civil_date`
+pub(crate) fn civil_days(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+
+/// The records an operation applies to: all the records of a zone, optionally narrowed down to a
+/// host and a record type
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecordSelector {
+    pub zone: String,
+    pub host: Option<String>,
+    pub rtype: Option<String>,
+    /// Leave out the records the provider generates itself, where it tells (`None` for the
+    /// default of the operation)
+    pub exclude_generated: Option<bool>,
+    /// Leave out the records which come from DNS templates, where the provider tells (`None` for
+    /// the default of the operation)
+    pub exclude_template: Option<bool>,
+}
+
+/// Search the selected records
+#[derive(Debug, Clone)]
+pub struct SearchRequest {
+    pub selector: RecordSelector,
+}
+
+/// Replace the selected records with the given ones
+#[derive(Debug, Clone)]
+pub struct UpdateRequest {
+    pub selector: RecordSelector,
+    pub records: Vec<Record>,
+    /// Wait for the provider to apply the change before returning, where it tells
+    pub wait: bool,
+}
+
+/// Add the given records next to the selected ones
+#[derive(Debug, Clone)]
+pub struct AddRequest {
+    pub selector: RecordSelector,
+    pub records: Vec<Record>,
+    /// Wait for the provider to apply the change before returning, where it tells
+    pub wait: bool,
+}
+
+/// Delete the selected records
+#[derive(Debug, Clone)]
+pub struct DeleteRequest {
+    pub selector: RecordSelector,
+    /// Wait for the provider to apply the change before returning, where it tells
+    pub wait: bool,
+}
+
+/// Point the selected host at the address of the client
+#[derive(Debug, Clone)]
+pub struct DynamicDnsRequest {
+    pub selector: RecordSelector,
+    /// The address to publish, when it was detected. Providers which can be told the address
+    /// publish it, the others publish the address the request comes from.
+    pub address: Option<IpAddr>,
+}
+
+/// What the provider published for a DDNS request
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DynamicDnsResponse {
+    /// The address the host points at now, when the provider tells it or was told it. Providers
+    /// publishing the address the request comes from may not tell it.
+    pub address: Option<IpAddr>,
+    /// What the provider answered, or what was done when it does not answer in words
+    pub message: String,
+}
+
+/// List the DNS templates which can be applied to the zone
+#[derive(Debug, Clone)]
+pub struct TemplatesRequest {
+    pub zone: String,
+}
+
+/// Apply a DNS template to the zone
+#[derive(Debug, Clone)]
+pub struct ApplyTemplateRequest {
+    pub zone: String,
+    pub template: String,
+}
+
+
+impl RecordSelector {
+    pub fn new(zone: &str, host: Option<&str>, rtype: Option<&str>) -> Self {
+        RecordSelector {
+            zone: zone.to_string(),
+            host: host.map(String::from),
+            rtype: rtype.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    pub fn zone(&self) -> &str {
+        &self.zone
+    }
+
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    pub fn rtype(&self) -> Option<&str> {
+        self.rtype.as_deref()
+    }
+}
+
+
+pub trait Provider: fmt::Debug + Send + Sync {
     fn get_name(&self) -> String;
     fn set_credentials(&mut self, c: config::Credentials);
 
+    /// Keep what the provider has to remember between runs in the state file, e.g. a server
+    /// asking not to be contacted again
+    fn set_state(&mut self, _state: State) {}
+
     /// Dynamic DNS capability
-    fn dynamic_dns(&self, argm: &ArgMatches) -> Result<bool>;
+    fn dynamic_dns(&self, request: &DynamicDnsRequest) -> Result<DynamicDnsResponse>;
 
     /// Search for DNS record(s) via the API
-    fn search(&self, argm: &ArgMatches) -> Result<Option<Vec<Record>>>;
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>>;
 
     /// Update DNS record(s)
-    fn update(&self, argm: &ArgMatches, records: &Vec<Record>) -> Result<bool>;
+    fn update(&self, request: &UpdateRequest) -> Result<bool>;
+
+    /// Add DNS record(s), keeping the existing ones. Records which already exist are refused.
+    ///
+    /// By default the selected records are searched and replaced with themselves plus the new
+    /// ones. This is not atomic: a change made by someone else in between is lost, as are records
+    /// the search leaves out. Providers whose API can append records, or replace only the record
+    /// sets the new records belong to, should do so instead.
+    fn add(&self, request: &AddRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let existing = self.search(&SearchRequest { selector: request.selector.clone() })?.unwrap_or_default();
+        check_duplicates(zone, &existing, &request.records)?;
+
+        let mut records = existing;
+        records.extend(request.records.iter().cloned());
+
+        self.update(&UpdateRequest {
+            selector: request.selector.clone(),
+            records,
+            wait: request.wait,
+        })?;
+        log::info!("Added {} record(s)", request.records.len());
+
+        Ok(true)
+    }
 
     /// Delete DNS record(s)
-    fn delete(&self, argm: &ArgMatches) -> Result<bool>;
+    fn delete(&self, request: &DeleteRequest) -> Result<bool>;
+
+    /// List the zones the credential(s) can access
+    fn list_zones(&self) -> Result<Vec<Zone>> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(format!("The {} provider can not list zones!", self.get_name())))
+    }
+
+    /// List the DNS templates which can be applied to the zone
+    fn list_templates(&self, _request: &TemplatesRequest) -> Result<Vec<Template>> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(format!("The {} provider has no DNS templates!", self.get_name())))
+    }
+
+    /// Apply a DNS template to the zone, adding its records
+    fn apply_template(&self, _request: &ApplyTemplateRequest) -> Result<bool> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(format!("The {} provider has no DNS templates!", self.get_name())))
+    }
 }
 
 
@@ -92,41 +768,133 @@ pub struct ProviderError {
     kind: ProviderErrorKind,
     // TODO: consider converting this into a &str as we should know all error messages sizes
     message: Option<String>,
+    /// Error of a dependency which caused this one, available through `Error::source()`
+    source: Option<Box<dyn error::Error + Send + Sync + 'static>>,
 }
 
 /// Enum to store various types of errors that can cause the application to fail.
+///
+/// The errors of the HTTP client, the parsers and the async runtime are not part of the kinds, so
+/// upgrading those does not change this API; they can be reached through `Error::source()`.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ProviderErrorKind {
     CredentialNotFound,
-    ReqwestFail(reqwest::Error),
-    SerdeJsonError(serde_json::Error),
+    ReqwestFail,
+    SerdeJsonError,
+    SerdeXmlError,
+    IoError(std::io::Error),
+    TaskFailed,
     DnsApiError,
+    InvalidRecord,
+    /// The request is missing something or asks for something which makes no sense
+    InvalidInput,
+    Unsupported,
+    AuthenticationFailed,
+    HostNotFound,
+    NotFqdn,
+    TooManyHosts,
+    BadAgent,
+    NotDonator,
+    Abuse,
+    ServerError,
+    DnsError,
+    UnexpectedResponse,
+    RateLimited,
+    DuplicateRecord,
 }
 
-type Result<T> = std::result::Result<T, ProviderError>;
+pub type Result<T> = std::result::Result<T, ProviderError>;
 
 impl ProviderError {
     pub fn new(kind: ProviderErrorKind) -> Self {
         ProviderError {
             kind,
             message: None,
+            source: None,
+        }
+    }
+
+    /// Create an error with a message describing it instead of the default one of the kind
+    pub fn with_message(kind: ProviderErrorKind, message: &str) -> Self {
+        ProviderError::new(kind).msg(String::from(message))
+    }
+
+    /// What went wrong, for callers which handle some errors differently
+    pub fn kind(&self) -> &ProviderErrorKind {
+        &self.kind
+    }
+
+    /// Whether the operation may succeed when tried again later: the network failed or timed out,
+    /// the server failed (5xx) or throttled the client (429). Other errors need the user to act,
+    /// e.g. credentials, records the API rejected or features the provider does not support.
+    pub fn is_transient(&self) -> bool {
+        match &self.kind {
+            ProviderErrorKind::ReqwestFail => self.source
+                .as_ref()
+                .and_then(|e| e.downcast_ref::<reqwest::Error>())
+                .is_some_and(is_network_error),
+            ProviderErrorKind::IoError(e) => matches!(e.kind(),
+                std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::NotConnected
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::UnexpectedEof
+                | std::io::ErrorKind::Interrupted),
+            ProviderErrorKind::ServerError
+            | ProviderErrorKind::DnsError
+            | ProviderErrorKind::RateLimited => true,
+            _ => false,
         }
     }
 
     /// Add (optionally) a different error message
     /// * `msg` - New message string
-    fn msg(mut self, msg: String) -> Self {
+    pub(crate) fn msg(mut self, msg: String) -> Self {
         self.message = Some(msg);
         self
     }
 
+    fn caused_by<E: error::Error + Send + Sync + 'static>(kind: ProviderErrorKind, err: E) -> Self {
+        ProviderError {
+            kind,
+            message: None,
+            source: Some(Box::new(err)),
+        }
+    }
+
+    #[doc(hidden)]
+    fn __get_source_message(&self) -> String {
+        self.source.as_ref().map(|e| e.to_string()).unwrap_or_default()
+    }
+
     #[doc(hidden)]
     fn __get_default_message(&self) -> String {
         match &self.kind {
             ProviderErrorKind::CredentialNotFound => String::from("Unable to find credential!"),
-            ProviderErrorKind::ReqwestFail(e) => format!("Reqwest: {}", e.to_string()),
-            ProviderErrorKind::SerdeJsonError(e) => format!("Serde-JSON: {}", e.to_string()),
+            ProviderErrorKind::ReqwestFail => format!("Reqwest: {}", self.__get_source_message()),
+            ProviderErrorKind::SerdeJsonError => format!("Serde-JSON: {}", self.__get_source_message()),
+            ProviderErrorKind::SerdeXmlError => format!("Serde-XML: {}", self.__get_source_message()),
+            ProviderErrorKind::IoError(e) => format!("IO: {}", e),
+            ProviderErrorKind::TaskFailed => format!("Task: {}", self.__get_source_message()),
             ProviderErrorKind::DnsApiError => String::from("Received API error!"),
+            ProviderErrorKind::InvalidRecord => String::from("Invalid DNS record!"),
+            ProviderErrorKind::InvalidInput => String::from("Invalid request!"),
+            ProviderErrorKind::Unsupported => String::from("Operation not supported by the provider!"),
+            ProviderErrorKind::AuthenticationFailed => String::from("Invalid username or password!"),
+            ProviderErrorKind::HostNotFound => String::from("Hostname does not exist under the account!"),
+            ProviderErrorKind::NotFqdn => String::from("Hostname is not a fully-qualified domain name!"),
+            ProviderErrorKind::TooManyHosts => String::from("Too many hosts specified in the update!"),
+            ProviderErrorKind::BadAgent => String::from("Client has been blocked by the provider!"),
+            ProviderErrorKind::NotDonator => String::from("Feature requires an upgraded account!"),
+            ProviderErrorKind::Abuse => String::from("Hostname has been blocked for abuse!"),
+            ProviderErrorKind::ServerError => String::from("Provider is experiencing problems, try again later!"),
+            ProviderErrorKind::DnsError => String::from("Provider DNS error, try again later!"),
+            ProviderErrorKind::UnexpectedResponse => String::from("Received unexpected response!"),
+            ProviderErrorKind::RateLimited => String::from("Too many requests, try again later!"),
+            ProviderErrorKind::DuplicateRecord => String::from("Record already exists!"),
         }
     }
 }
@@ -145,21 +913,38 @@ impl fmt::Display for ProviderError {
 impl error::Error for ProviderError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match &self.kind {
-            ProviderErrorKind::ReqwestFail(e) => Some(e),
-            ProviderErrorKind::SerdeJsonError(e) => Some(e),
-            _ => None,
+            ProviderErrorKind::IoError(e) => Some(e),
+            _ => self.source.as_ref().map(|e| e.as_ref() as &(dyn error::Error + 'static)),
         }
     }
 }
 
 impl From<reqwest::Error> for ProviderError {
     fn from(err: reqwest::Error) -> ProviderError {
-        ProviderError::new(ProviderErrorKind::ReqwestFail(err))
+        ProviderError::caused_by(ProviderErrorKind::ReqwestFail, err)
     }
 }
 
 impl From<serde_json::Error> for ProviderError {
     fn from(err: serde_json::Error) -> ProviderError {
-        ProviderError::new(ProviderErrorKind::SerdeJsonError(err))
+        ProviderError::caused_by(ProviderErrorKind::SerdeJsonError, err)
+    }
+}
+
+impl From<quick_xml::DeError> for ProviderError {
+    fn from(err: quick_xml::DeError) -> ProviderError {
+        ProviderError::caused_by(ProviderErrorKind::SerdeXmlError, err)
+    }
+}
+
+impl From<std::io::Error> for ProviderError {
+    fn from(err: std::io::Error) -> ProviderError {
+        ProviderError::new(ProviderErrorKind::IoError(err))
+    }
+}
+
+impl From<tokio::task::JoinError> for ProviderError {
+    fn from(err: tokio::task::JoinError) -> ProviderError {
+        ProviderError::caused_by(ProviderErrorKind::TaskFailed, err)
     }
 }
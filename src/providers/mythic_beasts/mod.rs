@@ -0,0 +1,606 @@
+mod token;
+
+use super::{Provider, http, RecordSelector, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, AddRequest, DeleteRequest, TemplatesRequest, ApplyTemplateRequest, Record, Zone, Template, ProviderError, ProviderErrorKind, Result, get_credential, api_url, check_duplicates, list_account_zones, status_error_kind};
+use super::retry::{Idempotency, SendRetry};
+use crate::config;
+use token::{Authorization, Tokens};
+
+use reqwest::blocking::{RequestBuilder, Response};
+use serde::{Serialize, Deserialize};
+
+const API_URL: &str = "https://api.mythic-beasts.com/dns/v2";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ApiResponse {
+    pub error: Option<String>,
+    pub errors: Option<Vec<String>>,
+    pub message: Option<String>,
+    pub records_added: Option<u32>,
+    pub records_removed: Option<u32>,
+    pub records: Option<Vec<Record>>,
+    pub zones: Option<Vec<String>>,
+    pub templates: Option<Vec<TemplateEntry>>,
+}
+
+/// Templates are listed either by name or with their details
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+enum TemplateEntry {
+    Name(String),
+    Template(Template),
+}
+
+#[derive(Debug)]
+pub struct MythicBeasts {
+    name: String,
+    credentials: Option<config::Credentials>,
+    tokens: Tokens,
+}
+
+
+impl MythicBeasts {
+    pub fn new() -> Self {
+        MythicBeasts {
+            name: String::from("mythic-beasts"),
+            credentials: None,
+            tokens: Tokens::default(),
+        }
+    }
+
+    fn build_api_endpoint(api_url: &str, selector: &RecordSelector, filter: Option<&str>) -> String {
+        let mut endpoint = format!("{}/zones/{}/records", api_url, selector.zone());
+
+        if let Some(host) = selector.host() {
+            endpoint.push_str(&format!("/{}", host));
+        }
+
+        if let Some(r#type) = selector.rtype() {
+            endpoint.push_str(&format!("/{}", r#type));
+        }
+
+        if let Some(f) = filter {
+            endpoint.push_str(&format!("?{}", f));
+        }
+
+        endpoint
+    }
+
+    /// Query leaving out the generated and template records, as the selector asks or else when
+    /// the operation does so by default
+    fn filter(selector: &RecordSelector, exclude_by_default: bool) -> Option<String> {
+        let mut filters = Vec::new();
+
+        if selector.exclude_generated.unwrap_or(exclude_by_default) {
+            filters.push("exclude-generated=true");
+        }
+        if selector.exclude_template.unwrap_or(exclude_by_default) {
+            filters.push("exclude-template=true");
+        }
+
+        if filters.is_empty() { None } else { Some(filters.join("&")) }
+    }
+
+    /// The records as the API takes them, without the flags it reports
+    fn records_body(records: &[Record]) -> std::collections::HashMap<&'static str, Vec<Record>> {
+        let records = records
+            .iter()
+            .map(|r| Record { template: None, generated: None, ..r.clone() })
+            .collect();
+
+        let mut body = std::collections::HashMap::new();
+        body.insert("records", records);
+        body
+    }
+
+    /// Send an authenticated request, failing when the API answers with an error which may go away
+    /// (5xx, 429) so it is not mistaken for one the API explains in the body
+    fn send(&self, credential: &config::Credential, request: RequestBuilder, idempotency: Idempotency) -> Result<Response> {
+        let response = self.authorize_and_send(credential, request, idempotency)?;
+        let status = response.status();
+
+        // The API failing or throttling the client is worth trying again, unlike the errors it explains
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let text = response.text().unwrap_or_default();
+            log::trace!("Received response: {}", &text);
+
+            return Err(ProviderError::new(status_error_kind(status))
+                .msg(format!("Mythic Beasts API request failed with {}", status)));
+        }
+
+        Ok(response)
+    }
+
+    /// Send a request authenticated with a token, or with the key and secret when no token could
+    /// be obtained. A rejected token is renewed and the request sent once more.
+    fn authorize_and_send(&self, credential: &config::Credential, request: RequestBuilder, idempotency: Idempotency) -> Result<Response> {
+        let token = match self.tokens.authorization(credential) {
+            Authorization::Bearer(t) => t,
+            Authorization::Basic => return request
+                .basic_auth(&credential.user, Some(&credential.pass))
+                .send_retrying(idempotency),
+        };
+
+        let again = request.try_clone();
+        let response = request.bearer_auth(&token).send_retrying(idempotency)?;
+
+        match again {
+            // The API did not act on the request, so it is safe to send it again
+            Some(request) if response.status() == reqwest::StatusCode::UNAUTHORIZED => {
+                log::debug!("The API rejected the token, requesting a new one");
+                let request = match self.tokens.renew(credential) {
+                    Authorization::Bearer(t) => request.bearer_auth(&t),
+                    Authorization::Basic => request.basic_auth(&credential.user, Some(&credential.pass)),
+                };
+
+                request.send_retrying(idempotency)
+            },
+            _ => Ok(response),
+        }
+    }
+}
+
+
+impl Provider for MythicBeasts {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    /// Point the host at the address the request comes from, which the API does not tell
+    fn dynamic_dns(&self, request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        let zone = request.selector.zone();
+        let host = match request.selector.host() {
+            Some(h) => h,
+            None => return Err(ProviderError::new(ProviderErrorKind::InvalidInput)
+                .msg(String::from("DDNS requires the host to point at the address!"))),
+        };
+        let credentials = get_credential(&self.credentials, zone, Some(host), None)?;
+        let endpoint = format!("{}/zones/{}/dynamic/{}", api_url(&credentials, API_URL), zone, host);
+
+        let request = http::client()
+            .put(&endpoint);
+        let response = self.send(&credentials, request, Idempotency::Idempotent)?;
+
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        let result: ApiResponse = serde_json::from_str(&text)?;
+
+        if let Some(e) = result.error {
+            return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                .msg(format!("Unable to use DDNS feature. Reason: {}", e)));
+        }
+
+        let message = result.message.unwrap_or_else(|| format!("Updated {} in {}", host, zone));
+        log::info!("{}", message);
+
+        Ok(DynamicDnsResponse { address: None, message })
+    }
+
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let credentials = get_credential(&self.credentials, zone, host, rtype)?;
+        let filter = MythicBeasts::filter(&request.selector, false);
+        let url = MythicBeasts::build_api_endpoint(&api_url(&credentials, API_URL), &request.selector, filter.as_deref());
+
+        let request = http::client()
+            .get(&url);
+        let response = self.send(&credentials, request, Idempotency::Idempotent)?;
+
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        let result: ApiResponse = serde_json::from_str(&text)?;
+        log::trace!("{:#?}", result);
+
+        if let Some(e) = result.error {
+            return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                .msg(format!("Unable to get search results. Reason: {}", e)));
+        }
+
+        Ok(result.records)
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let credentials = get_credential(&self.credentials, zone, host, rtype)?;
+        // Generated and template records are left alone unless asked otherwise
+        let filter = MythicBeasts::filter(&request.selector, true);
+        let url = MythicBeasts::build_api_endpoint(&api_url(&credentials, API_URL), &request.selector, filter.as_deref());
+
+        let request = http::client()
+            .delete(&url);
+        let response = self.send(&credentials, request, Idempotency::Idempotent)?;
+
+        let response_status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        let result: ApiResponse = serde_json::from_str(&text)?;
+        log::trace!("{:#?}", result);
+
+        if response_status.is_client_error() {
+            if response_status == reqwest::StatusCode::BAD_REQUEST {
+                if let Some(e) = result.errors {
+                    return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                        .msg(format!("Unable to delete selected record(s). Reasons: \n - {}", e.join("\n - "))));
+                }
+            }
+
+            if let Some(e) = result.error {
+                return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                    .msg(format!("Unable to delete selected record(s). Reason: {}", e)));
+            }
+        }
+
+        if let Some(r) = result.records_removed {
+            log::info!("Deleted {} record(s)", r);
+            return Ok(true);
+        }
+
+        Ok(true)
+    }
+
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let recs = MythicBeasts::records_body(&request.records);
+
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let credentials = get_credential(&self.credentials, zone, host, rtype)?;
+        // Generated and template records are left alone unless asked otherwise
+        let filter = MythicBeasts::filter(&request.selector, true);
+        let url = MythicBeasts::build_api_endpoint(&api_url(&credentials, API_URL), &request.selector, filter.as_deref());
+
+        let request = http::client()
+            .put(&url)
+            .json(&recs);
+        let response = self.send(&credentials, request, Idempotency::Idempotent)?;
+
+        let response_status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        let result: ApiResponse = serde_json::from_str(&text)?;
+        log::trace!("{:#?}", result);
+
+        if response_status.is_client_error() {
+            if response_status == reqwest::StatusCode::BAD_REQUEST {
+                if let Some(e) = result.errors {
+                    return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                        .msg(format!("Unable to update selected record(s). Reasons: \n - {}", e.join("\n - "))));
+                }
+            }
+
+            if let Some(e) = result.error {
+                return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                    .msg(format!("Unable to update selected record(s). Reason: {}", e)));
+            }
+        }
+
+        let records_added: u32 = result.records_added.unwrap_or_default();
+        let records_removed: u32 = result.records_removed.unwrap_or_default();
+
+        log::info!("Updated record(s)!");
+        log::debug!("Added {} record(s). Removed {} record(s)", records_added, records_removed);
+
+        Ok(true)
+    }
+
+    /// Append the records with a POST, which leaves the existing records alone
+    fn add(&self, request: &AddRequest) -> Result<bool> {
+        let existing = self.search(&SearchRequest { selector: request.selector.clone() })?.unwrap_or_default();
+        check_duplicates(request.selector.zone(), &existing, &request.records)?;
+
+        let recs = MythicBeasts::records_body(&request.records);
+
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let credentials = get_credential(&self.credentials, zone, host, rtype)?;
+        let url = MythicBeasts::build_api_endpoint(&api_url(&credentials, API_URL), &request.selector, None);
+
+        let request = http::client()
+            .post(&url)
+            .json(&recs);
+        // Sending it twice would add the records twice
+        let response = self.send(&credentials, request, Idempotency::NonIdempotent)?;
+
+        let response_status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        let result: ApiResponse = serde_json::from_str(&text)?;
+        log::trace!("{:#?}", result);
+
+        if response_status.is_client_error() {
+            if response_status == reqwest::StatusCode::BAD_REQUEST {
+                if let Some(e) = result.errors {
+                    return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                        .msg(format!("Unable to add record(s). Reasons: \n - {}", e.join("\n - "))));
+                }
+            }
+
+            if let Some(e) = result.error {
+                return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                    .msg(format!("Unable to add record(s). Reason: {}", e)));
+            }
+        }
+
+        log::info!("Added {} record(s)", result.records_added.unwrap_or_default());
+
+        Ok(true)
+    }
+
+    fn list_zones(&self) -> Result<Vec<Zone>> {
+        list_account_zones(&self.credentials, |credentials| {
+            let request = http::client().get(&format!("{}/zones", api_url(credentials, API_URL)));
+            let response = self.send(credentials, request, Idempotency::Idempotent)?;
+
+            let text = response.text()?;
+            log::trace!("Received response: {}", &text);
+
+            let result: ApiResponse = serde_json::from_str(&text)?;
+
+            if let Some(e) = result.error {
+                return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                    .msg(format!("Unable to list the zones. Reason: {}", e)));
+            }
+
+            Ok(result.zones.unwrap_or_default().iter().map(|z| Zone::new(z)).collect())
+        })
+    }
+
+    fn list_templates(&self, request: &TemplatesRequest) -> Result<Vec<Template>> {
+        let credentials = get_credential(&self.credentials, &request.zone, None, None)?;
+        let url = format!("{}/zones/{}/templates", api_url(&credentials, API_URL), request.zone);
+
+        let request = http::client()
+            .get(&url);
+        let response = self.send(&credentials, request, Idempotency::Idempotent)?;
+
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        let result: ApiResponse = serde_json::from_str(&text)?;
+
+        if let Some(e) = result.error {
+            return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                .msg(format!("Unable to list the templates. Reason: {}", e)));
+        }
+
+        Ok(result.templates
+            .unwrap_or_default()
+            .into_iter()
+            .map(|t| match t {
+                TemplateEntry::Name(name) => Template { name, ..Default::default() },
+                TemplateEntry::Template(t) => t,
+            })
+            .collect())
+    }
+
+    fn apply_template(&self, request: &ApplyTemplateRequest) -> Result<bool> {
+        let credentials = get_credential(&self.credentials, &request.zone, None, None)?;
+        let url = format!("{}/zones/{}/templates/{}", api_url(&credentials, API_URL), request.zone, request.template);
+
+        let request = http::client()
+            .post(&url);
+        // Sending it twice could add the records of the template twice
+        let response = self.send(&credentials, request, Idempotency::NonIdempotent)?;
+
+        let response_status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        let result: ApiResponse = serde_json::from_str(&text)?;
+        log::trace!("{:#?}", result);
+
+        if response_status.is_client_error() || response_status.is_server_error() {
+            if let Some(e) = result.errors {
+                return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                    .msg(format!("Unable to apply the template. Reasons: \n - {}", e.join("\n - "))));
+            }
+
+            return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                .msg(format!("Unable to apply the template. Reason: {}", result.error.unwrap_or_else(|| response_status.to_string()))));
+        }
+
+        log::info!("Applied the template, adding {} record(s)", result.records_added.unwrap_or_default());
+
+        Ok(true)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{Reply, Request, Server};
+
+    const RECORDS: &str = include_str!("../../../tests/fixtures/mythic_beasts/records.json");
+    const ZONES: &str = include_str!("../../../tests/fixtures/mythic_beasts/zones.json");
+
+    /// The recorded records, filtered by host and type as the API does
+    fn records(request: &Request) -> Reply {
+        let segments: Vec<&str> = request.path().trim_start_matches("/zones/example.com/records").split('/').skip(1).collect();
+        let mut body: serde_json::Value = serde_json::from_str(RECORDS).unwrap();
+
+        if let Some(records) = body["records"].as_array_mut() {
+            records.retain(|r| {
+                segments.first().is_none_or(|h| r["host"] == *h)
+                    && segments.get(1).is_none_or(|t| r["type"] == *t)
+                    && (request.query("exclude-generated").is_none() || r["generated"] == false)
+                    && (request.query("exclude-template").is_none() || r["template"].is_null())
+            });
+        }
+
+        Reply::new(200, &body.to_string())
+    }
+
+    /// A Mythic Beasts API issuing tokens at /login and serving example.com to the requests
+    /// authenticated with them
+    fn api() -> Server {
+        Server::start(|request| {
+            if request.path() == "/login" {
+                return Reply::new(200, r#"{"access_token": "token", "expires_in": 300, "token_type": "bearer"}"#);
+            }
+            if request.header("Authorization") != Some("Bearer token") {
+                return Reply::new(401, r#"{"error": "Authentication failed"}"#);
+            }
+
+            match (request.method.as_str(), request.path()) {
+                ("GET", "/zones") => Reply::new(200, ZONES),
+                ("GET", p) if p.starts_with("/zones/example.com/records") => records(request),
+                ("PUT", "/zones/example.com/dynamic/www") => Reply::new(200, r#"{"message": "1 record updated"}"#),
+                ("PUT", p) if p.starts_with("/zones/example.com/records") => Reply::new(200, r#"{"records_added": 1, "records_removed": 1}"#),
+                ("DELETE", "/zones/example.com/records/www/TXT") => Reply::new(400, r#"{"errors": ["No records match the selection"]}"#),
+                ("DELETE", p) if p.starts_with("/zones/example.com/records") => Reply::new(200, r#"{"records_removed": 1}"#),
+                ("POST", "/zones/example.com/templates/busy") => Reply::new(503, "Service Unavailable"),
+                _ => Reply::new(404, r#"{"error": "Zone not found"}"#),
+            }
+        })
+    }
+
+    fn provider(server: &Server, api_url: &str) -> MythicBeasts {
+        let mut provider = MythicBeasts::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("mythic-beasts"),
+            user: String::from("key"),
+            pass: String::from("secret"),
+            api_url: Some(api_url.to_string()),
+            auth_url: Some(format!("{}/login", server.url())),
+            ..Default::default()
+        }]);
+
+        provider
+    }
+
+    #[test]
+    fn searches_the_records_of_a_host() {
+        let server = api();
+        let records = provider(&server, server.url())
+            .search(&SearchRequest { selector: RecordSelector::new("example.com", Some("www"), None) })
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].data, "192.0.2.1");
+        assert_eq!(records[1].template, Some(true));
+        assert_eq!(records[1].generated, Some(false));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path(), "/login");
+        assert_eq!(requests[1].target, "/zones/example.com/records/www");
+    }
+
+    #[test]
+    fn maps_the_fields_of_mx_and_srv_records() {
+        let server = api();
+        let records = provider(&server, server.url())
+            .search(&SearchRequest { selector: RecordSelector::new("example.com", None, None) })
+            .unwrap()
+            .unwrap();
+
+        let mx = records.iter().find(|r| r.r#type == "MX").unwrap();
+        assert_eq!(mx.mx_priority, Some(10));
+
+        let srv = records.iter().find(|r| r.r#type == "SRV").unwrap();
+        assert_eq!((srv.srv_priority, srv.srv_weight, srv.srv_port), (Some(10), Some(5), Some(5060)));
+    }
+
+    #[test]
+    fn honours_an_api_url_with_a_trailing_slash() {
+        let server = api();
+        let api_url = format!("{}/", server.url());
+        let records = provider(&server, &api_url)
+            .search(&SearchRequest { selector: RecordSelector::new("example.com", Some("www"), Some("A")) })
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(server.requests()[1].target, "/zones/example.com/records/www/A");
+    }
+
+    #[test]
+    fn updates_the_address_of_a_host() {
+        let server = api();
+        let request = DynamicDnsRequest { selector: RecordSelector::new("example.com", Some("www"), None), address: None };
+
+        // The API publishes the address the request comes from, without telling it
+        let response = provider(&server, server.url()).dynamic_dns(&request).unwrap();
+        assert_eq!(response, DynamicDnsResponse { address: None, message: String::from("1 record updated") });
+        let requests = server.requests();
+        assert_eq!(requests[1].method, "PUT");
+        assert_eq!(requests[1].target, "/zones/example.com/dynamic/www");
+    }
+
+    #[test]
+    fn requires_a_host_for_ddns() {
+        let server = api();
+        let request = DynamicDnsRequest { selector: RecordSelector::new("example.com", None, None), address: None };
+        let error = provider(&server, server.url()).dynamic_dns(&request).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::InvalidInput));
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn replaces_the_records_leaving_generated_and_template_ones_alone() {
+        let server = api();
+        let record = Record { host: String::from("www"), ttl: 300, r#type: String::from("A"), data: String::from("192.0.2.9"), template: Some(false), ..Default::default() };
+        let request = UpdateRequest { selector: RecordSelector::new("example.com", Some("www"), Some("A")), records: vec![record], wait: false };
+
+        assert!(provider(&server, server.url()).update(&request).unwrap());
+        let sent = &server.requests()[1];
+        assert_eq!(sent.target, "/zones/example.com/records/www/A?exclude-generated=true&exclude-template=true");
+
+        let body: serde_json::Value = serde_json::from_str(&sent.body).unwrap();
+        assert_eq!(body["records"][0]["data"], "192.0.2.9");
+        assert!(body["records"][0].get("template").is_none());
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_api() {
+        let server = api();
+        let error = provider(&server, server.url())
+            .delete(&DeleteRequest { selector: RecordSelector::new("example.com", Some("www"), Some("TXT")), wait: false })
+            .unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DnsApiError));
+        assert!(!error.is_transient());
+        assert!(error.to_string().contains("No records match the selection"));
+
+        let error = provider(&server, server.url())
+            .search(&SearchRequest { selector: RecordSelector::new("example.net", None, None) })
+            .unwrap_err();
+        assert!(error.to_string().contains("Zone not found"));
+    }
+
+    #[test]
+    fn lists_the_zones() {
+        let server = api();
+        let zones = provider(&server, server.url()).list_zones().unwrap();
+
+        assert_eq!(zones.iter().map(|z| z.name.as_str()).collect::<Vec<&str>>(), vec!["example.com", "example.org"]);
+    }
+
+    #[test]
+    fn reports_the_api_being_unavailable_as_transient() {
+        let server = api();
+        let error = provider(&server, server.url())
+            .apply_template(&ApplyTemplateRequest { zone: String::from("example.com"), template: String::from("busy") })
+            .unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::ServerError));
+        assert!(error.is_transient());
+        // Applying a template is not sent twice
+        assert_eq!(server.requests().iter().filter(|r| r.path().contains("/templates/")).count(), 1);
+    }
+}
@@ -0,0 +1,330 @@
+//! Bearer tokens of the Mythic Beasts API.
+//!
+//! The API key and secret are exchanged for a short-lived token at the auth endpoint, so they are
+//! only sent when a token is issued instead of with every request. Tokens are kept in memory, and
+//! in the `token_cache` file of the credential when set, until shortly before they expire. When
+//! the auth endpoint does not issue a token the requests fall back to basic authentication: for
+//! good when it rejects the request (401, 403 or 404), else until it is worth asking again.
+use crate::config;
+use crate::providers::{http, ProviderError, ProviderErrorKind, Result};
+use crate::providers::retry::{Idempotency, SendRetry};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+
+const AUTH_URL: &str = "https://auth.mythic-beasts.com/login";
+
+/// Tokens are renewed this many seconds before they expire, so they do not expire in flight
+const EXPIRY_MARGIN: u64 = 30;
+
+/// Seconds basic authentication is used for after the auth endpoint failed for another reason
+/// (e.g. it could not be reached), before a token is requested again
+const FALLBACK_DURATION: u64 = 300;
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Token {
+    access_token: String,
+    /// Seconds since the epoch
+    expires_at: u64,
+}
+
+/// How requests are authenticated
+pub enum Authorization {
+    Bearer(String),
+    /// Send the key and secret with every request
+    Basic,
+}
+
+#[derive(Clone)]
+enum Cached {
+    Token(Token),
+    /// The auth endpoint did not issue a token, and should not be asked again before the time (in
+    /// seconds since the epoch) when there is one
+    Unavailable(Option<u64>),
+}
+
+/// Tokens of the credentials in use, by auth endpoint and API key
+#[derive(Default)]
+pub struct Tokens {
+    tokens: Mutex<HashMap<String, Cached>>,
+}
+
+
+impl Token {
+    fn is_valid(&self) -> bool {
+        now() + EXPIRY_MARGIN < self.expires_at
+    }
+}
+
+impl Cached {
+    fn authorization(&self) -> Authorization {
+        match self {
+            Cached::Token(t) => Authorization::Bearer(t.access_token.clone()),
+            Cached::Unavailable(_) => Authorization::Basic,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        match self {
+            Cached::Token(t) => t.is_valid(),
+            Cached::Unavailable(retry_at) => retry_at.is_none_or(|at| now() < at),
+        }
+    }
+}
+
+impl Tokens {
+    /// Authorization for the requests of the credential, requesting a token if there is no valid one
+    pub fn authorization(&self, credential: &config::Credential) -> Authorization {
+        self.get(credential, false)
+    }
+
+    /// Authorization with a new token, after the API rejected the current one
+    pub fn renew(&self, credential: &config::Credential) -> Authorization {
+        self.get(credential, true)
+    }
+
+    fn get(&self, credential: &config::Credential, renew: bool) -> Authorization {
+        let auth_url = credential.auth_url.as_deref().unwrap_or(AUTH_URL);
+        let key = format!("{} {}", auth_url, credential.user);
+
+        // Held while requesting the token, so operations running in parallel share a single one
+        let mut tokens = self.tokens.lock().expect("Lock is never poisoned");
+
+        if !renew {
+            if let Some(cached) = tokens.get(&key).filter(|c| c.is_valid()) {
+                return cached.authorization();
+            }
+
+            if let Some(token) = credential.token_cache.as_deref().and_then(|path| load(path, &key)) {
+                log::debug!("Using the API token cached on disk");
+                let cached = Cached::Token(token);
+                tokens.insert(key, cached.clone());
+                return cached.authorization();
+            }
+        }
+
+        let cached = match request_token(auth_url, credential) {
+            Ok(token) => {
+                if let Some(path) = credential.token_cache.as_deref() {
+                    if let Err(e) = save(path, &key, &token) {
+                        log::warn!("Unable to cache the API token in {}: {}", path, e);
+                    }
+                }
+                Cached::Token(token)
+            },
+            // The endpoint will keep refusing to issue a token
+            Err(e) if matches!(e.kind(), ProviderErrorKind::AuthenticationFailed | ProviderErrorKind::Unsupported) => {
+                log::warn!("Falling back to basic authentication. {}", e);
+                Cached::Unavailable(None)
+            },
+            Err(e) => {
+                log::warn!("Falling back to basic authentication for {} seconds. {}", FALLBACK_DURATION, e);
+                Cached::Unavailable(Some(now() + FALLBACK_DURATION))
+            },
+        };
+
+        tokens.insert(key, cached.clone());
+        cached.authorization()
+    }
+}
+
+impl fmt::Debug for Tokens {
+    // Keep the tokens out of debug output
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = self.tokens.lock().map(|t| t.len()).unwrap_or_default();
+        f.debug_struct("Tokens").field("count", &count).finish()
+    }
+}
+
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Exchange the API key and secret of the credential for a token
+fn request_token(auth_url: &str, credential: &config::Credential) -> Result<Token> {
+    // Issuing a token twice is harmless, the first one is simply never used
+    let response = http::client()
+        .post(auth_url)
+        .basic_auth(&credential.user, Some(&credential.pass))
+        .form(&[("grant_type", "client_credentials")])
+        .send_retrying(Idempotency::Idempotent)?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let kind = match status {
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => ProviderErrorKind::AuthenticationFailed,
+            reqwest::StatusCode::NOT_FOUND => ProviderErrorKind::Unsupported,
+            _ => ProviderErrorKind::DnsApiError,
+        };
+
+        return Err(ProviderError::new(kind)
+            .msg(format!("Unable to obtain an API token. Received status {}", status)));
+    }
+
+    let token: TokenResponse = serde_json::from_str(&response.text()?)?;
+    log::debug!("Obtained an API token valid for {} seconds", token.expires_in);
+
+    Ok(Token {
+        access_token: token.access_token,
+        expires_at: now() + token.expires_in,
+    })
+}
+
+/// Valid token of the key from the cache file, if any
+fn load(path: &str, key: &str) -> Option<Token> {
+    let content = fs::read_to_string(path).ok()?;
+    let tokens: HashMap<String, Token> = serde_json::from_str(&content).ok()?;
+
+    tokens.get(key).filter(|t| t.is_valid()).cloned()
+}
+
+/// Store the token of the key in the cache file, dropping the expired ones. The file is replaced
+/// at once and only readable by the owner.
+fn save(path: &str, key: &str, token: &Token) -> Result<()> {
+    let mut tokens: HashMap<String, Token> = fs::read_to_string(path)
+        .ok()
+        .and_then(|c| serde_json::from_str(&c).ok())
+        .unwrap_or_default();
+
+    tokens.retain(|_, t| t.is_valid());
+    tokens.insert(key.to_string(), token.clone());
+
+    // Created afresh so a leftover file does not keep its permissions
+    let temporary = format!("{}.tmp", path);
+    let _ = fs::remove_file(&temporary);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&temporary)?;
+    file.write_all(serde_json::to_string(&tokens)?.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{Reply, Server};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU16, Ordering};
+
+    /// An auth endpoint answering with the status, issuing a token when it is 200
+    fn auth_endpoint(status: Arc<AtomicU16>) -> Server {
+        Server::start(move |_| match status.load(Ordering::SeqCst) {
+            200 => Reply::new(200, r#"{"access_token": "token", "expires_in": 300}"#),
+            s => Reply { headers: vec![(String::from("Retry-After"), String::from("0"))], ..Reply::new(s, "") },
+        })
+    }
+
+    fn credential(server: &Server) -> config::Credential {
+        config::Credential {
+            provider: String::from("mythic-beasts"),
+            user: String::from("key"),
+            pass: String::from("secret"),
+            auth_url: Some(format!("{}/login", server.url())),
+            ..Default::default()
+        }
+    }
+
+    fn is_bearer(authorization: Authorization) -> bool {
+        matches!(authorization, Authorization::Bearer(_))
+    }
+
+    #[test]
+    fn requests_a_token_once_and_sends_the_key_only_to_the_auth_endpoint() {
+        let server = auth_endpoint(Arc::new(AtomicU16::new(200)));
+        let tokens = Tokens::default();
+
+        assert!(is_bearer(tokens.authorization(&credential(&server))));
+        assert!(is_bearer(tokens.authorization(&credential(&server))));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].body, "grant_type=client_credentials");
+        assert!(requests[0].header("Authorization").unwrap().starts_with("Basic "));
+    }
+
+    #[test]
+    fn renews_the_token() {
+        let server = auth_endpoint(Arc::new(AtomicU16::new(200)));
+        let tokens = Tokens::default();
+
+        assert!(is_bearer(tokens.authorization(&credential(&server))));
+        assert!(is_bearer(tokens.renew(&credential(&server))));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn keeps_to_basic_authentication_when_the_endpoint_refuses_tokens() {
+        for status in &[401, 403, 404] {
+            let server = auth_endpoint(Arc::new(AtomicU16::new(*status)));
+            let tokens = Tokens::default();
+
+            assert!(!is_bearer(tokens.authorization(&credential(&server))));
+            assert!(!is_bearer(tokens.authorization(&credential(&server))));
+            assert_eq!(server.requests().len(), 1, "status {}", status);
+        }
+    }
+
+    #[test]
+    fn asks_for_a_token_again_after_a_temporary_failure() {
+        let status = Arc::new(AtomicU16::new(503));
+        let server = auth_endpoint(Arc::clone(&status));
+        let tokens = Tokens::default();
+
+        assert!(!is_bearer(tokens.authorization(&credential(&server))));
+        let attempts = server.requests().len();
+
+        // Basic authentication is used meanwhile, without asking the endpoint
+        status.store(200, Ordering::SeqCst);
+        assert!(!is_bearer(tokens.authorization(&credential(&server))));
+        assert_eq!(server.requests().len(), attempts);
+
+        // Once the fallback expired
+        for cached in tokens.tokens.lock().unwrap().values_mut() {
+            *cached = Cached::Unavailable(Some(now()));
+        }
+        assert!(is_bearer(tokens.authorization(&credential(&server))));
+        assert_eq!(server.requests().len(), attempts + 1);
+    }
+
+    #[test]
+    fn shares_the_token_through_the_cache_file() {
+        let server = auth_endpoint(Arc::new(AtomicU16::new(200)));
+        let path = std::env::temp_dir().join(format!("ddnsclient-tokens-{}.json", std::process::id()));
+        let credential = config::Credential { token_cache: Some(path.to_string_lossy().to_string()), ..credential(&server) };
+
+        assert!(is_bearer(Tokens::default().authorization(&credential)));
+        assert!(is_bearer(Tokens::default().authorization(&credential)));
+        assert_eq!(server.requests().len(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,107 @@
+//! No-IP speaks the dyndns2 protocol so it is a dyndns2 provider with a well-known server.
+use super::dyndns2::Dyndns2;
+
+const API_URL: &str = "https://dynupdate.no-ip.com";
+
+/// No-IP blocks clients which do not identify themselves with a meaningful user agent (`badagent`),
+/// so it is sent whatever the user agent configured for the other providers
+const USER_AGENT: &str = concat!("ddnsclient/", env!("CARGO_PKG_VERSION"), " ", env!("CARGO_PKG_REPOSITORY"));
+
+pub fn new() -> Dyndns2 {
+    Dyndns2::with_server("noip", API_URL).with_user_agent(USER_AGENT)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Credential;
+    use crate::providers::{Provider, DynamicDnsRequest, DynamicDnsResponse, RecordSelector, ProviderErrorKind};
+    use crate::providers::test_server::{Reply, Server};
+
+    fn provider(server: &Server) -> Dyndns2 {
+        let mut provider = new();
+        provider.set_credentials(vec![Credential {
+            provider: String::from("noip"),
+            user: String::from("user"),
+            pass: String::from("secret"),
+            api_url: Some(server.url().to_string()),
+            ..Default::default()
+        }]);
+
+        provider
+    }
+
+    fn ddns(provider: &Dyndns2, zone: &str, host: Option<&str>) -> crate::providers::Result<DynamicDnsResponse> {
+        provider.dynamic_dns(&DynamicDnsRequest { selector: RecordSelector::new(zone, host, None), address: None })
+    }
+
+    #[test]
+    fn updates_the_host_by_its_fully_qualified_name() {
+        let server = Server::start(|_| Reply::new(200, "good 203.0.113.1\r\n"));
+        let provider = provider(&server);
+
+        // The address the server published is reported, with its answer
+        let response = ddns(&provider, "ddns.net", Some("myhost")).unwrap();
+        assert_eq!(response.address, Some("203.0.113.1".parse().unwrap()));
+        assert_eq!(response.message, "good 203.0.113.1");
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].path(), "/nic/update");
+        assert_eq!(requests[0].query("hostname").as_deref(), Some("myhost.ddns.net"));
+        // user:secret
+        assert_eq!(requests[0].header("Authorization"), Some("Basic dXNlcjpzZWNyZXQ="));
+        assert_eq!(requests[0].header("User-Agent"), Some(USER_AGENT));
+    }
+
+    #[test]
+    fn takes_the_zone_as_the_hostname() {
+        let server = Server::start(|_| Reply::new(200, "nochg 203.0.113.1"));
+        let provider = provider(&server);
+
+        ddns(&provider, "myhost.ddns.net", None).unwrap();
+        assert_eq!(server.requests()[0].query("hostname").as_deref(), Some("myhost.ddns.net"));
+    }
+
+    #[test]
+    fn stops_contacting_the_server_after_badauth() {
+        let server = Server::start(|_| Reply::new(200, "badauth"));
+        let provider = provider(&server);
+
+        let error = ddns(&provider, "myhost.ddns.net", None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+
+        let error = ddns(&provider, "myhost.ddns.net", None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn backs_off_after_a_server_error() {
+        let server = Server::start(|_| Reply::new(200, "911"));
+        let provider = provider(&server);
+
+        let error = ddns(&provider, "myhost.ddns.net", None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::ServerError));
+
+        let error = ddns(&provider, "myhost.ddns.net", None).unwrap_err();
+        assert!(error.to_string().contains("back off"));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn reports_unknown_hosts_and_unexpected_answers() {
+        let server = Server::start(|r| match r.query("hostname").as_deref() {
+            Some("missing.ddns.net") => Reply::new(200, "nohost"),
+            _ => Reply::new(200, "<html>maintenance</html>"),
+        });
+
+        let error = ddns(&provider(&server), "missing.ddns.net", None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::HostNotFound));
+
+        let error = ddns(&provider(&server), "myhost.ddns.net", None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::UnexpectedResponse));
+    }
+}
@@ -0,0 +1,607 @@
+use super::{Provider, http, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, AddRequest, DeleteRequest, Record, ProviderError, ProviderErrorKind, Result, get_credential, api_url, check_duplicates, list_account_zones, fqdn, relative_host, status_error_kind};
+use super::retry::{Idempotency, SendRetry};
+use crate::config;
+
+use serde::{Serialize, Deserialize};
+
+/// Default API endpoint of a local PowerDNS Authoritative server
+const API_URL: &str = "http://127.0.0.1:8081/api/v1/servers/localhost";
+
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    error: Option<String>,
+    errors: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Zone {
+    #[serde(default)]
+    name: String,
+    kind: Option<String>,
+    serial: Option<u64>,
+    dnssec: Option<bool>,
+    /// Only included when asking for a single zone
+    #[serde(default)]
+    rrsets: Vec<RRSet>,
+}
+
+/// A set of records sharing the same name and type
+#[derive(Serialize, Deserialize, Debug)]
+struct RRSet {
+    name: String,
+    r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changetype: Option<String>,
+    #[serde(default)]
+    records: Vec<RRSetRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RRSetRecord {
+    content: String,
+    #[serde(default)]
+    disabled: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Patch {
+    rrsets: Vec<RRSet>,
+}
+
+#[derive(Debug)]
+pub struct PowerDns {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+
+impl RRSet {
+    fn is_selected(&self, zone: &str, name: Option<&str>, rtype: Option<&str>) -> bool {
+        let is_apex = relative_host(zone, &self.name) == "@";
+
+        // Never touch the records which keep the zone working
+        if self.r#type == "SOA" || (is_apex && self.r#type == "NS") {
+            return false;
+        }
+
+        name.is_none_or(|n| n.eq_ignore_ascii_case(self.name.trim_end_matches('.')))
+            && rtype.is_none_or(|t| t.eq_ignore_ascii_case(&self.r#type))
+    }
+
+    fn delete(name: &str, rtype: &str) -> Self {
+        RRSet {
+            name: name.to_string(),
+            r#type: rtype.to_string(),
+            ttl: None,
+            changetype: Some(String::from("DELETE")),
+            records: vec![],
+        }
+    }
+}
+
+
+impl PowerDns {
+    pub fn new() -> Self {
+        PowerDns {
+            name: String::from("powerdns"),
+            credentials: None,
+        }
+    }
+
+    fn get_credential(&self, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<(String, String)> {
+        PowerDns::api_access(&get_credential(&self.credentials, zone, host, rtype)?)
+    }
+
+    /// Base URL of the API and the token of the credential
+    fn api_access(credential: &config::Credential) -> Result<(String, String)> {
+        let api_url = api_url(credential, API_URL);
+
+        match &credential.token {
+            Some(t) => Ok((api_url, t.clone())),
+            None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+                .msg(String::from("PowerDNS requires an API key as token!"))),
+        }
+    }
+
+    /// Zones are identified by their canonical name (with the trailing dot)
+    fn build_api_endpoint(api_url: &str, zone: &str) -> String {
+        format!("{}/zones/{}.", api_url, zone.trim_end_matches('.'))
+    }
+
+    /// Turn an error response of the API into a `ProviderError`
+    fn check_response(response: reqwest::blocking::Response, action: &str) -> Result<String> {
+        let status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        if status.is_success() {
+            return Ok(text);
+        }
+
+        let kind = status_error_kind(status);
+
+        match serde_json::from_str::<ApiError>(&text) {
+            Ok(ApiError { errors: Some(e), .. }) => Err(ProviderError::new(kind)
+                .msg(format!("Unable to {}. Reasons: \n - {}", action, e.join("\n - ")))),
+            Ok(ApiError { error: Some(e), .. }) => Err(ProviderError::new(kind)
+                .msg(format!("Unable to {}. Reason: {}", action, e))),
+            _ => Err(ProviderError::new(kind)
+                .msg(format!("Unable to {}. Received status {}", action, status))),
+        }
+    }
+
+    fn get_rrsets(api_url: &str, token: &str, zone: &str) -> Result<Vec<RRSet>> {
+        let response = http::client()
+            .get(&PowerDns::build_api_endpoint(api_url, zone))
+            .header("X-API-Key", token)
+            .send_retrying(Idempotency::Idempotent)?;
+
+        let text = PowerDns::check_response(response, "get the records of the zone")?;
+        let zone: Zone = serde_json::from_str(&text)?;
+
+        Ok(zone.rrsets)
+    }
+
+    fn patch(api_url: &str, token: &str, zone: &str, rrsets: Vec<RRSet>, action: &str) -> Result<()> {
+        let response = http::client()
+            .patch(&PowerDns::build_api_endpoint(api_url, zone))
+            .header("X-API-Key", token)
+            .json(&Patch { rrsets })
+            // The changes replace or delete whole RRsets, so sending them twice does no harm
+            .send_retrying(Idempotency::Idempotent)?;
+
+        PowerDns::check_response(response, action)?;
+
+        Ok(())
+    }
+}
+
+
+impl Provider for PowerDns {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn dynamic_dns(&self, _request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(String::from("PowerDNS can not detect the address of the client, use update with an A/AAAA record instead!")))
+    }
+
+    /// Search the records of the zone. PowerDNS groups records in RRsets, which are flattened
+    /// into one record per value. Disabled records are skipped.
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let name = host.map(|h| fqdn(zone, Some(h)));
+        let mut records: Vec<Record> = Vec::new();
+
+        for rrset in PowerDns::get_rrsets(&api_url, &token, zone)? {
+            if name.as_ref().is_some_and(|n| !n.eq_ignore_ascii_case(rrset.name.trim_end_matches('.'))) {
+                continue;
+            }
+
+            if rtype.is_some_and(|t| !t.eq_ignore_ascii_case(&rrset.r#type)) {
+                continue;
+            }
+
+            let host = relative_host(zone, &rrset.name);
+            for r in rrset.records.iter().filter(|r| !r.disabled) {
+                records.push(Record::from_content(&host, rrset.ttl.unwrap_or(0), &rrset.r#type, &r.content)?);
+            }
+        }
+
+        Ok(Some(records))
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let name = host.map(|h| fqdn(zone, Some(h)));
+        let deletions: Vec<RRSet> = PowerDns::get_rrsets(&api_url, &token, zone)?
+            .iter()
+            .filter(|r| r.is_selected(zone, name.as_deref(), rtype))
+            .map(|r| RRSet::delete(&r.name, &r.r#type))
+            .collect();
+
+        let count = deletions.len();
+        if count == 0 {
+            log::info!("Nothing to delete");
+            return Ok(true);
+        }
+
+        PowerDns::patch(&api_url, &token, zone, deletions, "delete selected record(s)")?;
+        log::info!("Deleted {} record set(s)", count);
+
+        Ok(true)
+    }
+
+    /// Replace all records selected by zone|host|type with the given ones. The records are grouped
+    /// into RRsets which are REPLACEd, while the selected RRsets which are not part of the new
+    /// records are DELETEd, all in a single PATCH.
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let records = &request.records;
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let mut rrsets: Vec<RRSet> = Vec::new();
+
+        for record in records {
+            let name = format!("{}.", fqdn(zone, Some(&record.host)));
+            let rtype = record.r#type.to_uppercase();
+            let content = RRSetRecord {
+                content: record.to_content(),
+                disabled: false,
+            };
+
+            match rrsets.iter_mut().find(|r| r.name.eq_ignore_ascii_case(&name) && r.r#type == rtype) {
+                Some(rrset) => {
+                    // A RRset has a single TTL, use the lowest one of its records
+                    rrset.ttl = rrset.ttl.map(|t| t.min(record.ttl));
+                    rrset.records.push(content);
+                },
+                None => rrsets.push(RRSet {
+                    name,
+                    r#type: rtype,
+                    ttl: Some(record.ttl),
+                    changetype: Some(String::from("REPLACE")),
+                    records: vec![content],
+                }),
+            }
+        }
+
+        let replaced = rrsets.len();
+        let name = host.map(|h| fqdn(zone, Some(h)));
+
+        for existing in PowerDns::get_rrsets(&api_url, &token, zone)? {
+            if !existing.is_selected(zone, name.as_deref(), rtype) {
+                continue;
+            }
+
+            if rrsets.iter().any(|r| r.name.eq_ignore_ascii_case(&existing.name) && r.r#type == existing.r#type) {
+                continue;
+            }
+
+            rrsets.push(RRSet::delete(&existing.name, &existing.r#type));
+        }
+
+        let removed = rrsets.len() - replaced;
+        PowerDns::patch(&api_url, &token, zone, rrsets, "update selected record(s)")?;
+
+        log::info!("Updated record(s)!");
+        log::debug!("Replaced {} record set(s). Removed {} record set(s)", replaced, removed);
+
+        Ok(true)
+    }
+
+    /// Add the records to their RRsets, which are REPLACEd with their current records (disabled
+    /// ones included) plus the new ones. The other RRsets of the selection are left alone.
+    fn add(&self, request: &AddRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let (api_url, token) = self.get_credential(zone, host, rtype)?;
+
+        let existing: Vec<RRSet> = PowerDns::get_rrsets(&api_url, &token, zone)?
+            .into_iter()
+            .filter(|r| request.records.iter().any(|record| {
+                r.name.trim_end_matches('.').eq_ignore_ascii_case(&fqdn(zone, Some(&record.host)))
+                    && r.r#type.eq_ignore_ascii_case(&record.r#type)
+            }))
+            .collect();
+
+        // Disabled records count as well, they are kept in the RRset
+        let mut current: Vec<Record> = Vec::new();
+        for rrset in &existing {
+            let host = relative_host(zone, &rrset.name);
+            for r in &rrset.records {
+                current.push(Record::from_content(&host, rrset.ttl.unwrap_or(0), &rrset.r#type, &r.content)?);
+            }
+        }
+        check_duplicates(zone, &current, &request.records)?;
+
+        let mut rrsets: Vec<RRSet> = Vec::new();
+
+        for record in &request.records {
+            let name = format!("{}.", fqdn(zone, Some(&record.host)));
+            let rtype = record.r#type.to_uppercase();
+
+            let index = match rrsets.iter().position(|r| r.name.eq_ignore_ascii_case(&name) && r.r#type == rtype) {
+                Some(i) => i,
+                None => {
+                    let current = existing.iter().find(|r| r.name.eq_ignore_ascii_case(&name) && r.r#type == rtype);
+                    rrsets.push(RRSet {
+                        name: current.map(|r| r.name.clone()).unwrap_or(name),
+                        r#type: rtype,
+                        ttl: current.and_then(|r| r.ttl),
+                        changetype: Some(String::from("REPLACE")),
+                        records: current
+                            .map(|r| r.records.iter().map(|r| RRSetRecord { content: r.content.clone(), disabled: r.disabled }).collect())
+                            .unwrap_or_default(),
+                    });
+                    rrsets.len() - 1
+                },
+            };
+
+            let rrset = &mut rrsets[index];
+            // A RRset has a single TTL, use the lowest one of its records
+            rrset.ttl = Some(rrset.ttl.map_or(record.ttl, |t| t.min(record.ttl)));
+            rrset.records.push(RRSetRecord {
+                content: record.to_content(),
+                disabled: false,
+            });
+        }
+
+        let count = request.records.len();
+        PowerDns::patch(&api_url, &token, zone, rrsets, "add record(s)")?;
+        log::info!("Added {} record(s)", count);
+
+        Ok(true)
+    }
+
+    fn list_zones(&self) -> Result<Vec<super::Zone>> {
+        list_account_zones(&self.credentials, |credential| {
+            let (api_url, token) = PowerDns::api_access(credential)?;
+
+            let response = http::client()
+                .get(&format!("{}/zones", api_url))
+                .header("X-API-Key", token)
+                .send_retrying(Idempotency::Idempotent)?;
+
+            let text = PowerDns::check_response(response, "list the zones")?;
+            let zones: Vec<Zone> = serde_json::from_str(&text)?;
+
+            Ok(zones
+                .into_iter()
+                .map(|z| super::Zone::new(&z.name)
+                    .with("kind", z.kind)
+                    .with("serial", z.serial)
+                    .with("dnssec", z.dnssec))
+                .collect())
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+    use crate::providers::test_server::{Reply, Request, Server};
+
+    const ZONE: &str = r#"{
+        "name": "example.com.",
+        "kind": "Native",
+        "serial": 2024010101,
+        "rrsets": [
+            {"name": "example.com.", "type": "SOA", "ttl": 3600, "records": [{"content": "ns1.example.com. hostmaster.example.com. 2024010101 10800 3600 604800 3600", "disabled": false}]},
+            {"name": "example.com.", "type": "NS", "ttl": 3600, "records": [{"content": "ns1.example.com.", "disabled": false}]},
+            {"name": "example.com.", "type": "MX", "ttl": 3600, "records": [{"content": "10 mail.example.com.", "disabled": false}]},
+            {"name": "www.example.com.", "type": "A", "ttl": 300, "records": [
+                {"content": "192.0.2.1", "disabled": false},
+                {"content": "192.0.2.2", "disabled": true}
+            ]},
+            {"name": "www.example.com.", "type": "AAAA", "ttl": 300, "records": [{"content": "2001:db8::1", "disabled": false}]}
+        ]
+    }"#;
+
+    /// A PowerDNS API serving the zone, and accepting every change
+    fn api() -> Server {
+        Server::start(|request| match (request.method.as_str(), request.path()) {
+            ("GET", "/zones/example.com.") => Reply::new(200, ZONE),
+            ("GET", "/zones") => Reply::new(200, r#"[{"name": "example.com.", "kind": "Native", "serial": 2024010101, "dnssec": false}]"#),
+            ("PATCH", "/zones/example.com.") => Reply::new(204, ""),
+            _ => Reply::new(404, r#"{"error": "Not Found"}"#),
+        })
+    }
+
+    fn provider(server: &Server) -> PowerDns {
+        let mut provider = PowerDns::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("powerdns"),
+            token: Some(String::from("secret")),
+            api_url: Some(server.url().to_string()),
+            ..Default::default()
+        }]);
+
+        provider
+    }
+
+    /// The RRsets of the PATCH request
+    fn patched(request: &Request) -> Vec<serde_json::Value> {
+        let patch: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        patch["rrsets"].as_array().unwrap().clone()
+    }
+
+    #[test]
+    fn searches_the_enabled_records_of_the_host() {
+        let server = api();
+        let provider = provider(&server);
+
+        let records = provider.search(&SearchRequest {
+            selector: RecordSelector::new("example.com", Some("www"), None),
+        }).unwrap().unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].r#type.as_str(), records[0].data.as_str(), records[0].ttl), ("A", "192.0.2.1", 300));
+        assert_eq!((records[1].r#type.as_str(), records[1].data.as_str()), ("AAAA", "2001:db8::1"));
+
+        let requests = server.requests();
+        assert_eq!(requests[0].header("X-API-Key"), Some("secret"));
+    }
+
+    #[test]
+    fn maps_the_priority_of_mx_records() {
+        let server = api();
+        let provider = provider(&server);
+
+        let records = provider.search(&SearchRequest {
+            selector: RecordSelector::new("example.com", Some("@"), Some("MX")),
+        }).unwrap().unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].host, "@");
+        assert_eq!(records[0].mx_priority, Some(10));
+        assert_eq!(records[0].data, "mail.example.com");
+    }
+
+    #[test]
+    fn replaces_and_deletes_record_sets_in_one_patch() {
+        let server = api();
+        let provider = provider(&server);
+
+        provider.update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("www"), None),
+            records: vec![
+                Record { host: String::from("www"), ttl: 600, r#type: String::from("A"), data: String::from("192.0.2.10"), ..Default::default() },
+                Record { host: String::from("www"), ttl: 60, r#type: String::from("A"), data: String::from("192.0.2.11"), ..Default::default() },
+            ],
+            wait: false,
+        }).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, "PATCH");
+
+        let rrsets = patched(&requests[1]);
+        assert_eq!(rrsets.len(), 2);
+        assert_eq!(rrsets[0]["name"], "www.example.com.");
+        assert_eq!(rrsets[0]["type"], "A");
+        assert_eq!(rrsets[0]["changetype"], "REPLACE");
+        // A RRset has a single TTL
+        assert_eq!(rrsets[0]["ttl"], 60);
+        assert_eq!(rrsets[0]["records"].as_array().unwrap().len(), 2);
+        assert_eq!(rrsets[1]["name"], "www.example.com.");
+        assert_eq!(rrsets[1]["type"], "AAAA");
+        assert_eq!(rrsets[1]["changetype"], "DELETE");
+    }
+
+    #[test]
+    fn adds_to_the_record_set_keeping_the_disabled_records() {
+        let server = api();
+        let provider = provider(&server);
+
+        provider.add(&AddRequest {
+            selector: RecordSelector::new("example.com", Some("www"), None),
+            records: vec![
+                Record { host: String::from("www"), ttl: 300, r#type: String::from("A"), data: String::from("192.0.2.3"), ..Default::default() },
+                Record { host: String::from("ftp"), ttl: 300, r#type: String::from("A"), data: String::from("192.0.2.4"), ..Default::default() },
+            ],
+            wait: false,
+        }).unwrap();
+
+        // Only the RRsets of the new records are replaced, the AAAA RRset of the host is kept
+        let rrsets = patched(&server.requests()[1]);
+        assert_eq!(rrsets.len(), 2);
+        assert_eq!(rrsets[0]["name"], "www.example.com.");
+        assert_eq!(rrsets[0]["changetype"], "REPLACE");
+        assert_eq!(rrsets[0]["records"], serde_json::json!([
+            {"content": "192.0.2.1", "disabled": false},
+            {"content": "192.0.2.2", "disabled": true},
+            {"content": "192.0.2.3", "disabled": false},
+        ]));
+        assert_eq!(rrsets[1]["name"], "ftp.example.com.");
+        assert_eq!(rrsets[1]["ttl"], 300);
+        assert_eq!(rrsets[1]["records"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn refuses_to_add_records_which_exist_even_disabled() {
+        let server = api();
+        let provider = provider(&server);
+
+        let error = provider.add(&AddRequest {
+            selector: RecordSelector::new("example.com", Some("www"), Some("A")),
+            records: vec![Record { host: String::from("www"), ttl: 300, r#type: String::from("A"), data: String::from("192.0.2.2"), ..Default::default() }],
+            wait: false,
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DuplicateRecord));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn never_deletes_the_soa_and_apex_ns_records() {
+        let server = api();
+        let provider = provider(&server);
+
+        provider.delete(&DeleteRequest {
+            selector: RecordSelector::new("example.com", None, None),
+            wait: false,
+        }).unwrap();
+
+        let rrsets = patched(&server.requests()[1]);
+        let deleted: Vec<(&str, &str)> = rrsets.iter()
+            .map(|r| (r["name"].as_str().unwrap(), r["type"].as_str().unwrap()))
+            .collect();
+        assert_eq!(deleted, vec![("example.com.", "MX"), ("www.example.com.", "A"), ("www.example.com.", "AAAA")]);
+        assert!(rrsets.iter().all(|r| r["changetype"] == "DELETE"));
+    }
+
+    #[test]
+    fn skips_the_patch_when_nothing_is_selected() {
+        let server = api();
+        let provider = provider(&server);
+
+        provider.delete(&DeleteRequest {
+            selector: RecordSelector::new("example.com", Some("ftp"), None),
+            wait: false,
+        }).unwrap();
+
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn lists_the_zones_of_the_server() {
+        let server = api();
+        let provider = provider(&server);
+
+        let zones = provider.list_zones().unwrap();
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].name, "example.com");
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_api() {
+        let server = Server::start(|_| Reply::new(422, r#"{"error": "RRset www.example.com. IN CNAME: Conflicts with pre-existing RRset"}"#));
+        let error = provider(&server).delete(&DeleteRequest {
+            selector: RecordSelector::new("example.com", None, None),
+            wait: false,
+        }).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::DnsApiError));
+        assert!(error.to_string().contains("Conflicts with pre-existing RRset"));
+
+        let server = Server::start(|_| Reply::new(401, r#"{"error": "Unauthorized"}"#));
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+    }
+
+    #[test]
+    fn requires_an_api_key() {
+        let mut provider = PowerDns::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("powerdns"),
+            ..Default::default()
+        }]);
+
+        let error = provider.search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::CredentialNotFound));
+    }
+}
@@ -0,0 +1,357 @@
+//! Sending API requests again when they fail for reasons which are likely to go away: the API
+//! throttling the client (status 429), temporary server errors (5xx), timeouts and connections
+//! which could not be established or were reset.
+//!
+//! Requests are sent again after an exponential backoff with jitter, or after the delay the API
+//! asks for with the Retry-After header. Requests which must not be repeated once the server may
+//! have acted on them are marked `Idempotency::NonIdempotent` and are only sent again when the
+//! server certainly did not process them (throttled, or the connection was refused).
+use super::{ProviderError, ProviderErrorKind, Result, civil_days};
+
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use reqwest::StatusCode;
+use reqwest::blocking::{RequestBuilder, Response};
+
+/// Number of times a request is sent at most, unless configured otherwise
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+
+/// Wait before the first retry, doubled after every attempt
+const BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest wait between two attempts when the API does not say how long to wait
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Longest wait the API can ask for, anything longer is reported as an error
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+static MAX_ATTEMPTS: AtomicU32 = AtomicU32::new(DEFAULT_MAX_ATTEMPTS);
+
+/// Whether sending a request more than once has the same effect as sending it once
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Idempotency {
+    Idempotent,
+    NonIdempotent,
+}
+
+/// Sending requests through the retry policy
+pub trait SendRetry {
+    fn send_retrying(self, idempotency: Idempotency) -> Result<Response>;
+}
+
+
+impl Idempotency {
+    /// Idempotency as defined by the HTTP method. APIs which use POST or PATCH in an idempotent
+    /// way (e.g. to replace a set of records) should be marked explicitly instead.
+    pub fn of(method: &reqwest::Method) -> Self {
+        match *method {
+            reqwest::Method::POST | reqwest::Method::PATCH => Idempotency::NonIdempotent,
+            _ => Idempotency::Idempotent,
+        }
+    }
+}
+
+impl SendRetry for RequestBuilder {
+    fn send_retrying(self, idempotency: Idempotency) -> Result<Response> {
+        send(self, idempotency)
+    }
+}
+
+
+/// Set how many times a request is sent at most
+pub fn set_max_attempts(attempts: u32) {
+    MAX_ATTEMPTS.store(attempts.max(1), Ordering::Relaxed);
+}
+
+fn max_attempts() -> u32 {
+    MAX_ATTEMPTS.load(Ordering::Relaxed)
+}
+
+/// Send a request, sending it again for as long as it fails for a temporary reason and attempts
+/// are left
+pub fn send(request: RequestBuilder, idempotency: Idempotency) -> Result<Response> {
+    let attempts = max_attempts();
+    let mut request = request;
+    let mut attempt = 1;
+
+    loop {
+        // Requests with a streamed body can not be copied, so they are only sent once
+        let next = request.try_clone();
+        let outcome = request.send();
+
+        let wait = match &outcome {
+            Ok(response) => retry_response(response, idempotency, attempt),
+            Err(e) => retry_error(e, idempotency, attempt),
+        };
+
+        let (wait, reason) = match wait {
+            Some(w) => w,
+            None => return outcome.map_err(ProviderError::from),
+        };
+
+        let throttled = matches!(&outcome, Ok(r) if r.status() == StatusCode::TOO_MANY_REQUESTS);
+
+        request = match next {
+            Some(r) if attempt < attempts && wait <= MAX_RETRY_AFTER => r,
+            _ if throttled => return Err(ProviderError::new(ProviderErrorKind::RateLimited)
+                .msg(format!("Request has been throttled by the provider, try again in {} seconds!", wait.as_secs()))),
+            _ => return outcome.map_err(ProviderError::from),
+        };
+
+        log::warn!("{}, sending the request again in {} ms (attempt {} of {})", reason, wait.as_millis(), attempt + 1, attempts);
+        thread::sleep(wait);
+        attempt += 1;
+    }
+}
+
+/// How long to wait before sending the request again after this response, if at all
+fn retry_response(response: &Response, idempotency: Idempotency, attempt: u32) -> Option<(Duration, String)> {
+    let status = response.status();
+
+    let retry = match status {
+        // The API did not process the request
+        StatusCode::TOO_MANY_REQUESTS => true,
+        StatusCode::INTERNAL_SERVER_ERROR
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT => idempotency == Idempotency::Idempotent,
+        _ => false,
+    };
+
+    if !retry {
+        return None;
+    }
+
+    let wait = retry_after(response).unwrap_or_else(|| backoff(attempt));
+
+    Some((wait, format!("Received status {}", status)))
+}
+
+/// How long to wait before sending the request again after this error, if at all
+fn retry_error(error: &reqwest::Error, idempotency: Idempotency, attempt: u32) -> Option<(Duration, String)> {
+    let retry = match io_error_kind(error) {
+        // The request never reached the server
+        Some(io::ErrorKind::ConnectionRefused) => true,
+        Some(io::ErrorKind::ConnectionReset)
+        | Some(io::ErrorKind::ConnectionAborted)
+        | Some(io::ErrorKind::BrokenPipe)
+        | Some(io::ErrorKind::UnexpectedEof)
+        | Some(io::ErrorKind::TimedOut) => idempotency == Idempotency::Idempotent,
+        _ => error.is_timeout() && idempotency == Idempotency::Idempotent,
+    };
+
+    if !retry {
+        return None;
+    }
+
+    Some((backoff(attempt), error.to_string()))
+}
+
+/// The IO error underneath a request error, if any
+fn io_error_kind(error: &reqwest::Error) -> Option<io::ErrorKind> {
+    let mut source = error.source();
+
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<io::Error>() {
+            return Some(io.kind());
+        }
+        source = e.source();
+    }
+
+    None
+}
+
+/// Exponential backoff with jitter: a random wait between half and all of the doubled delay, so
+/// clients which failed together do not all come back at the same time
+fn backoff(attempt: u32) -> Duration {
+    let delay = BASE_DELAY
+        .checked_mul(1 << (attempt - 1).min(16))
+        .unwrap_or(MAX_DELAY)
+        .min(MAX_DELAY);
+
+    let half = delay.as_millis() as u64 / 2;
+    let jitter = RandomState::new().build_hasher().finish() % (half + 1);
+
+    Duration::from_millis(half + jitter)
+}
+
+/// The delay asked for by the Retry-After header, given in seconds or as an HTTP date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let at = parse_http_date(value)?;
+
+    Some(Duration::from_secs(at.saturating_sub(now)))
+}
+
+/// Seconds since the epoch of an HTTP date (e.g. `Wed, 21 Oct 2015 07:28:00 GMT`)
+fn parse_http_date(value: &str) -> Option<u64> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    if fields.len() != 6 || fields[5] != "GMT" {
+        return None;
+    }
+
+    let day: u32 = fields[1].parse().ok()?;
+    let month = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"]
+        .iter()
+        .position(|m| *m == fields[2])? as u32 + 1;
+    let year: i64 = fields[3].parse().ok()?;
+
+    let time: Vec<u64> = fields[4].split(':').map(|t| t.parse().ok()).collect::<Option<_>>()?;
+    if time.len() != 3 {
+        return None;
+    }
+
+    let days = u64::try_from(civil_days(year, month, day)).ok()?;
+
+    Some(days * 86400 + time[0] * 3600 + time[1] * 60 + time[2])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::http;
+    use crate::providers::test_server::{Reply, Server};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Shortest wait of the backoff after the attempt: half of the doubled delay
+    fn backoff_floor(attempt: u32) -> Duration {
+        (BASE_DELAY * (1 << (attempt - 1))).min(MAX_DELAY) / 2
+    }
+
+    /// Fails with `status` (asking to retry right away) the first `failures` times
+    fn failing_server(failures: usize, status: u16) -> Server {
+        let count = Arc::new(AtomicUsize::new(0));
+
+        Server::start(move |_| {
+            if count.fetch_add(1, Ordering::SeqCst) < failures {
+                Reply { headers: vec![(String::from("Retry-After"), String::from("0"))], ..Reply::new(status, "") }
+            } else {
+                Reply::new(200, "ok")
+            }
+        })
+    }
+
+    #[test]
+    fn doubles_the_backoff_up_to_the_maximum() {
+        for attempt in 1..=8 {
+            let wait = backoff(attempt);
+            assert!(wait >= backoff_floor(attempt) && wait <= backoff_floor(attempt) * 2, "attempt {}: {:?}", attempt, wait);
+        }
+
+        assert!(backoff(100) <= MAX_DELAY);
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        assert_eq!(parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT"), Some(1_445_412_480));
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_http_date("Wed, 21 Oct 2015 07:28:00 CET"), None);
+        assert_eq!(parse_http_date("Wed, 21 Foo 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_http_date("Wed, 21 Oct 2015 07:28 GMT"), None);
+        assert_eq!(parse_http_date("120"), None);
+    }
+
+    #[test]
+    fn waits_as_long_as_retry_after_asks() {
+        let server = Server::start(|request| {
+            let value = match request.path() {
+                "/seconds" => String::from("120"),
+                "/date" => String::from("Fri, 01 Jan 2100 00:00:00 GMT"),
+                "/past" => String::from("Wed, 21 Oct 2015 07:28:00 GMT"),
+                _ => String::from("soon"),
+            };
+            Reply { headers: vec![(String::from("Retry-After"), value)], ..Reply::new(429, "") }
+        });
+        let get = |path: &str| http::client().get(&format!("{}{}", server.url(), path)).send().unwrap();
+
+        assert_eq!(retry_after(&get("/seconds")), Some(Duration::from_secs(120)));
+        assert!(retry_after(&get("/date")).unwrap() > Duration::from_secs(365 * 86400));
+        assert_eq!(retry_after(&get("/past")), Some(Duration::from_secs(0)));
+        assert_eq!(retry_after(&get("/other")), None);
+    }
+
+    #[test]
+    fn retries_server_errors_of_idempotent_requests() {
+        let server = failing_server(2, 503);
+        let response = send(http::client().get(server.url()), Idempotency::Idempotent).unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        let server = failing_server(10, 500);
+        let response = send(http::client().get(server.url()), Idempotency::Idempotent).unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(server.requests().len(), max_attempts() as usize);
+    }
+
+    #[test]
+    fn never_resends_non_idempotent_requests_the_server_may_have_processed() {
+        let server = failing_server(1, 503);
+        let response = send(http::client().post(server.url()).body("{}"), Idempotency::NonIdempotent).unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn resends_non_idempotent_requests_when_throttled() {
+        let server = failing_server(1, 429);
+        let response = send(http::client().post(server.url()).body("{}"), Idempotency::NonIdempotent).unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn reports_long_throttling_without_waiting() {
+        let server = Server::start(|_| Reply { headers: vec![(String::from("Retry-After"), String::from("301"))], ..Reply::new(429, "") });
+        let error = send(http::client().get(server.url()), Idempotency::Idempotent).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::RateLimited));
+        assert!(error.to_string().contains("301 seconds"));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn returns_the_response_when_the_server_asks_for_a_long_wait() {
+        let server = Server::start(|_| Reply { headers: vec![(String::from("Retry-After"), String::from("301"))], ..Reply::new(503, "") });
+        let response = send(http::client().get(server.url()), Idempotency::Idempotent).unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn resends_requests_refused_before_reaching_the_server() {
+        // Nothing listens on the port anymore once the listener is dropped
+        let url = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/", listener.local_addr().unwrap())
+        };
+        let started = std::time::Instant::now();
+        let error = send(http::client().post(&url).body("{}"), Idempotency::NonIdempotent).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::ReqwestFail));
+        // Waited for the backoff of every attempt but the last
+        assert!(started.elapsed() >= (1..max_attempts()).map(backoff_floor).sum());
+    }
+
+}
@@ -0,0 +1,977 @@
+mod tsig;
+mod wire;
+
+use super::{Provider, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, AddRequest, DeleteRequest, Record, ProviderError, ProviderErrorKind, Result, get_credential, check_duplicates, fqdn, relative_host};
+use crate::config;
+use wire::{Message, Question, Reader, ResourceRecord};
+
+use std::io::prelude::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_PORT: u16 = 53;
+
+/// TTL of the records published through DDNS
+const DDNS_TTL: u32 = 60;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// RFC 2136 dynamic DNS update provider.
+///
+/// Talks DNS directly to the primary server given by the `api_url` of the credential (e.g.
+/// `ns1.example.com:53`). Messages are signed with TSIG when the credential has a key: `user` is
+/// the key name, `pass` the base64 encoded secret and `algorithm` either hmac-sha256 (default)
+/// or hmac-sha512.
+#[derive(Debug)]
+pub struct Rfc2136 {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+/// A TCP connection to the DNS server
+struct Connection {
+    stream: TcpStream,
+    key: Option<tsig::Key>,
+}
+
+
+impl Connection {
+    fn open(credential: &config::Credential) -> Result<Self> {
+        Connection::connect(server_address(credential)?, tsig_key(credential)?)
+    }
+
+    fn connect(address: SocketAddr, key: Option<tsig::Key>) -> Result<Self> {
+        let stream = TcpStream::connect_timeout(&address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        log::debug!("Connected to DNS server {}", address);
+
+        Ok(Connection {
+            stream,
+            key,
+        })
+    }
+
+    fn send(&mut self, message: &Message) -> Result<Option<tsig::Verifier>> {
+        let mut bytes = message.to_bytes()?;
+        let verifier = match &self.key {
+            Some(key) => Some(key.sign(&mut bytes)?),
+            None => None,
+        };
+
+        self.stream.write_all(&(bytes.len() as u16).to_be_bytes())?;
+        self.stream.write_all(&bytes)?;
+
+        Ok(verifier)
+    }
+
+    fn receive(&mut self, id: u16, verifier: &mut Option<tsig::Verifier>) -> Result<Message> {
+        let mut len = [0u8; 2];
+        self.stream.read_exact(&mut len)?;
+
+        let mut raw = vec![0u8; u16::from_be_bytes(len) as usize];
+        self.stream.read_exact(&mut raw)?;
+
+        let message = Message::parse(&raw)?;
+        log::trace!("Received message: {:?}", message);
+
+        if message.id != id {
+            return Err(ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+                .msg(format!("Received response to message {}, expected {}", message.id, id)));
+        }
+
+        if let Some(v) = verifier {
+            v.verify(&raw, &message)?;
+        }
+
+        check_rcode(&message)?;
+
+        Ok(message)
+    }
+
+    /// Send a message and wait for the response
+    fn exchange(&mut self, message: &Message) -> Result<Message> {
+        let mut verifier = self.send(message)?;
+        self.receive(message.id, &mut verifier)
+    }
+
+    /// Fetch all the records of a zone (AXFR). The SOA record is only returned once.
+    fn transfer(&mut self, zone: &str) -> Result<Vec<ResourceRecord>> {
+        let mut request = Message::new(message_id(), wire::OPCODE_QUERY);
+        request.questions.push(Question {
+            name: zone.to_string(),
+            qtype: wire::TYPE_AXFR,
+            qclass: wire::CLASS_IN,
+        });
+
+        let mut verifier = self.send(&request)?;
+        let mut records = Vec::new();
+        let mut soa_seen = false;
+
+        loop {
+            let response = self.receive(request.id, &mut verifier)?;
+
+            for rr in response.answers {
+                if rr.rtype == wire::TYPE_SOA {
+                    // The transfer starts and ends with the SOA record of the zone
+                    if soa_seen {
+                        return Ok(records);
+                    }
+                    soa_seen = true;
+                }
+
+                records.push(rr);
+            }
+
+            if !soa_seen {
+                return Err(ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+                    .msg(format!("Zone transfer of {} did not start with a SOA record!", zone)));
+            }
+        }
+    }
+}
+
+
+impl Rfc2136 {
+    pub fn new() -> Self {
+        Rfc2136 {
+            name: String::from("rfc2136"),
+            credentials: None,
+        }
+    }
+
+    /// Build the RRset deletions for everything selected by zone|host|type. Without a host the
+    /// zone is transferred to find the RRsets to remove. The SOA and NS records of the apex are
+    /// never removed.
+    fn get_deletions(conn: &mut Connection, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<Vec<ResourceRecord>> {
+        let rtype = match rtype {
+            Some(t) => Some(parse_type(t)?),
+            None => None,
+        };
+
+        if let Some(h) = host {
+            return Ok(vec![delete_rrset(&fqdn(zone, Some(h)), rtype.unwrap_or(wire::TYPE_ANY))]);
+        }
+
+        let mut deletions: Vec<ResourceRecord> = Vec::new();
+
+        for rr in conn.transfer(zone)? {
+            let is_apex = relative_host(zone, &rr.name) == "@";
+
+            if rr.rtype == wire::TYPE_SOA || (is_apex && rr.rtype == wire::TYPE_NS) {
+                continue;
+            }
+
+            if rtype.is_some_and(|t| t != rr.rtype) {
+                continue;
+            }
+
+            if deletions.iter().any(|d| d.rtype == rr.rtype && d.name.eq_ignore_ascii_case(&rr.name)) {
+                continue;
+            }
+
+            deletions.push(delete_rrset(&rr.name, rr.rtype));
+        }
+
+        Ok(deletions)
+    }
+}
+
+
+impl Provider for Rfc2136 {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    /// Publish the address of the request. The server can not tell the public address of the
+    /// client, as the connection may go through NAT or a VPN, so it has to be detected first.
+    fn dynamic_dns(&self, request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        let zone = request.selector.zone();
+        let host = match request.selector.host() {
+            Some(h) => h,
+            None => return Err(ProviderError::new(ProviderErrorKind::InvalidInput)
+                .msg(String::from("DDNS requires the host to point at the address!"))),
+        };
+        let address = match request.address {
+            Some(a) => a,
+            None => return Err(ProviderError::new(ProviderErrorKind::Unsupported)
+                .msg(String::from("A DNS server can not tell the public address of the client, it has to be detected first!"))),
+        };
+        let name = fqdn(zone, Some(host));
+
+        let credentials = get_credential(&self.credentials, zone, Some(host), None)?;
+        let mut conn = Connection::open(&credentials)?;
+
+        let (rtype, rdata) = match address {
+            IpAddr::V4(ip) => (wire::TYPE_A, ip.octets().to_vec()),
+            IpAddr::V6(ip) => (wire::TYPE_AAAA, ip.octets().to_vec()),
+        };
+
+        let mut message = update_message(zone);
+        message.authority.push(delete_rrset(&name, rtype));
+        message.authority.push(ResourceRecord {
+            name: name.clone(),
+            rtype,
+            class: wire::CLASS_IN,
+            ttl: DDNS_TTL,
+            rdata,
+        });
+
+        conn.exchange(&message)?;
+        let message = format!("Updated {} {} record to {}", name, wire::type_to_name(rtype), address);
+        log::info!("{}", message);
+
+        Ok(DynamicDnsResponse { address: Some(address), message })
+    }
+
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = match request.selector.rtype() {
+            Some(t) => Some(parse_type(t)?),
+            None => None,
+        };
+
+        let credentials = get_credential(&self.credentials, zone, host, request.selector.rtype())?;
+        let mut conn = Connection::open(&credentials)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+
+        let records: Result<Vec<Record>> = conn.transfer(zone)?
+            .iter()
+            .filter(|rr| name.as_ref().is_none_or(|n| n.eq_ignore_ascii_case(&rr.name)))
+            .filter(|rr| rtype.is_none_or(|t| t == rr.rtype))
+            .map(|rr| decode_record(zone, rr))
+            .collect();
+
+        Ok(Some(records?))
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+
+        let credentials = get_credential(&self.credentials, zone, host, rtype)?;
+        let mut conn = Connection::open(&credentials)?;
+
+        let deletions = Rfc2136::get_deletions(&mut conn, zone, host, rtype)?;
+        let count = deletions.len();
+
+        if count == 0 {
+            log::info!("Nothing to delete");
+            return Ok(true);
+        }
+
+        let mut message = update_message(zone);
+        message.authority = deletions;
+        conn.exchange(&message)?;
+
+        log::info!("Deleted {} record set(s)", count);
+
+        Ok(true)
+    }
+
+    /// Replace all records selected by zone|host|type with the given ones in a single UPDATE
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let records = &request.records;
+
+        let credentials = get_credential(&self.credentials, zone, host, rtype)?;
+        let mut conn = Connection::open(&credentials)?;
+
+        let mut message = update_message(zone);
+        message.authority = Rfc2136::get_deletions(&mut conn, zone, host, rtype)?;
+        let removed = message.authority.len();
+
+        for record in records {
+            message.authority.push(add_record(zone, record)?);
+        }
+
+        conn.exchange(&message)?;
+
+        log::info!("Updated record(s)!");
+        log::debug!("Added {} record(s). Removed {} record set(s)", records.len(), removed);
+
+        Ok(true)
+    }
+
+    /// Add the records in a single UPDATE, which the server adds to their RRsets as they are then
+    fn add(&self, request: &AddRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+
+        let existing = self.search(&SearchRequest { selector: request.selector.clone() })?.unwrap_or_default();
+        check_duplicates(zone, &existing, &request.records)?;
+
+        let credentials = get_credential(&self.credentials, zone, host, rtype)?;
+        let mut conn = Connection::open(&credentials)?;
+
+        let mut message = update_message(zone);
+        for record in &request.records {
+            message.authority.push(add_record(zone, record)?);
+        }
+
+        conn.exchange(&message)?;
+        log::info!("Added {} record(s)", request.records.len());
+
+        Ok(true)
+    }
+}
+
+
+/// Look up the records of the name and type with the server (e.g. `resolver1.opendns.com`,
+/// `192.0.2.1:5353`), asking it to resolve the name recursively if needed
+pub(crate) fn query(server: &str, name: &str, rtype: &str) -> Result<Vec<Record>> {
+    let mut connection = Connection::connect(socket_address(server)?, None)?;
+
+    let mut request = Message::new(message_id(), wire::OPCODE_QUERY);
+    request.recursion_desired = true;
+    request.questions.push(Question {
+        name: name.to_string(),
+        qtype: parse_type(rtype)?,
+        qclass: wire::CLASS_IN,
+    });
+
+    let response = connection.exchange(&request)?;
+
+    response.answers
+        .iter()
+        .filter(|rr| rr.rtype == request.questions[0].qtype)
+        .map(|rr| decode_record(name, rr))
+        .collect()
+}
+
+/// Parse the server address from the credential (e.g. `ns1.example.com`, `192.0.2.1:5353`, `[2001:db8::1]:53`)
+fn server_address(credential: &config::Credential) -> Result<SocketAddr> {
+    match &credential.api_url {
+        Some(s) => socket_address(s.trim_start_matches("dns://").trim_start_matches("tcp://").trim_end_matches('/')),
+        None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+            .msg(String::from("The rfc2136 provider requires the address of the DNS server as api_url!"))),
+    }
+}
+
+fn socket_address(server: &str) -> Result<SocketAddr> {
+    let address = if server.parse::<SocketAddr>().is_ok() {
+        server.to_string()
+    } else if let Ok(ip) = server.parse::<Ipv6Addr>() {
+        format!("[{}]:{}", ip, DEFAULT_PORT)
+    } else if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{}:{}", server, DEFAULT_PORT)
+    };
+
+    match address.to_socket_addrs()?.next() {
+        Some(a) => Ok(a),
+        None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+            .msg(format!("Unable to resolve the DNS server {}!", server))),
+    }
+}
+
+/// Build the TSIG key from the credential, if one was configured
+fn tsig_key(credential: &config::Credential) -> Result<Option<tsig::Key>> {
+    if credential.user.is_empty() {
+        log::warn!("No TSIG key configured, sending unsigned messages");
+        return Ok(None);
+    }
+
+    let algorithm_name = credential.algorithm.as_deref().unwrap_or("hmac-sha256");
+    let algorithm = match tsig::Algorithm::from_name(algorithm_name) {
+        Some(a) => a,
+        None => return Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+            .msg(format!("Unsupported TSIG algorithm {}!", algorithm_name))),
+    };
+
+    let secret = match base64::decode(credential.pass.trim()) {
+        Ok(s) => s,
+        Err(e) => return Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+            .msg(format!("TSIG secret is not valid base64: {}", e))),
+    };
+
+    Ok(Some(tsig::Key {
+        name: credential.user.clone(),
+        algorithm,
+        secret,
+    }))
+}
+
+fn message_id() -> u16 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u16 ^ std::process::id() as u16)
+        .unwrap_or(0)
+}
+
+/// An UPDATE message for the zone, with the update section still empty
+fn update_message(zone: &str) -> Message {
+    let mut message = Message::new(message_id(), wire::OPCODE_UPDATE);
+    message.questions.push(Question {
+        name: zone.to_string(),
+        qtype: wire::TYPE_SOA,
+        qclass: wire::CLASS_IN,
+    });
+
+    message
+}
+
+/// Update section entry which deletes a whole RRset (or all RRsets of the name for type ANY)
+fn delete_rrset(name: &str, rtype: u16) -> ResourceRecord {
+    ResourceRecord {
+        name: name.to_string(),
+        rtype,
+        class: wire::CLASS_ANY,
+        ttl: 0,
+        rdata: Vec::new(),
+    }
+}
+
+/// Update section entry which adds the record to its RRset
+fn add_record(zone: &str, record: &Record) -> Result<ResourceRecord> {
+    let (rtype, rdata) = encode_rdata(record)?;
+
+    Ok(ResourceRecord {
+        name: fqdn(zone, Some(&record.host)),
+        rtype,
+        class: wire::CLASS_IN,
+        ttl: record.ttl,
+        rdata,
+    })
+}
+
+fn parse_type(rtype: &str) -> Result<u16> {
+    match wire::type_from_name(rtype) {
+        Some(t) => Ok(t),
+        None => Err(ProviderError::new(ProviderErrorKind::InvalidRecord)
+            .msg(format!("Unsupported record type {}!", rtype))),
+    }
+}
+
+fn check_rcode(message: &Message) -> Result<()> {
+    let reason = match message.rcode {
+        0 => return Ok(()),
+        1 => "FORMERR, the server was unable to interpret the message",
+        2 => "SERVFAIL, the server failed to process the message",
+        3 => "NXDOMAIN, the name does not exist",
+        4 => "NOTIMP, the server does not support the operation",
+        5 => "REFUSED, the server refused the operation",
+        6 => "YXDOMAIN, a name exists when it should not",
+        7 => "YXRRSET, a record set exists when it should not",
+        8 => "NXRRSET, a record set does not exist when it should",
+        9 => return Err(ProviderError::new(ProviderErrorKind::AuthenticationFailed)
+            .msg(String::from("NOTAUTH, the server is not authoritative for the zone or the TSIG key was rejected"))),
+        10 => "NOTZONE, a name is outside of the zone",
+        _ => "unknown error",
+    };
+
+    Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+        .msg(format!("DNS server returned {} (rcode {})", reason, message.rcode)))
+}
+
+/// Encode the data of a record in wire format
+fn encode_rdata(record: &Record) -> Result<(u16, Vec<u8>)> {
+    let rtype = parse_type(&record.r#type)?;
+    let invalid = |what: &str| ProviderError::new(ProviderErrorKind::InvalidRecord)
+        .msg(format!("Invalid {} record {}: {}", record.r#type, record.data, what));
+    let mut rdata = Vec::new();
+
+    match rtype {
+        wire::TYPE_A => {
+            let ip: Ipv4Addr = record.data.parse().map_err(|_| invalid("not an IPv4 address"))?;
+            rdata.extend_from_slice(&ip.octets());
+        },
+        wire::TYPE_AAAA => {
+            let ip: Ipv6Addr = record.data.parse().map_err(|_| invalid("not an IPv6 address"))?;
+            rdata.extend_from_slice(&ip.octets());
+        },
+        wire::TYPE_NS | wire::TYPE_CNAME | wire::TYPE_PTR => wire::put_name(&mut rdata, &record.data)?,
+        wire::TYPE_MX => {
+            wire::put_u16(&mut rdata, record.mx_priority.unwrap_or(0) as u16);
+            wire::put_name(&mut rdata, &record.data)?;
+        },
+        wire::TYPE_TXT => {
+            // Long values are split into several strings of at most 255 characters
+            let bytes = record.data.as_bytes();
+            if bytes.is_empty() {
+                rdata.push(0);
+            }
+            for chunk in bytes.chunks(255) {
+                rdata.push(chunk.len() as u8);
+                rdata.extend_from_slice(chunk);
+            }
+        },
+        wire::TYPE_SRV => {
+            wire::put_u16(&mut rdata, record.srv_priority.unwrap_or(0) as u16);
+            wire::put_u16(&mut rdata, record.srv_weight.unwrap_or(0) as u16);
+            wire::put_u16(&mut rdata, record.srv_port.unwrap_or(0) as u16);
+            wire::put_name(&mut rdata, &record.data)?;
+        },
+        wire::TYPE_CAA => {
+            rdata.push(record.caa_flags.unwrap_or(0) as u8);
+            wire::put_string(&mut rdata, record.caa_property.as_deref().unwrap_or("issue"))?;
+            rdata.extend_from_slice(record.data.as_bytes());
+        },
+        wire::TYPE_SSHFP => {
+            rdata.push(record.sshfp_algorithm.unwrap_or(0) as u8);
+            rdata.push(record.sshfp_type.unwrap_or(0) as u8);
+            rdata.extend(hex::decode(&record.data).map_err(|_| invalid("fingerprint is not hexadecimal"))?);
+        },
+        wire::TYPE_TLSA => {
+            rdata.push(record.tlsa_usage.unwrap_or(0) as u8);
+            rdata.push(record.tlsa_selector.unwrap_or(0) as u8);
+            rdata.push(record.tlsa_matching.unwrap_or(0) as u8);
+            rdata.extend(hex::decode(&record.data).map_err(|_| invalid("certificate data is not hexadecimal"))?);
+        },
+        _ => return Err(invalid("type can not be updated")),
+    }
+
+    Ok((rtype, rdata))
+}
+
+/// Decode a record received from the server
+fn decode_record(zone: &str, rr: &ResourceRecord) -> Result<Record> {
+    let mut record = Record {
+        host: relative_host(zone, &rr.name),
+        ttl: rr.ttl,
+        r#type: wire::type_to_name(rr.rtype),
+        ..Default::default()
+    };
+    let mut data = Reader::new(&rr.rdata);
+
+    record.data = match rr.rtype {
+        wire::TYPE_A => {
+            let b = data.bytes(4)?;
+            Ipv4Addr::new(b[0], b[1], b[2], b[3]).to_string()
+        },
+        wire::TYPE_AAAA => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(data.bytes(16)?);
+            Ipv6Addr::from(octets).to_string()
+        },
+        wire::TYPE_NS | wire::TYPE_CNAME | wire::TYPE_PTR => data.name()?,
+        wire::TYPE_MX => {
+            record.mx_priority = Some(u32::from(data.u16()?));
+            data.name()?
+        },
+        wire::TYPE_TXT => {
+            let mut text = String::new();
+            while !data.is_empty() {
+                text.push_str(&data.string()?);
+            }
+            text
+        },
+        wire::TYPE_SRV => {
+            record.srv_priority = Some(u32::from(data.u16()?));
+            record.srv_weight = Some(u32::from(data.u16()?));
+            record.srv_port = Some(u32::from(data.u16()?));
+            data.name()?
+        },
+        wire::TYPE_CAA => {
+            record.caa_flags = Some(u32::from(data.u8()?));
+            record.caa_property = Some(data.string()?);
+            String::from_utf8_lossy(data.rest()).to_string()
+        },
+        wire::TYPE_SSHFP => {
+            record.sshfp_algorithm = Some(u32::from(data.u8()?));
+            record.sshfp_type = Some(u32::from(data.u8()?));
+            hex::encode(data.rest())
+        },
+        wire::TYPE_TLSA => {
+            record.tlsa_usage = Some(u32::from(data.u8()?));
+            record.tlsa_selector = Some(u32::from(data.u8()?));
+            record.tlsa_matching = Some(u32::from(data.u8()?));
+            hex::encode(data.rest())
+        },
+        wire::TYPE_SOA => {
+            let mname = data.name()?;
+            let rname = data.name()?;
+            let timers: Vec<String> = (0..5)
+                .map(|_| data.u32().map(|v| v.to_string()))
+                .collect::<Result<Vec<String>>>()?;
+            format!("{} {} {}", mname, rname, timers.join(" "))
+        },
+        // RFC 3597 representation of unknown types
+        _ => format!("\\# {} {}", rr.rdata.len(), hex::encode(&rr.rdata)),
+    };
+
+    Ok(record)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// A stand-in DNS server over TCP: it answers every message with the ones the handler returns,
+    /// signed when it was given a key, and keeps the messages for the test to check what was sent
+    struct DnsServer {
+        address: SocketAddr,
+        messages: Arc<Mutex<Vec<Message>>>,
+    }
+
+    impl DnsServer {
+        fn start<F>(key: Option<tsig::Key>, handler: F) -> Self
+        where
+            F: Fn(&Message) -> Vec<Message> + Send + 'static,
+        {
+            let listener = TcpListener::bind("127.0.0.1:0").expect("The loopback interface is available");
+            let address = listener.local_addr().expect("The listener has an address");
+            let messages = Arc::new(Mutex::new(Vec::new()));
+            let received = Arc::clone(&messages);
+
+            thread::spawn(move || {
+                for mut stream in listener.incoming().flatten() {
+                    while let Some(raw) = read(&mut stream) {
+                        let message = Message::parse(&raw).expect("The client sends valid messages");
+                        let mut signer = key.as_ref().map(|k| tsig::Signer::verify_request(k, &raw, &message)
+                            .expect("The client signs with the key of the server"));
+
+                        let responses = handler(&message);
+                        received.lock().expect("Lock is never poisoned").push(message);
+
+                        for response in responses {
+                            let mut bytes = response.to_bytes().expect("The responses of the tests are valid");
+                            if let Some(s) = &mut signer {
+                                s.sign(&mut bytes).expect("The key of the tests is valid");
+                            }
+                            let _ = stream.write_all(&(bytes.len() as u16).to_be_bytes());
+                            let _ = stream.write_all(&bytes);
+                        }
+                    }
+                }
+            });
+
+            DnsServer {
+                address,
+                messages,
+            }
+        }
+
+        /// The messages received so far, oldest first
+        fn messages(&self) -> Vec<Message> {
+            self.messages.lock().expect("Lock is never poisoned").clone()
+        }
+    }
+
+    fn read(stream: &mut TcpStream) -> Option<Vec<u8>> {
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).ok()?;
+        let mut raw = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut raw).ok()?;
+
+        Some(raw)
+    }
+
+    /// A response with the same id and no records
+    fn reply(request: &Message, rcode: u8) -> Message {
+        let mut response = Message::new(request.id, request.opcode);
+        response.rcode = rcode;
+        response.questions = request.questions.clone();
+
+        response
+    }
+
+    fn record(name: &str, rtype: u16, rdata: Vec<u8>) -> ResourceRecord {
+        ResourceRecord {
+            name: name.to_string(),
+            rtype,
+            class: wire::CLASS_IN,
+            ttl: 300,
+            rdata,
+        }
+    }
+
+    fn soa() -> ResourceRecord {
+        let mut rdata = Vec::new();
+        wire::put_name(&mut rdata, "ns1.example.com").unwrap();
+        wire::put_name(&mut rdata, "hostmaster.example.com").unwrap();
+        for timer in &[1, 3600, 600, 86400, 300] {
+            wire::put_u32(&mut rdata, *timer);
+        }
+
+        record("example.com", wire::TYPE_SOA, rdata)
+    }
+
+    fn credential(server: &DnsServer, key: Option<&tsig::Key>) -> config::Credential {
+        config::Credential {
+            provider: String::from("rfc2136"),
+            user: key.map(|k| k.name.clone()).unwrap_or_default(),
+            pass: key.map(|k| base64::encode(&k.secret)).unwrap_or_default(),
+            algorithm: key.map(|_| String::from("hmac-sha512")),
+            api_url: Some(server.address.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn provider(credential: config::Credential) -> Rfc2136 {
+        let mut provider = Rfc2136::new();
+        provider.set_credentials(vec![credential]);
+
+        provider
+    }
+
+    fn key() -> tsig::Key {
+        tsig::Key {
+            name: String::from("ddns-key."),
+            algorithm: tsig::Algorithm::HmacSha512,
+            secret: b"a secret shared with the server".to_vec(),
+        }
+    }
+
+    fn ddns(provider: &Rfc2136, address: Option<IpAddr>) -> Result<DynamicDnsResponse> {
+        provider.dynamic_dns(&DynamicDnsRequest {
+            selector: RecordSelector::new("example.com", Some("home"), None),
+            address,
+        })
+    }
+
+    #[test]
+    fn publishes_the_detected_address() {
+        let server = DnsServer::start(None, |m| vec![reply(m, 0)]);
+        let provider = provider(credential(&server, None));
+
+        let response = ddns(&provider, Some("203.0.113.7".parse().unwrap())).unwrap();
+        assert_eq!(response.address, Some("203.0.113.7".parse().unwrap()));
+
+        let messages = server.messages();
+        assert_eq!(messages.len(), 1);
+        let update = &messages[0];
+        assert_eq!(update.opcode, wire::OPCODE_UPDATE);
+        assert_eq!(update.questions[0].name, "example.com");
+        assert_eq!(update.questions[0].qtype, wire::TYPE_SOA);
+
+        // The A RRset of the host is replaced with the address
+        assert_eq!(update.authority.len(), 2);
+        assert_eq!(update.authority[0].name, "home.example.com");
+        assert_eq!(update.authority[0].rtype, wire::TYPE_A);
+        assert_eq!(update.authority[0].class, wire::CLASS_ANY);
+        assert_eq!(update.authority[1].rtype, wire::TYPE_A);
+        assert_eq!(update.authority[1].class, wire::CLASS_IN);
+        assert_eq!(update.authority[1].ttl, DDNS_TTL);
+        assert_eq!(update.authority[1].rdata, vec![203, 0, 113, 7]);
+    }
+
+    #[test]
+    fn publishes_ipv6_addresses_as_aaaa_records() {
+        let server = DnsServer::start(None, |m| vec![reply(m, 0)]);
+        let provider = provider(credential(&server, None));
+
+        let address: Ipv6Addr = "2001:db8::7".parse().unwrap();
+        ddns(&provider, Some(IpAddr::V6(address))).unwrap();
+
+        let update = &server.messages()[0];
+        assert_eq!(update.authority[0].rtype, wire::TYPE_AAAA);
+        assert_eq!(update.authority[1].rdata, address.octets().to_vec());
+    }
+
+    #[test]
+    fn does_not_guess_the_address() {
+        let server = DnsServer::start(None, |m| vec![reply(m, 0)]);
+        let provider = provider(credential(&server, None));
+
+        let error = ddns(&provider, None).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::Unsupported));
+        assert!(server.messages().is_empty());
+    }
+
+    #[test]
+    fn replaces_the_records_of_the_host() {
+        let server = DnsServer::start(None, |m| vec![reply(m, 0)]);
+        let provider = provider(credential(&server, None));
+
+        provider.update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("@"), Some("MX")),
+            records: vec![Record {
+                host: String::from("@"),
+                ttl: 3600,
+                r#type: String::from("MX"),
+                data: String::from("mail.example.com"),
+                mx_priority: Some(10),
+                ..Default::default()
+            }],
+            wait: false,
+        }).unwrap();
+
+        let update = &server.messages()[0];
+        assert_eq!(update.authority.len(), 2);
+        assert_eq!(update.authority[0].name, "example.com");
+        assert_eq!(update.authority[0].rtype, wire::TYPE_MX);
+        assert_eq!(update.authority[0].class, wire::CLASS_ANY);
+
+        let mx = decode_record("example.com", &update.authority[1]).unwrap();
+        assert_eq!(mx.host, "@");
+        assert_eq!(mx.ttl, 3600);
+        assert_eq!(mx.mx_priority, Some(10));
+        assert_eq!(mx.data, "mail.example.com");
+    }
+
+    /// A server with www A 192.0.2.1, accepting every update
+    fn zone_server() -> DnsServer {
+        DnsServer::start(None, |m| {
+            let mut response = reply(m, 0);
+            if m.opcode == wire::OPCODE_QUERY {
+                response.answers.push(soa());
+                response.answers.push(record("www.example.com", wire::TYPE_A, vec![192, 0, 2, 1]));
+                response.answers.push(soa());
+            }
+
+            vec![response]
+        })
+    }
+
+    fn add(provider: &Rfc2136, data: &str) -> Result<bool> {
+        provider.add(&AddRequest {
+            selector: RecordSelector::new("example.com", Some("www"), Some("A")),
+            records: vec![Record { host: String::from("www"), ttl: 300, r#type: String::from("A"), data: data.to_string(), ..Default::default() }],
+            wait: false,
+        })
+    }
+
+    #[test]
+    fn adds_records_without_deleting_any() {
+        let server = zone_server();
+        add(&provider(credential(&server, None)), "192.0.2.3").unwrap();
+
+        let messages = server.messages();
+        assert_eq!(messages.len(), 2);
+        let update = &messages[1];
+        assert_eq!(update.opcode, wire::OPCODE_UPDATE);
+        assert_eq!(update.authority.len(), 1);
+        assert_eq!(update.authority[0].name, "www.example.com");
+        assert_eq!(update.authority[0].class, wire::CLASS_IN);
+        assert_eq!(update.authority[0].rdata, vec![192, 0, 2, 3]);
+    }
+
+    #[test]
+    fn refuses_to_add_records_which_exist() {
+        let server = zone_server();
+        let error = add(&provider(credential(&server, None)), "192.0.2.1").unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DuplicateRecord));
+        assert_eq!(server.messages().len(), 1);
+    }
+
+    #[test]
+    fn searches_a_transfer_spread_over_several_messages() {
+        let server = DnsServer::start(None, |m| {
+            let mut first = reply(m, 0);
+            first.answers.push(soa());
+            first.answers.push(record("www.example.com", wire::TYPE_A, vec![192, 0, 2, 1]));
+            let mut second = reply(m, 0);
+            second.answers.push(record("www.example.com", wire::TYPE_AAAA, "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec()));
+            second.answers.push(record("mail.example.com", wire::TYPE_A, vec![192, 0, 2, 2]));
+            second.answers.push(soa());
+
+            vec![first, second]
+        });
+        let provider = provider(credential(&server, None));
+
+        let found = provider.search(&SearchRequest {
+            selector: RecordSelector::new("example.com", Some("www"), None),
+        }).unwrap().unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].r#type, "A");
+        assert_eq!(found[0].data, "192.0.2.1");
+        assert_eq!(found[1].r#type, "AAAA");
+        assert_eq!(found[1].data, "2001:db8::1");
+
+        let transfer = &server.messages()[0];
+        assert_eq!(transfer.questions[0].qtype, wire::TYPE_AXFR);
+    }
+
+    #[test]
+    fn deletes_every_record_set_of_the_zone_but_the_apex() {
+        let server = DnsServer::start(None, |m| {
+            let mut response = reply(m, 0);
+            if m.opcode == wire::OPCODE_QUERY {
+                response.answers.push(soa());
+                response.answers.push(record("example.com", wire::TYPE_NS, vec![0]));
+                response.answers.push(record("www.example.com", wire::TYPE_A, vec![192, 0, 2, 1]));
+                response.answers.push(record("www.example.com", wire::TYPE_A, vec![192, 0, 2, 3]));
+                response.answers.push(soa());
+            }
+
+            vec![response]
+        });
+        let provider = provider(credential(&server, None));
+
+        provider.delete(&DeleteRequest {
+            selector: RecordSelector::new("example.com", None, None),
+            wait: false,
+        }).unwrap();
+
+        let messages = server.messages();
+        assert_eq!(messages.len(), 2);
+        let update = &messages[1];
+        assert_eq!(update.authority.len(), 1);
+        assert_eq!(update.authority[0].name, "www.example.com");
+        assert_eq!(update.authority[0].rtype, wire::TYPE_A);
+        assert_eq!(update.authority[0].class, wire::CLASS_ANY);
+    }
+
+    #[test]
+    fn signs_updates_and_verifies_the_responses() {
+        let server = DnsServer::start(Some(key()), |m| vec![reply(m, 0)]);
+        let provider = provider(credential(&server, Some(&key())));
+
+        ddns(&provider, Some("203.0.113.7".parse().unwrap())).unwrap();
+
+        let update = &server.messages()[0];
+        assert_eq!(update.additional.len(), 1);
+        assert_eq!(update.additional[0].rtype, wire::TYPE_TSIG);
+        assert_eq!(update.additional[0].name, "ddns-key");
+    }
+
+    #[test]
+    fn verifies_signed_transfers() {
+        let server = DnsServer::start(Some(key()), |m| {
+            let mut first = reply(m, 0);
+            first.answers.push(soa());
+            let mut second = reply(m, 0);
+            second.answers.push(record("www.example.com", wire::TYPE_A, vec![192, 0, 2, 1]));
+            second.answers.push(soa());
+
+            vec![first, second]
+        });
+        let provider = provider(credential(&server, Some(&key())));
+
+        let found = provider.search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, Some("A")),
+        }).unwrap().unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].host, "www");
+    }
+
+    #[test]
+    fn rejects_unsigned_responses_to_signed_updates() {
+        let server = DnsServer::start(None, |m| vec![reply(m, 0)]);
+        let provider = provider(credential(&server, Some(&key())));
+
+        let error = ddns(&provider, Some("203.0.113.7".parse().unwrap())).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+    }
+
+    #[test]
+    fn reports_the_rcode_of_the_server() {
+        let server = DnsServer::start(None, |m| vec![reply(m, 5)]);
+        let error = ddns(&provider(credential(&server, None)), Some("203.0.113.7".parse().unwrap())).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::DnsApiError));
+        assert!(error.to_string().contains("REFUSED"));
+
+        let server = DnsServer::start(None, |m| vec![reply(m, 9)]);
+        let error = ddns(&provider(credential(&server, None)), Some("203.0.113.7".parse().unwrap())).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+    }
+}
@@ -0,0 +1,450 @@
+//! Transaction signatures (RFC 8945) for the messages exchanged with the server.
+use super::wire::{self, Message, Reader, CLASS_ANY, TYPE_TSIG};
+use crate::providers::{ProviderError, ProviderErrorKind, Result};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Sha512};
+
+/// Allowed difference (in seconds) between our clock and the server's
+const FUDGE: u16 = 300;
+
+/// Maximum number of unsigned messages allowed in between signed ones during a zone transfer
+const MAX_UNSIGNED: usize = 99;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Algorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+impl Algorithm {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim_end_matches('.').to_lowercase().as_str() {
+            "hmac-sha256" => Some(Algorithm::HmacSha256),
+            "hmac-sha512" => Some(Algorithm::HmacSha512),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::HmacSha256 => "hmac-sha256",
+            Algorithm::HmacSha512 => "hmac-sha512",
+        }
+    }
+
+    fn sign(self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC can take a key of any size");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            },
+            Algorithm::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(secret).expect("HMAC can take a key of any size");
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            },
+        }
+    }
+
+    fn verify(self, secret: &[u8], data: &[u8], expected: &[u8]) -> bool {
+        match self {
+            Algorithm::HmacSha256 => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC can take a key of any size");
+                mac.update(data);
+                mac.verify_slice(expected).is_ok()
+            },
+            Algorithm::HmacSha512 => {
+                let mut mac = Hmac::<Sha512>::new_from_slice(secret).expect("HMAC can take a key of any size");
+                mac.update(data);
+                mac.verify_slice(expected).is_ok()
+            },
+        }
+    }
+}
+
+/// A TSIG key as configured on the server (e.g. `key "name" { algorithm ...; secret ...; };`)
+#[derive(Debug, Clone)]
+pub struct Key {
+    pub name: String,
+    pub algorithm: Algorithm,
+    pub secret: Vec<u8>,
+}
+
+/// The content of a received TSIG record
+struct Signature {
+    time_signed: u64,
+    fudge: u16,
+    mac: Vec<u8>,
+    original_id: u16,
+    error: u16,
+}
+
+
+impl Key {
+    /// Sign an encoded message by appending a TSIG record to it.
+    ///
+    /// Returns a verifier for the response(s) of the server, as those are signed using the MAC
+    /// of the request.
+    pub fn sign(&self, message: &mut Vec<u8>) -> Result<Verifier> {
+        let time_signed = now();
+
+        let mut data = message.clone();
+        self.put_variables(&mut data, time_signed, FUDGE, 0)?;
+        let mac = self.algorithm.sign(&self.secret, &data);
+
+        let id = u16::from_be_bytes([message[0], message[1]]);
+        let mut rdata = Vec::new();
+        wire::put_name(&mut rdata, self.algorithm.name())?;
+        wire::put_u48(&mut rdata, time_signed);
+        wire::put_u16(&mut rdata, FUDGE);
+        wire::put_u16(&mut rdata, mac.len() as u16);
+        rdata.extend_from_slice(&mac);
+        wire::put_u16(&mut rdata, id);
+        wire::put_u16(&mut rdata, 0);
+        wire::put_u16(&mut rdata, 0);
+
+        wire::put_record(message, &wire::ResourceRecord {
+            name: self.name.to_lowercase(),
+            rtype: TYPE_TSIG,
+            class: CLASS_ANY,
+            ttl: 0,
+            rdata,
+        })?;
+        set_additional_count(message, 1);
+
+        Ok(Verifier {
+            key: self.clone(),
+            previous_mac: mac,
+            unsigned: Vec::new(),
+            unsigned_count: 0,
+            first: true,
+        })
+    }
+
+    /// The TSIG variables which are covered by the MAC
+    fn put_variables(&self, buf: &mut Vec<u8>, time_signed: u64, fudge: u16, error: u16) -> Result<()> {
+        wire::put_name(buf, &self.name.to_lowercase())?;
+        wire::put_u16(buf, CLASS_ANY);
+        wire::put_u32(buf, 0);
+        wire::put_name(buf, self.algorithm.name())?;
+        wire::put_u48(buf, time_signed);
+        wire::put_u16(buf, fudge);
+        wire::put_u16(buf, error);
+        wire::put_u16(buf, 0);
+
+        Ok(())
+    }
+}
+
+
+/// Verifies the response(s) to a signed request
+pub struct Verifier {
+    key: Key,
+    previous_mac: Vec<u8>,
+    /// Messages received since the last signed one (zone transfers only sign every few messages)
+    unsigned: Vec<u8>,
+    unsigned_count: usize,
+    first: bool,
+}
+
+impl Verifier {
+    pub fn verify(&mut self, raw: &[u8], message: &Message) -> Result<()> {
+        let offset = match message.tsig_offset {
+            Some(o) => o,
+            None => {
+                self.unsigned_count += 1;
+                if self.first || self.unsigned_count > MAX_UNSIGNED {
+                    return Err(ProviderError::new(ProviderErrorKind::AuthenticationFailed)
+                        .msg(String::from("Response of the server is not signed!")));
+                }
+
+                self.unsigned.extend_from_slice(raw);
+                return Ok(());
+            },
+        };
+
+        let signature = read_signature(raw, offset)?;
+
+        if signature.error != 0 {
+            return Err(ProviderError::new(ProviderErrorKind::AuthenticationFailed)
+                .msg(format!("Server rejected the TSIG signature: {}", error_name(signature.error))));
+        }
+
+        // The MAC covers the message as it was before the TSIG record was added
+        let mut unsigned = raw[..offset].to_vec();
+        unsigned[0..2].copy_from_slice(&signature.original_id.to_be_bytes());
+        set_additional_count(&mut unsigned, -1);
+
+        let mut data = Vec::new();
+        wire::put_u16(&mut data, self.previous_mac.len() as u16);
+        data.extend_from_slice(&self.previous_mac);
+        data.extend_from_slice(&self.unsigned);
+        data.extend_from_slice(&unsigned);
+
+        if self.first {
+            self.key.put_variables(&mut data, signature.time_signed, signature.fudge, signature.error)?;
+        } else {
+            wire::put_u48(&mut data, signature.time_signed);
+            wire::put_u16(&mut data, signature.fudge);
+        }
+
+        if !self.key.algorithm.verify(&self.key.secret, &data, &signature.mac) {
+            return Err(ProviderError::new(ProviderErrorKind::AuthenticationFailed)
+                .msg(String::from("TSIG signature of the response is not valid!")));
+        }
+
+        if now().abs_diff(signature.time_signed) > u64::from(signature.fudge) {
+            return Err(ProviderError::new(ProviderErrorKind::AuthenticationFailed)
+                .msg(String::from("TSIG signature of the response has expired, check the clock of this machine!")));
+        }
+
+        self.previous_mac = signature.mac;
+        self.unsigned.clear();
+        self.unsigned_count = 0;
+        self.first = false;
+
+        Ok(())
+    }
+}
+
+
+fn read_signature(raw: &[u8], offset: usize) -> Result<Signature> {
+    let mut reader = Reader::at(raw, offset);
+    reader.name()?;
+    reader.bytes(10)?;
+    reader.name()?;
+
+    let time_signed = reader.u48()?;
+    let fudge = reader.u16()?;
+    let mac_size = reader.u16()? as usize;
+    let mac = reader.bytes(mac_size)?.to_vec();
+    let original_id = reader.u16()?;
+    let error = reader.u16()?;
+
+    Ok(Signature { time_signed, fudge, mac, original_id, error })
+}
+
+fn set_additional_count(message: &mut [u8], delta: i32) {
+    let count = i32::from(u16::from_be_bytes([message[10], message[11]])) + delta;
+    message[10..12].copy_from_slice(&(count as u16).to_be_bytes());
+}
+
+fn error_name(error: u16) -> String {
+    match error {
+        16 => String::from("BADSIG"),
+        17 => String::from("BADKEY"),
+        18 => String::from("BADTIME"),
+        22 => String::from("BADTRUNC"),
+        _ => format!("error {}", error),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+
+/// The server side of the signatures, for the stand-in server of the tests: checks the signature
+/// of a request, then signs the response(s) with the MAC of the request
+#[cfg(test)]
+pub(super) struct Signer {
+    key: Key,
+    previous_mac: Vec<u8>,
+    first: bool,
+}
+
+#[cfg(test)]
+impl Signer {
+    pub fn verify_request(key: &Key, raw: &[u8], message: &Message) -> Result<Self> {
+        let offset = message.tsig_offset.ok_or_else(|| ProviderError::new(ProviderErrorKind::AuthenticationFailed)
+            .msg(String::from("Request is not signed!")))?;
+        let signature = read_signature(raw, offset)?;
+
+        let mut data = raw[..offset].to_vec();
+        data[0..2].copy_from_slice(&signature.original_id.to_be_bytes());
+        set_additional_count(&mut data, -1);
+        key.put_variables(&mut data, signature.time_signed, signature.fudge, signature.error)?;
+
+        if !key.algorithm.verify(&key.secret, &data, &signature.mac) {
+            return Err(ProviderError::new(ProviderErrorKind::AuthenticationFailed)
+                .msg(String::from("TSIG signature of the request is not valid!")));
+        }
+
+        Ok(Signer {
+            key: key.clone(),
+            previous_mac: signature.mac,
+            first: true,
+        })
+    }
+
+    /// Sign an encoded response by appending a TSIG record to it
+    pub fn sign(&mut self, message: &mut Vec<u8>) -> Result<()> {
+        let time_signed = now();
+
+        let mut data = Vec::new();
+        wire::put_u16(&mut data, self.previous_mac.len() as u16);
+        data.extend_from_slice(&self.previous_mac);
+        data.extend_from_slice(message);
+        if self.first {
+            self.key.put_variables(&mut data, time_signed, FUDGE, 0)?;
+        } else {
+            wire::put_u48(&mut data, time_signed);
+            wire::put_u16(&mut data, FUDGE);
+        }
+        let mac = self.key.algorithm.sign(&self.key.secret, &data);
+
+        let id = u16::from_be_bytes([message[0], message[1]]);
+        let mut rdata = Vec::new();
+        wire::put_name(&mut rdata, self.key.algorithm.name())?;
+        wire::put_u48(&mut rdata, time_signed);
+        wire::put_u16(&mut rdata, FUDGE);
+        wire::put_u16(&mut rdata, mac.len() as u16);
+        rdata.extend_from_slice(&mac);
+        wire::put_u16(&mut rdata, id);
+        wire::put_u16(&mut rdata, 0);
+        wire::put_u16(&mut rdata, 0);
+
+        wire::put_record(message, &wire::ResourceRecord {
+            name: self.key.name.to_lowercase(),
+            rtype: TYPE_TSIG,
+            class: CLASS_ANY,
+            ttl: 0,
+            rdata,
+        })?;
+        set_additional_count(message, 1);
+
+        self.previous_mac = mac;
+        self.first = false;
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::wire::{Question, TYPE_A, TYPE_SOA, CLASS_IN, OPCODE_QUERY, OPCODE_UPDATE};
+
+    fn key(algorithm: Algorithm) -> Key {
+        Key {
+            name: String::from("ddns-key."),
+            algorithm,
+            secret: b"0123456789abcdef0123456789abcdef".to_vec(),
+        }
+    }
+
+    fn message(id: u16, opcode: u8) -> Message {
+        let mut message = Message::new(id, opcode);
+        message.questions.push(Question {
+            name: String::from("example.com"),
+            qtype: TYPE_SOA,
+            qclass: CLASS_IN,
+        });
+
+        message
+    }
+
+    /// Sign a request, and check its signature as the server would
+    fn signed_request(key: &Key, id: u16) -> (Verifier, Signer) {
+        let mut raw = message(id, OPCODE_UPDATE).to_bytes().unwrap();
+        let verifier = key.sign(&mut raw).unwrap();
+
+        let parsed = Message::parse(&raw).unwrap();
+        assert_eq!(parsed.additional.len(), 1);
+        assert_eq!(parsed.additional[0].rtype, TYPE_TSIG);
+        let signer = Signer::verify_request(key, &raw, &parsed).unwrap();
+
+        (verifier, signer)
+    }
+
+    fn response(signer: Option<&mut Signer>, id: u16) -> (Vec<u8>, Message) {
+        let mut raw = message(id, OPCODE_UPDATE).to_bytes().unwrap();
+        if let Some(signer) = signer {
+            signer.sign(&mut raw).unwrap();
+        }
+        let parsed = Message::parse(&raw).unwrap();
+
+        (raw, parsed)
+    }
+
+    #[test]
+    fn signs_requests_and_verifies_responses() {
+        for algorithm in [Algorithm::HmacSha256, Algorithm::HmacSha512] {
+            let key = key(algorithm);
+            let (mut verifier, mut signer) = signed_request(&key, 4242);
+
+            let (raw, parsed) = response(Some(&mut signer), 4242);
+            verifier.verify(&raw, &parsed).unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_requests_signed_with_another_secret() {
+        let mut raw = message(1, OPCODE_UPDATE).to_bytes().unwrap();
+        key(Algorithm::HmacSha256).sign(&mut raw).unwrap();
+
+        let mut other = key(Algorithm::HmacSha256);
+        other.secret = b"another secret".to_vec();
+        assert!(Signer::verify_request(&other, &raw, &Message::parse(&raw).unwrap()).is_err());
+    }
+
+    #[test]
+    fn rejects_tampered_and_unsigned_responses() {
+        let key = key(Algorithm::HmacSha256);
+
+        let (mut verifier, mut signer) = signed_request(&key, 7);
+        let (mut raw, _) = response(Some(&mut signer), 7);
+        // Flip the rcode of the signed response
+        raw[3] ^= 0x05;
+        let error = verifier.verify(&raw, &Message::parse(&raw).unwrap()).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+
+        let (mut verifier, _) = signed_request(&key, 8);
+        let (raw, parsed) = response(None, 8);
+        let error = verifier.verify(&raw, &parsed).unwrap_err();
+        assert!(error.to_string().contains("not signed"));
+    }
+
+    #[test]
+    fn verifies_zone_transfers_signed_every_few_messages() {
+        let key = key(Algorithm::HmacSha512);
+        let (mut verifier, mut signer) = signed_request(&key, 99);
+
+        // The first message has to be signed, later ones may be signed every few messages
+        let mut transfer: Vec<Vec<u8>> = Vec::new();
+        let mut first = message(99, OPCODE_QUERY).to_bytes().unwrap();
+        signer.sign(&mut first).unwrap();
+        transfer.push(first);
+
+        let mut unsigned = message(99, OPCODE_QUERY);
+        unsigned.answers.push(wire::ResourceRecord {
+            name: String::from("www.example.com"),
+            rtype: TYPE_A,
+            class: CLASS_IN,
+            ttl: 300,
+            rdata: vec![192, 0, 2, 1],
+        });
+        let unsigned = unsigned.to_bytes().unwrap();
+        transfer.push(unsigned.clone());
+
+        // The MAC of the next signed message covers the unsigned ones before it
+        let mut last = message(99, OPCODE_QUERY).to_bytes().unwrap();
+        let mut covered = unsigned;
+        let len = covered.len();
+        covered.extend_from_slice(&last);
+        signer.sign(&mut covered).unwrap();
+        last = covered.split_off(len);
+        transfer.push(last);
+
+        for raw in &transfer {
+            verifier.verify(raw, &Message::parse(raw).unwrap()).unwrap();
+        }
+    }
+}
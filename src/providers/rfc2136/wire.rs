@@ -0,0 +1,400 @@
+//! Minimal DNS wire format support: just enough to build UPDATE and AXFR messages and to
+//! read the responses of the server.
+use crate::providers::{ProviderError, ProviderErrorKind, Result};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_NS: u16 = 2;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_MX: u16 = 15;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_SSHFP: u16 = 44;
+pub const TYPE_TLSA: u16 = 52;
+pub const TYPE_TSIG: u16 = 250;
+pub const TYPE_AXFR: u16 = 252;
+pub const TYPE_ANY: u16 = 255;
+pub const TYPE_CAA: u16 = 257;
+
+pub const CLASS_IN: u16 = 1;
+pub const CLASS_ANY: u16 = 255;
+
+pub const OPCODE_QUERY: u8 = 0;
+pub const OPCODE_UPDATE: u8 = 5;
+
+const TYPE_NAMES: [(u16, &str); 14] = [
+    (TYPE_A, "A"),
+    (TYPE_NS, "NS"),
+    (TYPE_CNAME, "CNAME"),
+    (TYPE_SOA, "SOA"),
+    (TYPE_PTR, "PTR"),
+    (TYPE_MX, "MX"),
+    (TYPE_TXT, "TXT"),
+    (TYPE_AAAA, "AAAA"),
+    (TYPE_SRV, "SRV"),
+    (TYPE_SSHFP, "SSHFP"),
+    (TYPE_TLSA, "TLSA"),
+    (TYPE_CAA, "CAA"),
+    (TYPE_TSIG, "TSIG"),
+    (TYPE_ANY, "ANY"),
+];
+
+/// Maximum number of compression pointers followed while reading a name
+const MAX_POINTERS: usize = 64;
+
+pub fn type_from_name(name: &str) -> Option<u16> {
+    TYPE_NAMES
+        .iter()
+        .find(|(_, n)| n.eq_ignore_ascii_case(name))
+        .map(|(t, _)| *t)
+}
+
+pub fn type_to_name(rtype: u16) -> String {
+    match TYPE_NAMES.iter().find(|(t, _)| *t == rtype) {
+        Some((_, n)) => n.to_string(),
+        None => format!("TYPE{}", rtype),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+/// A resource record. Names embedded in the data are always stored uncompressed.
+#[derive(Debug, Clone)]
+pub struct ResourceRecord {
+    pub name: String,
+    pub rtype: u16,
+    pub class: u16,
+    pub ttl: u32,
+    pub rdata: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Message {
+    pub id: u16,
+    pub opcode: u8,
+    pub rcode: u8,
+    /// Ask the server to resolve the question recursively (RD), for queries to resolvers
+    pub recursion_desired: bool,
+    /// Question section, or the zone section of an UPDATE message
+    pub questions: Vec<Question>,
+    /// Answer section, or the prerequisite section of an UPDATE message
+    pub answers: Vec<ResourceRecord>,
+    /// Authority section, or the update section of an UPDATE message
+    pub authority: Vec<ResourceRecord>,
+    pub additional: Vec<ResourceRecord>,
+    /// Offset of the TSIG record within the parsed message, if the message was signed
+    pub tsig_offset: Option<usize>,
+}
+
+
+impl Message {
+    pub fn new(id: u16, opcode: u8) -> Self {
+        Message {
+            id,
+            opcode,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(512);
+        let mut flags: u16 = (u16::from(self.opcode) & 0x0f) << 11 | (u16::from(self.rcode) & 0x0f);
+        if self.recursion_desired {
+            flags |= 0x0100;
+        }
+
+        put_u16(&mut buf, self.id);
+        put_u16(&mut buf, flags);
+        put_u16(&mut buf, self.questions.len() as u16);
+        put_u16(&mut buf, self.answers.len() as u16);
+        put_u16(&mut buf, self.authority.len() as u16);
+        put_u16(&mut buf, self.additional.len() as u16);
+
+        for q in &self.questions {
+            put_name(&mut buf, &q.name)?;
+            put_u16(&mut buf, q.qtype);
+            put_u16(&mut buf, q.qclass);
+        }
+
+        for rr in self.answers.iter().chain(&self.authority).chain(&self.additional) {
+            put_record(&mut buf, rr)?;
+        }
+
+        Ok(buf)
+    }
+
+    pub fn parse(buf: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(buf);
+
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let counts = [reader.u16()?, reader.u16()?, reader.u16()?, reader.u16()?];
+
+        let mut message = Message {
+            id,
+            opcode: ((flags >> 11) & 0x0f) as u8,
+            rcode: (flags & 0x0f) as u8,
+            recursion_desired: flags & 0x0100 != 0,
+            ..Default::default()
+        };
+
+        for _ in 0..counts[0] {
+            message.questions.push(Question {
+                name: reader.name()?,
+                qtype: reader.u16()?,
+                qclass: reader.u16()?,
+            });
+        }
+
+        for _ in 0..counts[1] {
+            message.answers.push(reader.record()?);
+        }
+
+        for _ in 0..counts[2] {
+            message.authority.push(reader.record()?);
+        }
+
+        for i in 0..counts[3] {
+            let offset = reader.pos;
+            let rr = reader.record()?;
+
+            // TSIG has to be the last record of the message
+            if rr.rtype == TYPE_TSIG && i == counts[3] - 1 {
+                message.tsig_offset = Some(offset);
+            }
+
+            message.additional.push(rr);
+        }
+
+        Ok(message)
+    }
+}
+
+
+/// Cursor over a received message which knows how to follow compression pointers
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pub pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+
+    /// Reader over a single record data. Names in it must not be compressed.
+    pub fn at(buf: &'a [u8], pos: usize) -> Self {
+        Reader { buf, pos }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.pos + n > self.buf.len() {
+            return Err(malformed("message is truncated"));
+        }
+
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+
+        Ok(bytes)
+    }
+
+    pub fn rest(&mut self) -> &'a [u8] {
+        let bytes = &self.buf[self.pos.min(self.buf.len())..];
+        self.pos = self.buf.len();
+
+        bytes
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u48(&mut self) -> Result<u64> {
+        let b = self.bytes(6)?;
+        Ok(b.iter().fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte)))
+    }
+
+    /// A <character-string>: one length byte followed by the data
+    pub fn string(&mut self) -> Result<String> {
+        let len = self.u8()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).to_string())
+    }
+
+    /// Read a domain name, following compression pointers. The root is returned as "."
+    pub fn name(&mut self) -> Result<String> {
+        let mut labels: Vec<String> = Vec::new();
+        let mut pos = self.pos;
+        let mut pointers = 0;
+        let mut end = None;
+
+        loop {
+            let len = *self.buf.get(pos).ok_or_else(|| malformed("name is truncated"))? as usize;
+
+            match len & 0xc0 {
+                0x00 => {
+                    if len == 0 {
+                        pos += 1;
+                        break;
+                    }
+
+                    let label = self.buf.get(pos + 1..pos + 1 + len).ok_or_else(|| malformed("label is truncated"))?;
+                    labels.push(String::from_utf8_lossy(label).to_string());
+                    pos += 1 + len;
+                },
+                0xc0 => {
+                    let low = *self.buf.get(pos + 1).ok_or_else(|| malformed("pointer is truncated"))? as usize;
+
+                    if end.is_none() {
+                        end = Some(pos + 2);
+                    }
+
+                    pointers += 1;
+                    if pointers > MAX_POINTERS {
+                        return Err(malformed("too many compression pointers"));
+                    }
+
+                    pos = ((len & 0x3f) << 8) | low;
+                },
+                _ => return Err(malformed("unsupported label type")),
+            }
+        }
+
+        self.pos = end.unwrap_or(pos);
+
+        if labels.is_empty() {
+            return Ok(String::from("."));
+        }
+
+        Ok(labels.join("."))
+    }
+
+    pub fn record(&mut self) -> Result<ResourceRecord> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let len = self.u16()? as usize;
+        let start = self.pos;
+        self.bytes(len)?;
+
+        // Rebuild the data of records which embed names, so they no longer depend on the message
+        let mut rdata = Vec::with_capacity(len);
+        let mut data = Reader::at(self.buf, start);
+
+        match rtype {
+            // Deletions in UPDATE messages have no data whatever the type
+            _ if len == 0 => (),
+            TYPE_NS | TYPE_CNAME | TYPE_PTR => put_name(&mut rdata, &data.name()?)?,
+            TYPE_MX => {
+                put_u16(&mut rdata, data.u16()?);
+                put_name(&mut rdata, &data.name()?)?;
+            },
+            TYPE_SRV => {
+                rdata.extend_from_slice(data.bytes(6)?);
+                put_name(&mut rdata, &data.name()?)?;
+            },
+            TYPE_SOA => {
+                put_name(&mut rdata, &data.name()?)?;
+                put_name(&mut rdata, &data.name()?)?;
+                rdata.extend_from_slice(data.bytes(20)?);
+            },
+            TYPE_TSIG => {
+                put_name(&mut rdata, &data.name()?)?;
+                rdata.extend_from_slice(&self.buf[data.pos..start + len]);
+            },
+            _ => rdata.extend_from_slice(&self.buf[start..start + len]),
+        }
+
+        Ok(ResourceRecord { name, rtype, class, ttl, rdata })
+    }
+}
+
+
+pub fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+pub fn put_u48(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_be_bytes()[2..]);
+}
+
+/// Write a domain name without compression. A trailing dot is optional.
+pub fn put_name(buf: &mut Vec<u8>, name: &str) -> Result<()> {
+    let name = name.trim_end_matches('.');
+
+    if name.len() > 253 {
+        return Err(invalid(format!("Name {} is too long!", name)));
+    }
+
+    if !name.is_empty() {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(invalid(format!("Name {} has an invalid label!", name)));
+            }
+
+            buf.push(label.len() as u8);
+            buf.extend_from_slice(label.as_bytes());
+        }
+    }
+
+    buf.push(0);
+
+    Ok(())
+}
+
+/// Write a <character-string>, which is limited to 255 bytes
+pub fn put_string(buf: &mut Vec<u8>, value: &str) -> Result<()> {
+    if value.len() > 255 {
+        return Err(invalid(format!("Value {} is longer than 255 characters!", value)));
+    }
+
+    buf.push(value.len() as u8);
+    buf.extend_from_slice(value.as_bytes());
+
+    Ok(())
+}
+
+pub fn put_record(buf: &mut Vec<u8>, rr: &ResourceRecord) -> Result<()> {
+    put_name(buf, &rr.name)?;
+    put_u16(buf, rr.rtype);
+    put_u16(buf, rr.class);
+    put_u32(buf, rr.ttl);
+    put_u16(buf, rr.rdata.len() as u16);
+    buf.extend_from_slice(&rr.rdata);
+
+    Ok(())
+}
+
+fn malformed(reason: &str) -> ProviderError {
+    ProviderError::new(ProviderErrorKind::UnexpectedResponse)
+        .msg(format!("Received malformed DNS message: {}", reason))
+}
+
+fn invalid(message: String) -> ProviderError {
+    ProviderError::new(ProviderErrorKind::InvalidRecord).msg(message)
+}
@@ -0,0 +1,1002 @@
+mod sigv4;
+
+use super::{Provider, http, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, AddRequest, DeleteRequest, Record, ProviderError, ProviderErrorKind, Result, get_credential, check_duplicates, list_account_zones, fqdn, relative_host, status_error_kind};
+use super::retry::{Idempotency, SendRetry};
+use crate::config;
+use sigv4::{AwsKey, Signer};
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::{Deserialize};
+
+const API_URL: &str = "https://route53.amazonaws.com";
+const API_VERSION: &str = "2013-04-01";
+const XML_NAMESPACE: &str = "https://route53.amazonaws.com/doc/2013-04-01/";
+
+/// Route 53 is a global service, signed as if it lived in us-east-1
+const SIGNING_REGION: &str = "us-east-1";
+
+/// How often and for how long to poll a change until it has propagated
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const POLL_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// AWS Route 53 provider.
+///
+/// The access key is taken from the credential (`user` is the access key id, `pass` the secret
+/// and `token` an optional session token), falling back to the standard AWS environment
+/// variables and then to the shared credentials file.
+#[derive(Debug)]
+pub struct Route53 {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+/// Everything needed to send signed requests to the API
+struct Session {
+    api_url: String,
+    signer: Signer,
+}
+
+/// A simple (non-alias) record set, with its values in presentation format
+#[derive(Debug)]
+struct RRSet {
+    name: String,
+    r#type: String,
+    ttl: u32,
+    values: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ListHostedZonesByNameResponse {
+    #[serde(default)]
+    hosted_zones: HostedZones,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ListHostedZonesResponse {
+    #[serde(default)]
+    hosted_zones: HostedZones,
+    is_truncated: bool,
+    next_marker: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct HostedZones {
+    #[serde(rename = "HostedZone", default)]
+    hosted_zone: Vec<HostedZone>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct HostedZone {
+    id: String,
+    name: String,
+    resource_record_set_count: Option<u64>,
+    config: Option<HostedZoneConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct HostedZoneConfig {
+    private_zone: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ListResourceRecordSetsResponse {
+    #[serde(default)]
+    resource_record_sets: ResourceRecordSets,
+    is_truncated: bool,
+    next_record_name: Option<String>,
+    next_record_type: Option<String>,
+    next_record_identifier: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ResourceRecordSets {
+    #[serde(rename = "ResourceRecordSet", default)]
+    resource_record_set: Vec<ResourceRecordSet>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ResourceRecordSet {
+    name: String,
+    r#type: String,
+    #[serde(rename = "TTL")]
+    ttl: Option<u32>,
+    set_identifier: Option<String>,
+    alias_target: Option<serde::de::IgnoredAny>,
+    resource_records: Option<ResourceRecords>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResourceRecords {
+    #[serde(rename = "ResourceRecord", default)]
+    resource_record: Vec<ResourceRecord>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ResourceRecord {
+    value: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ChangeResponse {
+    change_info: ChangeInfo,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ChangeInfo {
+    id: String,
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ErrorDetail {
+    code: String,
+    message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct InvalidChangeBatch {
+    messages: Messages,
+}
+
+#[derive(Deserialize, Debug)]
+struct Messages {
+    #[serde(rename = "Message", default)]
+    message: Vec<String>,
+}
+
+
+impl ResourceRecordSet {
+    /// Alias records and records with a routing policy can not be expressed as plain records
+    fn into_rrset(self) -> Option<RRSet> {
+        if self.alias_target.is_some() || self.set_identifier.is_some() {
+            log::debug!("Skipping {} record set {} as it is an alias or has a routing policy", self.r#type, self.name);
+            return None;
+        }
+
+        Some(RRSet {
+            name: decode_name(&self.name),
+            r#type: self.r#type,
+            ttl: self.ttl.unwrap_or(0),
+            values: self.resource_records.map(|r| r.resource_record.into_iter().map(|r| r.value).collect()).unwrap_or_default(),
+        })
+    }
+}
+
+impl RRSet {
+    /// Group the records into record sets by name and type
+    fn group(zone: &str, records: &[Record]) -> Vec<RRSet> {
+        let mut rrsets: Vec<RRSet> = Vec::new();
+
+        for record in records {
+            let name = format!("{}.", fqdn(zone, Some(&record.host)));
+            let rtype = record.r#type.to_uppercase();
+
+            match rrsets.iter_mut().find(|r| r.name.eq_ignore_ascii_case(&name) && r.r#type == rtype) {
+                Some(rrset) => {
+                    // A record set has a single TTL, use the lowest one of its records
+                    rrset.ttl = rrset.ttl.min(record.ttl);
+                    rrset.values.push(record.to_content());
+                },
+                None => rrsets.push(RRSet {
+                    name,
+                    r#type: rtype,
+                    ttl: record.ttl,
+                    values: vec![record.to_content()],
+                }),
+            }
+        }
+
+        rrsets
+    }
+
+    fn is_selected(&self, zone: &str, name: Option<&str>, rtype: Option<&str>) -> bool {
+        let is_apex = relative_host(zone, &self.name) == "@";
+
+        // Never touch the records which keep the zone working
+        if self.r#type == "SOA" || (is_apex && self.r#type == "NS") {
+            return false;
+        }
+
+        name.is_none_or(|n| n.eq_ignore_ascii_case(self.name.trim_end_matches('.')))
+            && rtype.is_none_or(|t| t.eq_ignore_ascii_case(&self.r#type))
+    }
+
+    fn is_same(&self, other: &RRSet) -> bool {
+        self.ttl == other.ttl
+            && self.values.len() == other.values.len()
+            && self.values.iter().all(|v| other.values.iter().any(|o| o.eq_ignore_ascii_case(v)))
+    }
+
+    fn to_xml(&self, action: &str) -> String {
+        let values: String = self.values
+            .iter()
+            .map(|v| format!("<ResourceRecord><Value>{}</Value></ResourceRecord>", escape(v)))
+            .collect();
+
+        format!("<Change><Action>{}</Action><ResourceRecordSet><Name>{}</Name><Type>{}</Type><TTL>{}</TTL><ResourceRecords>{}</ResourceRecords></ResourceRecordSet></Change>",
+            action, escape(&self.name), self.r#type, self.ttl, values)
+    }
+}
+
+
+impl Session {
+    fn open(credential: &config::Credential) -> Result<Self> {
+        Ok(Session {
+            api_url: credential.api_url.clone().unwrap_or_else(|| String::from(API_URL)).trim_end_matches('/').to_string(),
+            signer: Signer {
+                key: aws_key(credential)?,
+                region: String::from(SIGNING_REGION),
+                service: String::from("route53"),
+            },
+        })
+    }
+
+    /// Send a signed request to the API and return the body of a successful response
+    fn request(&self, method: reqwest::Method, path: &str, query: &[(&str, &str)], body: Option<String>) -> Result<String> {
+        let mut url = reqwest::Url::parse(&format!("{}/{}/{}", self.api_url, API_VERSION, path))
+            .map_err(|e| ProviderError::new(ProviderErrorKind::DnsApiError)
+                .msg(format!("Invalid Route 53 API URL {}: {}", self.api_url, e)))?;
+
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+
+        let payload = body.unwrap_or_default();
+        let mut request = http::client().request(method.clone(), url.clone());
+
+        for (name, value) in self.signer.sign(method.as_str(), &url, payload.as_bytes()) {
+            request = request.header(name.as_str(), value);
+        }
+
+        if !payload.is_empty() {
+            request = request.header("content-type", "text/xml").body(payload);
+        }
+
+        // A change batch submitted twice fails (or creates records twice), so only reads are retried
+        let response = request.send_retrying(Idempotency::of(&method))?;
+        let status = response.status();
+        let text = response.text()?;
+        log::trace!("Received response: {}", &text);
+
+        if status.is_success() {
+            return Ok(text);
+        }
+
+        let kind = status_error_kind(status);
+
+        if let Ok(e) = quick_xml::de::from_str::<InvalidChangeBatch>(&text) {
+            return Err(ProviderError::new(kind)
+                .msg(format!("Route 53 rejected the changes. Reasons: \n - {}", e.messages.message.join("\n - "))));
+        }
+
+        match quick_xml::de::from_str::<ErrorResponse>(&text) {
+            Ok(e) => Err(ProviderError::new(kind)
+                .msg(format!("Route 53 API request failed. Reason: {} ({})", e.error.message.unwrap_or_default(), e.error.code))),
+            Err(_) => Err(ProviderError::new(kind)
+                .msg(format!("Route 53 API request failed. Received status {}", status))),
+        }
+    }
+
+    fn get_hosted_zone_id(&self, zone: &str) -> Result<String> {
+        let text = self.request(reqwest::Method::GET, "hostedzonesbyname", &[("dnsname", zone), ("maxitems", "1")], None)?;
+        let response: ListHostedZonesByNameResponse = quick_xml::de::from_str(&text)?;
+
+        match response.hosted_zones.hosted_zone.into_iter().find(|z| z.name.trim_end_matches('.').eq_ignore_ascii_case(zone.trim_end_matches('.'))) {
+            Some(z) => Ok(z.id.trim_start_matches("/hostedzone/").to_string()),
+            None => Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                .msg(format!("Hosted zone {} not found in the AWS account", zone))),
+        }
+    }
+
+    /// Fetch all hosted zones of the account, following pagination
+    fn get_hosted_zones(&self) -> Result<Vec<HostedZone>> {
+        let mut zones: Vec<HostedZone> = Vec::new();
+        let mut marker: Option<String> = None;
+
+        loop {
+            let query: Vec<(&str, &str)> = marker.iter().map(|m| ("marker", m.as_str())).collect();
+            let text = self.request(reqwest::Method::GET, "hostedzone", &query, None)?;
+            let page: ListHostedZonesResponse = quick_xml::de::from_str(&text)?;
+
+            zones.extend(page.hosted_zones.hosted_zone);
+
+            match page.next_marker {
+                Some(m) if page.is_truncated => marker = Some(m),
+                _ => break,
+            }
+        }
+
+        Ok(zones)
+    }
+
+    /// Fetch the record sets of the hosted zone, following pagination. When a name is given the
+    /// listing starts at it and stops once past it, as record sets are sorted by name.
+    fn get_rrsets(&self, zone_id: &str, name: Option<&str>, rtype: Option<&str>) -> Result<Vec<RRSet>> {
+        let path = format!("hostedzone/{}/rrset", zone_id);
+        let mut rrsets: Vec<RRSet> = Vec::new();
+
+        let mut next_name = name.map(|n| n.to_string());
+        let mut next_type = name.and(rtype).map(|t| t.to_uppercase());
+        let mut next_identifier: Option<String> = None;
+
+        loop {
+            let mut query: Vec<(&str, &str)> = Vec::new();
+            if let Some(n) = &next_name {
+                query.push(("name", n));
+            }
+            if let Some(t) = &next_type {
+                query.push(("type", t));
+            }
+            if let Some(i) = &next_identifier {
+                query.push(("identifier", i));
+            }
+
+            let text = self.request(reqwest::Method::GET, &path, &query, None)?;
+            let page: ListResourceRecordSetsResponse = quick_xml::de::from_str(&text)?;
+
+            let mut past_name = false;
+            for rrset in page.resource_record_sets.resource_record_set.into_iter().filter_map(|r| r.into_rrset()) {
+                if name.is_some_and(|n| !n.eq_ignore_ascii_case(rrset.name.trim_end_matches('.'))) {
+                    past_name = true;
+                    continue;
+                }
+
+                rrsets.push(rrset);
+            }
+
+            if !page.is_truncated || past_name {
+                break;
+            }
+
+            next_name = page.next_record_name;
+            next_type = page.next_record_type;
+            next_identifier = page.next_record_identifier;
+        }
+
+        Ok(rrsets
+            .into_iter()
+            .filter(|r| rtype.is_none_or(|t| t.eq_ignore_ascii_case(&r.r#type)))
+            .collect())
+    }
+
+    /// Submit a change batch and return the id of the change
+    fn change(&self, zone_id: &str, changes: &[(&str, &RRSet)]) -> Result<String> {
+        let body = format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ChangeResourceRecordSetsRequest xmlns=\"{}\"><ChangeBatch><Comment>{}</Comment><Changes>{}</Changes></ChangeBatch></ChangeResourceRecordSetsRequest>",
+            XML_NAMESPACE,
+            escape(&format!("ddnsclient {}", clap::crate_version!())),
+            changes.iter().map(|(action, rrset)| rrset.to_xml(action)).collect::<String>());
+
+        let text = self.request(reqwest::Method::POST, &format!("hostedzone/{}/rrset/", zone_id), &[], Some(body))?;
+        let response: ChangeResponse = quick_xml::de::from_str(&text)?;
+        log::debug!("Submitted change {} ({})", response.change_info.id, response.change_info.status);
+
+        Ok(response.change_info.id.trim_start_matches("/change/").to_string())
+    }
+
+    /// Poll the change until it has been propagated to all Route 53 DNS servers
+    fn wait_for_change(&self, change_id: &str) -> Result<()> {
+        let deadline = Instant::now() + POLL_TIMEOUT;
+
+        loop {
+            let text = self.request(reqwest::Method::GET, &format!("change/{}", change_id), &[], None)?;
+            let response: ChangeResponse = quick_xml::de::from_str(&text)?;
+
+            if response.change_info.status == "INSYNC" {
+                log::info!("Change {} is in sync", change_id);
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                return Err(ProviderError::new(ProviderErrorKind::DnsApiError)
+                    .msg(format!("Change {} is still {} after {} seconds", change_id, response.change_info.status, POLL_TIMEOUT.as_secs())));
+            }
+
+            log::debug!("Change {} is {}, checking again in {} seconds", change_id, response.change_info.status, POLL_INTERVAL.as_secs());
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn submit(&self, zone_id: &str, changes: &[(&str, &RRSet)], wait: bool) -> Result<()> {
+        let change_id = self.change(zone_id, changes)?;
+
+        if wait {
+            self.wait_for_change(&change_id)?;
+        }
+
+        Ok(())
+    }
+}
+
+
+impl Route53 {
+    pub fn new() -> Self {
+        Route53 {
+            name: String::from("route53"),
+            credentials: None,
+        }
+    }
+
+    fn open_session(&self, zone: &str, host: Option<&str>, rtype: Option<&str>) -> Result<Session> {
+        let credential = get_credential(&self.credentials, zone, host, rtype)?;
+        Session::open(&credential)
+    }
+}
+
+
+impl Provider for Route53 {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn dynamic_dns(&self, _request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(String::from("Route 53 can not detect the address of the client, use update with an A/AAAA record instead!")))
+    }
+
+    /// Search the record sets of the hosted zone, flattened into one record per value. Alias
+    /// records and records with a routing policy are skipped.
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let session = self.open_session(zone, host, rtype)?;
+
+        let zone_id = session.get_hosted_zone_id(zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+        let mut records: Vec<Record> = Vec::new();
+
+        for rrset in session.get_rrsets(&zone_id, name.as_deref(), rtype)? {
+            let host = relative_host(zone, &rrset.name);
+            for value in &rrset.values {
+                records.push(Record::from_content(&host, rrset.ttl, &rrset.r#type, value)?);
+            }
+        }
+
+        Ok(Some(records))
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let session = self.open_session(zone, host, rtype)?;
+
+        let zone_id = session.get_hosted_zone_id(zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+
+        let rrsets: Vec<RRSet> = session.get_rrsets(&zone_id, name.as_deref(), rtype)?
+            .into_iter()
+            .filter(|r| r.is_selected(zone, name.as_deref(), rtype))
+            .collect();
+
+        if rrsets.is_empty() {
+            log::info!("Nothing to delete");
+            return Ok(true);
+        }
+
+        let changes: Vec<(&str, &RRSet)> = rrsets.iter().map(|r| ("DELETE", r)).collect();
+        session.submit(&zone_id, &changes, request.wait)?;
+        log::info!("Deleted {} record set(s)", rrsets.len());
+
+        Ok(true)
+    }
+
+    /// Replace all records selected by zone|host|type with the given ones. The records are grouped
+    /// into record sets which are UPSERTed when they changed, while the selected record sets which
+    /// are not part of the new records are DELETEd, all in a single change batch.
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let session = self.open_session(zone, host, rtype)?;
+        let wanted = RRSet::group(zone, &request.records);
+
+        let zone_id = session.get_hosted_zone_id(zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+        let existing: Vec<RRSet> = session.get_rrsets(&zone_id, name.as_deref(), rtype)?
+            .into_iter()
+            .filter(|r| r.is_selected(zone, name.as_deref(), rtype))
+            .collect();
+
+        let find = |rrset: &RRSet, set: &[RRSet]| -> Option<usize> {
+            set.iter().position(|r| r.name.eq_ignore_ascii_case(&rrset.name) && r.r#type == rrset.r#type)
+        };
+
+        let mut changes: Vec<(&str, &RRSet)> = Vec::new();
+
+        for rrset in &wanted {
+            match find(rrset, &existing) {
+                Some(i) if existing[i].is_same(rrset) => (),
+                _ => changes.push(("UPSERT", rrset)),
+            }
+        }
+
+        let upserted = changes.len();
+
+        for rrset in &existing {
+            if find(rrset, &wanted).is_none() {
+                changes.push(("DELETE", rrset));
+            }
+        }
+
+        if changes.is_empty() {
+            log::info!("Records are up to date");
+            return Ok(true);
+        }
+
+        let removed = changes.len() - upserted;
+        session.submit(&zone_id, &changes, request.wait)?;
+
+        log::info!("Updated record(s)!");
+        log::debug!("Upserted {} record set(s). Removed {} record set(s)", upserted, removed);
+
+        Ok(true)
+    }
+
+    /// Add the records to their record sets. A record set which exists is DELETEd with its current
+    /// values and CREATEd again with the new ones in the same change batch, so Route 53 rejects
+    /// the batch when the record set changed in the meantime instead of losing that change.
+    fn add(&self, request: &AddRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let session = self.open_session(zone, request.selector.host(), request.selector.rtype())?;
+        let zone_id = session.get_hosted_zone_id(zone)?;
+
+        let added = RRSet::group(zone, &request.records);
+        let mut existing: Vec<RRSet> = Vec::new();
+        for rrset in &added {
+            existing.extend(session.get_rrsets(&zone_id, Some(rrset.name.trim_end_matches('.')), Some(&rrset.r#type))?);
+        }
+
+        let mut current: Vec<Record> = Vec::new();
+        for rrset in &existing {
+            let host = relative_host(zone, &rrset.name);
+            for value in &rrset.values {
+                current.push(Record::from_content(&host, rrset.ttl, &rrset.r#type, value)?);
+            }
+        }
+        check_duplicates(zone, &current, &request.records)?;
+
+        let mut created: Vec<RRSet> = Vec::new();
+        for rrset in added {
+            created.push(match existing.iter().find(|r| r.name.eq_ignore_ascii_case(&rrset.name) && r.r#type == rrset.r#type) {
+                Some(e) => RRSet {
+                    name: e.name.clone(),
+                    r#type: rrset.r#type,
+                    ttl: e.ttl.min(rrset.ttl),
+                    values: e.values.iter().cloned().chain(rrset.values).collect(),
+                },
+                None => rrset,
+            });
+        }
+
+        let mut changes: Vec<(&str, &RRSet)> = existing.iter().map(|r| ("DELETE", r)).collect();
+        changes.extend(created.iter().map(|r| ("CREATE", r)));
+
+        session.submit(&zone_id, &changes, request.wait)?;
+        log::info!("Added {} record(s)", request.records.len());
+
+        Ok(true)
+    }
+
+    fn list_zones(&self) -> Result<Vec<super::Zone>> {
+        list_account_zones(&self.credentials, |credential| {
+            let session = Session::open(credential)?;
+
+            Ok(session.get_hosted_zones()?
+                .into_iter()
+                .map(|z| super::Zone::new(&z.name)
+                    .with("id", z.id.trim_start_matches("/hostedzone/"))
+                    .with("record_count", z.resource_record_set_count)
+                    .with("private", z.config.and_then(|c| c.private_zone)))
+                .collect())
+        })
+    }
+}
+
+
+/// Resolve the access key: the credential itself, then the environment, then the shared
+/// credentials file
+fn aws_key(credential: &config::Credential) -> Result<AwsKey> {
+    if !credential.user.is_empty() && !credential.pass.is_empty() {
+        return Ok(AwsKey {
+            access_key_id: credential.user.clone(),
+            secret_access_key: credential.pass.clone(),
+            session_token: credential.token.clone(),
+        });
+    }
+
+    if let (Ok(id), Ok(secret)) = (env::var("AWS_ACCESS_KEY_ID"), env::var("AWS_SECRET_ACCESS_KEY")) {
+        log::debug!("Using the AWS access key from the environment");
+        return Ok(AwsKey {
+            access_key_id: id,
+            secret_access_key: secret,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        });
+    }
+
+    let profile = env::var("AWS_PROFILE").unwrap_or_else(|_| String::from("default"));
+    let path = match env::var_os("AWS_SHARED_CREDENTIALS_FILE") {
+        Some(p) => PathBuf::from(p),
+        None => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".aws").join("credentials"),
+            None => return Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+                .msg(String::from("Unable to find an AWS access key!"))),
+        },
+    };
+
+    let content = fs::read_to_string(&path).map_err(|e| ProviderError::new(ProviderErrorKind::CredentialNotFound)
+        .msg(format!("Unable to find an AWS access key! Could not read {}: {}", path.display(), e)))?;
+
+    match read_profile(&content, &profile) {
+        Some(key) => {
+            log::debug!("Using the AWS access key of profile {} from {}", profile, path.display());
+            Ok(key)
+        },
+        None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+            .msg(format!("Profile {} in {} does not have an AWS access key!", profile, path.display()))),
+    }
+}
+
+/// Read the access key of a profile from the INI formatted shared credentials file
+fn read_profile(content: &str, profile: &str) -> Option<AwsKey> {
+    let mut in_profile = false;
+    let (mut id, mut secret, mut token) = (None, None, None);
+
+    for line in content.lines().map(|l| l.trim()) {
+        if line.starts_with('[') && line.ends_with(']') {
+            in_profile = line[1..line.len() - 1].trim() == profile;
+            continue;
+        }
+
+        if !in_profile || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            let value = Some(value.trim().to_string());
+            match key.trim().to_lowercase().as_str() {
+                "aws_access_key_id" => id = value,
+                "aws_secret_access_key" => secret = value,
+                "aws_session_token" => token = value,
+                _ => (),
+            }
+        }
+    }
+
+    Some(AwsKey {
+        access_key_id: id?,
+        secret_access_key: secret?,
+        session_token: token,
+    })
+}
+
+/// Route 53 returns some characters of the names escaped as octal codes (e.g. \052 for *)
+fn decode_name(name: &str) -> String {
+    let mut decoded = String::new();
+    let mut chars = name.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            decoded.push(c);
+            continue;
+        }
+
+        let code: String = (0..3).filter_map(|_| chars.next_if(|d| d.is_digit(8))).collect();
+        match u8::from_str_radix(&code, 8) {
+            Ok(b) if code.len() == 3 => decoded.push(b as char),
+            _ => {
+                decoded.push(c);
+                decoded.push_str(&code);
+            },
+        }
+    }
+
+    decoded
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+    use crate::providers::test_server::{Reply, Request, Server};
+
+    const ZONE_ID: &str = "Z1D633PJN98FT9";
+
+    /// A Route 53 API answering with responses recorded from the real one
+    fn api() -> Server {
+        Server::start(|request| {
+            let path = request.path().trim_start_matches("/2013-04-01/");
+            let body = match (request.method.as_str(), path) {
+                ("GET", "hostedzonesbyname") => include_str!("../../../tests/fixtures/route53/hosted_zones_by_name.xml"),
+                ("GET", "hostedzone") if request.query("marker").is_some() => include_str!("../../../tests/fixtures/route53/hosted_zones_page_2.xml"),
+                ("GET", "hostedzone") => include_str!("../../../tests/fixtures/route53/hosted_zones_page_1.xml"),
+                // The listing starts at the name asked for, and the second page starts at www
+                ("GET", "hostedzone/Z1D633PJN98FT9/rrset") if request.query("name").is_some_and(|n| n.starts_with("www")) => include_str!("../../../tests/fixtures/route53/rrsets_page_2.xml"),
+                ("GET", "hostedzone/Z1D633PJN98FT9/rrset") => include_str!("../../../tests/fixtures/route53/rrsets_page_1.xml"),
+                ("POST", "hostedzone/Z1D633PJN98FT9/rrset/") => include_str!("../../../tests/fixtures/route53/change.xml"),
+                ("GET", "change/C2682N5HXP0BZ4") => include_str!("../../../tests/fixtures/route53/change_insync.xml"),
+                _ => return Reply::new(404, "<ErrorResponse><Error><Code>NoSuchHostedZone</Code></Error></ErrorResponse>"),
+            };
+
+            Reply::new(200, body)
+        })
+    }
+
+    fn provider(server: &Server) -> Route53 {
+        let mut provider = Route53::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("route53"),
+            user: String::from("AKIDEXAMPLE"),
+            pass: String::from("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+            api_url: Some(server.url().to_string()),
+            ..Default::default()
+        }]);
+
+        provider
+    }
+
+    fn search(provider: &Route53, host: Option<&str>, rtype: Option<&str>) -> Vec<Record> {
+        provider.search(&SearchRequest {
+            selector: RecordSelector::new("example.com", host, rtype),
+        }).unwrap().unwrap()
+    }
+
+    fn a(host: &str, data: &str) -> Record {
+        Record {
+            host: host.to_string(),
+            ttl: 300,
+            r#type: String::from("A"),
+            data: data.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The change batch sent to the API
+    fn change_batch(server: &Server) -> Option<Request> {
+        server.requests().into_iter().find(|r| r.method == "POST")
+    }
+
+    #[test]
+    fn follows_the_pages_of_record_sets() {
+        let server = api();
+        let records = search(&provider(&server), None, None);
+
+        let summary: Vec<(&str, &str, &str)> = records.iter()
+            .map(|r| (r.host.as_str(), r.r#type.as_str(), r.data.as_str()))
+            .collect();
+        assert_eq!(summary.len(), 8);
+        assert!(summary.contains(&("*", "A", "192.0.2.9")));
+        assert!(summary.contains(&("www", "A", "192.0.2.1")));
+        assert!(summary.contains(&("www", "A", "192.0.2.2")));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].query("dnsname").as_deref(), Some("example.com"));
+        assert_eq!(requests[2].query("name").as_deref(), Some("www.example.com."));
+        assert_eq!(requests[2].query("type").as_deref(), Some("A"));
+    }
+
+    #[test]
+    fn skips_aliases_and_routing_policies() {
+        let server = api();
+        let records = search(&provider(&server), Some("www"), None);
+
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| r.r#type == "A"));
+    }
+
+    #[test]
+    fn maps_the_priority_weight_and_port() {
+        let server = api();
+        let records = search(&provider(&server), None, None);
+
+        let mx = records.iter().find(|r| r.r#type == "MX").unwrap();
+        assert_eq!((mx.host.as_str(), mx.data.as_str(), mx.mx_priority), ("@", "mail.example.com", Some(10)));
+
+        let srv = records.iter().find(|r| r.r#type == "SRV").unwrap();
+        assert_eq!((srv.srv_priority, srv.srv_weight, srv.srv_port), (Some(10), Some(60), Some(5060)));
+    }
+
+    #[test]
+    fn signs_the_requests() {
+        let server = api();
+        search(&provider(&server), Some("www"), Some("A"));
+
+        let request = &server.requests()[0];
+        let authorization = request.header("Authorization").unwrap();
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+        assert!(authorization.contains("/us-east-1/route53/aws4_request, SignedHeaders=host;x-amz-date, Signature="));
+        assert!(request.header("X-Amz-Date").is_some());
+    }
+
+    #[test]
+    fn upserts_and_deletes_in_one_change_batch() {
+        let server = api();
+
+        provider(&server).update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("www"), None),
+            records: vec![Record {
+                host: String::from("www"),
+                ttl: 60,
+                r#type: String::from("TXT"),
+                data: String::from("a < b"),
+                ..Default::default()
+            }],
+            wait: false,
+        }).unwrap();
+
+        let batch = change_batch(&server).unwrap();
+        assert_eq!(batch.header("Content-Type"), Some("text/xml"));
+        assert!(batch.body.contains("<Change><Action>UPSERT</Action><ResourceRecordSet><Name>www.example.com.</Name><Type>TXT</Type><TTL>60</TTL><ResourceRecords><ResourceRecord><Value>&quot;a &lt; b&quot;</Value></ResourceRecord></ResourceRecords></ResourceRecordSet></Change>"));
+        assert!(batch.body.contains("<Change><Action>DELETE</Action><ResourceRecordSet><Name>www.example.com.</Name><Type>A</Type><TTL>300</TTL><ResourceRecords><ResourceRecord><Value>192.0.2.1</Value></ResourceRecord><ResourceRecord><Value>192.0.2.2</Value></ResourceRecord></ResourceRecords></ResourceRecordSet></Change>"));
+        // The alias and the record with a routing policy are left alone
+        assert_eq!(batch.body.matches("<Change>").count(), 2);
+    }
+
+    #[test]
+    fn adds_by_swapping_the_record_set_in_one_change_batch() {
+        let server = api();
+
+        provider(&server).add(&AddRequest {
+            selector: RecordSelector::new("example.com", None, None),
+            records: vec![a("www", "192.0.2.3"), a("ftp", "192.0.2.4")],
+            wait: false,
+        }).unwrap();
+
+        // Deleting the record set with its current values fails when it changed meanwhile
+        let batch = change_batch(&server).unwrap();
+        assert!(batch.body.contains("<Change><Action>DELETE</Action><ResourceRecordSet><Name>www.example.com.</Name><Type>A</Type><TTL>300</TTL><ResourceRecords><ResourceRecord><Value>192.0.2.1</Value></ResourceRecord><ResourceRecord><Value>192.0.2.2</Value></ResourceRecord></ResourceRecords></ResourceRecordSet></Change>\
+            <Change><Action>CREATE</Action><ResourceRecordSet><Name>www.example.com.</Name><Type>A</Type><TTL>300</TTL><ResourceRecords><ResourceRecord><Value>192.0.2.1</Value></ResourceRecord><ResourceRecord><Value>192.0.2.2</Value></ResourceRecord><ResourceRecord><Value>192.0.2.3</Value></ResourceRecord></ResourceRecords></ResourceRecordSet></Change>"));
+        assert!(batch.body.contains("<Change><Action>CREATE</Action><ResourceRecordSet><Name>ftp.example.com.</Name><Type>A</Type><TTL>300</TTL><ResourceRecords><ResourceRecord><Value>192.0.2.4</Value></ResourceRecord></ResourceRecords></ResourceRecordSet></Change>"));
+        assert_eq!(batch.body.matches("<Change>").count(), 3);
+    }
+
+    #[test]
+    fn refuses_to_add_records_which_exist() {
+        let server = api();
+
+        let error = provider(&server).add(&AddRequest {
+            selector: RecordSelector::new("example.com", Some("www"), Some("A")),
+            records: vec![a("www", "192.0.2.2")],
+            wait: false,
+        }).unwrap_err();
+
+        assert!(matches!(error.kind(), ProviderErrorKind::DuplicateRecord));
+        assert!(change_batch(&server).is_none());
+    }
+
+    #[test]
+    fn skips_the_change_batch_when_up_to_date() {
+        let server = api();
+
+        provider(&server).update(&UpdateRequest {
+            selector: RecordSelector::new("example.com", Some("www"), Some("A")),
+            records: vec![a("www", "192.0.2.2"), a("www", "192.0.2.1")],
+            wait: false,
+        }).unwrap();
+
+        assert!(change_batch(&server).is_none());
+    }
+
+    #[test]
+    fn never_deletes_the_soa_and_apex_ns_records() {
+        let server = api();
+
+        provider(&server).delete(&DeleteRequest {
+            selector: RecordSelector::new("example.com", Some("@"), None),
+            wait: false,
+        }).unwrap();
+
+        let batch = change_batch(&server).unwrap();
+        assert_eq!(batch.body.matches("<Change>").count(), 1);
+        assert!(batch.body.contains("<Name>example.com.</Name><Type>MX</Type>"));
+    }
+
+    #[test]
+    fn waits_for_the_change_to_be_in_sync() {
+        let server = api();
+
+        provider(&server).delete(&DeleteRequest {
+            selector: RecordSelector::new("example.com", Some("www"), Some("A")),
+            wait: true,
+        }).unwrap();
+
+        let requests = server.requests();
+        let last = requests.last().unwrap();
+        assert_eq!((last.method.as_str(), last.path()), ("GET", "/2013-04-01/change/C2682N5HXP0BZ4"));
+    }
+
+    #[test]
+    fn follows_the_pages_of_hosted_zones() {
+        let server = api();
+        let zones = provider(&server).list_zones().unwrap();
+
+        assert_eq!(zones.len(), 2);
+        assert_eq!(zones[0].name, "example.com");
+        assert_eq!(zones[0].metadata["id"], ZONE_ID);
+        assert_eq!(zones[1].metadata["private"], true);
+        assert_eq!(server.requests()[1].query("marker").as_deref(), Some("Z2682N5HXP0BZ4"));
+    }
+
+    #[test]
+    fn reports_the_rejected_changes() {
+        let server = Server::start(|request| match request.method.as_str() {
+            "POST" => Reply::new(400, include_str!("../../../tests/fixtures/route53/invalid_change_batch.xml")),
+            _ if request.path().ends_with("hostedzonesbyname") => Reply::new(200, include_str!("../../../tests/fixtures/route53/hosted_zones_by_name.xml")),
+            _ => Reply::new(200, include_str!("../../../tests/fixtures/route53/rrsets_page_2.xml")),
+        });
+
+        let error = provider(&server).delete(&DeleteRequest {
+            selector: RecordSelector::new("example.com", Some("www"), Some("A")),
+            wait: false,
+        }).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::DnsApiError));
+        assert!(error.to_string().contains("but it was not found"));
+    }
+
+    #[test]
+    fn reports_the_errors_of_the_api() {
+        let server = Server::start(|_| Reply::new(403, include_str!("../../../tests/fixtures/route53/signature_does_not_match.xml")));
+
+        let error = provider(&server).search(&SearchRequest {
+            selector: RecordSelector::new("example.com", None, None),
+        }).unwrap_err();
+        assert!(matches!(error.kind(), ProviderErrorKind::AuthenticationFailed));
+        assert!(error.to_string().contains("(SignatureDoesNotMatch)"));
+    }
+
+    #[test]
+    fn reads_the_profile_of_the_shared_credentials_file() {
+        let content = "[default]\naws_access_key_id = AKIDDEFAULT\naws_secret_access_key = default-secret\n\n\
+            [dns]\n# Used by ddnsclient\naws_access_key_id=AKIDDNS\naws_secret_access_key=dns-secret\naws_session_token = token\n";
+
+        let key = read_profile(content, "dns").unwrap();
+        assert_eq!((key.access_key_id.as_str(), key.secret_access_key.as_str()), ("AKIDDNS", "dns-secret"));
+        assert_eq!(key.session_token.as_deref(), Some("token"));
+
+        let key = read_profile(content, "default").unwrap();
+        assert_eq!((key.access_key_id.as_str(), key.session_token), ("AKIDDEFAULT", None));
+
+        assert!(read_profile(content, "missing").is_none());
+    }
+
+    #[test]
+    fn decodes_the_escaped_characters_of_names() {
+        assert_eq!(decode_name("\\052.example.com."), "*.example.com.");
+        assert_eq!(decode_name("a\\100b.example.com."), "a@b.example.com.");
+        assert_eq!(decode_name("back\\slash"), "back\\slash");
+    }
+}
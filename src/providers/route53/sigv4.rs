@@ -0,0 +1,193 @@
+//! AWS Signature Version 4 request signing.
+use crate::providers::civil_date;
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+/// Access key used to sign the requests, optionally with the token of a temporary session
+#[derive(Debug, Clone)]
+pub struct AwsKey {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+/// Signs the requests sent to a service in a region
+#[derive(Debug)]
+pub struct Signer {
+    pub key: AwsKey,
+    pub region: String,
+    pub service: String,
+}
+
+
+impl Signer {
+    /// Headers to add to the request so it is authenticated, for a request sent now
+    pub fn sign(&self, method: &str, url: &reqwest::Url, payload: &[u8]) -> Vec<(String, String)> {
+        self.sign_at(method, url, payload, SystemTime::now())
+    }
+
+    fn sign_at(&self, method: &str, url: &reqwest::Url, payload: &[u8], time: SystemTime) -> Vec<(String, String)> {
+        let amz_date = timestamp(time);
+        let date = &amz_date[..8];
+
+        let host = match url.port() {
+            Some(p) => format!("{}:{}", url.host_str().unwrap_or_default(), p),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        // Headers have to be sorted by name
+        let mut headers = vec![
+            (String::from("host"), host),
+            (String::from("x-amz-date"), amz_date.clone()),
+        ];
+        if let Some(t) = &self.key.session_token {
+            headers.push((String::from("x-amz-security-token"), t.clone()));
+        }
+
+        let signed_headers = headers.iter().map(|(n, _)| n.as_str()).collect::<Vec<&str>>().join(";");
+        let canonical_headers: String = headers.iter().map(|(n, v)| format!("{}:{}\n", n, v.trim())).collect();
+
+        let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            canonical_uri(url),
+            canonical_query(url),
+            canonical_headers,
+            signed_headers,
+            hex::encode(Sha256::digest(payload)),
+        );
+        log::trace!("Canonical request: {}", canonical_request);
+
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!("{}\n{}\n{}\n{}",
+            ALGORITHM,
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes())),
+        );
+
+        let mut key = hmac(format!("AWS4{}", self.key.secret_access_key).as_bytes(), date.as_bytes());
+        for part in &[self.region.as_str(), self.service.as_str(), "aws4_request"] {
+            key = hmac(&key, part.as_bytes());
+        }
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+
+        let mut result: Vec<(String, String)> = headers.into_iter().filter(|(n, _)| n != "host").collect();
+        result.push((String::from("authorization"), format!("{} Credential={}/{}, SignedHeaders={}, Signature={}",
+            ALGORITHM, self.key.access_key_id, scope, signed_headers, signature)));
+
+        result
+    }
+}
+
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode everything but the unreserved characters of RFC 3986
+fn uri_encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Every segment of the (already encoded) path is encoded once more, as for all services but S3
+fn canonical_uri(url: &reqwest::Url) -> String {
+    let path = url.path();
+    if path.is_empty() {
+        return String::from("/");
+    }
+
+    path.split('/').map(uri_encode).collect::<Vec<String>>().join("/")
+}
+
+fn canonical_query(url: &reqwest::Url) -> String {
+    let mut pairs: Vec<(String, String)> = url.query_pairs()
+        .map(|(k, v)| (uri_encode(&k), uri_encode(&v)))
+        .collect();
+    pairs.sort();
+
+    pairs.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<String>>().join("&")
+}
+
+/// Format the time as required by the signature, e.g. 20150830T123600Z
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_date(secs);
+    let rem = secs % 86400;
+
+    format!("{:04}{:02}{:02}T{:02}{:02}{:02}Z", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// 2015-08-30T12:36:00Z, the time of the requests of the AWS signature test suite
+    fn test_suite_time() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_440_938_160)
+    }
+
+    fn signer(session_token: Option<&str>) -> Signer {
+        Signer {
+            key: AwsKey {
+                access_key_id: String::from("AKIDEXAMPLE"),
+                secret_access_key: String::from("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY"),
+                session_token: session_token.map(String::from),
+            },
+            region: String::from("us-east-1"),
+            service: String::from("service"),
+        }
+    }
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn formats_the_time_of_the_request() {
+        assert_eq!(timestamp(test_suite_time()), "20150830T123600Z");
+    }
+
+    #[test]
+    fn signs_like_the_aws_test_suite() {
+        // get-vanilla
+        let url = reqwest::Url::parse("https://example.amazonaws.com/").unwrap();
+        let headers = signer(None).sign_at("GET", &url, b"", test_suite_time());
+
+        assert_eq!(header(&headers, "x-amz-date"), Some("20150830T123600Z"));
+        assert_eq!(header(&headers, "authorization"), Some("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+            SignedHeaders=host;x-amz-date, Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"));
+        assert_eq!(header(&headers, "host"), None);
+    }
+
+    #[test]
+    fn signs_the_session_token() {
+        let url = reqwest::Url::parse("https://example.amazonaws.com/").unwrap();
+        let headers = signer(Some("token")).sign_at("GET", &url, b"", test_suite_time());
+
+        assert_eq!(header(&headers, "x-amz-security-token"), Some("token"));
+        assert!(header(&headers, "authorization").unwrap().contains("SignedHeaders=host;x-amz-date;x-amz-security-token,"));
+    }
+
+    #[test]
+    fn sorts_and_encodes_the_query() {
+        let url = reqwest::Url::parse("https://example.amazonaws.com/2013-04-01/hostedzone?name=%2A.example.com.&maxitems=1").unwrap();
+
+        assert_eq!(canonical_uri(&url), "/2013-04-01/hostedzone");
+        assert_eq!(canonical_query(&url), "maxitems=1&name=%2A.example.com.");
+    }
+}
@@ -0,0 +1,143 @@
+//! A stand-in HTTP server for the tests of the providers: it answers every request with what the
+//! handler of the test returns, and keeps the requests for the test to check what was sent.
+//!
+//! Every connection is closed after one request, so the shared client never reuses a connection to
+//! a server which is gone.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A request received by the server
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub method: String,
+    /// Path of the request, with the query string
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// The answer to a request
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// A server listening on a random port of the loopback interface until the test ends
+pub struct Server {
+    url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+
+impl Request {
+    /// Path of the request, without the query string
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    /// Value of the (decoded) query parameter
+    pub fn query(&self, name: &str) -> Option<String> {
+        let url = reqwest::Url::parse(&format!("http://localhost{}", self.target)).ok()?;
+        url.query_pairs().find(|(n, _)| n == name).map(|(_, v)| v.into_owned())
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+impl Reply {
+    pub fn new(status: u16, body: &str) -> Self {
+        Reply {
+            status,
+            headers: vec![],
+            body: body.to_string(),
+        }
+    }
+}
+
+impl Server {
+    pub fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&Request) -> Reply + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("The loopback interface is available");
+        let url = format!("http://{}", listener.local_addr().expect("The listener has an address"));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&requests);
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Some(request) = read(&stream) {
+                    let reply = handler(&request);
+                    received.lock().expect("Lock is never poisoned").push(request);
+                    let _ = write(stream, &reply);
+                }
+            }
+        });
+
+        Server {
+            url,
+            requests,
+        }
+    }
+
+    /// Base URL of the server, e.g. `http://127.0.0.1:41234`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The requests received so far, oldest first
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().expect("Lock is never poisoned").clone()
+    }
+}
+
+
+fn read(stream: &TcpStream) -> Option<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+
+    let mut fields = line.split_whitespace();
+    let mut request = Request {
+        method: fields.next()?.to_string(),
+        target: fields.next()?.to_string(),
+        ..Default::default()
+    };
+
+    loop {
+        line.clear();
+        reader.read_line(&mut line).ok()?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        let (name, value) = header.split_once(':')?;
+        request.headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let length: usize = request.header("Content-Length").and_then(|l| l.parse().ok()).unwrap_or(0);
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).ok()?;
+    request.body = String::from_utf8(body).ok()?;
+
+    Some(request)
+}
+
+fn write(mut stream: TcpStream, reply: &Reply) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} Stand-in\r\nContent-Length: {}\r\nConnection: close\r\n", reply.status, reply.body.len());
+    for (name, value) in &reply.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(reply.body.as_bytes())?;
+    stream.flush()
+}
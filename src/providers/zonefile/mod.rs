@@ -0,0 +1,513 @@
+mod parser;
+
+use super::{Provider, DynamicDnsRequest, DynamicDnsResponse, SearchRequest, UpdateRequest, DeleteRequest, Record, Zone, ProviderError, ProviderErrorKind, Result, get_credential, fqdn, relative_host, reconcile, civil_date};
+use crate::config;
+use parser::{Entry, RecordEntry, ZoneFile};
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Zone file provider.
+///
+/// Manages the records of a BIND master file on disk, given by the `path` of the credential.
+/// Changes only rewrite the affected records, bump the serial of the SOA record and are followed
+/// by the `reload_command` of the credential (e.g. `rndc reload example.com`), when there is one.
+#[derive(Debug)]
+pub struct ZoneFileProvider {
+    name: String,
+    credentials: Option<config::Credentials>,
+}
+
+
+impl RecordEntry {
+    fn is_selected(&self, zone: &str, name: Option<&str>, rtype: Option<&str>) -> bool {
+        if self.class != "IN" {
+            return false;
+        }
+
+        name.is_none_or(|n| n.eq_ignore_ascii_case(&self.owner))
+            && rtype.is_none_or(|t| t.eq_ignore_ascii_case(&self.r#type))
+            && !keeps_the_zone_working(zone, &self.owner, &self.r#type)
+    }
+
+    fn to_record(&self, zone: &str) -> Result<Record> {
+        Record::from_content(&relative_host(zone, &self.owner), self.ttl, &self.r#type, &self.content())
+    }
+}
+
+
+impl ZoneFileProvider {
+    pub fn new() -> Self {
+        ZoneFileProvider {
+            name: String::from("zonefile"),
+            credentials: None,
+        }
+    }
+
+    fn get_path(credential: &config::Credential) -> Result<&str> {
+        match &credential.path {
+            Some(p) => Ok(p),
+            None => Err(ProviderError::new(ProviderErrorKind::CredentialNotFound)
+                .msg(String::from("The zonefile provider requires the path of the zone file!"))),
+        }
+    }
+
+    fn load(path: &str, zone: &str) -> Result<ZoneFile> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            let message = format!("Unable to read zone file {}: {}", path, e);
+            ProviderError::new(ProviderErrorKind::IoError(e)).msg(message)
+        })?;
+
+        ZoneFile::parse(&content, zone)
+    }
+
+    /// Replace the selected records with the given ones and write the file, if anything changed
+    fn apply(credential: &config::Credential, zone: &str, host: Option<&str>, rtype: Option<&str>, records: &[Record]) -> Result<bool> {
+        let path = ZoneFileProvider::get_path(credential)?;
+        let mut file = ZoneFileProvider::load(path, zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+
+        // Left out on both sides, so the records of a search can be given back as they are
+        let records: Vec<Record> = records
+            .iter()
+            .filter(|r| !keeps_the_zone_working(zone, &fqdn(zone, Some(&r.host)), &r.r#type))
+            .cloned()
+            .collect();
+
+        let mut existing: Vec<(usize, Record)> = Vec::new();
+        for (i, entry) in file.entries.iter().enumerate() {
+            if let Some(r) = entry.record.as_ref().filter(|r| r.is_selected(zone, name.as_deref(), rtype)) {
+                existing.push((i, r.to_record(zone)?));
+            }
+        }
+
+        let mut changes = reconcile(zone, existing, &records);
+
+        // A record whose data changed takes the place of the one it replaces
+        changes.replace_in_place(|i, record| file.entries[*i].record.as_ref()
+            .is_some_and(|r| r.owner.eq_ignore_ascii_case(&fqdn(zone, Some(&record.host))) && r.r#type.eq_ignore_ascii_case(&record.r#type)));
+
+        if changes.create.is_empty() && changes.update.is_empty() && changes.delete.is_empty() {
+            log::info!("Records are up to date");
+            return Ok(false);
+        }
+
+        for (i, record) in &changes.update {
+            let entry = &mut file.entries[*i];
+            let current = entry.record.as_mut().expect("Only records are reconciled");
+            entry.text = parser::render(&current.origin, &current.owner, record.ttl, &record.r#type.to_uppercase(), &record.to_content());
+            current.explicit_owner = true;
+        }
+
+        // New records go after the last record of the same owner, or at the end of the file
+        let mut inserts: Vec<(Option<usize>, String)> = Vec::new();
+        for record in &changes.create {
+            let owner = fqdn(zone, Some(&record.host)).to_lowercase();
+            let position = file.entries.iter().rposition(|e| e.record.as_ref().is_some_and(|r| r.owner == owner));
+            let origin = match position {
+                Some(p) => file.entries[p].record.as_ref().map(|r| r.origin.clone()).unwrap_or_default(),
+                None => file.origin.clone(),
+            };
+
+            inserts.push((position, parser::render(&origin, &owner, record.ttl, &record.r#type.to_uppercase(), &record.to_content())));
+        }
+
+        bump_serial(&mut file)?;
+
+        let mut output = String::new();
+        let mut last_owner: Option<String> = None;
+
+        for (i, entry) in file.entries.iter().enumerate() {
+            if !changes.delete.contains(&i) {
+                write_entry(&mut output, entry, &mut last_owner);
+            }
+
+            for (_, text) in inserts.iter().filter(|(p, _)| *p == Some(i)) {
+                output.push_str(text);
+            }
+            if inserts.iter().any(|(p, _)| *p == Some(i)) {
+                last_owner = entry.record.as_ref().map(|r| r.owner.clone());
+            }
+        }
+
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        for (_, text) in inserts.iter().filter(|(p, _)| p.is_none()) {
+            output.push_str(text);
+        }
+
+        write_file(path, &output)?;
+        log::debug!("Added {} record(s). Changed {} record(s). Removed {} record(s)", changes.create.len(), changes.update.len(), changes.delete.len());
+
+        reload(credential)?;
+
+        Ok(true)
+    }
+}
+
+
+impl Provider for ZoneFileProvider {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn set_credentials(&mut self, c: config::Credentials) {
+        self.credentials = Some(c);
+    }
+
+    fn dynamic_dns(&self, _request: &DynamicDnsRequest) -> Result<DynamicDnsResponse> {
+        Err(ProviderError::new(ProviderErrorKind::Unsupported)
+            .msg(String::from("A zone file can not tell the address of the client, use update with an A/AAAA record instead!")))
+    }
+
+    fn search(&self, request: &SearchRequest) -> Result<Option<Vec<Record>>> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let credential = get_credential(&self.credentials, zone, host, rtype)?;
+
+        let file = ZoneFileProvider::load(ZoneFileProvider::get_path(&credential)?, zone)?;
+        let name = host.map(|h| fqdn(zone, Some(h)));
+        let mut records: Vec<Record> = Vec::new();
+
+        for record in file.entries.iter().filter_map(|e| e.record.as_ref()) {
+            if record.class != "IN" {
+                continue;
+            }
+
+            if name.as_ref().is_some_and(|n| !n.eq_ignore_ascii_case(&record.owner)) {
+                continue;
+            }
+
+            if rtype.is_some_and(|t| !t.eq_ignore_ascii_case(&record.r#type)) {
+                continue;
+            }
+
+            records.push(record.to_record(zone)?);
+        }
+
+        Ok(Some(records))
+    }
+
+    fn delete(&self, request: &DeleteRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let credential = get_credential(&self.credentials, zone, host, rtype)?;
+
+        if ZoneFileProvider::apply(&credential, zone, host, rtype, &[])? {
+            log::info!("Deleted record(s)");
+        }
+
+        Ok(true)
+    }
+
+    /// Replace all records selected by zone|host|type with the given ones. Records which did not
+    /// change are left exactly as they were written.
+    fn update(&self, request: &UpdateRequest) -> Result<bool> {
+        let zone = request.selector.zone();
+        let host = request.selector.host();
+        let rtype = request.selector.rtype();
+        let records = &request.records;
+        let credential = get_credential(&self.credentials, zone, host, rtype)?;
+
+        if ZoneFileProvider::apply(&credential, zone, host, rtype, records)? {
+            log::info!("Updated record(s)!");
+        }
+
+        Ok(true)
+    }
+
+    /// The zones are the ones configured, along with the serial of their file
+    fn list_zones(&self) -> Result<Vec<Zone>> {
+        let mut zones: Vec<Zone> = Vec::new();
+
+        for credential in self.credentials.iter().flatten() {
+            let (zone, path) = match (&credential.zone, &credential.path) {
+                (Some(z), Some(p)) => (z, p),
+                _ => continue,
+            };
+
+            let serial = match ZoneFileProvider::load(path, zone) {
+                Ok(file) => soa_serial(&file),
+                Err(e) => {
+                    log::warn!("{}", e);
+                    None
+                },
+            };
+
+            zones.push(Zone::new(zone).with("path", path.as_str()).with("serial", serial));
+        }
+
+        zones.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(zones)
+    }
+}
+
+
+/// Whether the record is one which keeps the zone working (the SOA record and the NS records of
+/// the apex), which are never touched
+fn keeps_the_zone_working(zone: &str, owner: &str, rtype: &str) -> bool {
+    rtype.eq_ignore_ascii_case("SOA")
+        || rtype.eq_ignore_ascii_case("NS") && owner.trim_end_matches('.').eq_ignore_ascii_case(zone.trim_end_matches('.'))
+}
+
+/// Write an entry, giving it back its owner when the record it inherited the owner from is gone
+fn write_entry(output: &mut String, entry: &Entry, last_owner: &mut Option<String>) {
+    match &entry.record {
+        Some(r) => {
+            if !r.explicit_owner && last_owner.as_deref() != Some(r.owner.as_str()) {
+                output.push_str(&parser::with_owner(entry));
+            } else {
+                output.push_str(&entry.text);
+            }
+            *last_owner = Some(r.owner.clone());
+        },
+        None => output.push_str(&entry.text),
+    }
+}
+
+/// Serial of the SOA record of the zone, if it has one
+fn soa_serial(file: &ZoneFile) -> Option<u32> {
+    file.entries.iter()
+        .filter_map(|e| e.record.as_ref())
+        .find(|r| r.r#type == "SOA")
+        .and_then(|r| r.rdata.get(2))
+        .and_then(|s| s.parse().ok())
+}
+
+/// Increase the serial of the SOA record, following the YYYYMMDDnn convention when the zone
+/// already does
+fn bump_serial(file: &mut ZoneFile) -> Result<()> {
+    let entry = match file.entries.iter_mut().find(|e| e.record.as_ref().is_some_and(|r| r.r#type == "SOA")) {
+        Some(e) => e,
+        None => {
+            log::warn!("The zone file has no SOA record, the serial is not updated");
+            return Ok(());
+        },
+    };
+
+    let current = entry.record.as_ref()
+        .and_then(|r| r.rdata.get(2))
+        .and_then(|s| s.parse::<u32>().ok())
+        .ok_or_else(|| ProviderError::new(ProviderErrorKind::InvalidRecord)
+            .msg(String::from("The SOA record of the zone file does not have a valid serial")))?;
+
+    let serial = next_serial(current, SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+    log::debug!("Serial of the zone changed from {} to {}", current, serial);
+
+    entry.set_serial(serial);
+
+    Ok(())
+}
+
+/// The serial following the current one at the time (in seconds since the epoch): the first
+/// serial of the day (YYYYMMDD00) when the zone follows the YYYYMMDDnn convention and has no serial
+/// of today yet, and the next number otherwise
+fn next_serial(current: u32, now: u64) -> u32 {
+    let (year, month, day) = civil_date(now);
+    let today = (year as u32) * 1_000_000 + month * 10_000 + day * 100;
+
+    let is_date = (1_970_010_100..=4_294_967_295).contains(&current)
+        && (1..=12).contains(&(current / 10_000 % 100))
+        && (1..=31).contains(&(current / 100 % 100));
+
+    if is_date && current < today { today } else { current.wrapping_add(1) }
+}
+
+/// Replace the file through a temporary file, so it is never left half written
+fn write_file(path: &str, content: &str) -> Result<()> {
+    let target = Path::new(path);
+    let temporary = target.with_file_name(format!(".{}.tmp", target.file_name().and_then(|n| n.to_str()).unwrap_or("zone")));
+
+    fs::write(&temporary, content)?;
+    if let Ok(metadata) = fs::metadata(target) {
+        fs::set_permissions(&temporary, metadata.permissions())?;
+    }
+    fs::rename(&temporary, target)?;
+
+    Ok(())
+}
+
+fn reload(credential: &config::Credential) -> Result<()> {
+    let command = match &credential.reload_command {
+        Some(c) => c,
+        None => return Ok(()),
+    };
+
+    log::debug!("Running {}", command);
+    let output = Command::new("sh").arg("-c").arg(command).output()?;
+
+    if !output.status.success() {
+        return Err(ProviderError::new(ProviderErrorKind::DnsError)
+            .msg(format!("Reload command failed ({}): {}", output.status, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+
+    log::info!("Reloaded the zone");
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::RecordSelector;
+
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    const ZONE: &str = include_str!("../../../tests/fixtures/zonefile/example.com.zone");
+
+    /// 2024-03-02T01:00:00Z
+    const MARCH_2ND: u64 = 1_709_341_200;
+
+    /// A copy of the example zone, and the provider editing it
+    fn provider(name: &str) -> (ZoneFileProvider, PathBuf) {
+        let path = env::temp_dir().join(format!("ddnsclient-zonefile-{}-{}.zone", process::id(), name));
+        fs::write(&path, ZONE).unwrap();
+
+        let mut provider = ZoneFileProvider::new();
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("zonefile"),
+            zone: Some(String::from("example.com")),
+            path: Some(path.to_string_lossy().to_string()),
+            ..Default::default()
+        }]);
+
+        (provider, path)
+    }
+
+    fn search(provider: &ZoneFileProvider, host: Option<&str>, rtype: Option<&str>) -> Vec<Record> {
+        provider.search(&SearchRequest { selector: RecordSelector::new("example.com", host, rtype) }).unwrap().unwrap()
+    }
+
+    fn update(provider: &ZoneFileProvider, host: Option<&str>, rtype: Option<&str>, records: Vec<Record>) {
+        provider.update(&UpdateRequest { selector: RecordSelector::new("example.com", host, rtype), records, wait: false }).unwrap();
+    }
+
+    fn record(host: &str, rtype: &str, data: &str) -> Record {
+        Record::from_content(host, 300, rtype, data).unwrap()
+    }
+
+    /// The example zone with the serial the change gives it
+    fn changed_zone() -> String {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        ZONE.replace("2024030101", &next_serial(2024030101, now).to_string())
+    }
+
+    #[test]
+    fn searches_the_records_of_a_host() {
+        let (provider, path) = provider("search");
+        let records = search(&provider, Some("www"), None);
+        fs::remove_file(&path).unwrap();
+
+        let found: Vec<(&str, &str)> = records.iter().map(|r| (r.r#type.as_str(), r.data.as_str())).collect();
+        assert_eq!(found, vec![("A", "192.0.2.1"), ("AAAA", "2001:db8::1")]);
+        assert!(records.iter().all(|r| r.host == "www" && r.ttl == 300));
+    }
+
+    #[test]
+    fn leaves_the_file_alone_when_the_records_are_given_back() {
+        let (provider, path) = provider("round-trip");
+        let records = search(&provider, None, None);
+        assert!(records.iter().any(|r| r.r#type == "SOA"));
+
+        update(&provider, None, None, records);
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Not even the serial changed
+        assert_eq!(content, ZONE);
+    }
+
+    #[test]
+    fn never_touches_the_soa_and_apex_ns_records() {
+        let (provider, path) = provider("zone-records");
+        update(&provider, None, None, vec![]);
+        let records = search(&provider, None, None);
+        fs::remove_file(&path).unwrap();
+
+        let types: Vec<&str> = records.iter().map(|r| r.r#type.as_str()).collect();
+        assert_eq!(types, vec!["SOA", "NS", "NS"]);
+    }
+
+    #[test]
+    fn rewrites_the_changed_records_only() {
+        let (provider, path) = provider("update");
+        update(&provider, Some("www"), Some("A"), vec![record("www", "A", "192.0.2.2")]);
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        // Comments, blank lines and the order of the records are kept
+        assert_eq!(content, changed_zone().replace("www\t300\tIN\tA\t192.0.2.1\n", "www\t300\tIN\tA\t192.0.2.2\n"));
+    }
+
+    #[test]
+    fn gives_the_owner_back_to_records_which_inherited_it() {
+        let (provider, path) = provider("delete");
+        provider.delete(&DeleteRequest { selector: RecordSelector::new("example.com", Some("www"), Some("A")), wait: false }).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(content, changed_zone().replace("www\t300\tIN\tA\t192.0.2.1\n\t300\tIN\tAAAA", "www\t300\tIN\tAAAA"));
+    }
+
+    #[test]
+    fn adds_records_after_the_records_of_their_owner() {
+        let (provider, path) = provider("add");
+        update(&provider, Some("www"), Some("TXT"), vec![record("www", "TXT", "\"hello\"")]);
+        update(&provider, Some("new"), None, vec![record("new", "A", "192.0.2.3")]);
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let expected = changed_zone()
+            .replace("2001:db8::1\n", "2001:db8::1\nwww\t300\tIN\tTXT\t\"hello\"\n")
+            + "new.example.com.\t300\tIN\tA\t192.0.2.3\n";
+        // Both changes bumped the serial
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let serial = next_serial(2024030101, now);
+        assert_eq!(content, expected.replace(&serial.to_string(), &next_serial(serial, now).to_string()));
+    }
+
+    #[test]
+    fn bumps_serials_following_the_date_convention() {
+        // The first change of the day
+        assert_eq!(next_serial(2024030101, MARCH_2ND), 2024030200);
+        // Later changes of the same day
+        assert_eq!(next_serial(2024030200, MARCH_2ND), 2024030201);
+        // More than 100 changes in a day run into the next day
+        assert_eq!(next_serial(2024030299, MARCH_2ND), 2024030300);
+        assert_eq!(next_serial(2024030300, MARCH_2ND), 2024030301);
+        // Serials which are not dates are counted up
+        assert_eq!(next_serial(42, MARCH_2ND), 43);
+        assert_eq!(next_serial(u32::MAX, MARCH_2ND), 0);
+    }
+
+    #[test]
+    fn runs_the_reload_command_after_a_change() {
+        let (mut provider, path) = provider("reload");
+        let marker = path.with_extension("reloaded");
+        let _ = fs::remove_file(&marker);
+        provider.set_credentials(vec![config::Credential {
+            provider: String::from("zonefile"),
+            zone: Some(String::from("example.com")),
+            path: Some(path.to_string_lossy().to_string()),
+            reload_command: Some(format!("touch {}", marker.display())),
+            ..Default::default()
+        }]);
+
+        update(&provider, Some("www"), Some("A"), vec![record("www", "A", "192.0.2.1")]);
+        assert!(!marker.exists());
+        update(&provider, Some("www"), Some("A"), vec![record("www", "A", "192.0.2.2")]);
+        assert!(marker.exists());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&marker).unwrap();
+    }
+}
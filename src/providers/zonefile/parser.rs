@@ -0,0 +1,458 @@
+//! Reading and writing BIND master files (RFC 1035 section 5) without losing their layout.
+//!
+//! A file is kept as a list of entries, each holding the exact text it was read from (one line,
+//! or several for records spanning lines within parentheses). Only the entries which change are
+//! written again, so comments, blank lines and ordering are preserved.
+use crate::providers::{ProviderError, ProviderErrorKind, Result};
+
+/// A piece of text within an entry, outside of comments and parentheses
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+/// A resource record read from the file
+#[derive(Debug, Clone)]
+pub struct RecordEntry {
+    /// Fully-qualified owner name, without the trailing dot
+    pub owner: String,
+    /// Whether the owner is written or inherited from the previous record
+    pub explicit_owner: bool,
+    /// The $ORIGIN relative names are completed with, where the record is
+    pub origin: String,
+    pub ttl: u32,
+    pub class: String,
+    pub r#type: String,
+    /// The data of the record with all names fully-qualified
+    pub rdata: Vec<String>,
+    /// Position of each field of the data within the text of the entry
+    spans: Vec<(usize, usize)>,
+}
+
+#[derive(Debug)]
+pub struct Entry {
+    pub text: String,
+    pub record: Option<RecordEntry>,
+}
+
+#[derive(Debug)]
+pub struct ZoneFile {
+    pub entries: Vec<Entry>,
+    /// The $ORIGIN at the end of the file
+    pub origin: String,
+}
+
+
+impl RecordEntry {
+    /// The record data in presentation format
+    pub fn content(&self) -> String {
+        self.rdata.join(" ")
+    }
+}
+
+impl Entry {
+    /// Replace the serial of a SOA record, keeping the rest of its text untouched
+    pub fn set_serial(&mut self, serial: u32) {
+        if let Some(record) = &mut self.record {
+            if let Some(&(start, end)) = record.spans.get(2) {
+                self.text.replace_range(start..end, &serial.to_string());
+                record.rdata[2] = serial.to_string();
+            }
+        }
+    }
+}
+
+
+impl ZoneFile {
+    pub fn parse(content: &str, origin: &str) -> Result<Self> {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut origin = origin.trim_end_matches('.').to_lowercase();
+        let mut default_ttl: Option<u32> = None;
+        let mut last_owner: Option<String> = None;
+        let mut last_ttl: Option<u32> = None;
+
+        let mut text = String::new();
+        let mut depth = 0;
+        let mut line_number = 0;
+
+        for line in content.split_inclusive('\n') {
+            line_number += 1;
+            text.push_str(line);
+            depth += lex(line)?.1;
+
+            if depth > 0 {
+                continue;
+            }
+
+            let entry_text = std::mem::take(&mut text);
+            let (tokens, _) = lex(&entry_text)?;
+            let invalid = |msg: &str| ProviderError::new(ProviderErrorKind::InvalidRecord)
+                .msg(format!("Line {} of the zone file: {}", line_number, msg));
+
+            // Blank lines and comments
+            if tokens.is_empty() {
+                entries.push(Entry { text: entry_text, record: None });
+                continue;
+            }
+
+            if tokens[0].text.starts_with('$') {
+                let argument = tokens.get(1).map(|t| t.text.as_str());
+
+                match (tokens[0].text.to_uppercase().as_str(), argument) {
+                    ("$ORIGIN", Some(o)) => origin = absolute_name(o, &origin),
+                    ("$TTL", Some(t)) => default_ttl = Some(parse_ttl(t).ok_or_else(|| invalid("invalid $TTL"))?),
+                    ("$INCLUDE", _) => log::warn!("Line {} of the zone file: records of included files are not managed", line_number),
+                    _ => (),
+                }
+
+                entries.push(Entry { text: entry_text, record: None });
+                continue;
+            }
+
+            let explicit_owner = !entry_text.starts_with([' ', '\t']);
+            let mut tokens = tokens.into_iter().peekable();
+
+            let owner = if explicit_owner {
+                absolute_name(&tokens.next().expect("Entry has tokens").text, &origin)
+            } else {
+                last_owner.clone().ok_or_else(|| invalid("record without an owner"))?
+            };
+
+            let mut ttl: Option<u32> = None;
+            let mut class = String::from("IN");
+
+            for _ in 0..2 {
+                let token = tokens.peek().map(|t| t.text.to_uppercase()).unwrap_or_default();
+
+                if let Some(t) = parse_ttl(&token) {
+                    ttl = Some(t);
+                    tokens.next();
+                } else if matches!(token.as_str(), "IN" | "CH" | "HS" | "CS") {
+                    class = token;
+                    tokens.next();
+                }
+            }
+
+            let rtype = match tokens.next() {
+                Some(t) => t.text.to_uppercase(),
+                None => return Err(invalid("record without a type")),
+            };
+
+            let rest: Vec<Token> = tokens.collect();
+            let mut rdata: Vec<String> = rest.iter().map(|t| t.text.clone()).collect();
+
+            // Complete the relative names within the data
+            for i in name_fields(&rtype) {
+                if let Some(name) = rdata.get_mut(*i) {
+                    *name = format!("{}.", absolute_name(name, &origin));
+                }
+            }
+
+            let ttl = ttl.or(default_ttl).or(last_ttl).unwrap_or(0);
+            last_owner = Some(owner.clone());
+            last_ttl = Some(ttl);
+
+            entries.push(Entry {
+                text: entry_text,
+                record: Some(RecordEntry {
+                    owner,
+                    explicit_owner,
+                    origin: origin.clone(),
+                    ttl,
+                    class,
+                    r#type: rtype,
+                    rdata,
+                    spans: rest.iter().map(|t| (t.start, t.end)).collect(),
+                }),
+            });
+        }
+
+        if depth > 0 {
+            return Err(ProviderError::new(ProviderErrorKind::InvalidRecord)
+                .msg(String::from("The zone file ends within parentheses")));
+        }
+
+        Ok(ZoneFile { entries, origin })
+    }
+}
+
+
+/// Write a record on a single line, with names relative to the origin where possible
+pub fn render(origin: &str, owner: &str, ttl: u32, rtype: &str, content: &str) -> String {
+    format!("{}\t{}\tIN\t{}\t{}\n", relative_name(owner, origin), ttl, rtype, content)
+}
+
+/// Prefix an entry which inherits its owner with the owner, for when the record it inherited the
+/// owner from is gone
+pub fn with_owner(entry: &Entry) -> String {
+    match &entry.record {
+        Some(r) => format!("{}{}", relative_name(&r.owner, &r.origin), entry.text),
+        None => entry.text.clone(),
+    }
+}
+
+/// Name of a host as written in the file: @ for the origin, relative to the origin when within
+/// it and fully-qualified (with the trailing dot) otherwise
+fn relative_name(name: &str, origin: &str) -> String {
+    if name.eq_ignore_ascii_case(origin) {
+        return String::from("@");
+    }
+
+    let suffix = format!(".{}", origin);
+    if !origin.is_empty() && name.to_lowercase().ends_with(&suffix) {
+        return name[..name.len() - suffix.len()].to_string();
+    }
+
+    format!("{}.", name)
+}
+
+/// Fully-qualified form (without the trailing dot) of a name as written in the file
+fn absolute_name(name: &str, origin: &str) -> String {
+    if name == "@" {
+        return origin.to_string();
+    }
+
+    if let Some(n) = name.strip_suffix('.') {
+        return n.to_lowercase();
+    }
+
+    if origin.is_empty() {
+        return name.to_lowercase();
+    }
+
+    format!("{}.{}", name, origin).to_lowercase()
+}
+
+/// Fields of the data of a record which are domain names
+fn name_fields(rtype: &str) -> &'static [usize] {
+    match rtype {
+        "CNAME" | "NS" | "PTR" | "DNAME" => &[0],
+        "MX" => &[1],
+        "SRV" => &[3],
+        "SOA" => &[0, 1],
+        _ => &[],
+    }
+}
+
+/// A TTL in seconds (e.g. 3600) or in the BIND notation (e.g. 1h30m)
+fn parse_ttl(token: &str) -> Option<u32> {
+    if token.is_empty() || !token.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    if let Ok(t) = token.parse::<u32>() {
+        return Some(t);
+    }
+
+    let mut total: u32 = 0;
+    let mut number = String::new();
+
+    for c in token.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604_800,
+            _ => return None,
+        };
+
+        total = total.checked_add(number.parse::<u32>().ok()?.checked_mul(unit)?)?;
+        number.clear();
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(total)
+}
+
+/// Split text into tokens, skipping comments and parentheses. Also returns how much deeper within
+/// parentheses the text ends.
+fn lex(text: &str) -> Result<(Vec<Token>, i32)> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut depth = 0;
+    let mut current: Option<usize> = None;
+    let mut chars = text.char_indices().peekable();
+
+    let flush = |tokens: &mut Vec<Token>, current: &mut Option<usize>, end: usize| {
+        if let Some(start) = current.take() {
+            tokens.push(Token { text: text[start..end].to_string(), start, end });
+        }
+    };
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                flush(&mut tokens, &mut current, i);
+
+                let mut end = None;
+                while let Some((j, d)) = chars.next() {
+                    match d {
+                        '\\' => { chars.next(); },
+                        '"' => {
+                            end = Some(j + 1);
+                            break;
+                        },
+                        _ => (),
+                    }
+                }
+
+                match end {
+                    Some(e) => tokens.push(Token { text: text[i..e].to_string(), start: i, end: e }),
+                    None => return Err(ProviderError::new(ProviderErrorKind::InvalidRecord)
+                        .msg(String::from("Unterminated quoted string in the zone file"))),
+                }
+            },
+            ';' => {
+                flush(&mut tokens, &mut current, i);
+                for (_, d) in chars.by_ref() {
+                    if d == '\n' {
+                        break;
+                    }
+                }
+            },
+            '(' | ')' => {
+                flush(&mut tokens, &mut current, i);
+                depth += if c == '(' { 1 } else { -1 };
+            },
+            c if c.is_whitespace() => flush(&mut tokens, &mut current, i),
+            '\\' => {
+                current.get_or_insert(i);
+                chars.next();
+            },
+            _ => {
+                current.get_or_insert(i);
+            },
+        }
+    }
+
+    flush(&mut tokens, &mut current, text.len());
+
+    Ok((tokens, depth))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZONE: &str = include_str!("../../../tests/fixtures/zonefile/example.com.zone");
+
+    fn records(file: &ZoneFile) -> Vec<&RecordEntry> {
+        file.entries.iter().filter_map(|e| e.record.as_ref()).collect()
+    }
+
+    #[test]
+    fn keeps_the_text_of_every_entry() {
+        let file = ZoneFile::parse(ZONE, "example.com").unwrap();
+        let text: String = file.entries.iter().map(|e| e.text.as_str()).collect();
+
+        assert_eq!(text, ZONE);
+        // The SOA record spans several lines within parentheses
+        assert_eq!(records(&file).len(), 8);
+        assert_eq!(file.origin, "lab.example.com");
+    }
+
+    #[test]
+    fn reads_records_spanning_lines() {
+        let file = ZoneFile::parse(ZONE, "example.com").unwrap();
+        let soa = records(&file)[0];
+
+        assert_eq!(soa.owner, "example.com");
+        assert_eq!(soa.r#type, "SOA");
+        assert_eq!(soa.rdata, vec!["ns1.example.com.", "hostmaster.example.com.", "2024030101", "3600", "900", "1w", "300"]);
+        assert_eq!(soa.ttl, 3600);
+    }
+
+    #[test]
+    fn completes_implicit_owners_and_relative_names() {
+        let file = ZoneFile::parse(ZONE, "example.com").unwrap();
+        let records = records(&file);
+
+        assert_eq!(records[1].owner, "example.com");
+        assert!(!records[1].explicit_owner);
+        assert_eq!(records[1].content(), "ns1.example.com.");
+        assert_eq!(records[2].content(), "ns2.example.net.");
+        assert_eq!(records[3].content(), "10 mail.example.com.");
+
+        assert_eq!(records[5].owner, "www.example.com");
+        assert!(!records[5].explicit_owner);
+        assert_eq!(records[5].r#type, "AAAA");
+        assert_eq!(records[6].content(), "www.example.com.");
+    }
+
+    #[test]
+    fn follows_origin_and_ttl_directives() {
+        let file = ZoneFile::parse(ZONE, "example.com").unwrap();
+        let records = records(&file);
+
+        // $TTL 1h, unless the record has its own
+        assert_eq!(records[3].ttl, 3600);
+        assert_eq!(records[4].ttl, 300);
+        assert_eq!(records[6].ttl, 3600);
+
+        assert_eq!(records[7].owner, "printer.lab.example.com");
+        assert_eq!(records[7].origin, "lab.example.com");
+    }
+
+    #[test]
+    fn takes_the_ttl_of_the_previous_record_without_a_default() {
+        let file = ZoneFile::parse("www 300 IN A 192.0.2.1\nftp IN CNAME www\n", "example.com").unwrap();
+
+        assert_eq!(records(&file)[1].ttl, 300);
+    }
+
+    #[test]
+    fn reads_ttls_in_the_bind_notation() {
+        assert_eq!(parse_ttl("3600"), Some(3600));
+        assert_eq!(parse_ttl("1h30m"), Some(5400));
+        assert_eq!(parse_ttl("1w"), Some(604_800));
+        assert_eq!(parse_ttl("IN"), None);
+        assert_eq!(parse_ttl("1x"), None);
+        assert_eq!(parse_ttl("1h3"), None);
+    }
+
+    #[test]
+    fn keeps_quoted_strings_whole() {
+        let file = ZoneFile::parse("@ 300 IN TXT \"v=spf1 -all ; not a comment\" ; a comment\n", "example.com").unwrap();
+
+        assert_eq!(records(&file)[0].rdata, vec!["\"v=spf1 -all ; not a comment\""]);
+    }
+
+    #[test]
+    fn replaces_the_serial_only() {
+        let mut file = ZoneFile::parse(ZONE, "example.com").unwrap();
+        let entry = file.entries.iter_mut().find(|e| e.record.as_ref().is_some_and(|r| r.r#type == "SOA")).unwrap();
+        let before = entry.text.clone();
+
+        entry.set_serial(2024030102);
+
+        assert_eq!(entry.text, before.replace("2024030101", "2024030102"));
+        assert_eq!(entry.record.as_ref().unwrap().rdata[2], "2024030102");
+    }
+
+    #[test]
+    fn writes_names_relative_to_the_origin() {
+        assert_eq!(render("example.com", "example.com", 300, "A", "192.0.2.1"), "@\t300\tIN\tA\t192.0.2.1\n");
+        assert_eq!(render("example.com", "www.example.com", 300, "A", "192.0.2.1"), "www\t300\tIN\tA\t192.0.2.1\n");
+        assert_eq!(render("example.com", "www.example.net", 300, "A", "192.0.2.1"), "www.example.net.\t300\tIN\tA\t192.0.2.1\n");
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(ZoneFile::parse("@ IN SOA ns1 hostmaster ( 1 2 3 4 5\n", "example.com").is_err());
+        assert!(ZoneFile::parse("\tIN A 192.0.2.1\n", "example.com").is_err());
+        assert!(ZoneFile::parse("www IN\n", "example.com").is_err());
+        assert!(ZoneFile::parse("$TTL forever\n", "example.com").is_err());
+        assert!(ZoneFile::parse("@ IN TXT \"unterminated\n", "example.com").is_err());
+    }
+}
@@ -0,0 +1,435 @@
+//! The addresses published last, kept in a state file between runs.
+//!
+//! DDNS runs (e.g. from cron) compare the address of the client with the one they published last
+//! and skip the API call when it did not change, which keeps them well below the abuse limits of
+//! the providers. Only the address the provider reports (or was told) counts as published, so
+//! providers publishing the address the request comes from without telling it are always called.
+//! The file lives in `$XDG_STATE_HOME/ddnsclient/` (`~/.local/state/ddnsclient/` when not set)
+//! unless told otherwise. Runs happening at the same time take turns through a lock file, and the
+//! file is replaced at once so it is never left half written.
+//!
+//! The file also keeps the providers which asked not to be contacted again, e.g. a dyndns2 server
+//! answering `badauth`, so runs from cron keep to it as well.
+use crate::ip_detection;
+use crate::providers::{DynamicDnsResponse, Result, civil_date};
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use fs2::FileExt;
+use serde::{Serialize, Deserialize};
+
+/// What was published last for a host
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Entry {
+    /// The provider the address was published with. Entries of earlier versions have none, and
+    /// are never taken as published.
+    #[serde(default)]
+    pub provider: String,
+    pub zone: String,
+    pub host: String,
+    pub r#type: String,
+    /// The address published last
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// When the address was published, in seconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published_at: Option<u64>,
+    /// Outcome of the last attempt: what the provider answered, or the error when it failed
+    pub response: String,
+    /// When the last attempt happened, in seconds since the epoch
+    pub updated_at: u64,
+}
+
+/// A provider asking not to be contacted again for an account, or for one of its hosts, for a while
+/// or until the problem is fixed
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Hold {
+    pub provider: String,
+    pub account: String,
+    /// The host the hold is about, when it does not apply to the whole account
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// What the provider answered
+    pub response: String,
+    /// When the provider can be contacted again, in seconds since the epoch. Without it, the hold
+    /// lasts until it is cleared.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+}
+
+/// Everything the state file keeps
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Content {
+    #[serde(default)]
+    entries: Vec<Entry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    holds: Vec<Hold>,
+}
+
+/// The layouts of the file, which only kept the entries at first
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Layout {
+    Content(Content),
+    Entries(Vec<Entry>),
+}
+
+/// The state file
+#[derive(Debug, Clone)]
+pub struct State {
+    path: PathBuf,
+}
+
+
+impl Entry {
+    fn is(&self, provider: &str, zone: &str, host: &str, rtype: &str) -> bool {
+        self.provider == provider
+            && self.zone.eq_ignore_ascii_case(zone.trim_end_matches('.'))
+            && self.host.eq_ignore_ascii_case(host)
+            && self.r#type.eq_ignore_ascii_case(rtype)
+    }
+
+    /// Whether the entry is of the zone, host and record type, any of them when not given
+    pub fn matches(&self, zone: Option<&str>, host: Option<&str>, rtype: Option<&str>) -> bool {
+        let matches = |value: &str, wanted: Option<&str>| wanted.is_none_or(|w| value.eq_ignore_ascii_case(w.trim_end_matches('.')));
+
+        matches(&self.zone, zone) && matches(&self.host, host) && matches(&self.r#type, rtype)
+    }
+
+    /// The address published last, if it was published successfully
+    pub fn published_address(&self) -> Option<IpAddr> {
+        self.address.as_deref().and_then(|a| a.parse().ok())
+    }
+}
+
+impl Hold {
+    fn is(&self, provider: &str, account: &str, host: Option<&str>) -> bool {
+        self.provider == provider
+            && self.account == account
+            && self.host.as_deref().map(str::to_ascii_lowercase) == host.map(str::to_ascii_lowercase)
+    }
+
+    /// Whether the hold keeps the host of the account from being updated
+    fn applies_to(&self, provider: &str, account: &str, host: &str) -> bool {
+        self.provider == provider
+            && self.account == account
+            && self.host.as_deref().is_none_or(|h| h.eq_ignore_ascii_case(host))
+    }
+
+    /// Whether the hold is over
+    fn expired(&self, now: u64) -> bool {
+        self.until.is_some_and(|u| u <= now)
+    }
+}
+
+impl State {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        State {
+            path: path.into(),
+        }
+    }
+
+    /// `$XDG_STATE_HOME/ddnsclient/state.json`, or `~/.local/state/ddnsclient/state.json`
+    pub fn default_path() -> Option<PathBuf> {
+        let directory = match env::var_os("XDG_STATE_HOME").filter(|d| !d.is_empty()) {
+            Some(d) => PathBuf::from(d),
+            None => PathBuf::from(env::var_os("HOME").filter(|h| !h.is_empty())?).join(".local").join("state"),
+        };
+
+        Some(directory.join("ddnsclient").join("state.json"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// All the entries, oldest first
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        let lock = self.lock()?;
+        FileExt::lock_shared(&lock)?;
+
+        Ok(self.read()?.entries)
+    }
+
+    /// The entry of the host and record type published with the provider, if any
+    pub fn get(&self, provider: &str, zone: &str, host: &str, rtype: &str) -> Result<Option<Entry>> {
+        Ok(self.entries()?.into_iter().find(|e| e.is(provider, zone, host, rtype)))
+    }
+
+    /// Whether the address is the one published last for the host with the provider, so there is
+    /// no need to publish it again. A state which can not be read counts as a change.
+    pub fn is_published(&self, provider: &str, zone: &str, host: &str, address: IpAddr) -> bool {
+        match self.get(provider, zone, host, ip_detection::record_type(&address)) {
+            Ok(entry) => entry.and_then(|e| e.published_address()) == Some(address),
+            Err(e) => {
+                log::warn!("Unable to read the state file {}: {}", self.path.display(), e);
+                false
+            },
+        }
+    }
+
+    /// Remember the outcome of publishing an address for the host with the provider, under the
+    /// address the provider reports or else the `requested` one. Failing to do so is only logged,
+    /// as the address was published (or not) regardless.
+    pub fn remember(&self, provider: &str, zone: &str, host: &str, requested: Option<IpAddr>, outcome: &Result<DynamicDnsResponse>) {
+        let rtype = requested.as_ref().map(ip_detection::record_type);
+        let written = match (outcome, rtype) {
+            (Ok(DynamicDnsResponse { address: Some(address), message }), _) => self.published(provider, zone, host, *address, message),
+            // Which address the provider published is unknown, so none is taken as published
+            (Ok(response), Some(rtype)) => self.attempted(provider, zone, host, rtype, &response.message, true),
+            (Err(e), Some(rtype)) => self.attempted(provider, zone, host, rtype, &e.to_string(), false),
+            _ => return,
+        };
+
+        if let Err(e) = written {
+            log::warn!("Unable to update the state file {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Remember the address the provider published for the host
+    fn published(&self, provider: &str, zone: &str, host: &str, address: IpAddr, response: &str) -> Result<()> {
+        self.change(|content| {
+            let now = now();
+            let entry = entry(&mut content.entries, provider, zone, host, ip_detection::record_type(&address));
+            entry.address = Some(address.to_string());
+            entry.published_at = Some(now);
+            entry.response = response.to_string();
+            entry.updated_at = now;
+        })
+    }
+
+    /// Remember an attempt to publish for the host which did not tell the address: the provider
+    /// published one it did not tell, forgetting the address published last, or it failed,
+    /// keeping it
+    fn attempted(&self, provider: &str, zone: &str, host: &str, rtype: &str, response: &str, published: bool) -> Result<()> {
+        self.change(|content| {
+            let now = now();
+            let entry = entry(&mut content.entries, provider, zone, host, rtype);
+            if published {
+                entry.address = None;
+                entry.published_at = Some(now);
+            }
+            entry.response = response.to_string();
+            entry.updated_at = now;
+        })
+    }
+
+    /// Forget the entries of the zone, host and record type (all of them when not given), and
+    /// return how many were removed. Forgetting all the entries lifts the holds of the providers
+    /// as well.
+    pub fn clear(&self, zone: Option<&str>, host: Option<&str>, rtype: Option<&str>) -> Result<usize> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        self.change(|content| {
+            let before = content.entries.len();
+            content.entries.retain(|e| !e.matches(zone, host, rtype));
+            removed = before - content.entries.len();
+
+            if zone.is_none() && host.is_none() && rtype.is_none() {
+                content.holds.clear();
+            }
+        })?;
+
+        Ok(removed)
+    }
+
+    /// The hold of the provider keeping the host of the account from being updated, unless there is
+    /// none or it is over
+    pub fn hold(&self, provider: &str, account: &str, host: &str) -> Result<Option<Hold>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let lock = self.lock()?;
+        FileExt::lock_shared(&lock)?;
+
+        let now = now();
+        Ok(self.read()?.holds.into_iter().find(|h| h.applies_to(provider, account, host) && !h.expired(now)))
+    }
+
+    /// Remember that the provider asked not to be contacted again for the account (or only for the
+    /// host, when given), for the given time or until the hold is cleared
+    pub fn put_on_hold(&self, provider: &str, account: &str, host: Option<&str>, response: &str, duration: Option<Duration>) -> Result<()> {
+        self.change(|content| {
+            let now = now();
+            content.holds.retain(|h| !h.is(provider, account, host) && !h.expired(now));
+            content.holds.push(Hold {
+                provider: provider.to_string(),
+                account: account.to_string(),
+                host: host.map(str::to_string),
+                response: response.to_string(),
+                until: duration.map(|d| now + d.as_secs()),
+            });
+        })
+    }
+
+    /// Change the content while holding the lock, and replace the file with the result
+    fn change<F: FnOnce(&mut Content)>(&self, change: F) -> Result<()> {
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+
+        let lock = self.lock()?;
+        lock.lock_exclusive()?;
+
+        let mut content = self.read()?;
+        change(&mut content);
+
+        let temporary = self.sibling("tmp");
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(serde_json::to_string_pretty(&content)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)?;
+
+        Ok(())
+    }
+
+    fn read(&self) -> Result<Content> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) if content.trim().is_empty() => return Ok(Content::default()),
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Content::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        match serde_json::from_str(&content)? {
+            Layout::Content(content) => Ok(content),
+            Layout::Entries(entries) => Ok(Content { entries, holds: vec![] }),
+        }
+    }
+
+    /// The file is replaced on every change, so the lock is held on a file next to it. The lock is
+    /// released when the returned file is closed.
+    fn lock(&self) -> Result<fs::File> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.sibling("lock"))?;
+
+        Ok(file)
+    }
+
+    fn sibling(&self, extension: &str) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(".");
+        name.push(extension);
+        PathBuf::from(name)
+    }
+}
+
+
+/// The entry of the host and record type published with the provider, added if there is none yet
+fn entry<'a>(entries: &'a mut Vec<Entry>, provider: &str, zone: &str, host: &str, rtype: &str) -> &'a mut Entry {
+    let index = match entries.iter().position(|e| e.is(provider, zone, host, rtype)) {
+        Some(i) => i,
+        None => {
+            entries.push(Entry {
+                provider: provider.to_string(),
+                zone: zone.trim_end_matches('.').to_lowercase(),
+                host: host.to_lowercase(),
+                r#type: rtype.to_uppercase(),
+                ..Default::default()
+            });
+            entries.len() - 1
+        },
+    };
+
+    &mut entries[index]
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Seconds since the epoch as a UTC date and time, e.g. `2021-03-04 05:06:07`
+pub fn format_time(seconds: u64) -> String {
+    let (year, month, day) = civil_date(seconds);
+    let time = seconds % 86_400;
+
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::{ProviderError, ProviderErrorKind};
+
+    use std::process;
+
+    fn state(name: &str) -> State {
+        let state = State::new(env::temp_dir().join(format!("ddnsclient-state-{}-{}.json", process::id(), name)));
+        let _ = fs::remove_file(state.path());
+        state
+    }
+
+    fn response(address: Option<&str>, message: &str) -> Result<DynamicDnsResponse> {
+        Ok(DynamicDnsResponse { address: address.map(|a| a.parse().unwrap()), message: message.to_string() })
+    }
+
+    #[test]
+    fn keeps_the_address_the_provider_reports() {
+        let state = state("reported");
+        let requested: IpAddr = "203.0.113.1".parse().unwrap();
+        state.remember("dyndns2", "example.com", "home", Some(requested), &response(Some("2001:db8::1"), "good 2001:db8::1"));
+
+        assert!(!state.is_published("dyndns2", "example.com", "home", requested));
+        assert!(state.is_published("dyndns2", "example.com", "home", "2001:db8::1".parse().unwrap()));
+
+        let entry = state.get("dyndns2", "example.com", "home", "AAAA").unwrap().unwrap();
+        assert_eq!(entry.response, "good 2001:db8::1");
+        assert!(entry.published_at.is_some());
+    }
+
+    #[test]
+    fn keys_the_entries_by_provider() {
+        let state = state("provider");
+        let address: IpAddr = "203.0.113.1".parse().unwrap();
+        state.remember("rfc2136", "example.com", "home", Some(address), &response(Some("203.0.113.1"), "Updated"));
+
+        assert!(state.is_published("rfc2136", "example.com", "home", address));
+        assert!(!state.is_published("cloudflare", "example.com", "home", address));
+    }
+
+    #[test]
+    fn takes_no_address_as_published_when_the_provider_does_not_tell_it() {
+        let state = state("untold");
+        let address: IpAddr = "203.0.113.1".parse().unwrap();
+        state.remember("mythic-beasts", "example.com", "home", Some(address), &response(Some("203.0.113.1"), "Updated"));
+        state.remember("mythic-beasts", "example.com", "home", Some(address), &response(None, "1 record updated"));
+
+        assert!(!state.is_published("mythic-beasts", "example.com", "home", address));
+        assert_eq!(state.get("mythic-beasts", "example.com", "home", "A").unwrap().unwrap().response, "1 record updated");
+    }
+
+    #[test]
+    fn keeps_the_published_address_when_publishing_fails() {
+        let state = state("failed");
+        let address: IpAddr = "203.0.113.1".parse().unwrap();
+        state.remember("rfc2136", "example.com", "home", Some(address), &response(Some("203.0.113.1"), "Updated"));
+        state.remember("rfc2136", "example.com", "home", Some("203.0.113.2".parse().unwrap()), &Err(ProviderError::with_message(ProviderErrorKind::ServerError, "SERVFAIL")));
+
+        let entry = state.get("rfc2136", "example.com", "home", "A").unwrap().unwrap();
+        assert_eq!(entry.published_address(), Some(address));
+        assert!(entry.response.contains("SERVFAIL"));
+    }
+
+    #[test]
+    fn formats_times_as_utc() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00");
+        assert_eq!(format_time(1_614_834_367), "2021-03-04 05:06:07");
+    }
+}